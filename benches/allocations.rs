@@ -0,0 +1,128 @@
+//
+//  Heap allocations per request, for a HEAD, a PROPFIND Depth 0 and
+//  an OPTIONS against MemFs, through `handle` and `handle_with`.
+//  Counted with a global allocator that wraps the system allocator.
+//
+//  The counts are compared with a budget, so that a change that
+//  adds allocations to the hot path does not go unnoticed. If a
+//  change makes a count go down, lower the budget.
+//
+//  Run with `cargo bench --bench allocations`.
+//
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures_util::StreamExt;
+use http::{Request, StatusCode};
+
+use dav_server::{body::Body, memfs::MemFs, memls::MemLs, DavConfig, DavHandler};
+
+const REQUESTS: usize = 1000;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// The average number of allocations of a request, including
+// building the request and reading the response body. With a
+// `principal`, the request goes through `handle_with`, like it
+// does in servers that have a principal per user.
+async fn count(
+    dav: &DavHandler,
+    name: &str,
+    budget: usize,
+    principal: Option<&str>,
+    make_req: impl Fn() -> Request<Body>,
+    status: StatusCode,
+) {
+    let start = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..REQUESTS {
+        let resp = match principal {
+            Some(principal) => {
+                let config = DavConfig::new().principal(principal);
+                dav.handle_with(config, make_req()).await
+            }
+            None => dav.handle(make_req()).await,
+        };
+        assert_eq!(resp.status(), status);
+        let mut body = resp.into_body();
+        while let Some(chunk) = body.next().await {
+            chunk.unwrap();
+        }
+    }
+    let allocs = (ALLOCATIONS.load(Ordering::Relaxed) - start) / REQUESTS;
+    println!(
+        "{:<28} {:>6} allocations/request (budget {})",
+        name, allocs, budget
+    );
+    assert!(
+        allocs <= budget,
+        "{}: {} allocations per request, budget is {}",
+        name,
+        allocs,
+        budget
+    );
+}
+
+fn main() {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        let req = Request::put("/file.txt")
+            .body(Body::from("hello world"))
+            .unwrap();
+        assert_eq!(dav.handle(req).await.status(), StatusCode::CREATED);
+
+        let head = || Request::head("/file.txt").body(Body::empty()).unwrap();
+        let propfind = || {
+            Request::builder()
+                .method("PROPFIND")
+                .uri("/file.txt")
+                .header("Depth", "0")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let options = || {
+            Request::builder()
+                .method("OPTIONS")
+                .uri("/file.txt")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let ok = StatusCode::OK;
+        let multi = StatusCode::MULTI_STATUS;
+        count(&dav, "HEAD", 57, None, head, ok).await;
+        count(&dav, "PROPFIND depth 0", 330, None, propfind, multi).await;
+        count(&dav, "OPTIONS", 25, None, options, ok).await;
+
+        let alice = Some("alice");
+        count(&dav, "HEAD handle_with", 62, alice, head, ok).await;
+        let name = "PROPFIND depth 0 handle_with";
+        count(&dav, name, 335, alice, propfind, multi).await;
+    });
+}
@@ -0,0 +1,413 @@
+//
+//  In-process request throughput against MemFs, PUT throughput
+//  against LocalFs for each `Durability` setting, and case-insensitive
+//  lookups on LocalFs with and without the lookup cache.
+//
+//  Also: concurrent GET + PROPFIND on LocalFs on a current-thread and
+//  a multi-threaded runtime with a small blocking pool, with and without
+//  `LocalFs::blocking_threads`, and how long an unrelated
+//  `spawn_blocking` call has to wait meanwhile.
+//
+//  And: a 256 MB GET from a MemFs with 1 ms read latency, through a
+//  duplex pipe with a slow reader, for several `read_ahead` depths.
+//
+//  Run with `cargo bench --bench requests`.
+//
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures_util::{FutureExt, StreamExt};
+use http::{Request, StatusCode};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Runtime;
+
+use dav_server::davpath::DavPath;
+use dav_server::fs::*;
+use dav_server::{body::Body, localfs::LocalFs, memfs::MemFs, memls::MemLs};
+use dav_server::{DavHandler, Durability};
+
+const UPLOADS: u32 = 1000;
+
+fn current_thread() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+// send a request and read the whole response body.
+async fn request(dav: &DavHandler, req: Request<Body>, status: StatusCode) {
+    let resp = dav.handle(req).await;
+    assert_eq!(resp.status(), status);
+    let mut body = resp.into_body();
+    while let Some(chunk) = body.next().await {
+        chunk.unwrap();
+    }
+}
+
+fn propfind(path: &str, depth: &str) -> Request<Body> {
+    Request::builder()
+        .method("PROPFIND")
+        .uri(path)
+        .header("Depth", depth)
+        .body(Body::empty())
+        .unwrap()
+}
+
+// HEAD and PROPFIND on a MemFs with 11 files.
+fn memfs(c: &mut Criterion) {
+    let rt = current_thread();
+    let dav = DavHandler::builder()
+        .filesystem(MemFs::new())
+        .locksystem(MemLs::new())
+        .build_handler();
+    rt.block_on(async {
+        let req = Request::put("/file.txt")
+            .body(Body::from("hello world"))
+            .unwrap();
+        assert_eq!(dav.handle(req).await.status(), StatusCode::CREATED);
+        for i in 0..10 {
+            let path = format!("/file{}.txt", i);
+            let req = Request::put(path).body(Body::from("hello")).unwrap();
+            assert_eq!(dav.handle(req).await.status(), StatusCode::CREATED);
+        }
+    });
+
+    c.bench_function("HEAD file", |b| {
+        b.to_async(&rt).iter(|| {
+            let req = Request::head("/file.txt").body(Body::empty()).unwrap();
+            request(&dav, req, StatusCode::OK)
+        })
+    });
+    c.bench_function("PROPFIND depth 0", |b| {
+        b.to_async(&rt)
+            .iter(|| request(&dav, propfind("/file.txt", "0"), StatusCode::MULTI_STATUS))
+    });
+    c.bench_function("PROPFIND depth 1", |b| {
+        b.to_async(&rt)
+            .iter(|| request(&dav, propfind("/", "1"), StatusCode::MULTI_STATUS))
+    });
+}
+
+// PUT files of 4 KB each to a fresh LocalFs directory, for each
+// `Durability` setting. After UPLOADS files, existing ones are overwritten.
+fn put_localfs(c: &mut Criterion) {
+    let rt = current_thread();
+    let data = bytes::Bytes::from(vec![b'x'; 4096]);
+    let mut group = c.benchmark_group("PUT");
+    for durability in [Durability::None, Durability::Fdatasync, Durability::Fsync] {
+        let dir = std::env::temp_dir().join(format!("dav-bench-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .durability(durability)
+            .build_handler();
+
+        let mut i = 0;
+        let id = BenchmarkId::from_parameter(format!("{:?}", durability));
+        group.bench_function(id, |b| {
+            b.to_async(&rt).iter(|| {
+                let path = format!("/file{}", i % UPLOADS);
+                i += 1;
+                let req = Request::put(path).body(Body::from(data.clone())).unwrap();
+                let dav = &dav;
+                async move {
+                    assert!(dav.handle(req).await.status().is_success());
+                }
+            })
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    group.finish();
+}
+
+// HEAD paths in a 3-level tree of 1000 files, with the wrong case,
+// with and without the lookup cache.
+fn case_insensitive(c: &mut Criterion) {
+    let rt = current_thread();
+    let dir = std::env::temp_dir().join(format!("dav-bench-nocase-{}", std::process::id()));
+    for i in 0..UPLOADS {
+        let sub = dir.join(format!("d{}/e{}", i / 100, i / 10 % 10));
+        std::fs::create_dir_all(&sub).unwrap();
+        std::fs::write(sub.join(format!("f{}", i % 10)), b"x").unwrap();
+    }
+    // directories that changed in the last 2 seconds are not cached.
+    std::thread::sleep(Duration::from_secs(2));
+
+    let mut group = c.benchmark_group("HEAD nocase");
+    for cache_size in [0, 4096] {
+        let dav = DavHandler::builder()
+            .filesystem(
+                LocalFs::new(&dir, false, true, false).case_insensitive_cache_size(cache_size),
+            )
+            .build_handler();
+        let mut i = 0;
+        group.bench_function(BenchmarkId::from_parameter(cache_size), |b| {
+            b.to_async(&rt).iter(|| {
+                let n = i % UPLOADS;
+                i += 1;
+                let path = format!("/D{}/E{}/F{}", n / 100, n / 10 % 10, n % 10);
+                let req = Request::head(path).body(Body::empty()).unwrap();
+                request(&dav, req, StatusCode::OK)
+            })
+        });
+    }
+    group.finish();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// MemFs with a delay on every read, like a slow disk.
+#[derive(Clone)]
+struct SlowFs(Box<MemFs>);
+
+#[derive(Debug)]
+struct SlowFile(Box<dyn DavFile>);
+
+impl DavFileSystem for SlowFs {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        async move {
+            let file = self.0.open(path, options).await?;
+            Ok(Box::new(SlowFile(file)) as Box<dyn DavFile>)
+        }
+        .boxed()
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        self.0.read_dir(path, meta)
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.0.metadata(path)
+    }
+}
+
+impl DavFile for SlowFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        self.0.metadata()
+    }
+    fn write_buf(&mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<'_, ()> {
+        self.0.write_buf(buf)
+    }
+    fn write_bytes(&mut self, buf: bytes::Bytes) -> FsFuture<'_, ()> {
+        self.0.write_bytes(buf)
+    }
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, bytes::Bytes> {
+        async move {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            self.0.read_bytes(count).await
+        }
+        .boxed()
+    }
+    fn seek(&mut self, pos: std::io::SeekFrom) -> FsFuture<'_, u64> {
+        self.0.seek(pos)
+    }
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        self.0.flush()
+    }
+}
+
+// GET a 256 MB file in 1 MB chunks. Reads take 1 ms, and sending a chunk
+// takes 1 ms too. Like hyper, the body is not polled while a chunk is sent.
+async fn get_big(dav: &DavHandler, chunk_size: usize) {
+    let (mut tx, mut rx) = tokio::io::duplex(chunk_size);
+    let reader = tokio::spawn(async move {
+        let mut buf = vec![0; chunk_size];
+        let mut total = 0;
+        loop {
+            match rx.read(&mut buf).await {
+                Ok(0) | Err(_) => break total,
+                Ok(n) => total += n,
+            }
+        }
+    });
+
+    let req = Request::get("/big").body(Body::empty()).unwrap();
+    let mut body = dav.handle(req).await.into_body();
+    while let Some(chunk) = body.next().await {
+        tx.write_all(&chunk.unwrap()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    drop(tx);
+    assert_eq!(reader.await.unwrap(), 256 * chunk_size);
+}
+
+fn get_read_ahead(c: &mut Criterion) {
+    const CHUNK: usize = 1024 * 1024;
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .unwrap();
+    let fs = SlowFs(MemFs::new());
+    rt.block_on(async {
+        let dav = DavHandler::builder()
+            .filesystem(fs.0.clone())
+            .build_handler();
+        let data = vec![b'x'; 256 * CHUNK];
+        let req = Request::put("/big")
+            .body(Body::from(bytes::Bytes::from(data)))
+            .unwrap();
+        assert_eq!(dav.handle(req).await.status(), StatusCode::CREATED);
+    });
+
+    let mut group = c.benchmark_group("GET read_ahead");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(256 * CHUNK as u64));
+    for read_ahead in [0, 1, 2, 4] {
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(fs.clone()))
+            .read_buf_size(CHUNK)
+            .read_ahead(read_ahead)
+            .build_handler();
+        group.bench_function(BenchmarkId::from_parameter(read_ahead), |b| {
+            b.to_async(&rt).iter(|| get_big(&dav, CHUNK))
+        });
+    }
+    group.finish();
+}
+
+// request `i` of client `c`: GET one of 100 files, or PROPFIND the root.
+async fn client_request(dav: &DavHandler, c: usize, i: usize) {
+    let req = if i % 2 == 0 {
+        let path = format!("/file{}", (c * 50 + i) % 100);
+        Request::get(path).body(Body::empty()).unwrap()
+    } else {
+        propfind("/", "1")
+    };
+    let mut body = dav.handle(req).await.into_body();
+    while let Some(chunk) = body.next().await {
+        chunk.unwrap();
+    }
+}
+
+// 32 clients doing GET and PROPFIND on LocalFs until `stop` is set.
+fn spawn_clients(
+    dav: &Arc<DavHandler>,
+    stop: &Arc<AtomicBool>,
+) -> Vec<tokio::task::JoinHandle<()>> {
+    (0..32)
+        .map(|c| {
+            let dav = dav.clone();
+            let stop = stop.clone();
+            tokio::spawn(async move {
+                let mut i = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    client_request(&dav, c, i).await;
+                    i += 1;
+                }
+            })
+        })
+        .collect()
+}
+
+// Concurrent GET + PROPFIND on LocalFs on a current-thread and a
+// multi-threaded runtime with a small blocking pool, with and without
+// `LocalFs::blocking_threads`. Measures the time for 32 clients to do
+// 50 requests each, and how long a trivial spawn_blocking() call has to
+// wait while that load is running.
+fn concurrent_localfs(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("dav-bench-pool-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for i in 0..100 {
+        std::fs::write(dir.join(format!("file{}", i)), vec![b'x'; 65536]).unwrap();
+    }
+    let multi = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .max_blocking_threads(4)
+        .enable_all()
+        .build()
+        .unwrap();
+    let current = tokio::runtime::Builder::new_current_thread()
+        .max_blocking_threads(4)
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("GET+PROPFIND");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(32 * 50));
+    for (name, rt) in [("current", &current), ("multi", &multi)] {
+        for threads in [0, 4] {
+            let mut fs = LocalFs::new(&dir, false, false, false);
+            if threads > 0 {
+                fs = fs.blocking_threads(threads);
+            }
+            let dav = Arc::new(DavHandler::builder().filesystem(fs).build_handler());
+            let id = BenchmarkId::new(name, format!("pool {}", threads));
+            group.bench_function(id, |b| {
+                b.to_async(rt).iter(|| {
+                    let dav = dav.clone();
+                    async move {
+                        let clients = (0..32).map(|c| {
+                            let dav = dav.clone();
+                            tokio::spawn(async move {
+                                for i in 0..50 {
+                                    client_request(&dav, c, i).await;
+                                }
+                            })
+                        });
+                        for client in clients.collect::<Vec<_>>() {
+                            client.await.unwrap();
+                        }
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("spawn_blocking under GET+PROPFIND");
+    group.sample_size(10);
+    for (name, rt) in [("current", &current), ("multi", &multi)] {
+        for threads in [0, 4] {
+            let mut fs = LocalFs::new(&dir, false, false, false);
+            if threads > 0 {
+                fs = fs.blocking_threads(threads);
+            }
+            let dav = Arc::new(DavHandler::builder().filesystem(fs).build_handler());
+            let id = BenchmarkId::new(name, format!("pool {}", threads));
+            group.bench_function(id, |b| {
+                b.to_async(rt).iter_custom(|iters| {
+                    let dav = dav.clone();
+                    async move {
+                        let stop = Arc::new(AtomicBool::new(false));
+                        let clients = spawn_clients(&dav, &stop);
+                        let mut elapsed = Duration::ZERO;
+                        for _ in 0..iters {
+                            let t = Instant::now();
+                            tokio::task::spawn_blocking(|| ()).await.unwrap();
+                            elapsed += t.elapsed();
+                        }
+                        stop.store(true, Ordering::Relaxed);
+                        for client in clients {
+                            client.await.unwrap();
+                        }
+                        elapsed
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+criterion_group!(
+    benches,
+    memfs,
+    put_localfs,
+    case_insensitive,
+    get_read_ahead,
+    concurrent_localfs
+);
+criterion_main!(benches);
@@ -1,5 +1,4 @@
 use dav_server::{fakels::FakeLs, localfs::LocalFs, DavHandler};
-use std::convert::Infallible;
 
 #[tokio::main]
 async fn main() {
@@ -12,20 +11,9 @@ async fn main() {
         .locksystem(FakeLs::new())
         .build_handler();
 
-    let make_service = hyper::service::make_service_fn(move |_| {
-        let dav_server = dav_server.clone();
-        async move {
-            let func = move |req| {
-                let dav_server = dav_server.clone();
-                async move { Ok::<_, Infallible>(dav_server.handle(req).await) }
-            };
-            Ok::<_, Infallible>(hyper::service::service_fn(func))
-        }
-    });
-
     println!("hyper example: listening on {:?} serving {}", addr, dir);
     let _ = hyper::Server::bind(&addr)
-        .serve(make_service)
+        .serve(dav_server.into_make_service())
         .await
         .map_err(|e| eprintln!("server error: {}", e));
 }
@@ -51,7 +51,7 @@ impl FromRequest for DavRequest {
         for (name, value) in req.headers().iter() {
             builder = builder.header(name, value);
         }
-        let path = req.match_info().unprocessed();
+        let path = req.path();
         let tail = req.match_info().unprocessed();
         let prefix = match &path[..path.len() - tail.len()] {
             "" | "/" => None,
@@ -72,6 +72,7 @@ impl FromRequest for DavRequest {
 /// Body type for `DavRequest`.
 ///
 /// It wraps actix's `PayLoad` and implements `http_body::Body`.
+/// Data is passed on chunk by chunk as it arrives, nothing is buffered.
 #[pin_project]
 pub struct DavBody {
     #[pin]
@@ -89,11 +90,13 @@ impl http_body::Body for DavBody {
         let this = self.project();
         match this.body.poll_next(cx) {
             Poll::Ready(Some(Ok(data))) => Poll::Ready(Some(Ok(data))),
+            // Like the other body types, a broken payload is an
+            // unexpected end of the request body.
             Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(match err {
-                PayloadError::Incomplete(Some(err)) => err,
-                PayloadError::Incomplete(None) => io::ErrorKind::BrokenPipe.into(),
-                PayloadError::Io(err) => err,
-                other => io::Error::new(io::ErrorKind::Other, format!("{:?}", other)),
+                PayloadError::Incomplete(Some(err)) | PayloadError::Io(err) => {
+                    io::Error::new(io::ErrorKind::UnexpectedEof, err)
+                }
+                other => io::Error::new(io::ErrorKind::UnexpectedEof, other.to_string()),
             }))),
             Poll::Ready(None) => Poll::Ready(None),
             Poll::Pending => Poll::Pending,
@@ -144,3 +147,67 @@ impl actix_web::Responder for DavResponse {
         resp
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use futures_util::{stream, StreamExt};
+
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::testfs::{MockFs, MockOp};
+    use crate::DavHandler;
+
+    type PayloadStream = Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>>;
+
+    const CHUNK: usize = 64 * 1024;
+    const NCHUNKS: usize = 64;
+
+    #[actix_web::test]
+    async fn test_streaming_put() {
+        let fs = MockFs::builder().build();
+        let handler = DavHandler::builder().filesystem(fs.clone()).build_handler();
+
+        // Every chunk is only produced after the previous one has
+        // been written to the filesystem.
+        let w = fs.clone();
+        let body = stream::iter(0..NCHUNKS).map(move |n| {
+            assert_eq!(w.calls(MockOp::Write), n, "request body was buffered");
+            Ok::<_, PayloadError>(Bytes::from(vec![b'x'; CHUNK]))
+        });
+        let mut payload = dev::Payload::from(Box::pin(body) as PayloadStream);
+
+        let (req, _) = TestRequest::put().uri("/file.bin").to_http_parts();
+        let req = DavRequest::from_request(&req, &mut payload).await.unwrap();
+        let resp = handler.handle(req.request).await;
+        assert_eq!(resp.status(), 201);
+        assert_eq!(fs.calls(MockOp::Write), NCHUNKS);
+
+        let (req, _) = TestRequest::default().uri("/file.bin").to_http_parts();
+        let req = DavRequest::from_request(&req, &mut dev::Payload::None)
+            .await
+            .unwrap();
+        let resp = handler.handle(req.request).await;
+        assert_eq!(
+            resp.headers()["content-length"],
+            (CHUNK * NCHUNKS).to_string()
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_payload_error() {
+        let handler = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+
+        let body = stream::iter(vec![
+            Ok(Bytes::from_static(b"hello")),
+            Err(PayloadError::Incomplete(None)),
+        ]);
+        let mut payload = dev::Payload::from(Box::pin(body) as PayloadStream);
+        let (req, _) = TestRequest::put().uri("/file.txt").to_http_parts();
+        let req = DavRequest::from_request(&req, &mut payload).await.unwrap();
+        let resp = handler.handle(req.request).await;
+        assert!(resp.status().is_client_error() || resp.status().is_server_error());
+    }
+}
@@ -38,6 +38,7 @@
 //! [send]: async_stream/struct.Sender.html#method.send
 //!
 use std::cell::Cell;
+use std::collections::VecDeque;
 use std::future::Future;
 use std::marker::PhantomData;
 use std::pin::Pin;
@@ -113,6 +114,9 @@ impl<I, E> Sender<I, E> {
 pub struct AsyncStream<Item, Error> {
     item: Sender<Item, Error>,
     fut: Option<Pin<Box<dyn Future<Output = Result<(), Error>> + 'static + Send>>>,
+    // items that were produced but not yet returned.
+    ahead: VecDeque<Result<Item, Error>>,
+    read_ahead: usize,
 }
 
 impl<Item, Error: 'static + Send> AsyncStream<Item, Error> {
@@ -131,34 +135,71 @@ impl<Item, Error: 'static + Send> AsyncStream<Item, Error> {
         AsyncStream::<Item, Error> {
             item: sender.clone(),
             fut: Some(Box::pin(f(sender))),
+            ahead: VecDeque::new(),
+            read_ahead: 0,
         }
     }
+
+    // Keep running the future after an item was returned, until `count`
+    // items are waiting or the future blocks on something else, like a
+    // read. That read then runs while the consumer is busy sending the
+    // item that was returned.
+    pub(crate) fn read_ahead(mut self, count: usize) -> Self {
+        self.read_ahead = count;
+        self
+    }
 }
 
+impl<I, E> AsyncStream<I, E> {
+    // Poll the future until `count` items are waiting, the future is
+    // pending, or it is done.
+    fn fill(&mut self, cx: &mut Context<'_>, count: usize) {
+        while self.ahead.len() < count {
+            let fut = match self.fut.as_mut() {
+                Some(fut) => fut,
+                None => break,
+            };
+            match fut.as_mut().poll(cx) {
+                // If the future returned Poll::Ready, that signals the end of the stream.
+                Poll::Ready(res) => {
+                    self.fut = None;
+                    if let Err(e) = res {
+                        self.ahead.push_back(Err(e));
+                    }
+                }
+                Poll::Pending => {
+                    // Pending means that some sub-future returned pending. That sub-future
+                    // _might_ have been the SenderFuture returned by Sender.send, so
+                    // check if there is an item available in self.item.
+                    match self.item.0.replace(None) {
+                        Some(item) => self.ahead.push_back(Ok(item)),
+                        None => break,
+                    }
+                }
+            }
+        }
+    }
+}
+
+// The items are never pinned, only the future is, and that is boxed.
+impl<I, E> Unpin for AsyncStream<I, E> {}
+
 /// Stream implementation for Futures 0.3.
 impl<I, E: Unpin> Stream for AsyncStream<I, E> {
     type Item = Result<I, E>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<I, E>>> {
-        let pollres = {
-            let fut = self.fut.as_mut().unwrap();
-            fut.as_mut().poll(cx)
-        };
-        match pollres {
-            // If the future returned Poll::Ready, that signals the end of the stream.
-            Poll::Ready(Ok(_)) => Poll::Ready(None),
-            Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e))),
-            Poll::Pending => {
-                // Pending means that some sub-future returned pending. That sub-future
-                // _might_ have been the SenderFuture returned by Sender.send, so
-                // check if there is an item available in self.item.
-                let mut item = self.item.0.replace(None);
-                if item.is_none() {
-                    Poll::Pending
-                } else {
-                    Poll::Ready(Some(Ok(item.take().unwrap())))
-                }
-            }
+        let this = &mut *self;
+        this.fill(cx, 1);
+        let item = this.ahead.pop_front();
+        if item.is_some() && this.read_ahead > 0 {
+            let count = this.read_ahead;
+            this.fill(cx, count);
+        }
+        match item {
+            Some(item) => Poll::Ready(Some(item)),
+            None if this.fut.is_none() => Poll::Ready(None),
+            None => Poll::Pending,
         }
     }
 }
@@ -8,7 +8,7 @@ use std::task::{Context, Poll};
 use bytes::{Buf, Bytes};
 use futures_util::stream::Stream;
 use http::header::HeaderMap;
-use http_body::Body as HttpBody;
+use http_body::{Body as HttpBody, SizeHint};
 
 use crate::async_stream::AsyncStream;
 
@@ -65,6 +65,18 @@ impl HttpBody for Body {
     ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
         Poll::Ready(Ok(None))
     }
+
+    fn is_end_stream(&self) -> bool {
+        matches!(self.inner, BodyType::Bytes(None) | BodyType::Empty)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.inner {
+            BodyType::Bytes(Some(ref b)) => SizeHint::with_exact(b.len() as u64),
+            BodyType::Bytes(None) | BodyType::Empty => SizeHint::with_exact(0),
+            BodyType::AsyncStream(_) => SizeHint::default(),
+        }
+    }
 }
 
 impl From<String> for Body {
@@ -1,12 +1,15 @@
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use headers::HeaderMapExt;
-use http::{Method, StatusCode};
+use futures_util::StreamExt;
+use headers::{Header, HeaderMapExt};
+use http::header::{IF_MATCH, IF_NONE_MATCH};
+use http::{HeaderMap, Method, Response, StatusCode};
 
+use crate::body::Body;
 use crate::davheaders::{self, ETag};
 use crate::davpath::DavPath;
-use crate::fs::{DavFileSystem, DavMetaData};
-use crate::ls::DavLockSystem;
+use crate::fs::DavMetaData;
+use crate::PreconditionHandler;
 
 type Request = http::Request<()>;
 
@@ -20,6 +23,187 @@ fn round_time(tm: impl Into<SystemTime>) -> SystemTime {
     }
 }
 
+// The validators of a resource: its ETag and Last-Modified date.
+//
+// The GET, HEAD and PUT responses, the 304 and 412 responses, and
+// PROPFIND getetag / getlastmodified all get them from here, so that a
+// client sees exactly the same strings everywhere.
+#[derive(Debug, Clone)]
+pub(crate) struct Validators {
+    pub etag: Option<ETag>,
+    pub last_modified: Option<headers::LastModified>,
+}
+
+impl Validators {
+    // Only for DavInner::validators, which knows about collections.
+    fn from_meta(meta: &dyn DavMetaData) -> Validators {
+        Validators {
+            etag: ETag::from_meta(meta),
+            last_modified: meta.modified().ok().map(headers::LastModified::from),
+        }
+    }
+
+    // The Last-Modified date as it appears in the header.
+    pub(crate) fn last_modified_str(&self) -> Option<String> {
+        let mut values = Vec::new();
+        self.last_modified.as_ref()?.encode(&mut values);
+        values.pop()?.to_str().ok().map(|s| s.to_string())
+    }
+
+    // Set the Last-Modified and ETag headers.
+    pub(crate) fn insert(&self, headers: &mut HeaderMap) {
+        if let Some(lm) = self.last_modified {
+            headers.typed_insert(lm);
+        }
+        if let Some(ref etag) = self.etag {
+            headers.typed_insert(etag.clone());
+        }
+    }
+}
+
+// 64 bit FNV-1a. Unlike DefaultHasher its result is the same on every
+// platform and in every release, so the ETags built with it are stable.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_time(&mut self, meta: &dyn DavMetaData) {
+        match meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        {
+            Some(d) => {
+                self.write_u64(d.as_secs());
+                self.write_u64(d.subsec_nanos() as u64);
+            }
+            None => self.write_u64(u64::MAX),
+        }
+    }
+}
+
+// Builds the ETag of a collection, see DavConfig::hashed_collection_etags.
+// It is derived from the collection's own mtime and the name, size and
+// mtime of every member. The members are combined in an order-independent
+// way, since read_dir() does not return them in any particular order.
+struct CollectionHasher {
+    count: u64,
+    sum: u64,
+}
+
+impl CollectionHasher {
+    fn new() -> CollectionHasher {
+        CollectionHasher { count: 0, sum: 0 }
+    }
+
+    fn add(&mut self, name: &[u8], meta: &dyn DavMetaData) {
+        let mut h = Fnv::new();
+        h.write_u64(name.len() as u64);
+        h.write(name);
+        h.write_u64(meta.len());
+        h.write_time(meta);
+        self.count += 1;
+        self.sum = self.sum.wrapping_add(h.0);
+    }
+
+    // A weak tag: it follows the listing, not the bytes of the index
+    // that a GET returns, so it never matches If-Match or If-Range.
+    fn etag(&self, meta: &dyn DavMetaData) -> ETag {
+        let mut h = Fnv::new();
+        h.write_time(meta);
+        h.write_u64(self.count);
+        h.write_u64(self.sum);
+        ETag::new(true, format!("{:x}-{:016x}", self.count, h.0)).unwrap()
+    }
+}
+
+impl crate::DavInner {
+    // The validators of the resource at `path`. Everything that sends or
+    // compares an ETag or Last-Modified date gets them from here.
+    pub(crate) async fn validators(&self, path: &DavPath, meta: &dyn DavMetaData) -> Validators {
+        let mut validators = Validators::from_meta(meta);
+        if meta.is_dir() && self.hashed_collection_etags.unwrap_or(true) {
+            if let Some(etag) = self.collection_etag(path, meta).await {
+                validators.etag = Some(etag);
+            }
+        }
+        validators
+    }
+
+    // Read the collection at `path`, and hash the members that are listed.
+    async fn collection_etag(&self, path: &DavPath, meta: &dyn DavMetaData) -> Option<ETag> {
+        let mut path = path.clone();
+        path.add_slash();
+        let mut entries = self.fs.read_dir(&path, self.readdir_meta()).await.ok()?;
+        let mut hasher = CollectionHasher::new();
+        while let Some(dirent) = entries.next().await {
+            let name = dirent.name();
+            if let Ok(m) = dirent.metadata().await {
+                let mut npath = path.clone();
+                npath.push_segment(&name);
+                npath.add_slash_if(m.is_dir());
+                if self.is_listed(&npath, &*m) {
+                    hasher.add(&name, &*m);
+                }
+            }
+        }
+        Some(hasher.etag(meta))
+    }
+
+    // Response for a request whose preconditions evaluated to false (304 or 412).
+    // It carries the validators that a 200 response would have had, so that
+    // caches can update their stored metadata and clients can resync
+    // (RFC 7232, 4.1 and 4.2).
+    pub(crate) async fn precondition_response(
+        &self,
+        status: StatusCode,
+        path: &DavPath,
+        meta: Option<&dyn DavMetaData>,
+    ) -> Response<Body> {
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = status;
+        if let Some(meta) = meta {
+            self.validators(path, meta).await.insert(res.headers_mut());
+        }
+        if status != StatusCode::NOT_MODIFIED {
+            res.headers_mut().typed_insert(headers::ContentLength(0));
+        }
+        res
+    }
+
+    // Handle the HTTP conditional headers, with the ETag from `validators`.
+    async fn http_if_match(
+        &self,
+        req: &Request,
+        meta: Option<&dyn DavMetaData>,
+        path: &DavPath,
+    ) -> Option<StatusCode> {
+        // only compute the ETag if there is something to compare it with.
+        let h = req.headers();
+        let etag = match meta {
+            Some(meta) if h.contains_key(IF_MATCH) || h.contains_key(IF_NONE_MATCH) => {
+                self.validators(path, meta).await.etag
+            }
+            _ => None,
+        };
+        http_if_match_etag(req, meta, etag.as_ref(), self.precondition.as_deref())
+    }
+}
+
 pub(crate) fn ifrange_match(
     hdr: &davheaders::IfRange,
     tag: Option<&davheaders::ETag>,
@@ -51,16 +235,35 @@ pub(crate) fn etaglist_match(
     }
 }
 
+// If-None-Match uses the weak comparison function (RFC 7232, 3.2).
+fn etaglist_weak_match(
+    tags: &davheaders::ETagList,
+    exists: bool,
+    tag: Option<&davheaders::ETag>,
+) -> bool {
+    match *tags {
+        davheaders::ETagList::Star => exists,
+        davheaders::ETagList::Tags(ref t) => match tag {
+            Some(tag) => t.iter().any(|x| x.weak_eq(tag)),
+            None => false,
+        },
+    }
+}
+
 // Handle the if-headers: RFC 7232, HTTP/1.1 Conditional Requests.
-pub(crate) fn http_if_match(
+//
+// `etag` is the ETag of the resource, from DavInner::validators. `pre`
+// is the precondition hook from the config, which is evaluated last.
+pub(crate) fn http_if_match_etag(
     req: &Request,
-    meta: Option<&Box<dyn DavMetaData>>,
+    meta: Option<&dyn DavMetaData>,
+    etag: Option<&ETag>,
+    pre: Option<&PreconditionHandler>,
 ) -> Option<StatusCode> {
     let file_modified = meta.and_then(|m| m.modified().ok());
 
     if let Some(r) = req.headers().typed_get::<davheaders::IfMatch>() {
-        let etag = meta.and_then(ETag::from_meta);
-        if !etaglist_match(&r.0, meta.is_some(), etag.as_ref()) {
+        if !etaglist_match(&r.0, meta.is_some(), etag) {
             trace!("precondition fail: If-Match {:?}", r);
             return Some(StatusCode::PRECONDITION_FAILED);
         }
@@ -77,8 +280,7 @@ pub(crate) fn http_if_match(
     }
 
     if let Some(r) = req.headers().typed_get::<davheaders::IfNoneMatch>() {
-        let etag = meta.and_then(ETag::from_meta);
-        if etaglist_match(&r.0, meta.is_some(), etag.as_ref()) {
+        if etaglist_weak_match(&r.0, meta.is_some(), etag) {
             trace!("precondition fail: If-None-Match {:?}", r);
             if req.method() == Method::GET || req.method() == Method::HEAD {
                 return Some(StatusCode::NOT_MODIFIED);
@@ -96,141 +298,528 @@ pub(crate) fn http_if_match(
             }
         }
     }
-    None
+
+    let status = pre.and_then(|pre| pre(req, meta));
+    if let Some(status) = status {
+        trace!("precondition fail: hook returned {}", status);
+    }
+    status
 }
 
-// handle the If header: RFC4918, 10.4.  If Header
-//
-// returns true if the header was not present, or if any of the iflists
-// evaluated to true. Also returns a Vec of StateTokens that we encountered.
-//
-// caller should set the http status to 412 PreconditionFailed if
-// the return value from this function is false.
-//
-pub(crate) async fn dav_if_match<'a>(
-    req: &'a Request,
-    fs: &'a Box<dyn DavFileSystem + 'static>,
-    ls: &'a Option<Box<dyn DavLockSystem + 'static>>,
-    path: &'a DavPath,
-) -> (bool, Vec<String>) {
-    let mut tokens: Vec<String> = Vec::new();
-    let mut any_list_ok = false;
-
-    let r = match req.headers().typed_get::<davheaders::If>() {
-        Some(r) => r,
-        None => return (true, tokens),
-    };
-
-    for iflist in r.0.iter() {
-        // save and return all statetokens that we encountered.
-        let toks = iflist.conditions.iter().filter_map(|c| match c.item {
-            davheaders::IfItem::StateToken(ref t) => Some(t.to_owned()),
-            _ => None,
-        });
-        tokens.extend(toks);
-
-        // skip over if a previous list already evaluated to true.
-        if any_list_ok {
-            continue;
-        }
-
-        // find the resource that this list is about.
-        let mut pa: Option<DavPath> = None;
-        let (p, valid) = match iflist.resource_tag {
-            Some(ref url) => {
-                match DavPath::from_str_and_prefix(url.path(), path.prefix()) {
-                    Ok(p) => {
-                        // anchor davpath in pa.
-                        let p: &DavPath = pa.get_or_insert(p);
-                        (p, true)
-                    }
-                    Err(_) => (path, false),
-                }
-            }
-            None => (path, true),
+// Default maximum number of conditions in an If header.
+pub(crate) const MAX_IF_CONDITIONS: usize = 100;
+
+// Parse the If header of the request, once. Every condition in it may
+// cost a filesystem or locksystem lookup, so a header with more than
+// `max` of them is an error. An If header that does not parse is
+// ignored, as if it was not there.
+pub(crate) fn parse_if_header(req: &Request, max: usize) -> Result<Option<davheaders::If>, ()> {
+    match req.headers().get(&*davheaders::IF) {
+        Some(value) => match davheaders::If::decode_max(value, max) {
+            Ok(Some(r)) => Ok(Some(r)),
+            Ok(None) => Err(()),
+            Err(_) => Ok(None),
+        },
+        None => Ok(None),
+    }
+}
+
+// How a single condition of an If header evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CondResult {
+    // the state token is the token of a lock that covers the resource.
+    TokenOk,
+    // the state token does not belong to a lock on the resource.
+    TokenFail,
+    // DAV:no-lock never matches a lock (10.4.8).
+    NoLock,
+    // there is no lock system, so state tokens are always false.
+    NoLockSystem,
+    EtagMatch,
+    EtagMismatch,
+    // the resource does not exist or has no etag.
+    NoEtag,
+    // the tagged resource is not a valid path on this server.
+    BadResource,
+    // not evaluated, an earlier condition or list decided.
+    Skipped,
+}
+
+impl CondResult {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            CondResult::TokenOk => "token-ok",
+            CondResult::TokenFail => "token-fail",
+            CondResult::NoLock => "no-lock",
+            CondResult::NoLockSystem => "no-locksystem",
+            CondResult::EtagMatch => "etag-match",
+            CondResult::EtagMismatch => "etag-mismatch",
+            CondResult::NoEtag => "no-etag",
+            CondResult::BadResource => "bad-resource",
+            CondResult::Skipped => "skipped",
+        }
+    }
+
+    fn is_true(&self) -> bool {
+        matches!(*self, CondResult::TokenOk | CondResult::EtagMatch)
+    }
+}
+
+// The evaluation of one list of an If header.
+#[derive(Debug, Clone)]
+pub(crate) struct ListEval {
+    pub resource: String,
+    pub ok: bool,
+    pub conditions: Vec<(bool, CondResult)>,
+}
+
+// The evaluation of a complete If header.
+#[derive(Debug, Clone)]
+pub(crate) struct IfEval {
+    pub lists: Vec<ListEval>,
+    // index of the first list that evaluated to true.
+    pub matched: Option<usize>,
+    // all state tokens in the header.
+    pub tokens: Vec<String>,
+}
+
+impl IfEval {
+    // Short machine-readable summary, e.g.
+    // "matched=1; list0=fail(token-fail); list1=ok(etag-match,!token-fail)".
+    // A "!" marks a "Not" condition.
+    pub(crate) fn summary(&self) -> String {
+        let mut s = match self.matched {
+            Some(n) => format!("matched={}", n),
+            None => "matched=none".to_string(),
+        };
+        for (idx, list) in self.lists.iter().enumerate() {
+            let conds = list
+                .conditions
+                .iter()
+                .map(|(not, r)| format!("{}{}", if *not { "!" } else { "" }, r.as_str()))
+                .collect::<Vec<_>>();
+            let ok = if list.ok { "ok" } else { "fail" };
+            s.push_str(&format!("; list{}={}({})", idx, ok, conds.join(",")));
+        }
+        s
+    }
+}
+
+impl crate::DavInner {
+    // Evaluate the If header (RFC4918, 10.4), if there is one.
+    //
+    // Lists are evaluated until one is true, the conditions of a list
+    // until one is false. The rest is marked as skipped.
+    pub(crate) async fn if_eval(&self, path: &DavPath) -> Option<IfEval> {
+        let r = self.if_header.as_deref()?;
+        let mut eval = IfEval {
+            lists: Vec::new(),
+            matched: None,
+            tokens: Vec::new(),
         };
 
-        // now process the conditions. they must all be true.
-        let mut list_ok = false;
-        for cond in iflist.conditions.iter() {
-            let cond_ok = match cond.item {
-                davheaders::IfItem::StateToken(ref s) => {
-                    // tokens in DAV: namespace always evaluate to false (10.4.8)
-                    if !valid || s.starts_with("DAV:") {
-                        false
-                    } else {
-                        match *ls {
-                            Some(ref ls) => ls.check(p, None, true, false, vec![s]).is_ok(),
-                            None => false,
+        for iflist in r.0.iter() {
+            // find the resource that this list is about.
+            let mut pa: Option<DavPath> = None;
+            let (p, valid) = match iflist.resource_tag {
+                Some(ref url) => {
+                    match DavPath::from_str_and_prefix(url.path(), path.prefix()) {
+                        Ok(p) => {
+                            // anchor davpath in pa.
+                            let p: &DavPath = pa.get_or_insert(p);
+                            (p, true)
                         }
+                        Err(_) => (path, false),
                     }
                 }
-                davheaders::IfItem::ETag(ref tag) => {
-                    if !valid {
-                        // invalid location, so always false.
-                        false
-                    } else {
-                        match fs.metadata(p).await {
-                            Ok(meta) => {
-                                // exists and may have metadata ..
-                                if let Some(mtag) = ETag::from_meta(meta) {
-                                    tag == &mtag
-                                } else {
-                                    false
-                                }
+                None => (path, true),
+            };
+            let mut list = ListEval {
+                resource: match iflist.resource_tag {
+                    Some(ref url) => url.to_string(),
+                    None => p.as_url_string(),
+                },
+                ok: false,
+                conditions: Vec::new(),
+            };
+
+            // skip over if a previous list already evaluated to true.
+            if eval.matched.is_some() {
+                let skipped = iflist
+                    .conditions
+                    .iter()
+                    .map(|c| (c.not, CondResult::Skipped));
+                list.conditions.extend(skipped);
+                eval.lists.push(list);
+                continue;
+            }
+
+            // now process the conditions. they must all be true.
+            let mut failed = false;
+            for cond in iflist.conditions.iter() {
+                if failed {
+                    list.conditions.push((cond.not, CondResult::Skipped));
+                    continue;
+                }
+                let result = if !valid {
+                    // invalid location, so always false.
+                    CondResult::BadResource
+                } else {
+                    match cond.item {
+                        davheaders::IfItem::StateToken(ref s) => {
+                            // only true if the lock the token names covers the resource.
+                            match self.ls {
+                                _ if s == "DAV:no-lock" => CondResult::NoLock,
+                                Some(ref ls) if ls.covers(p, s) => CondResult::TokenOk,
+                                Some(_) => CondResult::TokenFail,
+                                None => CondResult::NoLockSystem,
                             }
-                            Err(_) => {
-                                // metadata error, fail.
-                                false
+                        }
+                        davheaders::IfItem::ETag(ref tag) => {
+                            // exists and may have metadata ..
+                            let etag = match self.fs.metadata(p).await {
+                                Ok(meta) => self.validators(p, &*meta).await.etag,
+                                Err(_) => None,
+                            };
+                            match etag {
+                                Some(ref mtag) if tag == mtag => CondResult::EtagMatch,
+                                Some(_) => CondResult::EtagMismatch,
+                                None => CondResult::NoEtag,
                             }
                         }
                     }
+                };
+                list.conditions.push((cond.not, result));
+                failed = result.is_true() == cond.not;
+            }
+            list.ok = !failed && !list.conditions.is_empty();
+            if list.ok {
+                eval.matched = Some(eval.lists.len());
+            }
+            eval.lists.push(list);
+        }
+
+        // save and return all statetokens that we encountered.
+        let conditions = r.0.iter().flat_map(|list| list.conditions.iter());
+        eval.tokens = conditions
+            .filter_map(|c| match c.item {
+                davheaders::IfItem::StateToken(ref t) => Some(t.clone()),
+                _ => None,
+            })
+            .collect();
+
+        // the first evaluation is the one of the request itself.
+        if let Some(ref slot) = self.if_debug {
+            slot.lock().unwrap().get_or_insert_with(|| eval.clone());
+        }
+        Some(eval)
+    }
+
+    // handle the If header: RFC4918, 10.4.  If Header
+    //
+    // returns true if the header was not present, or if any of the iflists
+    // evaluated to true. Also returns a Vec of StateTokens that we encountered.
+    //
+    // caller should set the http status to 412 PreconditionFailed if
+    // the return value from this function is false.
+    //
+    pub(crate) async fn dav_if_match(&self, path: &DavPath) -> (bool, Vec<String>) {
+        let eval = match self.if_eval(path).await {
+            Some(eval) => eval,
+            None => return (true, Vec::new()),
+        };
+
+        // RUST_LOG=dav_server::conditional=trace shows how it went.
+        if log_enabled!(log::Level::Trace) {
+            for (idx, list) in eval.lists.iter().enumerate() {
+                trace!("If list {}: resource {} => {}", idx, list.resource, list.ok);
+                for (not, result) in &list.conditions {
+                    let not = if *not { "Not " } else { "" };
+                    trace!("If list {}:   {}{}", idx, not, result.as_str());
                 }
-            };
-            if cond_ok == cond.not {
-                list_ok = false;
-                break;
             }
-            list_ok = true;
+            trace!("If: {}", eval.summary());
         }
-        if list_ok {
-            any_list_ok = true;
+        if eval.matched.is_none() {
+            trace!("precondition fail: If {:?}", self.if_header);
         }
+        (eval.matched.is_some(), eval.tokens)
     }
-    if !any_list_ok {
-        trace!("precondition fail: If {:?}", r.0);
+
+    // Handle both the HTTP conditional If: headers, and the webdav If: header.
+    pub(crate) async fn if_match(
+        &self,
+        req: &Request,
+        meta: Option<&dyn DavMetaData>,
+        path: &DavPath,
+    ) -> Option<StatusCode> {
+        match self.dav_if_match(path).await {
+            (true, _) => {}
+            (false, _) => return Some(StatusCode::PRECONDITION_FAILED),
+        }
+        self.http_if_match(req, meta, path).await
+    }
+
+    // Like if_match, but also returns all "associated state-tokens"
+    pub(crate) async fn if_match_get_tokens(
+        &self,
+        req: &Request,
+        meta: Option<&dyn DavMetaData>,
+        path: &DavPath,
+    ) -> Result<Vec<String>, StatusCode> {
+        if let Some(code) = self.http_if_match(req, meta, path).await {
+            return Err(code);
+        }
+        match self.dav_if_match(path).await {
+            (true, v) => Ok(v),
+            (false, _) => Err(StatusCode::PRECONDITION_FAILED),
+        }
     }
-    (any_list_ok, tokens)
 }
 
-// Handle both the HTTP conditional If: headers, and the webdav If: header.
-pub(crate) async fn if_match<'a>(
-    req: &'a Request,
-    meta: Option<&'a Box<dyn DavMetaData + 'static>>,
-    fs: &'a Box<dyn DavFileSystem + 'static>,
-    ls: &'a Option<Box<dyn DavLockSystem + 'static>>,
-    path: &'a DavPath,
-) -> Option<StatusCode> {
-    match dav_if_match(req, fs, ls, path).await {
-        (true, _) => {}
-        (false, _) => return Some(StatusCode::PRECONDITION_FAILED),
-    }
-    http_if_match(req, meta)
-}
-
-// Like if_match, but also returns all "associated state-tokens"
-pub(crate) async fn if_match_get_tokens<'a>(
-    req: &'a Request,
-    meta: Option<&'a Box<dyn DavMetaData + 'static>>,
-    fs: &'a Box<dyn DavFileSystem + 'static>,
-    ls: &'a Option<Box<dyn DavLockSystem + 'static>>,
-    path: &'a DavPath,
-) -> Result<Vec<String>, StatusCode> {
-    if let Some(code) = http_if_match(req, meta) {
-        return Err(code);
-    }
-    match dav_if_match(req, fs, ls, path).await {
-        (true, v) => Ok(v),
-        (false, _) => Err(StatusCode::PRECONDITION_FAILED),
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::fakels::FakeLs;
+    use crate::fs::{DavFileSystem, OpenOptions};
+    use crate::ls::DavLockSystem;
+    use crate::memfs::MemFs;
+    use crate::memls::MemLs;
+    use crate::{DavConfig, DavInner};
+
+    async fn setup() -> (
+        Box<dyn DavFileSystem>,
+        Option<Box<dyn DavLockSystem>>,
+        String,
+        String,
+    ) {
+        let fs: Box<dyn DavFileSystem> = MemFs::new();
+        let ls: Box<dyn DavLockSystem> = MemLs::new();
+        let path = DavPath::new("/file").unwrap();
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        fs.open(&path, oo).await.unwrap();
+        let meta = fs.metadata(&path).await.unwrap();
+        let etag = ETag::from_meta(&*meta).unwrap().as_str().to_string();
+        let lock = ls.lock(&path, None, None, None, false, false).unwrap();
+        (fs, Some(ls), etag, lock.token)
+    }
+
+    // The handler state of a request with If header `value`.
+    fn inner(
+        fs: &Box<dyn DavFileSystem>,
+        ls: &Option<Box<dyn DavLockSystem>>,
+        value: &str,
+    ) -> DavInner {
+        let mut config = DavConfig::new().filesystem(fs.clone());
+        if let Some(ls) = ls {
+            config = config.locksystem(ls.clone());
+        }
+        let req = http::Request::put("/")
+            .header("If", value)
+            .body(())
+            .unwrap();
+        let mut inner = DavInner::from(config);
+        inner.if_header = parse_if_header(&req, usize::MAX).unwrap().map(Arc::new);
+        inner
+    }
+
+    #[test]
+    fn test_fnv() {
+        // the test vectors of the FNV reference implementation.
+        for (data, hash) in [("", 0xcbf29ce484222325), ("a", 0xaf63dc4c8601ec8c)] {
+            let mut h = Fnv::new();
+            h.write(data.as_bytes());
+            assert_eq!(h.0, hash);
+        }
+    }
+
+    #[test]
+    fn test_parse_if_header() {
+        let req = |value: &str| {
+            http::Request::put("/")
+                .header("If", value)
+                .body(())
+                .unwrap()
+        };
+        let r = parse_if_header(&req("(<urn:uuid:a>) (<urn:uuid:b>)"), 2).unwrap();
+        assert_eq!(r.unwrap().0.len(), 2);
+        assert!(parse_if_header(&req("(<urn:uuid:a> <urn:uuid:b> <urn:uuid:c>)"), 2).is_err());
+        // a header that does not parse is left alone.
+        assert_eq!(parse_if_header(&req("(<urn:uuid:a>"), 2), Ok(None));
+        let none = http::Request::put("/").body(()).unwrap();
+        assert_eq!(parse_if_header(&none, 2), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_dav_if_match() {
+        let (fs, ls, etag, token) = setup().await;
+        let path = DavPath::new("/file").unwrap();
+        let check = |value: String| {
+            let inner = inner(&fs, &ls, &value);
+            let path = &path;
+            async move {
+                let (ok, _) = inner.dav_if_match(path).await;
+                let summary = inner.if_eval(path).await.unwrap().summary();
+                (ok, summary)
+            }
+        };
+
+        // no If header.
+        let mut dav = inner(&fs, &ls, "");
+        dav.if_header = None;
+        assert_eq!(dav.dav_if_match(&path).await, (true, vec![]));
+
+        let (ok, s) = check(format!("(<{}> [{}])", token, etag)).await;
+        assert!(ok);
+        assert_eq!(s, "matched=0; list0=ok(token-ok,etag-match)");
+
+        // the first condition fails, the second is not looked at.
+        let (ok, s) = check(format!("(<urn:uuid:x> [{}])", etag)).await;
+        assert!(!ok);
+        assert_eq!(s, "matched=none; list0=fail(token-fail,skipped)");
+
+        // Not.
+        let (ok, s) = check("(Not <urn:uuid:x>)".to_string()).await;
+        assert!(ok);
+        assert_eq!(s, "matched=0; list0=ok(!token-fail)");
+        let (ok, s) = check(format!("(Not [{}])", etag)).await;
+        assert!(!ok);
+        assert_eq!(s, "matched=none; list0=fail(!etag-match)");
+        let (ok, s) = check("(<DAV:no-lock>) (Not <DAV:no-lock>)".to_string()).await;
+        assert!(ok);
+        assert_eq!(s, "matched=1; list0=fail(no-lock); list1=ok(!no-lock)");
+
+        // lists after the one that matched are skipped.
+        let (ok, s) = check(format!("(<{}>) (<urn:uuid:x>)", token)).await;
+        assert!(ok);
+        assert_eq!(s, "matched=0; list0=ok(token-ok); list1=fail(skipped)");
+    }
+
+    #[tokio::test]
+    async fn test_dav_if_match_tagged() {
+        let (fs, ls, etag, token) = setup().await;
+        let path = DavPath::new("/other").unwrap();
+        let check = |value: String| {
+            let inner = inner(&fs, &ls, &value);
+            let path = &path;
+            async move {
+                let (ok, tokens) = inner.dav_if_match(path).await;
+                let eval = inner.if_eval(path).await.unwrap();
+                (ok, tokens, eval)
+            }
+        };
+
+        // the tag decides which resource the conditions apply to.
+        let (ok, tokens, eval) =
+            check(format!("<http://host/file> (<{}> [{}])", token, etag)).await;
+        assert!(ok);
+        assert_eq!(tokens, vec![token.clone()]);
+        assert_eq!(eval.lists[0].resource, "http://host/file");
+        let (ok, _, eval) = check(format!("([{}])", etag)).await;
+        assert!(!ok);
+        assert_eq!(eval.summary(), "matched=none; list0=fail(no-etag)");
+        assert_eq!(eval.lists[0].resource, "/other");
+
+        // a resource that does not exist has no etag.
+        let (ok, _, eval) = check(format!(
+            "<http://host/nothere> ([{}]) <http://host/file> ([{}])",
+            etag, etag
+        ))
+        .await;
+        assert!(ok);
+        assert_eq!(
+            eval.summary(),
+            "matched=1; list0=fail(no-etag); list1=ok(etag-match)"
+        );
+
+        // conditions on a tag outside of our prefix are false.
+        let dav = inner(
+            &fs,
+            &ls,
+            &format!(
+                "<http://host/x/f> (<{}>) <http://host/x/f> (Not <{}>)",
+                token, token
+            ),
+        );
+        let path = DavPath::from_str_and_prefix("/dav/other", "/dav").unwrap();
+        let (ok, tokens) = dav.dav_if_match(&path).await;
+        assert!(ok);
+        assert_eq!(tokens, vec![token.clone(), token.clone()]);
+        let eval = dav.if_eval(&path).await.unwrap();
+        assert_eq!(
+            eval.summary(),
+            "matched=1; list0=fail(bad-resource); list1=ok(!bad-resource)"
+        );
+
+        // without a lock system state tokens are false.
+        let dav = inner(&fs, &None, &format!("(<{}>) (Not <{}>)", token, token));
+        let path = DavPath::new("/file").unwrap();
+        let (ok, _) = dav.dav_if_match(&path).await;
+        assert!(ok);
+        let eval = dav.if_eval(&path).await.unwrap();
+        assert_eq!(
+            eval.summary(),
+            "matched=1; list0=fail(no-locksystem); list1=ok(!no-locksystem)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dav_if_match_locks() {
+        let fs: Box<dyn DavFileSystem> = MemFs::new();
+        let ls: Box<dyn DavLockSystem> = MemLs::new();
+        let lock = |path: &str, deep: bool| {
+            let path = DavPath::new(path).unwrap();
+            ls.lock(&path, None, None, None, false, deep).unwrap().token
+        };
+        // a is a depth-infinity lock, b a depth-0 lock.
+        let a = lock("/a/", true);
+        let b = lock("/b/", false);
+        let c = lock("/c", false);
+        let ls = Some(ls);
+
+        let table = [
+            ("/a/", "(<{a}>)", true),
+            ("/a/x/y", "(<{a}>)", true),
+            ("/a/x", "(Not <{a}>)", false),
+            ("/b/", "(<{b}>)", true),
+            ("/b/x", "(<{b}>)", false),
+            ("/b/x", "(Not <{b}>)", true),
+            // the token exists, but its lock is elsewhere.
+            ("/c", "(<{a}>)", false),
+            ("/c", "(Not <{a}>)", true),
+            ("/x", "(<{c}>)", false),
+            ("/c", "(<{a}>) (<{c}>)", true),
+            // tagged lists are about the tagged resource.
+            ("/c", "<http://h/a/y> (<{a}>)", true),
+            ("/c", "<http://h/b/y> (<{b}>)", false),
+            ("/a/y", "<http://h/c> (<{a}>)", false),
+            ("/a/y", "<http://h/c> (Not <{a}>)", true),
+            ("/x", "<http://h/b/> (<{b}>) <http://h/c> (<{c}>)", true),
+            ("/x", "<http://h/b/> (<{b}> <{c}>)", false),
+            // DAV:no-lock is always false, other DAV: tokens just do not match.
+            ("/c", "(<DAV:no-lock>)", false),
+            ("/c", "(Not <DAV:no-lock>)", true),
+            ("/c", "(<{c}>) (<DAV:no-lock>)", true),
+            ("/c", "(<DAV:other>)", false),
+            ("/c", "(Not <DAV:other>)", true),
+        ];
+        for (path, value, expect) in table.iter() {
+            let value = value
+                .replace("{a}", &a)
+                .replace("{b}", &b)
+                .replace("{c}", &c);
+            let dpath = DavPath::new(path).unwrap();
+            let (ok, _) = inner(&fs, &ls, &value).dav_if_match(&dpath).await;
+            assert_eq!(ok, *expect, "{} If: {}", path, value);
+        }
+
+        // FakeLs accepts any token.
+        let ls: Option<Box<dyn DavLockSystem>> = Some(FakeLs::new());
+        let path = DavPath::new("/x").unwrap();
+        let dav = inner(&fs, &ls, &format!("(<{}>)", c));
+        assert!(dav.dav_if_match(&path).await.0);
     }
 }
@@ -2,24 +2,38 @@
 // This module contains the main entry point of the library,
 // DavHandler.
 //
+use std::collections::BTreeMap;
 use std::error::Error as StdError;
 use std::io;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use bytes::{self, buf::Buf};
+use futures_util::future::BoxFuture;
 use futures_util::stream::Stream;
 use headers::HeaderMapExt;
-use http::{Request, Response, StatusCode};
+use http::{HeaderName, HeaderValue, Request, Response, StatusCode, Uri};
 use http_body::Body as HttpBody;
+use tokio::sync::Semaphore;
 
 use crate::body::{Body, StreamBody};
+use crate::conditional::{parse_if_header, IfEval, MAX_IF_CONDITIONS};
 use crate::davheaders;
 use crate::davpath::DavPath;
-use crate::util::{dav_method, DavMethod, DavMethodSet};
+use crate::negotiate::negotiate;
+use crate::util::{
+    dav_method, dav_xml_error, html_error_page, limit_error, windows_name_problem, DavMethod,
+    DavMethodSet,
+};
 
 use crate::errors::DavError;
 use crate::fs::*;
+use crate::handle_jobs::Jobs;
+use crate::limitfs::LimitFs;
 use crate::ls::*;
+use crate::reserved::{ReservedNamespace, Route};
+use crate::slashfs::SlashFs;
+use crate::timeoutfs::{timed_out, within, TimeoutFs};
 use crate::voidfs::{is_voidfs, VoidFs};
 use crate::DavResult;
 
@@ -36,8 +50,9 @@ pub struct DavHandler {
 /// Configuration of the handler.
 #[derive(Default)]
 pub struct DavConfig {
-    // Prefix to be stripped off when handling request.
-    pub(crate) prefix: Option<String>,
+    // Prefix to be stripped off when handling request. Shared, so that
+    // merging configs in `handle_with` does not copy it.
+    pub(crate) prefix: Option<Arc<str>>,
     // Filesystem backend.
     pub(crate) fs: Option<Box<dyn DavFileSystem>>,
     // Locksystem backend.
@@ -46,17 +61,379 @@ pub struct DavConfig {
     pub(crate) allow: Option<DavMethodSet>,
     // Principal is webdav speak for "user", used to give locks an owner (if a locksystem is
     // active).
-    pub(crate) principal: Option<String>,
+    pub(crate) principal: Option<Arc<str>>,
+    // Finds the principal of a request, if `principal` is not set.
+    pub(crate) principal_extractor: Option<Arc<PrincipalHandler>>,
+    // Answer requests without a principal with a 401 and this challenge.
+    pub(crate) require_principal: Option<HeaderValue>,
     // Hide symbolic links? `None` maps to `true`.
     pub(crate) hide_symlinks: Option<bool>,
+    // COPY and MOVE without an Overwrite header. `None` maps to `true`.
+    pub(crate) default_overwrite: Option<bool>,
+    // Ignore a #fragment in the Destination header. `None` maps to `true`.
+    pub(crate) strip_destination_fragment: Option<bool>,
     // Does GET on a directory return indexes.
     pub(crate) autoindex: Option<bool>,
+    // Derive the ETag of a collection from its members.
+    pub(crate) hashed_collection_etags: Option<bool>,
     // index.html
     pub(crate) indexfile: Option<String>,
+    // Archive formats a collection can be downloaded as.
+    pub(crate) archive_download: Option<ArchiveFormats>,
     // read buffer size in bytes
     pub(crate) read_buf_size: Option<usize>,
+    // number of read buffers to read ahead on a GET.
+    pub(crate) read_ahead: Option<usize>,
+    // write buffer size in bytes
+    pub(crate) write_buf_size: Option<usize>,
+    // Decompress PUT and POST bodies with a Content-Encoding.
+    pub(crate) decompress_uploads: Option<bool>,
+    // Keep a new file after a PUT with fewer bytes than Content-Length.
+    pub(crate) keep_partial_uploads: Option<bool>,
+    // Redirect GET on a collection without a trailing slash with a 308.
+    pub(crate) permanent_collection_redirect: Option<bool>,
     // Does GET on a file return 302 redirect.
     pub(crate) redirect: Option<bool>,
+    // Decides if and where GET on a file redirects.
+    pub(crate) redirect_handler: Option<Arc<RedirectHandler>>,
+    // Extra preconditions, evaluated after the If-* headers.
+    pub(crate) precondition: Option<Arc<PreconditionHandler>>,
+    // What to report as creationdate if the filesystem has no birth time.
+    pub(crate) creationdate_fallback: Option<CreationDateFallback>,
+    // charset to add to text/* files that are served.
+    pub(crate) default_charset: Option<String>,
+    // Send cache-busting headers with a 404 to Microsoft clients. `None` maps to `true`.
+    pub(crate) ms_nocache_404: Option<bool>,
+    // Adjusts the client quirks that were selected by user-agent.
+    pub(crate) quirks_handler: Option<Arc<ClientQuirksHandler>>,
+    // Send the ETag in an OC-ETag header as well after PUT.
+    pub(crate) oc_etag: Option<bool>,
+    // Only the principal that owns a lock may use its token.
+    pub(crate) strict_lock_owner: Option<bool>,
+    // Refuse GET on a file with an exclusive lock of another principal.
+    pub(crate) get_respects_locks: Option<bool>,
+    // Refuse to create names that are not valid on Windows.
+    pub(crate) windows_name_safety: Option<bool>,
+    // Support RFC 4437 redirect references.
+    pub(crate) redirectrefs: Option<bool>,
+    // Support RFC 5689 extended MKCOL.
+    pub(crate) extended_mkcol: Option<bool>,
+    // LOCK on an unmapped URL does not create an empty file (RFC 2518).
+    pub(crate) lock_null_resources: Option<bool>,
+    // Sync uploaded files to stable storage.
+    pub(crate) durability: Option<Durability>,
+    // Limits the number of concurrent filesystem calls, over all requests.
+    pub(crate) fs_concurrency_limit: Option<Arc<Semaphore>>,
+    // Time out filesystem calls, and requests.
+    pub(crate) operation_timeout: Option<Duration>,
+    // How often to remove expired locks from the locksystem.
+    pub(crate) lock_sweep_interval: Option<Duration>,
+    // URL path of the health endpoint.
+    pub(crate) health_path: Option<String>,
+    // When `build_handler` was called, for the uptime.
+    pub(crate) started: Option<Instant>,
+    // Request size limits.
+    pub(crate) limits: Option<Limits>,
+    // Report how the If header was evaluated in a response header.
+    pub(crate) debug_conditions: Option<bool>,
+    // Handlers for methods that we do not implement ourselves.
+    pub(crate) extension_methods: Option<BTreeMap<String, Arc<dyn ExtensionHandler>>>,
+    // Headers to remove from the request before handling it.
+    pub(crate) strip_request_headers: Option<Vec<HeaderName>>,
+    // Response to a GET on a collection without an index.
+    pub(crate) directory_get_mode: Option<DirectoryGetMode>,
+    // What to do with directory members that cannot be read.
+    pub(crate) unreadable_entries: Option<UnreadableEntries>,
+    // Send an HTML page with errors on GET and HEAD from browsers.
+    pub(crate) html_errors: Option<bool>,
+    // Send the messages of FsError::Other in multistatus responses.
+    pub(crate) error_details: Option<bool>,
+    // Retry-After for a 503 without one from the filesystem.
+    pub(crate) retry_after: Option<Duration>,
+    // URL path of the status resources of background COPY and MOVE jobs.
+    pub(crate) async_copymove: Option<String>,
+    // How many background jobs are kept at most.
+    pub(crate) max_jobs: Option<usize>,
+    // Paths that clients cannot list or create.
+    pub(crate) reserved_namespace: Option<ReservedNamespace>,
+    // Those, and the paths of the features, created in `build_handler`.
+    pub(crate) reserved: Option<Arc<ReservedNamespace>>,
+    // The background jobs, created in `build_handler`.
+    pub(crate) jobs: Option<Arc<Jobs>>,
+}
+
+/// Callback that decides if a `GET` or `HEAD` on a file is answered
+/// with a redirect. See [`DavConfig::redirect_handler`].
+pub type RedirectHandler =
+    dyn Fn(&DavPath, &dyn DavMetaData) -> Option<(StatusCode, Uri)> + Send + Sync;
+
+/// Callback that decides which client quirks apply to a request.
+/// See [`DavConfig::client_quirks`].
+pub type ClientQuirksHandler = dyn Fn(&Request<()>, ClientQuirks) -> ClientQuirks + Send + Sync;
+
+/// Workarounds for the bugs and peculiarities of specific clients.
+///
+/// For every request, a set of quirks is selected by the `User-Agent`
+/// header with [`ClientQuirks::from_user_agent`]. That can be changed
+/// with [`DavConfig::client_quirks`]. The default is the set for a
+/// client without a `User-Agent`.
+///
+/// More quirks may be added, so this struct can not be built field by
+/// field outside of this crate: start from `from_user_agent` or
+/// `default` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ClientQuirks {
+    /// Send `Cache-Control`, `Pragma`, `Expires` and `Vary` headers with
+    /// a `404 Not Found`. The Windows mini-redirector caches a 404 for
+    /// 30-60 seconds, case-insensitively, so after a `dir www` a
+    /// `dir WWW` fails even if `WWW` exists.
+    pub ms_404_nocache: bool,
+    /// Answer an `allprop` `PROPFIND` with the `Win32*` properties in
+    /// the `urn:schemas-microsoft-com:` namespace as well. Windows
+    /// Explorer needs them to show file attributes and times.
+    pub ms_propfind_allprop_extras: bool,
+    /// Take the length of a `PUT` body from `X-Expected-Entity-Length`
+    /// if there is no `Content-Length`. The macOS Finder sends bodies
+    /// chunked, with the length in that header (see
+    /// <https://evertpot.com/260/>).
+    pub finder_expected_length: bool,
+    /// Send `quota-used-bytes` zero-padded to 14 digits. The macOS
+    /// WebDAVFS client misreads the value otherwise, and shows a
+    /// disk usage that is off by a factor of 10 or so.
+    pub osx_dscaring: bool,
+}
+
+impl ClientQuirks {
+    /// The default rules.
+    ///
+    /// `ms_404_nocache` and `ms_propfind_allprop_extras` are on for user
+    /// agents that contain `Microsoft`, `osx_dscaring` for `WebDAVFS`.
+    /// `finder_expected_length` is on for every client, since only the
+    /// Finder sends that header.
+    pub fn from_user_agent(ua: &str) -> ClientQuirks {
+        let ms = ua.contains("Microsoft");
+        ClientQuirks {
+            ms_404_nocache: ms,
+            ms_propfind_allprop_extras: ms,
+            finder_expected_length: true,
+            osx_dscaring: ua.contains("WebDAVFS"),
+        }
+    }
+}
+
+impl Default for ClientQuirks {
+    fn default() -> ClientQuirks {
+        ClientQuirks::from_user_agent("")
+    }
+}
+
+/// Callback that finds the principal of a request.
+/// See [`DavConfig::principal_extractor`].
+pub type PrincipalHandler = dyn Fn(&Request<()>) -> Option<String> + Send + Sync;
+
+/// Ready-made callbacks for [`DavConfig::principal_extractor`].
+///
+/// These only read the request. Checking the credentials is up to a
+/// proxy or middleware in front of the handler.
+pub struct PrincipalExtractor;
+
+impl PrincipalExtractor {
+    /// The user name of HTTP Basic authentication (RFC 7617), from the
+    /// `Authorization` header. The password, which may contain colons,
+    /// is ignored.
+    pub fn basic_auth() -> impl Fn(&Request<()>) -> Option<String> + Send + Sync + 'static {
+        |req| {
+            let auth = req
+                .headers()
+                .typed_get::<headers::Authorization<headers::authorization::Basic>>()?;
+            Some(auth.username().to_string()).filter(|u| !u.is_empty())
+        }
+    }
+
+    /// The value of a request header, like `X-Remote-User` set by an
+    /// authenticating proxy. The proxy must remove the header from
+    /// the requests of clients, or they can pick their own principal.
+    ///
+    /// Panics if `name` is not a valid header name.
+    pub fn header(name: &str) -> impl Fn(&Request<()>) -> Option<String> + Send + Sync + 'static {
+        let name = HeaderName::from_bytes(name.as_bytes()).expect("invalid header name");
+        move |req| {
+            let value = req.headers().get(&name)?.to_str().ok()?.trim();
+            Some(value.to_string()).filter(|v| !v.is_empty())
+        }
+    }
+}
+
+/// The archive formats a collection can be downloaded as. See
+/// [`DavConfig::archive_download`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArchiveFormats {
+    /// An uncompressed POSIX tar file, `application/x-tar`.
+    pub tar: bool,
+    /// A gzip compressed tar file, `application/gzip`. Ignored without
+    /// the `compression` feature.
+    pub tar_gz: bool,
+    /// A zip file, `application/zip`. The files in it are stored without
+    /// compression. Zip64 is used for members and archives of 4 GiB and up.
+    pub zip: bool,
+}
+
+impl ArchiveFormats {
+    /// All formats.
+    pub fn all() -> ArchiveFormats {
+        ArchiveFormats {
+            tar: true,
+            tar_gz: true,
+            zip: true,
+        }
+    }
+}
+
+/// Limits on the size of a request, and on the work it can cause.
+/// See [`DavConfig::limits`].
+///
+/// A request that exceeds a limit fails with a `DAV:error` body that
+/// names the limit (the name of the field here).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum length of the `Destination` header of a `COPY` or `MOVE`,
+    /// in bytes (default 8192). Exceeding it is a `400 Bad Request`.
+    pub destination_len: usize,
+    /// Maximum number of conditions (state tokens and ETags) in an `If`
+    /// header, in all lists together (default 100). Exceeding it is a
+    /// `400 Bad Request`, since each condition can cost a filesystem or
+    /// locksystem lookup.
+    pub if_conditions: usize,
+    /// Maximum number of members listed in the response to a `PROPFIND`
+    /// on a collection (default 10000). The listing is cut off after that,
+    /// and ends with a `507 Insufficient Storage` response for the
+    /// collection itself (RFC 5323 5.3.1).
+    pub propfind_members: usize,
+    /// Maximum number of properties in the body of a `PROPFIND` or
+    /// `PROPPATCH` (default 10000). Exceeding it is a `400 Bad Request`.
+    pub props: usize,
+    /// Maximum size of a `PUT` or `POST` body after decompression, with
+    /// [`DavConfig::decompress_uploads`], in bytes (default 4 GiB).
+    /// Exceeding it is a `413 Payload Too Large`, and a file that was
+    /// created for the body is removed again.
+    pub decompressed_body: u64,
+    /// Maximum number of files and directories in an archive download,
+    /// see [`DavConfig::archive_download`] (default 100000).
+    pub archive_entries: usize,
+    /// Maximum depth of the directories in an archive download, below
+    /// the collection that is downloaded (default 64).
+    pub archive_depth: usize,
+    /// Maximum size of the data of the files in an archive download,
+    /// in bytes (default 64 GiB).
+    ///
+    /// The archive is sent while it is built, so when one of these limits
+    /// is reached the status has been sent already. The download is then
+    /// aborted, so that the client does not get an archive that looks
+    /// complete.
+    pub archive_size: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            destination_len: 8192,
+            if_conditions: MAX_IF_CONDITIONS,
+            propfind_members: 10000,
+            props: 10000,
+            decompressed_body: 4 << 30,
+            archive_entries: 100000,
+            archive_depth: 64,
+            archive_size: 64 << 30,
+        }
+    }
+}
+
+/// Callback that evaluates extra conditional request headers.
+/// See [`DavConfig::precondition`].
+pub type PreconditionHandler =
+    dyn Fn(&Request<()>, Option<&dyn DavMetaData>) -> Option<StatusCode> + Send + Sync;
+
+/// Handler for an HTTP method that the webdav handler does not
+/// implement itself, such as `REPORT`. See [`DavConfig::extension_method`].
+pub trait ExtensionHandler: Send + Sync {
+    /// Handle the request. `path` is the request path with the prefix
+    /// stripped off.
+    ///
+    /// The body is read from the client while the returned future runs.
+    /// When the future is done, the body ends, so read it before that.
+    fn handle(&self, path: DavPath, req: Request<Body>) -> BoxFuture<'_, Response<Body>>;
+}
+
+/// What to report as `creationdate` when the backend cannot supply a creation time.
+///
+/// Some filesystems (e.g. ext4 without `statx` support) do not record a birth time,
+/// so `DavMetaData::created()` returns an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreationDateFallback {
+    /// Leave the property out.
+    Omit,
+    /// Use the inode change time (or the modification time if that is earlier).
+    /// This is what Apache does, and the default.
+    StatusChanged,
+    /// Use the modification time.
+    Modified,
+}
+
+/// How durable an uploaded file is when `PUT` or `POST` returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// Only flush the file. The OS writes it out later. This is the default.
+    None,
+    /// Write the data to stable storage (`fdatasync`).
+    Fdatasync,
+    /// Write the data and all metadata to stable storage (`fsync`).
+    Fsync,
+}
+
+/// How to answer a `GET` on a collection when there is no index to show:
+/// `autoindex` is off and no `indexfile` is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DirectoryGetMode {
+    /// `404 Not Found`.
+    NotFound,
+    /// `403 Forbidden`, like Apache without `Options Indexes`. The default.
+    Forbidden,
+    /// `405 Method Not Allowed`.
+    MethodNotAllowed,
+    /// `302 Found` with this `Location`. A relative reference, like
+    /// `index.html` or `../`, is resolved by the client against the URL
+    /// of the collection.
+    Redirect(String),
+    /// `200 OK` with the `multistatus` body that a `PROPFIND` with
+    /// `Depth: 1` and no body would get: the properties of the collection
+    /// and its members, as `application/xml`.
+    Propfind,
+}
+
+/// What a `PROPFIND` listing or an autoindex does with a member of a
+/// collection whose metadata cannot be read, e.g. because of its permissions.
+///
+/// A member that disappeared while the collection was being read, or a
+/// dangling symbolic link, is always left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnreadableEntries {
+    /// Leave the member out. The default.
+    Skip,
+    /// List the member anyway: with the error status (`403 Forbidden`,
+    /// `500 Internal Server Error`, ...) as its response in a multistatus,
+    /// and without a size and date in an autoindex.
+    Report,
+}
+
+impl From<Durability> for SyncLevel {
+    fn from(d: Durability) -> SyncLevel {
+        match d {
+            Durability::None => SyncLevel::None,
+            Durability::Fdatasync => SyncLevel::Data,
+            Durability::Fsync => SyncLevel::All,
+        }
+    }
 }
 
 impl DavConfig {
@@ -67,16 +444,24 @@ impl DavConfig {
 
     /// Use the configuration that was built to generate a DavConfig.
     pub fn build_handler(self) -> DavHandler {
-        DavHandler {
-            config: Arc::new(self),
+        let mut this = self;
+        this.started = Some(Instant::now());
+        if this.async_copymove.is_some() {
+            this.jobs = Some(Arc::new(Jobs::default()));
+        }
+        this.reserved = Some(Arc::new(this.build_reserved()));
+        let config = Arc::new(this);
+        if let Some(interval) = config.lock_sweep_interval {
+            start_lock_sweeper(&config, interval);
         }
+        DavHandler { config }
     }
 
     /// Prefix to be stripped off before translating the rest of
     /// the request path to a filesystem path.
     pub fn strip_prefix(self, prefix: impl Into<String>) -> Self {
         let mut this = self;
-        this.prefix = Some(prefix.into());
+        this.prefix = Some(Arc::from(prefix.into()));
         this
     }
 
@@ -104,24 +489,116 @@ impl DavConfig {
     /// Set the name of the "webdav principal". This will be the owner of any created locks.
     pub fn principal(self, principal: impl Into<String>) -> Self {
         let mut this = self;
-        this.principal = Some(principal.into());
+        this.principal = Some(Arc::from(principal.into()));
         this
     }
 
-    /// Hide symbolic links (default is true)
+    /// Find the principal of a request with this callback (default: none).
+    ///
+    /// It is called at the start of every request that does not have a
+    /// principal set through [`principal`](Self::principal), for example
+    /// with `handle_with`. [`PrincipalExtractor`] has callbacks for the
+    /// common cases:
+    ///
+    /// ```
+    /// use dav_server::{DavHandler, PrincipalExtractor};
+    ///
+    /// let dav = DavHandler::builder()
+    ///     .principal_extractor(PrincipalExtractor::header("X-Remote-User"))
+    ///     .build_handler();
+    /// ```
+    pub fn principal_extractor<F>(self, handler: F) -> Self
+    where
+        F: Fn(&Request<()>) -> Option<String> + Send + Sync + 'static,
+    {
+        let mut this = self;
+        this.principal_extractor = Some(Arc::new(handler));
+        this
+    }
+
+    /// Answer requests without a principal with `401 Unauthorized`,
+    /// with `challenge` as the `WWW-Authenticate` header, like
+    /// `Basic realm="dav"` (default: anonymous requests are served).
+    ///
+    /// The [health path](Self::health_path) is served anyway.
+    ///
+    /// Panics if `challenge` is not a valid header value.
+    pub fn require_principal(self, challenge: impl Into<String>) -> Self {
+        let challenge = HeaderValue::from_str(&challenge.into()).expect("invalid challenge");
+        let mut this = self;
+        this.require_principal = Some(challenge);
+        this
+    }
+
+    /// Hide symbolic links (default is true).
+    ///
+    /// Hidden symlinks are left out of `PROPFIND` listings, directory
+    /// indexes and [hashed collection ETags](Self::hashed_collection_etags),
+    /// and a `MOVE` of one returns 404.
+    ///
+    /// Visible symlinks are followed: listings show the size and type of
+    /// the target, the same as a `GET` or `PROPFIND` on the link itself.
+    /// A `MOVE` of one moves the link, not what it points to.
     pub fn hide_symlinks(self, hide: bool) -> Self {
         let mut this = self;
         this.hide_symlinks = Some(hide);
         this
     }
 
+    /// What a `COPY` or `MOVE` without an `Overwrite` header does when the
+    /// destination exists (default `true`).
+    ///
+    /// RFC 4918 says a missing header means `Overwrite: T`, so by default
+    /// the destination is replaced. With `false` it is treated as
+    /// `Overwrite: F`, and the request fails with `412 Precondition Failed`.
+    pub fn default_overwrite(self, overwrite: bool) -> Self {
+        let mut this = self;
+        this.default_overwrite = Some(overwrite);
+        this
+    }
+
+    /// Ignore a fragment in the `Destination` header of `COPY` and `MOVE`
+    /// (default `true`).
+    ///
+    /// A query in the `Destination` is always ignored, like it is in the
+    /// request URI. Some buggy clients send a fragment as well, and it
+    /// is dropped too: it does not identify a different resource. With
+    /// this disabled, a request with a fragment fails with
+    /// `400 Bad Request`.
+    pub fn strip_destination_fragment(self, strip: bool) -> Self {
+        let mut this = self;
+        this.strip_destination_fragment = Some(strip);
+        this
+    }
+
     /// Does a GET on a directory produce a directory index.
+    ///
+    /// The index can be sorted by clicking on the column headers, or with
+    /// a query like `?sort=size&order=desc` (`sort` is `name`, `size` or
+    /// `date`, `order` is `asc` or `desc`).
     pub fn autoindex(self, autoindex: bool) -> Self {
         let mut this = self;
         this.autoindex = Some(autoindex);
         this
     }
 
+    /// Derive the ETag of a collection from its members (default true).
+    ///
+    /// Not every backend updates the modification time of a directory
+    /// when a member is added, removed or renamed, so the ETag of a
+    /// collection is a hash over the name, size and modification time of
+    /// the members that `PROPFIND` and the directory index list. It is a
+    /// weak tag (`W/"..."`), the same in `GET`, `PROPFIND` and the
+    /// conditional headers. Every ETag of a collection costs a read of
+    /// the directory, also for each collection in a `Depth: 1`
+    /// `PROPFIND` that asks for `getetag`. With `false`, a collection
+    /// has the ETag of its metadata, like a file.
+    pub fn hashed_collection_etags(self, enable: bool) -> Self {
+        let mut this = self;
+        this.hashed_collection_etags = Some(enable);
+        this
+    }
+
     /// Indexfile to show (index.html, usually).
     pub fn indexfile(self, indexfile: impl Into<String>) -> Self {
         let mut this = self;
@@ -129,6 +606,88 @@ impl DavConfig {
         this
     }
 
+    /// Allow a `GET` on a collection to download the whole subtree as
+    /// one archive file (default: none).
+    ///
+    /// A client asks for an archive with a `download=zip`, `download=tar`
+    /// or `download=tar.gz` query, or with an `Accept` header that prefers
+    /// `application/zip`, `application/x-tar` or `application/gzip` over
+    /// `text/html`, the directory index. A wildcard like `*/*` gets the
+    /// index. The response is an attachment named after the collection,
+    /// and it takes precedence over the `indexfile` and the `autoindex`.
+    ///
+    /// The archive is built while it is sent, so there is no
+    /// `Content-Length`. Symbolic links, and files that cannot be opened,
+    /// are left out, and with [`get_respects_locks`](Self::get_respects_locks)
+    /// so are files locked by someone else. The size of the archive is
+    /// limited by the `archive_*` fields of [`Limits`].
+    pub fn archive_download(self, formats: ArchiveFormats) -> Self {
+        let mut this = self;
+        this.archive_download = Some(formats);
+        this
+    }
+
+    /// How to answer a `GET` on a collection if `autoindex` is off and
+    /// there is no `indexfile` (default `DirectoryGetMode::Forbidden`).
+    ///
+    /// Panics if the location of a `DirectoryGetMode::Redirect` is not a
+    /// valid header value.
+    pub fn directory_get_mode(self, mode: DirectoryGetMode) -> Self {
+        if let DirectoryGetMode::Redirect(ref location) = mode {
+            HeaderValue::from_str(location).expect("invalid redirect location");
+        }
+        let mut this = self;
+        this.directory_get_mode = Some(mode);
+        this
+    }
+
+    /// What to do with a member of a collection that cannot be read when
+    /// listing the collection (default `UnreadableEntries::Skip`).
+    pub fn unreadable_entries(self, policy: UnreadableEntries) -> Self {
+        let mut this = self;
+        this.unreadable_entries = Some(policy);
+        this
+    }
+
+    /// Send a small HTML page with an error status (default false).
+    ///
+    /// Only on a `GET` or `HEAD` whose `Accept` header prefers `text/html`
+    /// over XML, like a browser's, and only if the response would
+    /// otherwise have no body. The page shows the status, the requested
+    /// path and a link to the parent collection. WebDAV clients and other
+    /// methods get the same responses as before.
+    pub fn html_errors(self, enable: bool) -> Self {
+        let mut this = self;
+        this.html_errors = Some(enable);
+        this
+    }
+
+    /// Include the message of an [`FsError::Other`] in the
+    /// `responsedescription` of the resource it is about, in the
+    /// multistatus response of a `DELETE`, `COPY` or `MOVE` (default false).
+    ///
+    /// These messages come from the backend and can reveal internal
+    /// details, so only enable this if the clients may see them. They
+    /// are always logged at debug level.
+    pub fn error_details(self, enable: bool) -> Self {
+        let mut this = self;
+        this.error_details = Some(enable);
+        this
+    }
+
+    /// `Retry-After` for a `503 Service Unavailable` response, in whole
+    /// seconds (default none).
+    ///
+    /// A filesystem that is temporarily unavailable can fail with an
+    /// [`FsError::Other`] with a `503` status hint. If it does not say
+    /// when to retry with [`FsError::with_retry_after`], this is sent,
+    /// so that clients back off instead of retrying right away.
+    pub fn retry_after(self, after: Duration) -> Self {
+        let mut this = self;
+        this.retry_after = Some(after);
+        this
+    }
+
     /// Read buffer size in bytes
     pub fn read_buf_size(self, size: usize) -> Self {
         let mut this = self;
@@ -136,100 +695,817 @@ impl DavConfig {
         this
     }
 
+    /// Number of chunks of `read_buf_size` bytes that a `GET` reads
+    /// ahead while the previous chunk is being sent (default 2).
+    ///
+    /// Without read-ahead, the next read from the filesystem only starts
+    /// when the previous chunk has been sent, so on a slow disk and a
+    /// high-latency link the two delays add up. This costs up to
+    /// `count * read_buf_size` bytes of memory per request. 0 disables it.
+    pub fn read_ahead(self, count: usize) -> Self {
+        let mut this = self;
+        this.read_ahead = Some(count);
+        this
+    }
+
+    /// Write buffer size in bytes (default 16 KiB). Smaller chunks of a
+    /// `PUT` or `POST` body are gathered up to this size before they are
+    /// written to the file. Larger chunks are written directly. 0 disables
+    /// the buffering.
+    pub fn write_buf_size(self, size: usize) -> Self {
+        let mut this = self;
+        this.write_buf_size = Some(size);
+        this
+    }
+
+    /// Decompress `PUT` and `POST` bodies sent with `Content-Encoding: gzip`
+    /// or `deflate`, and store the decompressed data (default `false`).
+    ///
+    /// Partial updates (`Content-Range`, SabreDAV `PATCH`) are stored as
+    /// sent. A body that fails to decompress is answered with `400 Bad
+    /// Request`, and a file that was created for it is removed again.
+    /// The decompressed size is limited by [`Limits::decompressed_body`].
+    #[cfg(any(docsrs, feature = "compression"))]
+    #[cfg_attr(docsrs, doc(cfg(feature = "compression")))]
+    pub fn decompress_uploads(self, decompress: bool) -> Self {
+        let mut this = self;
+        this.decompress_uploads = Some(decompress);
+        this
+    }
+
+    /// Keep what was received of a `PUT` that ends before its
+    /// `Content-Length`, or fails halfway (default `false`).
+    ///
+    /// A body that is shorter or longer than its `Content-Length` is
+    /// always answered with `400 Bad Request`. By default, a file that
+    /// was created by the request is removed again, so a broken upload
+    /// does not leave a truncated file behind. With this set, it is kept,
+    /// for clients that resume an upload with `Content-Range`. The member
+    /// that a broken `POST` created is always removed, since the client
+    /// never learned its name.
+    ///
+    /// An existing file that is replaced as a whole has been truncated
+    /// already, unless the filesystem stages writes, like
+    /// [`LocalFs::atomic_writes`](crate::localfs::LocalFs::atomic_writes).
+    pub fn keep_partial_uploads(self, keep: bool) -> Self {
+        let mut this = self;
+        this.keep_partial_uploads = Some(keep);
+        this
+    }
+
+    /// Make the redirect of a collection URL without a trailing slash
+    /// permanent (default `false`).
+    ///
+    /// `GET` and `HEAD` on a collection without a trailing slash are
+    /// always redirected to the URL with the slash. By default that is a
+    /// `302 Found`, with this enabled it is a `308 Permanent Redirect`,
+    /// which browsers may cache. Other methods are not redirected.
+    pub fn permanent_collection_redirect(self, permanent: bool) -> Self {
+        let mut this = self;
+        this.permanent_collection_redirect = Some(permanent);
+        this
+    }
+
+    /// Answer `GET` and `HEAD` on a file with a `302` redirect to
+    /// `DavFile::redirect_url`, if the file has one.
+    ///
+    /// Shorthand for a `redirect_handler` that does just that. If a
+    /// `redirect_handler` is set, this setting is ignored.
     pub fn redirect(self, redirect: bool) -> Self {
         let mut this = self;
         this.redirect = Some(redirect);
         this
     }
 
+    /// Decide per file if `GET` and `HEAD` are answered with a redirect.
+    ///
+    /// The handler is called with the path and metadata of the file,
+    /// before it is opened. If it returns a status and location, that
+    /// redirect is sent instead of the content. Range and conditional
+    /// headers are not looked at. The status must be a `3xx`, anything
+    /// else is answered with `500 Internal Server Error`. If it returns
+    /// `None` the file is served as usual. `PROPFIND` and other methods
+    /// are not affected.
+    ///
+    /// For example, to send clients to a CDN with a `307`, so that the
+    /// method is preserved:
+    ///
+    /// ```
+    /// use dav_server::{fakels::FakeLs, memfs::MemFs, DavHandler};
+    /// use http::StatusCode;
+    ///
+    /// let dav = DavHandler::builder()
+    ///     .filesystem(MemFs::new())
+    ///     .locksystem(FakeLs::new())
+    ///     .redirect_handler(|path, _meta| {
+    ///         let url = format!("https://cdn.example.com{}", path.as_url_string());
+    ///         Some((StatusCode::TEMPORARY_REDIRECT, url.parse().ok()?))
+    ///     })
+    ///     .build_handler();
+    /// ```
+    pub fn redirect_handler<F>(self, handler: F) -> Self
+    where
+        F: Fn(&DavPath, &dyn DavMetaData) -> Option<(StatusCode, Uri)> + Send + Sync + 'static,
+    {
+        let mut this = self;
+        this.redirect_handler = Some(Arc::new(handler));
+        this
+    }
+
+    /// Evaluate extra preconditions, for example `If-Schedule-Tag-Match`
+    /// in a CalDAV server.
+    ///
+    /// The handler is called with the request and the metadata of the
+    /// resource, or `None` if it does not exist. If it returns a status,
+    /// typically `412 Precondition Failed`, the request fails with that
+    /// status, without changing anything.
+    ///
+    /// It is called wherever the `If-Match`, `If-None-Match`,
+    /// `If-Modified-Since` and `If-Unmodified-Since` headers are, right
+    /// after them, and only if they did not already decide the response.
+    /// That is `GET`, `HEAD`, `PUT`, `POST`, `DELETE`, `COPY`, `MOVE`,
+    /// `PROPPATCH`, `MKCOL`, `LOCK` and `MKREDIRECTREF`, for the request
+    /// URL. A failing webdav `If` header may end the request before.
+    ///
+    /// ```
+    /// use dav_server::{memfs::MemFs, DavHandler};
+    /// use http::StatusCode;
+    ///
+    /// let dav = DavHandler::builder()
+    ///     .filesystem(MemFs::new())
+    ///     .precondition(|req, meta| {
+    ///         let tag = req.headers().get("if-schedule-tag-match")?;
+    ///         match meta {
+    ///             Some(_) if tag == "\"1\"" => None,
+    ///             _ => Some(StatusCode::PRECONDITION_FAILED),
+    ///         }
+    ///     })
+    ///     .build_handler();
+    /// ```
+    pub fn precondition<F>(self, handler: F) -> Self
+    where
+        F: Fn(&Request<()>, Option<&dyn DavMetaData>) -> Option<StatusCode> + Send + Sync + 'static,
+    {
+        let mut this = self;
+        this.precondition = Some(Arc::new(handler));
+        this
+    }
+
+    /// What to report as `creationdate` if the filesystem has no
+    /// creation time (default is `CreationDateFallback::StatusChanged`).
+    /// That default keeps the old behavior: the handler has always used
+    /// the inode change time in that case, like Apache does.
+    pub fn creationdate_fallback(self, fallback: CreationDateFallback) -> Self {
+        let mut this = self;
+        this.creationdate_fallback = Some(fallback);
+        this
+    }
+
+    /// Charset to add to the `Content-Type` of `text/*` files that are
+    /// served by GET, for example `"utf-8"`. Default is to not add a charset.
+    ///
+    /// Generated content like the autoindex page and XML responses is
+    /// always UTF-8 and is always sent with `charset=utf-8`.
+    pub fn default_charset(self, charset: impl Into<String>) -> Self {
+        let mut this = self;
+        this.default_charset = Some(charset.into());
+        this
+    }
+
+    /// Send `Cache-Control`, `Pragma`, `Expires` and `Vary` headers with a
+    /// `404 Not Found` response to Microsoft WebDAV clients, so that Windows
+    /// does not cache the 404 (default `true`).
+    ///
+    /// With `false`, the `ms_404_nocache` quirk is never selected by the
+    /// default rules. See [`ClientQuirks`].
+    pub fn ms_nocache_404(self, enable: bool) -> Self {
+        let mut this = self;
+        this.ms_nocache_404 = Some(enable);
+        this
+    }
+
+    /// Decide which client quirks apply to a request.
+    ///
+    /// The handler is called once at the start of every request, with
+    /// the quirks that the default rules selected, see
+    /// [`ClientQuirks::from_user_agent`]. It returns the quirks to use.
+    /// For example, to never send the Microsoft 404 headers, and to
+    /// treat another client like Windows Explorer:
+    ///
+    /// ```
+    /// use dav_server::{memfs::MemFs, DavHandler};
+    ///
+    /// let dav = DavHandler::builder()
+    ///     .filesystem(MemFs::new())
+    ///     .client_quirks(|req, mut quirks| {
+    ///         let ua = req.headers().get("user-agent");
+    ///         if ua.is_some_and(|ua| ua.as_bytes().starts_with(b"MyExplorer/")) {
+    ///             quirks.ms_propfind_allprop_extras = true;
+    ///         }
+    ///         quirks.ms_404_nocache = false;
+    ///         quirks
+    ///     })
+    ///     .build_handler();
+    /// ```
+    pub fn client_quirks<F>(self, handler: F) -> Self
+    where
+        F: Fn(&Request<()>, ClientQuirks) -> ClientQuirks + Send + Sync + 'static,
+    {
+        let mut this = self;
+        this.quirks_handler = Some(Arc::new(handler));
+        this
+    }
+
+    /// After a successful `PUT` or `POST`, send the new ETag in an
+    /// `OC-ETag` header as well as in `ETag` (default `false`).
+    ///
+    /// The ownCloud and Nextcloud sync clients look for this header.
+    pub fn oc_etag(self, enable: bool) -> Self {
+        let mut this = self;
+        this.oc_etag = Some(enable);
+        this
+    }
+
+    /// Only let the principal that created a lock use its lock token
+    /// (default `false`).
+    ///
+    /// Writes to a locked resource already need the lock token and a
+    /// matching principal, and fail with `423 Locked` otherwise. With
+    /// this option, submitting the token of a lock that belongs to
+    /// another principal fails with `403 Forbidden` instead, and so does
+    /// an `UNLOCK` of such a lock.
+    pub fn strict_lock_owner(self, strict: bool) -> Self {
+        let mut this = self;
+        this.strict_lock_owner = Some(strict);
+        this
+    }
+
+    /// Refuse `GET` and `HEAD` on a file that another principal holds
+    /// an exclusive lock on, with `423 Locked` (default `false`).
+    ///
+    /// A write lock does not keep others from reading, so by default
+    /// a locked file can be read by anyone. With this option, only the
+    /// principal that owns the lock can, as if the lock were a read lock
+    /// too. Shared locks and locks on collections do not count, except
+    /// for a depth-infinity lock on a parent collection. If the lock or
+    /// the request has no principal, the client must submit the lock
+    /// token in an `If` header to read the file.
+    pub fn get_respects_locks(self, enable: bool) -> Self {
+        let mut this = self;
+        this.get_respects_locks = Some(enable);
+        this
+    }
+
+    /// Refuse to create files and collections with a name that a
+    /// Windows client cannot access (default `false`).
+    ///
+    /// That is a reserved device name like `CON`, `NUL` or `COM1`
+    /// (also with an extension, like `nul.txt`), a name that ends with
+    /// a dot or a space, or a name with a character like `:` or `?`.
+    /// `PUT`, `MKCOL`, `MKREDIRECTREF` and the destination of `COPY` and
+    /// `MOVE` fail with `403 Forbidden` for such a name, with the reason
+    /// in the response body. Existing resources are not affected.
+    pub fn windows_name_safety(self, enable: bool) -> Self {
+        let mut this = self;
+        this.windows_name_safety = Some(enable);
+        this
+    }
+
+    /// Support redirect references (RFC 4437), if the filesystem
+    /// implements them (default `false`).
+    ///
+    /// This enables `MKREDIRECTREF`, and makes `GET`, `HEAD` and
+    /// `PROPFIND` on a reference return a redirect to its target.
+    /// A `PROPFIND` with `Apply-To-Redirect-Ref: T` reports on the
+    /// reference itself. Other methods always apply to the reference.
+    pub fn redirectrefs(self, enable: bool) -> Self {
+        let mut this = self;
+        this.redirectrefs = Some(enable);
+        this
+    }
+
+    /// Support extended MKCOL (RFC 5689) (default `false`).
+    ///
+    /// A `MKCOL` can then have a `DAV:mkcol` body that sets the
+    /// `resourcetype` of the new collection, like `C:calendar`, and
+    /// dead properties. The extra types are passed to
+    /// `DavFileSystem::create_collection`. A body of another
+    /// `Content-Type` than `application/xml` or `text/xml`, or any body
+    /// without this option, is refused with `415 Unsupported Media Type`.
+    pub fn extended_mkcol(self, enable: bool) -> Self {
+        let mut this = self;
+        this.extended_mkcol = Some(enable);
+        this
+    }
+
+    /// Lock an unmapped URL without creating it (default `false`).
+    ///
+    /// By default a `LOCK` on a URL that does not exist creates an empty
+    /// file there, as RFC 4918 requires. With this enabled, it only locks
+    /// the name: the resource does not exist until a `PUT` or `MKCOL`
+    /// with the lock token creates it, and if that never happens the name
+    /// is free again after `UNLOCK` or when the lock expires. Either way,
+    /// clients like Windows Explorer that create a file with `LOCK` and
+    /// then `PUT` work.
+    ///
+    /// This is only a subset of the lock-null resources of RFC 2518. The
+    /// locked name is not listed in a `PROPFIND` of its parent, and a
+    /// `PROPFIND`, `GET` or `DELETE` of the name itself gets
+    /// `404 Not Found`.
+    pub fn lock_null_resources(self, enable: bool) -> Self {
+        let mut this = self;
+        this.lock_null_resources = Some(enable);
+        this
+    }
+
+    /// Sync files that are uploaded with `PUT` or `POST` to stable storage
+    /// before the response is sent (default `Durability::None`).
+    ///
+    /// Syncing makes small uploads much slower, especially on spinning
+    /// disks and network filesystems.
+    pub fn durability(self, durability: Durability) -> Self {
+        let mut this = self;
+        this.durability = Some(durability);
+        this
+    }
+
+    /// Allow at most `limit` filesystem calls (`metadata`, `read_dir`,
+    /// `open`, ...) to run at the same time, over all requests that this
+    /// handler serves. Default: unlimited.
+    ///
+    /// Reading or writing an open file does not count, so a long
+    /// transfer does not take up a slot.
+    pub fn fs_concurrency_limit(self, limit: usize) -> Self {
+        let mut this = self;
+        this.fs_concurrency_limit = Some(Arc::new(Semaphore::new(limit.max(1))));
+        this
+    }
+
+    /// Fail with `504 Gateway Timeout` when a filesystem call takes
+    /// longer than `timeout` (default: no timeout).
+    ///
+    /// This keeps a hung backend from hanging the request. Each read or
+    /// write of an open file has the same timeout, so a long `GET` or
+    /// `PUT` is only cut off if it stalls. The handling of a request as
+    /// a whole, up to the response headers, is limited to `timeout` as
+    /// well, except for methods that upload data or that change the
+    /// filesystem: `PUT`, `PATCH`, `POST`, `COPY`, `MOVE`, `DELETE`,
+    /// `MKCOL`, `MKREDIRECTREF`, `PROPPATCH` and `LOCK`.
+    ///
+    /// A call that timed out can not always be stopped, and might still
+    /// complete after the `504` was sent. So calls that change the
+    /// filesystem, like creating a collection, renaming or changing
+    /// properties, have no timeout. Without a tokio runtime there is
+    /// no timer, and this option does nothing.
+    pub fn operation_timeout(self, timeout: Duration) -> Self {
+        let mut this = self;
+        this.operation_timeout = Some(timeout);
+        this
+    }
+
+    /// Remove locks that have timed out from the locksystem every
+    /// `interval` (default: never).
+    ///
+    /// `MemLs` ignores a lock as soon as it has timed out, but only frees
+    /// it when it is swept, so without this expired locks pile up in the
+    /// memory of a long-running server. The sweeper is a task that is
+    /// spawned by `build_handler`, which must be called from within a
+    /// tokio runtime. It stops by itself within `interval` after the
+    /// last clone of the handler is dropped. See `DavLockSystem::sweep`.
+    pub fn lock_sweep_interval(self, interval: Duration) -> Self {
+        let mut this = self;
+        this.lock_sweep_interval = Some(interval);
+        this
+    }
+
+    /// Answer requests for this URL path with a health report, for
+    /// liveness and readiness probes (default: none).
+    ///
+    /// The path is matched against the request path as it is, before
+    /// the prefix is stripped. It is not part of the WebDAV namespace:
+    /// a `GET` or `HEAD` returns a small JSON object with the version,
+    /// the uptime in seconds and the result of `DavFileSystem::healthcheck`,
+    /// other methods get a `405`, and anything below the path is `404`.
+    /// A file or collection in the filesystem with the same URL is left
+    /// out of listings, and cannot be copied or moved to.
+    ///
+    /// ```text
+    /// {"status":"ok","version":"0.5.7","uptime":3600,"backend":"ok"}
+    /// ```
+    ///
+    /// If the backend check fails or does not finish within 2 seconds,
+    /// `status` is `degraded`, `backend` is `failed` or `timeout`, and
+    /// the response is a `503 Service Unavailable`.
+    pub fn health_path(self, path: impl Into<String>) -> Self {
+        let mut this = self;
+        this.health_path = Some(path.into());
+        this
+    }
+
+    /// Run a `COPY` or `MOVE` of a collection in the background, with a
+    /// status resource below this URL path (default: off).
+    ///
+    /// Copying or moving a large collection can take longer than a client
+    /// or a proxy is willing to wait. With this set, a `COPY` or `MOVE`
+    /// of a collection with `Depth: infinity` is answered with
+    /// `202 Accepted` as soon as all checks are done. The `Location`
+    /// header points to a status resource for the job, which a `GET`
+    /// answers with its progress:
+    ///
+    /// ```text
+    /// {"state":"running","method":"COPY","href":"/src/","done":1200,"total":50000,"current":"/src/x/"}
+    /// ```
+    ///
+    /// `total` is the number of resources found so far, it grows while
+    /// the job works its way through the tree. A filesystem that copies
+    /// the tree in one go, with
+    /// [`copy_recursive`](crate::fs::DavFileSystem::copy_recursive),
+    /// reports it as one resource when it is done. When the job is done,
+    /// a `GET` returns the status and body that the request would
+    /// have had (`201`, `204` or `207`), for 10 minutes. Only the principal
+    /// that started a job can see its status.
+    ///
+    /// Like the [health path](Self::health_path), the path is matched
+    /// against the request path before the prefix is stripped and is not
+    /// part of the WebDAV namespace. Jobs are kept in memory, and need a
+    /// tokio runtime to run on; without one, the request is handled
+    /// the normal way. See [`max_jobs`](Self::max_jobs) for how many.
+    pub fn async_copymove(self, path: impl Into<String>) -> Self {
+        let mut this = self;
+        this.async_copymove = Some(path.into());
+        this
+    }
+
+    /// The maximum number of background jobs that are kept, running
+    /// or finished (default `64`).
+    ///
+    /// To make room for a new job, the status of the job that finished
+    /// first is forgotten before its 10 minutes are up. If all jobs are
+    /// still running, a `COPY` or `MOVE` that would start another one
+    /// fails with `503 Service Unavailable`. See
+    /// [`async_copymove`](Self::async_copymove).
+    pub fn max_jobs(self, max: usize) -> Self {
+        let mut this = self;
+        this.max_jobs = Some(max);
+        this
+    }
+
+    /// Reserve the URL path `prefix`, and everything below it, for use
+    /// by the application (default: none). Can be called more than once.
+    ///
+    /// A reserved resource is left out of `PROPFIND` listings, the
+    /// autoindex and archives, and clients cannot create it with `PUT`,
+    /// `PATCH`, `MKCOL`, `MKREDIRECTREF`, `COPY` or `MOVE` (`403 Forbidden`).
+    /// Other requests for it are handled as usual. Like the
+    /// [health path](Self::health_path), `prefix` is matched against
+    /// the request path before the prefix is stripped.
+    ///
+    /// A `COPY` or `MOVE` that would replace a collection with a reserved
+    /// prefix below it, or a `MOVE` that would move one, is refused too.
+    /// A `COPY` of a collection leaves reserved members out.
+    pub fn reserved_prefix(self, prefix: impl Into<String>) -> Self {
+        let mut this = self;
+        let reserved = this.reserved_namespace.get_or_insert_with(Default::default);
+        reserved.add_prefix(prefix, None);
+        this
+    }
+
+    /// Reserve the URL path `prefix`, like
+    /// [`reserved_prefix`](Self::reserved_prefix), and let `handler`
+    /// answer all requests for it and everything below it, whatever the
+    /// method. Can be called more than once.
+    ///
+    /// As for an [`extension_method`](Self::extension_method), the path
+    /// passed to the handler has the prefix stripped if it is below it.
+    pub fn reserved_handler(
+        self,
+        prefix: impl Into<String>,
+        handler: Arc<dyn ExtensionHandler>,
+    ) -> Self {
+        let mut this = self;
+        let reserved = this.reserved_namespace.get_or_insert_with(Default::default);
+        reserved.add_handler(prefix, handler);
+        this
+    }
+
+    /// Reserve every path segment that matches `pattern`, like
+    /// [`reserved_prefix`](Self::reserved_prefix) does for a path
+    /// (default: none). A `*` in the pattern matches any sequence,
+    /// so `.props*` reserves `/dir/.props-file` and `/.props/file`.
+    ///
+    /// A `COPY` of a collection leaves out the reserved members. A `MOVE`
+    /// takes them along, since they are still reserved at the destination.
+    pub fn reserved_name(self, pattern: impl Into<String>) -> Self {
+        let mut this = self;
+        let reserved = this.reserved_namespace.get_or_insert_with(Default::default);
+        reserved.add_name(pattern);
+        this
+    }
+
+    /// Limits on the size of requests (default `Limits::default()`).
+    ///
+    /// These are checked while parsing, before any work is done for a
+    /// request, so that a hostile client cannot make the server do a lot
+    /// of it before being refused.
+    pub fn limits(self, limits: Limits) -> Self {
+        let mut this = self;
+        this.limits = Some(limits);
+        this
+    }
+
+    /// Add an `X-Dav-Cond-Debug` header to responses on requests with
+    /// an `If` header, that shows how each list and condition evaluated
+    /// (default false). For example:
+    ///
+    /// ```text
+    /// X-Dav-Cond-Debug: matched=1; list0=fail(token-fail,skipped); list1=ok(!etag-mismatch)
+    /// ```
+    ///
+    /// A `!` marks a `Not` condition. State tokens themselves are not
+    /// included. It is the evaluation that the method handler did, so
+    /// the header is missing if the request failed before the `If`
+    /// header was looked at. This is meant for debugging unexpected
+    /// `412 Precondition Failed` responses. The same information is
+    /// logged at trace level with target `dav_server::conditional`, with
+    /// or without this option.
+    pub fn debug_conditions(self, enable: bool) -> Self {
+        let mut this = self;
+        this.debug_conditions = Some(enable);
+        this
+    }
+
+    /// Handle requests with method `name` with `handler`.
+    ///
+    /// This is only used for methods that the webdav handler does not
+    /// know about itself, for example `REPORT` or `MKCALENDAR`. Those
+    /// are answered with `501 Not Implemented` otherwise. Registered
+    /// methods are listed in the `Allow` header of `OPTIONS` responses.
+    ///
+    /// Panics if `name` is not a valid method name.
+    pub fn extension_method(self, name: &str, handler: Arc<dyn ExtensionHandler>) -> Self {
+        let method = http::Method::from_bytes(name.as_bytes()).expect("invalid method name");
+        let mut this = self;
+        this.extension_methods
+            .get_or_insert_with(BTreeMap::new)
+            .insert(method.as_str().to_string(), handler);
+        this
+    }
+
+    /// Remove these headers from every request before it is handled.
+    ///
+    /// For example `X-Litmus`, which the handler looks at to be
+    /// lenient with the litmus test suite, or `X-Forwarded-For` if
+    /// the server in front of the handler does not sanitize it.
+    pub fn strip_request_headers(self, headers: Vec<HeaderName>) -> Self {
+        let mut this = self;
+        this.strip_request_headers = Some(headers);
+        this
+    }
+
+    // The reserved paths of the user, and those of the features.
+    fn build_reserved(&self) -> ReservedNamespace {
+        let mut reserved = self.reserved_namespace.clone().unwrap_or_default();
+        if let Some(ref path) = self.health_path {
+            reserved.add_prefix(path.as_str(), Some(Route::Health));
+        }
+        if let Some(ref path) = self.async_copymove {
+            reserved.add_prefix(path.as_str(), Some(Route::Jobs));
+        }
+        reserved
+    }
+
     fn merge(&self, new: DavConfig) -> DavConfig {
+        // the reserved paths are rebuilt if the request changes any.
+        let reserved = match (
+            &new.reserved_namespace,
+            &new.health_path,
+            &new.async_copymove,
+        ) {
+            (None, None, None) => self.reserved.clone(),
+            _ => None,
+        };
         DavConfig {
             prefix: new.prefix.or_else(|| self.prefix.clone()),
             fs: new.fs.or_else(|| self.fs.clone()),
             ls: new.ls.or_else(|| self.ls.clone()),
             allow: new.allow.or(self.allow),
             principal: new.principal.or_else(|| self.principal.clone()),
+            principal_extractor: new
+                .principal_extractor
+                .or_else(|| self.principal_extractor.clone()),
+            require_principal: new
+                .require_principal
+                .or_else(|| self.require_principal.clone()),
             hide_symlinks: new.hide_symlinks.or(self.hide_symlinks),
+            default_overwrite: new.default_overwrite.or(self.default_overwrite),
+            strip_destination_fragment: new
+                .strip_destination_fragment
+                .or(self.strip_destination_fragment),
             autoindex: new.autoindex.or(self.autoindex),
+            hashed_collection_etags: new.hashed_collection_etags.or(self.hashed_collection_etags),
             indexfile: new.indexfile.or_else(|| self.indexfile.clone()),
+            archive_download: new.archive_download.or(self.archive_download),
             read_buf_size: new.read_buf_size.or(self.read_buf_size),
+            read_ahead: new.read_ahead.or(self.read_ahead),
+            write_buf_size: new.write_buf_size.or(self.write_buf_size),
+            decompress_uploads: new.decompress_uploads.or(self.decompress_uploads),
+            keep_partial_uploads: new.keep_partial_uploads.or(self.keep_partial_uploads),
+            permanent_collection_redirect: new
+                .permanent_collection_redirect
+                .or(self.permanent_collection_redirect),
             redirect: new.redirect.or(self.redirect),
+            redirect_handler: new
+                .redirect_handler
+                .or_else(|| self.redirect_handler.clone()),
+            precondition: new.precondition.or_else(|| self.precondition.clone()),
+            creationdate_fallback: new.creationdate_fallback.or(self.creationdate_fallback),
+            default_charset: new.default_charset.or_else(|| self.default_charset.clone()),
+            ms_nocache_404: new.ms_nocache_404.or(self.ms_nocache_404),
+            quirks_handler: new.quirks_handler.or_else(|| self.quirks_handler.clone()),
+            oc_etag: new.oc_etag.or(self.oc_etag),
+            strict_lock_owner: new.strict_lock_owner.or(self.strict_lock_owner),
+            get_respects_locks: new.get_respects_locks.or(self.get_respects_locks),
+            windows_name_safety: new.windows_name_safety.or(self.windows_name_safety),
+            redirectrefs: new.redirectrefs.or(self.redirectrefs),
+            extended_mkcol: new.extended_mkcol.or(self.extended_mkcol),
+            lock_null_resources: new.lock_null_resources.or(self.lock_null_resources),
+            durability: new.durability.or(self.durability),
+            directory_get_mode: new
+                .directory_get_mode
+                .or_else(|| self.directory_get_mode.clone()),
+            unreadable_entries: new.unreadable_entries.or(self.unreadable_entries),
+            html_errors: new.html_errors.or(self.html_errors),
+            error_details: new.error_details.or(self.error_details),
+            retry_after: new.retry_after.or(self.retry_after),
+            fs_concurrency_limit: new
+                .fs_concurrency_limit
+                .or_else(|| self.fs_concurrency_limit.clone()),
+            operation_timeout: new.operation_timeout.or(self.operation_timeout),
+            lock_sweep_interval: new.lock_sweep_interval.or(self.lock_sweep_interval),
+            health_path: new.health_path.or_else(|| self.health_path.clone()),
+            started: self.started,
+            async_copymove: new.async_copymove.or_else(|| self.async_copymove.clone()),
+            max_jobs: new.max_jobs.or(self.max_jobs),
+            reserved_namespace: new
+                .reserved_namespace
+                .or_else(|| self.reserved_namespace.clone()),
+            jobs: self.jobs.clone(),
+            reserved,
+            limits: new.limits.or(self.limits),
+            debug_conditions: new.debug_conditions.or(self.debug_conditions),
+            extension_methods: new
+                .extension_methods
+                .or_else(|| self.extension_methods.clone()),
+            strip_request_headers: new
+                .strip_request_headers
+                .or_else(|| self.strip_request_headers.clone()),
         }
     }
 }
 
+// Sweep the locksystem every `interval`, until the handler is gone.
+//
+// The task holds a weak reference to the config, so it does not keep
+// the handler alive. Each sweep is synchronous, so there is no await
+// point at which the task can be dropped halfway through one.
+fn start_lock_sweeper(config: &Arc<DavConfig>, interval: Duration) {
+    if config.ls.is_none() {
+        return;
+    }
+    let runtime = match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => runtime,
+        Err(_) => {
+            error!("lock_sweep_interval: not called from a tokio runtime, not sweeping");
+            return;
+        }
+    };
+    let config = Arc::downgrade(config);
+    runtime.spawn(async move {
+        let mut ticker = tokio::time::interval(interval.max(Duration::from_millis(1)));
+        // the first tick completes immediately.
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let config = match config.upgrade() {
+                Some(config) => config,
+                None => break,
+            };
+            if let Some(ref ls) = config.ls {
+                ls.sweep();
+            }
+        }
+        trace!("lock sweeper: handler dropped, exiting");
+    });
+}
+
+// Does `lock` belong to the client with `principal` and lock `tokens`.
+// See `DavInner::is_lock_owner`.
+pub(crate) fn lock_owner(lock: &DavLock, principal: Option<&str>, tokens: &[String]) -> bool {
+    match (lock.principal.as_deref(), principal) {
+        (Some(owner), Some(principal)) => owner == principal,
+        _ => tokens.iter().any(|t| t == &lock.token),
+    }
+}
+
+// Is this a GET or HEAD from a browser: does it prefer HTML over XML.
+fn wants_html<B>(req: &Request<B>) -> bool {
+    if req.method() != http::Method::GET && req.method() != http::Method::HEAD {
+        return false;
+    }
+    let accept = req
+        .headers()
+        .get_all("accept")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    // XML first, so that only a client that asks for HTML gets it.
+    let accept = Some(accept.as_str()).filter(|a| !a.is_empty());
+    let offers = ["application/xml", "text/xml", "text/html"];
+    negotiate(accept, &offers) == Some("text/html")
+}
+
 // The actual inner struct.
 //
 // At the start of the request, DavConfig is used to generate
 // a DavInner struct. DavInner::handle then handles the request.
+//
+// The config is shared with the handler, only the filesystem and
+// the set of allowed methods are per-request, since they are
+// changed while handling the request. All other settings are
+// read from the config through `Deref`.
+//
+// With `dry_run` set, handlers do all their checks but return
+// right before they change the filesystem or the locksystem.
+//
+// `quirks` is selected once, at the start of the request.
 pub(crate) struct DavInner {
-    pub prefix: String,
     pub fs: Box<dyn DavFileSystem>,
-    pub ls: Option<Box<dyn DavLockSystem>>,
     pub allow: Option<DavMethodSet>,
-    pub principal: Option<String>,
-    pub hide_symlinks: Option<bool>,
-    pub autoindex: Option<bool>,
-    pub indexfile: Option<String>,
-    pub read_buf_size: Option<usize>,
-    pub redirect: Option<bool>,
+    // the principal of this request. From the config, or found
+    // by the principal_extractor.
+    pub principal: Option<Arc<str>>,
+    pub dry_run: bool,
+    pub quirks: ClientQuirks,
+    pub reserved: Arc<ReservedNamespace>,
+    // the If header of this request, parsed.
+    pub if_header: Option<Arc<davheaders::If>>,
+    // where the handler leaves its evaluation of the If header, with
+    // DavConfig::debug_conditions.
+    pub if_debug: Option<Arc<Mutex<Option<IfEval>>>>,
+    config: Arc<DavConfig>,
 }
 
-impl From<DavConfig> for DavInner {
-    fn from(cfg: DavConfig) -> Self {
+impl From<Arc<DavConfig>> for DavInner {
+    fn from(config: Arc<DavConfig>) -> Self {
+        let reserved = match config.reserved {
+            Some(ref reserved) => reserved.clone(),
+            None => Arc::new(config.build_reserved()),
+        };
         DavInner {
-            prefix: cfg.prefix.unwrap_or_else(|| "".to_string()),
-            fs: cfg.fs.unwrap_or_else(|| VoidFs::new()),
-            ls: cfg.ls,
-            allow: cfg.allow,
-            principal: cfg.principal,
-            hide_symlinks: cfg.hide_symlinks,
-            autoindex: cfg.autoindex,
-            indexfile: cfg.indexfile,
-            read_buf_size: cfg.read_buf_size,
-            redirect: cfg.redirect,
+            fs: config.fs.clone().unwrap_or_else(|| VoidFs::new()),
+            allow: config.allow,
+            principal: config.principal.clone(),
+            dry_run: false,
+            quirks: ClientQuirks::default(),
+            reserved,
+            if_header: None,
+            if_debug: None,
+            config,
         }
     }
 }
 
-impl From<&DavConfig> for DavInner {
-    fn from(cfg: &DavConfig) -> Self {
-        DavInner {
-            prefix: cfg
-                .prefix
-                .as_ref()
-                .map(|p| p.to_owned())
-                .unwrap_or_else(|| "".to_string()),
-            fs: cfg.fs.clone().unwrap(),
-            ls: cfg.ls.clone(),
-            allow: cfg.allow,
-            principal: cfg.principal.clone(),
-            hide_symlinks: cfg.hide_symlinks,
-            autoindex: cfg.autoindex,
-            indexfile: cfg.indexfile.clone(),
-            read_buf_size: cfg.read_buf_size,
-            redirect: cfg.redirect,
-        }
+impl From<DavConfig> for DavInner {
+    fn from(cfg: DavConfig) -> Self {
+        DavInner::from(Arc::new(cfg))
     }
 }
 
 impl Clone for DavInner {
     fn clone(&self) -> Self {
         DavInner {
-            prefix: self.prefix.clone(),
             fs: self.fs.clone(),
-            ls: self.ls.clone(),
             allow: self.allow,
             principal: self.principal.clone(),
-            hide_symlinks: self.hide_symlinks,
-            autoindex: self.autoindex,
-            indexfile: self.indexfile.clone(),
-            read_buf_size: self.read_buf_size,
-            redirect: self.redirect,
+            dry_run: self.dry_run,
+            quirks: self.quirks,
+            reserved: self.reserved.clone(),
+            if_header: self.if_header.clone(),
+            if_debug: self.if_debug.clone(),
+            config: self.config.clone(),
         }
     }
 }
 
+impl std::ops::Deref for DavInner {
+    type Target = DavConfig;
+
+    fn deref(&self) -> &DavConfig {
+        &self.config
+    }
+}
+
 impl DavHandler {
     /// Create a new `DavHandler`.
     ///
@@ -255,10 +1531,75 @@ impl DavHandler {
         ReqError: StdError + Send + Sync + 'static,
         ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
     {
-        let inner = DavInner::from(&*self.config);
+        let inner = DavInner::from(self.config.clone());
         inner.handle(req).await
     }
 
+    /// Handle a webdav request, but return the error instead of
+    /// turning it into an error response.
+    ///
+    /// Useful if you want to log or count failed requests. Use
+    /// [`DavError::statuscode`] to get the status that `handle` would
+    /// have sent.
+    pub async fn try_handle<ReqBody, ReqData, ReqError>(
+        &self,
+        req: Request<ReqBody>,
+    ) -> Result<Response<Body>, DavError>
+    where
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+        ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+    {
+        let inner = DavInner::from(self.config.clone());
+        inner.try_handle(req).await
+    }
+
+    /// Check whether a request would succeed, without performing it.
+    ///
+    /// This runs the same checks as `handle` does: whether the method
+    /// is allowed, whether the resource (or the parent of a new one)
+    /// exists, the `If` and `If-*` headers, and locks. It stops right
+    /// before the filesystem or the locksystem would be changed, and
+    /// returns the error that `handle` would have returned, if any.
+    ///
+    /// The request body is not looked at. A `PROPPATCH` is only checked
+    /// up to the point where the body is parsed. A `LOCK`, a
+    /// `MKREDIRECTREF` and extension methods cannot be checked without
+    /// their body, and fail with `501 Not Implemented`.
+    ///
+    /// Nothing is locked while validating, so a request that validates
+    /// can still fail if something changes in the meantime.
+    pub async fn validate<B>(&self, req: &Request<B>) -> DavResult<()> {
+        let mut r = Request::new(Body::empty());
+        *r.method_mut() = req.method().clone();
+        *r.uri_mut() = req.uri().clone();
+        *r.version_mut() = req.version();
+        *r.headers_mut() = req.headers().clone();
+
+        let mut inner = DavInner::from(self.config.clone());
+        inner.dry_run = true;
+        let status = inner.try_handle(r).await?.status();
+        if status.is_client_error() || status.is_server_error() {
+            return Err(DavError::Status(status));
+        }
+        Ok(())
+    }
+
+    /// Like `try_handle`, but overriding parts of the config like `handle_with`.
+    pub async fn try_handle_with<ReqBody, ReqData, ReqError>(
+        &self,
+        config: DavConfig,
+        req: Request<ReqBody>,
+    ) -> Result<Response<Body>, DavError>
+    where
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+        ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+    {
+        let inner = DavInner::from(self.config.merge(config));
+        inner.try_handle(req).await
+    }
+
     /// Handle a webdav request, overriding parts of the config.
     ///
     /// For example, the `principal` can be set for this request.
@@ -266,6 +1607,20 @@ impl DavHandler {
     /// Or, the default config has no locksystem, and you pass in
     /// a fake locksystem (`FakeLs`) because this is a request from a
     /// windows or macos client that needs to see locking support.
+    ///
+    /// Settings that are not set in `config` are taken from the handler:
+    ///
+    /// ```
+    /// # async fn f(dav: dav_server::DavHandler, req: http::Request<dav_server::body::Body>) {
+    /// use dav_server::{DavConfig, DavMethodSet};
+    ///
+    /// let config = DavConfig::new()
+    ///     .principal("alice")
+    ///     .strip_prefix("/u/alice")
+    ///     .methods(DavMethodSet::WEBDAV_RO);
+    /// let resp = dav.handle_with(config, req).await;
+    /// # }
+    /// ```
     pub async fn handle_with<ReqBody, ReqData, ReqError>(
         &self,
         config: DavConfig,
@@ -297,7 +1652,7 @@ impl DavHandler {
             let (parts, body) = req.into_parts();
             Request::from_parts(parts, StreamBody::new(body))
         };
-        let inner = DavInner::from(&*self.config);
+        let inner = DavInner::from(self.config.clone());
         inner.handle(req).await
     }
 
@@ -305,44 +1660,172 @@ impl DavHandler {
     #[doc(hidden)]
     pub async fn handle_stream_with<ReqBody, ReqData, ReqError>(
         &self,
-        config: DavConfig,
-        req: Request<ReqBody>,
-    ) -> Response<Body>
-    where
-        ReqData: Buf + Send + 'static,
-        ReqError: StdError + Send + Sync + 'static,
-        ReqBody: Stream<Item = Result<ReqData, ReqError>>,
-    {
-        let req = {
-            let (parts, body) = req.into_parts();
-            Request::from_parts(parts, StreamBody::new(body))
-        };
-        let inner = DavInner::from(self.config.merge(config));
-        inner.handle(req).await
+        config: DavConfig,
+        req: Request<ReqBody>,
+    ) -> Response<Body>
+    where
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+        ReqBody: Stream<Item = Result<ReqData, ReqError>>,
+    {
+        let req = {
+            let (parts, body) = req.into_parts();
+            Request::from_parts(parts, StreamBody::new(body))
+        };
+        let inner = DavInner::from(self.config.merge(config));
+        inner.handle(req).await
+    }
+}
+
+impl Default for DavHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DavInner {
+    // the prefix to strip off.
+    pub(crate) fn prefix(&self) -> &str {
+        self.config.prefix.as_deref().unwrap_or("")
+    }
+
+    // How to read a directory for a listing. A hidden symlink needs its
+    // own metadata, so that it can be recognized and skipped. A visible
+    // one is followed, like GET and PROPFIND on the link itself do, so
+    // that they all report the size and type of the target.
+    pub(crate) fn readdir_meta(&self) -> ReadDirMeta {
+        match self.hide_symlinks {
+            Some(true) | None => ReadDirMeta::DataSymlink,
+            Some(false) => ReadDirMeta::Data,
+        }
+    }
+
+    // The status to list a member of a collection with, if its metadata
+    // failed with `err`. `None` if it should be left out.
+    pub(crate) fn unreadable_status(&self, err: FsError) -> Option<StatusCode> {
+        match (self.unreadable_entries, err) {
+            (_, FsError::NotFound) => None,
+            (Some(UnreadableEntries::Report), err) => Some(DavError::from(err).statuscode()),
+            (Some(UnreadableEntries::Skip) | None, _) => None,
+        }
+    }
+
+    // The request limits.
+    pub(crate) fn limits(&self) -> Limits {
+        self.config.limits.unwrap_or_default()
+    }
+
+    // Is this resource hidden by a reserved path, like the health path.
+    pub(crate) fn is_reserved_path(&self, path: &DavPath) -> bool {
+        self.reserved.contains(path)
+    }
+
+    // Is the member at `path` listed in PROPFIND and the directory index.
+    // Symlinks are only there if they are followed, reserved paths never.
+    pub(crate) fn is_listed(&self, path: &DavPath, meta: &dyn DavMetaData) -> bool {
+        !meta.is_symlink() && !self.is_reserved_path(path)
+    }
+
+    // helper. Only needed to create `path`: its parent must be an existing
+    // collection (409 Conflict), that we are allowed to see (403 Forbidden).
+    pub(crate) async fn check_parent<'a>(&'a self, path: &'a DavPath) -> DavResult<()> {
+        match self.fs.metadata(&path.parent()).await {
+            Ok(m) if m.is_dir() => Ok(()),
+            Err(FsError::Forbidden) => Err(StatusCode::FORBIDDEN.into()),
+            _ => Err(StatusCode::CONFLICT.into()),
+        }
+    }
+
+    // helper. In a dry run, what opening `path` for writing with `oo`
+    // would return, going by whether it `exists` and by its parent.
+    // The handler maps this like the result of the real call.
+    pub(crate) async fn dry_run_open(
+        &self,
+        path: &DavPath,
+        exists: bool,
+        oo: &OpenOptions,
+    ) -> FsResult<()> {
+        if exists {
+            return if oo.create_new {
+                Err(FsError::Exists)
+            } else {
+                Ok(())
+            };
+        }
+        if !oo.create {
+            return Err(FsError::NotFound);
+        }
+        match self.fs.metadata(&path.parent()).await {
+            Ok(m) if m.is_dir() => Ok(()),
+            Ok(_) => Err(FsError::NotFound),
+            Err(e) => Err(e),
+        }
+    }
+
+    // helper. status for a write that was refused because of `lock`.
+    pub(crate) fn lock_status(&self, lock: &DavLock, tokens: &[String]) -> StatusCode {
+        if self.strict_lock_owner.unwrap_or(false)
+            && tokens.iter().any(|t| t == &lock.token)
+            && !self.is_lock_owner(lock, tokens)
+        {
+            StatusCode::FORBIDDEN
+        } else {
+            StatusCode::LOCKED
+        }
+    }
+
+    // helper. The response to a request that fails on `lock`: a
+    // 423 Locked with the lock-token-submitted precondition, which
+    // names the root of the lock (RFC4918 16). See `lock_status`.
+    pub(crate) fn locked_response(
+        &self,
+        lock: &DavLock,
+        tokens: &[String],
+    ) -> DavResult<Response<Body>> {
+        let status = self.lock_status(lock, tokens);
+        if status != StatusCode::LOCKED {
+            return Err(status.into());
+        }
+        let href = lock.path.with_prefix().as_url_string();
+        let xml = format!(
+            "<D:lock-token-submitted><D:href>{}</D:href></D:lock-token-submitted>",
+            href
+        );
+        let mut res = Response::new(dav_xml_error(&xml));
+        *res.status_mut() = StatusCode::LOCKED;
+        let ct = "application/xml; charset=utf-8".to_owned();
+        res.headers_mut().typed_insert(davheaders::ContentType(ct));
+        Ok(res)
     }
-}
 
-impl Default for DavHandler {
-    fn default() -> Self {
-        Self::new()
+    // helper. Does the lock belong to the client? If both the lock and
+    // the request have a principal, compare those. Otherwise there is
+    // nothing to tell clients apart, and only the client that submitted
+    // the lock token owns the lock.
+    pub(crate) fn is_lock_owner(&self, lock: &DavLock, tokens: &[String]) -> bool {
+        lock_owner(lock, self.principal.as_deref(), tokens)
     }
-}
 
-impl DavInner {
-    // helper.
-    pub(crate) async fn has_parent<'a>(&'a self, path: &'a DavPath) -> bool {
-        let p = path.parent();
-        self.fs
-            .metadata(&p)
-            .await
-            .map(|m| m.is_dir())
-            .unwrap_or(false)
+    // helper. with windows_name_safety, refuse to create `path`
+    // if Windows cannot handle its name.
+    pub(crate) fn check_windows_name(&self, path: &DavPath) -> DavResult<()> {
+        if !self.windows_name_safety.unwrap_or(false) {
+            return Ok(());
+        }
+        match windows_name_problem(path.file_name_bytes()) {
+            Some(problem) => {
+                let name = String::from_utf8_lossy(path.file_name_bytes());
+                let detail = format!("\"{}\" {} on Windows\n", name, problem);
+                Err(DavError::from(StatusCode::FORBIDDEN).with_detail(detail))
+            }
+            None => Ok(()),
+        }
     }
 
     // helper.
     pub(crate) fn path(&self, req: &Request<()>) -> DavPath {
         // This never fails (has been checked before)
-        DavPath::from_uri_and_prefix(req.uri(), &self.prefix).unwrap()
+        DavPath::from_uri_and_prefix(req.uri(), self.prefix()).unwrap()
     }
 
     // See if this is a directory and if so, if we have
@@ -395,52 +1878,208 @@ impl DavInner {
         Ok(data)
     }
 
+    // The client quirks for this request.
+    fn select_quirks(&self, req: &Request<()>) -> ClientQuirks {
+        let ua = req
+            .headers()
+            .get("user-agent")
+            .and_then(|s| s.to_str().ok())
+            .unwrap_or("");
+        let mut quirks = ClientQuirks::from_user_agent(ua);
+        if !self.ms_nocache_404.unwrap_or(true) {
+            quirks.ms_404_nocache = false;
+        }
+        if let Some(ref handler) = self.quirks_handler {
+            quirks = handler(req, quirks);
+        }
+        quirks
+    }
+
+    // Set the principal from the principal_extractor, if there is none.
+    fn extract_principal(&mut self, req: &Request<()>) {
+        if self.principal.is_some() {
+            return;
+        }
+        let principal = match self.principal_extractor {
+            Some(ref extract) => extract(req),
+            None => None,
+        };
+        self.principal = principal.map(Arc::from);
+    }
+
+    // Find the principal and select the client quirks. Returns the
+    // response if a principal is required, but there is none.
+    fn prepare(&mut self, req: &Request<()>) -> Option<Response<Body>> {
+        self.extract_principal(req);
+        if self.principal.is_none() && !self.in_health_path(req.uri().path()) {
+            if let Some(ref challenge) = self.require_principal {
+                let mut res = Response::new(Body::empty());
+                *res.status_mut() = StatusCode::UNAUTHORIZED;
+                res.headers_mut()
+                    .insert("WWW-Authenticate", challenge.clone());
+                return Some(res);
+            }
+        }
+        self.quirks = self.select_quirks(req);
+        None
+    }
+
+    // dispatcher for `try_handle` and `validate`. Like `handle`, but
+    // errors are returned instead of turned into a response.
+    async fn try_handle<ReqBody, ReqData, ReqError>(
+        mut self,
+        req: Request<ReqBody>,
+    ) -> DavResult<Response<Body>>
+    where
+        ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+    {
+        let (parts, body) = req.into_parts();
+        let req = Request::from_parts(parts, ());
+        if let Some(res) = self.prepare(&req) {
+            return Ok(res);
+        }
+        let (parts, ()) = req.into_parts();
+        self.handle2(Request::from_parts(parts, body)).await
+    }
+
     // internal dispatcher.
-    async fn handle<ReqBody, ReqData, ReqError>(self, req: Request<ReqBody>) -> Response<Body>
+    async fn handle<ReqBody, ReqData, ReqError>(mut self, req: Request<ReqBody>) -> Response<Body>
     where
         ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
         ReqData: Buf + Send + 'static,
         ReqError: StdError + Send + Sync + 'static,
     {
-        let is_ms = req
-            .headers()
-            .get("user-agent")
-            .and_then(|s| s.to_str().ok())
-            .map(|s| s.contains("Microsoft"))
-            .unwrap_or(false);
+        // the callbacks get the request without its body.
+        let (req, body) = {
+            let (parts, body) = req.into_parts();
+            (Request::from_parts(parts, ()), body)
+        };
+        if let Some(res) = self.prepare(&req) {
+            return res;
+        }
+        let quirks = self.quirks;
+        let retry_after = self.retry_after;
+
+        // a browser gets an error page instead of an empty body.
+        let head = req.method() == http::Method::HEAD;
+        let html_error_path = if self.html_errors.unwrap_or(false) && wants_html(&req) {
+            Some(req.uri().path().to_string())
+        } else {
+            None
+        };
+
+        // the handler records how it evaluated the If header.
+        if self.debug_conditions.unwrap_or(false) {
+            self.if_debug = Some(Arc::new(Mutex::new(None)));
+        }
+        let if_debug = self.if_debug.clone();
 
         // Turn any DavError results into a HTTP error response.
-        match self.handle2(req).await {
+        let req = {
+            let (parts, ()) = req.into_parts();
+            Request::from_parts(parts, body)
+        };
+        let mut resp = match self.handle2(req).await {
             Ok(resp) => {
                 debug!("== END REQUEST result OK");
                 resp
             }
             Err(err) => {
                 debug!("== END REQUEST result {:?}", err);
-                let mut resp = Response::builder();
-                if is_ms && err.statuscode() == StatusCode::NOT_FOUND {
-                    // This is an attempt to convince Windows to not
-                    // cache a 404 NOT_FOUND for 30-60 seconds.
-                    //
-                    // That is a problem since windows caches the NOT_FOUND in a
-                    // case-insensitive way. So if "www" does not exist, but "WWW" does,
-                    // and you do a "dir www" and then a "dir WWW" the second one
-                    // will fail.
-                    //
-                    // Ofcourse the below is not sufficient. Fixes welcome.
-                    resp = resp
-                        .header("Cache-Control", "no-store, no-cache, must-revalidate")
-                        .header("Progma", "no-cache")
-                        .header("Expires", "0")
-                        .header("Vary", "*");
+                if let Some(e @ FsError::Other { .. }) = err.fs_error() {
+                    debug!("filesystem error: {}", e);
                 }
-                resp = resp.header("Content-Length", "0").status(err.statuscode());
+                let mut resp = Response::builder().status(err.statuscode());
                 if err.must_close() {
                     resp = resp.header("connection", "close");
                 }
-                resp.body(Body::empty()).unwrap()
+                if let Some(after) = err.retry_after() {
+                    resp = resp.header("Retry-After", after.as_secs());
+                }
+                match err.detail() {
+                    Some(detail) => resp
+                        .header("Content-Type", "text/plain; charset=utf-8")
+                        .header("Content-Length", detail.len())
+                        .body(Body::from(detail))
+                        .unwrap(),
+                    // RFC4918 11.2: 422 comes with a DAV:error body.
+                    None if err.statuscode() == StatusCode::UNPROCESSABLE_ENTITY => {
+                        let xml = concat!(
+                            r#"<?xml version="1.0" encoding="utf-8" ?>"#,
+                            "\n",
+                            r#"<D:error xmlns:D="DAV:"/>"#,
+                            "\n"
+                        );
+                        resp.header("Content-Type", "application/xml; charset=utf-8")
+                            .header("Content-Length", xml.len())
+                            .body(Body::from(xml))
+                            .unwrap()
+                    }
+                    None => resp
+                        .header("Content-Length", "0")
+                        .body(Body::empty())
+                        .unwrap(),
+                }
+            }
+        };
+
+        let status = resp.status();
+        let no_body = resp
+            .headers()
+            .get("content-length")
+            .is_some_and(|v| v == "0");
+        if let Some(path) = html_error_path.filter(|_| status.as_u16() >= 400 && no_body) {
+            let page = html_error_page(status, &path);
+            let h = resp.headers_mut();
+            h.insert(
+                "Content-Type",
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            );
+            h.typed_insert(headers::ContentLength(page.len() as u64));
+            if !head {
+                *resp.body_mut() = Body::from(page);
+            }
+        }
+
+        if status == StatusCode::SERVICE_UNAVAILABLE && !resp.headers().contains_key("retry-after")
+        {
+            if let Some(after) = retry_after {
+                resp.headers_mut()
+                    .insert("Retry-After", HeaderValue::from(after.as_secs()));
             }
         }
+
+        let cond_debug = if_debug.and_then(|eval| eval.lock().unwrap().take());
+        if let Some(value) = cond_debug.and_then(|e| HeaderValue::from_str(&e.summary()).ok()) {
+            resp.headers_mut().insert("x-dav-cond-debug", value);
+        }
+
+        if quirks.ms_404_nocache && resp.status() == StatusCode::NOT_FOUND {
+            // This is an attempt to convince Windows to not
+            // cache a 404 NOT_FOUND for 30-60 seconds.
+            //
+            // That is a problem since windows caches the NOT_FOUND in a
+            // case-insensitive way. So if "www" does not exist, but "WWW" does,
+            // and you do a "dir www" and then a "dir WWW" the second one
+            // will fail.
+            //
+            // Ofcourse the below is not sufficient. Fixes welcome.
+            //
+            // "Progma" is a typo that was sent for a long time, keep
+            // sending it for clients that might have come to depend on it.
+            let h = resp.headers_mut();
+            h.insert(
+                "Cache-Control",
+                HeaderValue::from_static("no-store, no-cache, must-revalidate"),
+            );
+            h.insert("Pragma", HeaderValue::from_static("no-cache"));
+            h.insert("Progma", HeaderValue::from_static("no-cache"));
+            h.insert("Expires", HeaderValue::from_static("0"));
+            h.insert("Vary", HeaderValue::from_static("*"));
+        }
+        resp
     }
 
     // internal dispatcher part 2.
@@ -454,7 +2093,12 @@ impl DavInner {
         ReqError: StdError + Send + Sync + 'static,
     {
         let (req, body) = {
-            let (parts, body) = req.into_parts();
+            let (mut parts, body) = req.into_parts();
+            if let Some(ref names) = self.strip_request_headers {
+                for name in names {
+                    parts.headers.remove(name);
+                }
+            }
             (Request::from_parts(parts, ()), body)
         };
 
@@ -465,10 +2109,31 @@ impl DavInner {
             }
         }
 
+        // the health endpoint and the job status resources are
+        // outside of the webdav namespace.
+        match self.reserved.route(req.uri().path()) {
+            Some(Route::Health) => return self.handle_health(&req).await,
+            Some(Route::Jobs) => return self.handle_job(&req),
+            Some(Route::Handler(n)) => {
+                if self.dry_run {
+                    return Err(StatusCode::NOT_IMPLEMENTED.into());
+                }
+                let handler = self.reserved.handler(n);
+                return self.handle_extension(handler, req, body).await;
+            }
+            None => {}
+        }
+
         // translate HTTP method to Webdav method.
         let method = match dav_method(req.method()) {
             Ok(m) => m,
             Err(e) => {
+                if let Some(handler) = self.extension_handler(req.method()) {
+                    if self.dry_run {
+                        return Err(StatusCode::NOT_IMPLEMENTED.into());
+                    }
+                    return self.handle_extension(handler, req, body).await;
+                }
                 debug!("refusing method {} request {}", req.method(), req.uri());
                 return Err(e);
             }
@@ -496,25 +2161,82 @@ impl DavInner {
             }
         }
 
-        // see if method is allowed.
-        if let Some(ref a) = self.allow {
-            if !a.contains(method) {
-                debug!(
-                    "method {} not allowed on request {}",
-                    req.method(),
-                    req.uri()
-                );
-                return Err(DavError::StatusClose(StatusCode::METHOD_NOT_ALLOWED));
-            }
+        // the filesystem might support only a subset of the methods.
+        if self.allow.is_none() {
+            self.allow = self.fs.allowed_methods();
+        }
+
+        // see if method is allowed. A 405 must list the allowed methods.
+        let mut allowed = self.allow.map(|a| a.contains(method)).unwrap_or(true);
+        if method == DavMethod::MkRedirectRef && !self.redirectrefs.unwrap_or(false) {
+            allowed = false;
+        }
+        if !allowed {
+            debug!(
+                "method {} not allowed on request {}",
+                req.method(),
+                req.uri()
+            );
+            let mut res = self.handle_options(&req).await?;
+            *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            res.headers_mut().typed_insert(headers::Connection::close());
+            return Ok(res);
+        }
+
+        // these need their body to know what they would do.
+        if self.dry_run && matches!(method, DavMethod::Lock | DavMethod::MkRedirectRef) {
+            return Err(StatusCode::NOT_IMPLEMENTED.into());
         }
 
         // make sure the request path is valid.
-        let path = DavPath::from_uri_and_prefix(req.uri(), &self.prefix)?;
+        let path = DavPath::from_uri_and_prefix(req.uri(), self.prefix())?;
+
+        // "*" is only valid for OPTIONS (RFC 9110 9.3.7), and does not
+        // refer to a resource.
+        if path.is_star() {
+            if method != DavMethod::Options {
+                return Err(DavError::Status(StatusCode::BAD_REQUEST));
+            }
+            return self.handle_options(&req).await;
+        }
+
+        // reserved resources cannot be created. COPY and MOVE check
+        // their destination themselves.
+        let creates = matches!(
+            method,
+            DavMethod::Put | DavMethod::Patch | DavMethod::MkCol | DavMethod::MkRedirectRef
+        );
+        if creates && self.is_reserved_path(&path) {
+            return Err(StatusCode::FORBIDDEN.into());
+        }
+
+        // and that the If header is not unreasonably large. It is parsed
+        // here once, and evaluated by the handlers.
+        match parse_if_header(&req, self.limits().if_conditions) {
+            Ok(r) => self.if_header = r.map(Arc::new),
+            Err(()) => {
+                debug!("too many conditions in If header on request {}", req.uri());
+                let mut res = limit_error(StatusCode::BAD_REQUEST, "if_conditions");
+                res.headers_mut()
+                    .insert("connection", HeaderValue::from_static("close"));
+                return Ok(res);
+            }
+        }
+
+        // limit concurrent filesystem calls, and make sure that a
+        // path with a trailing slash is a collection.
+        if let Some(sem) = self.fs_concurrency_limit.clone() {
+            self.fs = LimitFs::new(self.fs, sem);
+        }
+        if let Some(timeout) = self.operation_timeout {
+            self.fs = TimeoutFs::new(self.fs, timeout);
+        }
+        self.fs = SlashFs::new(self.fs);
 
-        // PUT is the only handler that reads the body itself. All the
+        // PUT and POST are the only handlers that read the body themselves. All the
         // other handlers either expected no body, or a pre-read Vec<u8>.
         let (body_strm, body_data) = match method {
-            DavMethod::Put | DavMethod::Patch => (Some(body), Vec::new()),
+            DavMethod::Put | DavMethod::Patch | DavMethod::Post => (Some(body), Vec::new()),
             _ => (None, self.read_request(body, 65536).await?),
         };
 
@@ -522,9 +2244,14 @@ impl DavInner {
         match method {
             DavMethod::Put
             | DavMethod::Patch
+            | DavMethod::Post
             | DavMethod::PropFind
             | DavMethod::PropPatch
-            | DavMethod::Lock => {}
+            | DavMethod::Lock
+            | DavMethod::Copy
+            | DavMethod::Move
+            | DavMethod::MkCol
+            | DavMethod::MkRedirectRef => {}
             _ => {
                 if !body_data.is_empty() {
                     return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE.into());
@@ -534,18 +2261,988 @@ impl DavInner {
 
         debug!("== START REQUEST {:?} {}", method, path);
 
-        let res = match method {
-            DavMethod::Options => self.handle_options(&req).await,
-            DavMethod::PropFind => self.handle_propfind(&req, &body_data).await,
-            DavMethod::PropPatch => self.handle_proppatch(&req, &body_data).await,
-            DavMethod::MkCol => self.handle_mkcol(&req).await,
-            DavMethod::Delete => self.handle_delete(&req).await,
-            DavMethod::Lock => self.handle_lock(&req, &body_data).await,
-            DavMethod::Unlock => self.handle_unlock(&req).await,
-            DavMethod::Head | DavMethod::Get => self.handle_get(&req).await,
-            DavMethod::Copy | DavMethod::Move => self.handle_copymove(&req, method).await,
-            DavMethod::Put | DavMethod::Patch => self.handle_put(&req, body_strm.unwrap()).await,
+        let timeout = match method {
+            DavMethod::Put
+            | DavMethod::Patch
+            | DavMethod::Post
+            | DavMethod::Copy
+            | DavMethod::Move
+            | DavMethod::Delete
+            | DavMethod::MkCol
+            | DavMethod::MkRedirectRef
+            | DavMethod::PropPatch
+            | DavMethod::Lock => None,
+            _ => self.operation_timeout,
+        };
+        let res = async move {
+            match method {
+                DavMethod::Options => self.handle_options(&req).await,
+                DavMethod::PropFind => self.handle_propfind(&req, &body_data).await,
+                DavMethod::PropPatch => self.handle_proppatch(&req, &body_data).await,
+                DavMethod::MkCol => self.handle_mkcol(&req, &body_data).await,
+                DavMethod::Delete => self.handle_delete(&req).await,
+                DavMethod::Lock => self.handle_lock(&req, &body_data).await,
+                DavMethod::Unlock => self.handle_unlock(&req).await,
+                DavMethod::Head | DavMethod::Get => self.handle_get(&req).await,
+                DavMethod::Copy | DavMethod::Move => {
+                    self.handle_copymove(&req, method, &body_data).await
+                }
+                DavMethod::Put | DavMethod::Patch => {
+                    self.handle_put(&req, body_strm.unwrap()).await
+                }
+                DavMethod::Post => self.handle_post(&req, body_strm.unwrap()).await,
+                DavMethod::MkRedirectRef => self.handle_mkredirectref(&req, &body_data).await,
+            }
+        };
+        match timeout {
+            Some(t) => match within(t, res).await {
+                Some(res) => res,
+                None => Err(timed_out().into()),
+            },
+            None => res.await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::testfs::{MockFs, MockOp};
+
+    #[tokio::test]
+    async fn test_try_handle() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+
+        let req = Request::get("/missing").body(Body::empty()).unwrap();
+        match dav.try_handle(req).await {
+            Err(DavError::FsError(FsError::NotFound)) => {}
+            other => panic!("unexpected result {:?}", other.map(|r| r.status())),
+        }
+
+        let req = Request::get("/missing").body(Body::empty()).unwrap();
+        assert_eq!(dav.handle(req).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_strip_request_headers() {
+        // X-Litmus allows a Depth: infinity PROPFIND.
+        let propfind = || {
+            Request::builder()
+                .method("PROPFIND")
+                .uri("/")
+                .header("Depth", "infinity")
+                .header("X-Litmus", "props: 1")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let status = dav.handle(propfind()).await.status();
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_request_headers(vec![HeaderName::from_static("x-litmus")])
+            .build_handler();
+        let status = dav.handle(propfind()).await.status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_options_star() {
+        let req = |method: &str| {
+            Request::builder()
+                .method(method)
+                .uri("*")
+                .body(Body::empty())
+                .unwrap()
+        };
+        for prefix in ["", "/dav"] {
+            let dav = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .strip_prefix(prefix)
+                .build_handler();
+            let res = dav.handle(req("OPTIONS")).await;
+            assert_eq!(res.status(), StatusCode::OK, "{:?}", prefix);
+            assert!(res.headers().contains_key("dav"));
+            let allow = res.headers()["allow"].to_str().unwrap();
+            let allow: Vec<_> = allow.split(',').collect();
+            for m in ["GET", "PUT", "POST", "MKCOL", "PROPFIND", "DELETE"] {
+                assert!(allow.contains(&m), "{:?}: {} not in {:?}", prefix, m, allow);
+            }
+
+            let res = dav.handle(req("GET")).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_if_conditions() {
+        let get = |n: usize| {
+            let list = "(<urn:uuid:x>)".repeat(n);
+            Request::get("/file")
+                .header("If", list)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let put = || Request::put("/file").body(Body::from("x")).unwrap();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        assert_eq!(dav.handle(put()).await.status(), StatusCode::CREATED);
+        // a list that fails evaluates to 412, too many is a 400.
+        let status = dav.handle(get(100)).await.status();
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let status = dav.handle(get(101)).await.status();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .limits(Limits {
+                if_conditions: 2,
+                ..Limits::default()
+            })
+            .build_handler();
+        assert_eq!(dav.handle(put()).await.status(), StatusCode::CREATED);
+        let status = dav.handle(get(2)).await.status();
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let status = dav.handle(get(3)).await.status();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_principal_extractor() {
+        use crate::memls::MemLs;
+        use crate::testing::DavClient;
+
+        let req = |name: &str, value: &str| Request::get("/").header(name, value).body(()).unwrap();
+        let basic = PrincipalExtractor::basic_auth();
+        // "alice:pa:ss"
+        let alice = req("Authorization", "Basic YWxpY2U6cGE6c3M=");
+        assert_eq!(basic(&alice).as_deref(), Some("alice"));
+        // ":pw"
+        assert_eq!(basic(&req("Authorization", "Basic OnB3")), None);
+        assert_eq!(basic(&req("Authorization", "Basic !!")), None);
+        assert_eq!(
+            basic(&req("Authorization", "Bearer YWxpY2U6cGE6c3M=")),
+            None
+        );
+        let header = PrincipalExtractor::header("X-Remote-User");
+        assert_eq!(
+            header(&req("x-remote-user", " bob ")).as_deref(),
+            Some("bob")
+        );
+        assert_eq!(header(&req("x-remote-user", "")), None);
+        assert_eq!(header(&alice), None);
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .principal_extractor(PrincipalExtractor::header("X-Remote-User"))
+            .require_principal("Basic realm=\"dav\"")
+            .strict_lock_owner(true)
+            .health_path("/healthz")
+            .build_handler();
+        let client = DavClient::new(dav.clone());
+        let request = |method: &str, user: &str, token: Option<&str>| {
+            let mut req = Request::builder()
+                .method(method)
+                .uri("/file")
+                .header("X-Remote-User", user);
+            if let Some(token) = token {
+                req = req.header("If", format!("(<{}>)", token));
+            }
+            req.body(Body::empty()).unwrap()
+        };
+
+        // anonymous requests are refused.
+        let res = client.get("/file").await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.headers()["www-authenticate"], "Basic realm=\"dav\"");
+        assert_eq!(client.get("/healthz").await.status(), StatusCode::OK);
+
+        // the principal owns the locks it creates.
+        let res = client.request(request("PUT", "alice", None)).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let mut lock = request("LOCK", "alice", None);
+        *lock.body_mut() = Body::from(concat!(
+            r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope>"#,
+            r#"<D:locktype><D:write/></D:locktype></D:lockinfo>"#
+        ));
+        let res = client.request(lock).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let token = res.headers()["lock-token"].to_str().unwrap();
+        let token = token.trim_matches(|c| c == '<' || c == '>').to_string();
+        let res = client.request(request("PUT", "bob", Some(&token))).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = client.request(request("PUT", "alice", Some(&token))).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        // a principal set for the request wins.
+        let config = DavConfig::new().principal("alice");
+        let res = dav
+            .handle_with(config, request("PUT", "bob", Some(&token)))
+            .await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_header_limits() {
+        use crate::testing::DavClient;
+
+        let client = DavClient::new(
+            DavHandler::builder()
+                .filesystem(MemFs::new())
+                .build_handler(),
+        );
+        assert_eq!(client.put_bytes("/file", "x").await, StatusCode::CREATED);
+        let req = |method: &str, name: &str, value: &str| {
+            Request::builder()
+                .method(method)
+                .uri("/file")
+                .header(name, value)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // oversized headers are refused without parsing all of them: what
+        // comes after the limit is not even looked at.
+        let cond = "(<urn:uuid:x> [\"e\"])".repeat(51) + "((garbage";
+        let res = client.request(req("GET", "If", &cond)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(res.headers()["connection"], "close");
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert!(
+            body.contains(">if_conditions</S:limit-exceeded>"),
+            "{}",
+            body
+        );
+
+        let dest = format!("http://localhost/{}", "a/".repeat(500_000));
+        let res = client.request(req("COPY", "Destination", &dest)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert!(
+            body.contains(">destination_len</S:limit-exceeded>"),
+            "{}",
+            body
+        );
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .limits(Limits {
+                destination_len: 20,
+                if_conditions: 2,
+                ..Limits::default()
+            });
+        let client = DavClient::new(dav.build_handler());
+        assert_eq!(client.put_bytes("/file", "x").await, StatusCode::CREATED);
+        let cond = "(<urn:uuid:x>)".repeat(2);
+        let res = client.request(req("GET", "If", &cond)).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(
+            client.copy("/file", "/file2", true).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            client.copy("/file", "/file-with-a-long-name", true).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_after() {
+        use crate::testing::{DavClient, Depth};
+
+        let fs = MemFs::new();
+        let plain = DavClient::new(DavHandler::builder().filesystem(fs.clone()).build_handler());
+        assert_eq!(plain.put_bytes("/file", "x").await, StatusCode::CREATED);
+
+        // two outages of the backend, the first comes with a hint.
+        let unavailable =
+            FsError::other("backend unavailable").with_status_hint(StatusCode::SERVICE_UNAVAILABLE);
+        let hint = unavailable.clone().with_retry_after(Duration::from_secs(7));
+        let flaky = MockFs::builder()
+            .filesystem(fs)
+            .fail_times(MockOp::Metadata, "*", hint, 1)
+            .fail_times(MockOp::Metadata, "*", unavailable, 1)
+            .build();
+        let dav = DavHandler::builder()
+            .filesystem(flaky)
+            .retry_after(Duration::from_secs(30))
+            .build_handler();
+        let client = DavClient::new(dav);
+
+        // a plain 503 instead of a multistatus, with the hint of the filesystem.
+        let req = Request::builder()
+            .method("PROPFIND")
+            .uri("/file")
+            .header("Depth", "0")
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()["retry-after"], "7");
+        assert!(res.body().is_empty());
+
+        // or the default.
+        let res = client.get("/file").await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()["retry-after"], "30");
+
+        // and then it is back.
+        let res = client.get("/file").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key("retry-after"));
+        let ms = client.propfind("/file", Depth::Zero, &[]).await.unwrap();
+        assert_eq!(ms.hrefs(), vec!["/file"]);
+    }
+
+    #[tokio::test]
+    async fn test_windows_name_safety() {
+        use crate::testing::DavClient;
+
+        let client = DavClient::new(
+            DavHandler::builder()
+                .filesystem(MemFs::new())
+                .windows_name_safety(true)
+                .build_handler(),
+        );
+        for name in ["/CON", "/foo.", "/foo%20"] {
+            let res = client
+                .request(Request::put(name).body(Body::from("x")).unwrap())
+                .await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "PUT {}", name);
+            let body = String::from_utf8_lossy(res.body()).to_string();
+            assert!(body.contains("on Windows"), "{}", body);
+            assert_eq!(
+                client.mkcol(name).await,
+                StatusCode::FORBIDDEN,
+                "MKCOL {}",
+                name
+            );
+        }
+        assert_eq!(
+            client.put_bytes("/con.txt", "x").await,
+            StatusCode::FORBIDDEN
+        );
+
+        assert_eq!(client.put_bytes("/file", "x").await, StatusCode::CREATED);
+        assert_eq!(
+            client.move_("/file", "/NUL", false).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            client.copy("/file", "/foo.", false).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            client.move_("/file", "/file2", false).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(client.mkcol("/CONFIG").await, StatusCode::CREATED);
+
+        // off by default.
+        let client = DavClient::new(
+            DavHandler::builder()
+                .filesystem(MemFs::new())
+                .build_handler(),
+        );
+        assert_eq!(client.put_bytes("/CON", "x").await, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        use crate::memls::MemLs;
+        use crate::testing::{DavClient, Depth};
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        let client = DavClient::new(dav.clone());
+        assert_eq!(
+            client.put_bytes("/file", "hello").await,
+            StatusCode::CREATED
+        );
+        assert_eq!(client.mkcol("/dir").await, StatusCode::CREATED);
+
+        let validate = |method: &str, path: &str, headers: &[(&str, &str)]| {
+            let mut req = Request::builder().method(method).uri(path);
+            for (name, value) in headers {
+                req = req.header(*name, *value);
+            }
+            let req = req.body(()).unwrap();
+            let dav = dav.clone();
+            async move { dav.validate(&req).await.map_err(|e| e.status()) }
+        };
+        let dest = [("Destination", "http://localhost/moved")];
+
+        assert_eq!(validate("PUT", "/new", &[]).await, Ok(()));
+        assert_eq!(
+            validate("PUT", "/file", &[("If-None-Match", "*")]).await,
+            Err(StatusCode::PRECONDITION_FAILED)
+        );
+        assert_eq!(
+            validate("PUT", "/nodir/new", &[]).await,
+            Err(StatusCode::CONFLICT)
+        );
+        assert_eq!(validate("MKCOL", "/newdir", &[]).await, Ok(()));
+        assert_eq!(
+            validate("MKCOL", "/dir", &[]).await,
+            Err(StatusCode::METHOD_NOT_ALLOWED)
+        );
+        assert_eq!(validate("DELETE", "/file", &[]).await, Ok(()));
+        assert_eq!(
+            validate("DELETE", "/missing", &[]).await,
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(validate("MOVE", "/file", &dest).await, Ok(()));
+        assert_eq!(
+            validate("MOVE", "/file", &[("If-Match", "\"nope\""), dest[0]]).await,
+            Err(StatusCode::PRECONDITION_FAILED)
+        );
+        assert_eq!(
+            validate("COPY", "/dir", &[]).await,
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(validate("PROPPATCH", "/file", &[]).await, Ok(()));
+        assert_eq!(
+            validate("LOCK", "/file", &[]).await,
+            Err(StatusCode::NOT_IMPLEMENTED)
+        );
+
+        // nothing was changed.
+        assert_eq!(client.get("/new").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(client.get("/newdir/").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(client.get("/moved").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(client.get("/file").await.body().as_ref(), b"hello");
+
+        // locks are checked, and not removed by a validated UNLOCK.
+        let token = client.lock("/file", Depth::Zero).await.unwrap();
+        let lock_token = format!("<{}>", token);
+        let if_token = format!("(<{}>)", token);
+        assert_eq!(
+            validate("DELETE", "/file", &[]).await,
+            Err(StatusCode::LOCKED)
+        );
+        assert_eq!(
+            validate("DELETE", "/file", &[("If", &if_token)]).await,
+            Ok(())
+        );
+        assert_eq!(
+            validate("UNLOCK", "/file", &[("Lock-Token", "<urn:uuid:nope>")]).await,
+            Err(StatusCode::CONFLICT)
+        );
+        assert_eq!(
+            validate("UNLOCK", "/file", &[("Lock-Token", &lock_token)]).await,
+            Ok(())
+        );
+        assert_eq!(validate("PUT", "/file", &[]).await, Err(StatusCode::LOCKED));
+        assert_eq!(client.unlock("/file", &token).await, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_validate_prepares_request() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let c = calls.clone();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .client_quirks(move |_req, quirks| {
+                c.fetch_add(1, Ordering::SeqCst);
+                quirks
+            })
+            .build_handler();
+        let req = Request::put("/new").body(()).unwrap();
+        assert!(dav.validate(&req).await.is_ok());
+        let req = Request::options("/").body(Body::empty()).unwrap();
+        assert!(dav.try_handle(req).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // a required principal is checked too.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .require_principal("Basic realm=\"dav\"")
+            .build_handler();
+        let req = Request::put("/new").body(()).unwrap();
+        let err = dav.validate(&req).await.unwrap_err();
+        assert_eq!(err.statuscode(), StatusCode::UNAUTHORIZED);
+        let req = Request::get("/").body(Body::empty()).unwrap();
+        let res = dav.try_handle(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_handle_is_send() {
+        fn assert_send<T: Send>(_: T) {}
+        // Body is Send but not Sync, so it must not be borrowed across an await.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .debug_conditions(true)
+            .build_handler();
+        let req = Request::get("/").body(Body::empty()).unwrap();
+        assert_send(dav.handle(req));
+    }
+
+    #[tokio::test]
+    async fn test_debug_conditions() {
+        let put = |cond: Option<&str>| {
+            let mut req = Request::put("/file");
+            if let Some(cond) = cond {
+                req = req.header("If", cond);
+            }
+            req.body(Body::from("x")).unwrap()
+        };
+        let debug = |res: &Response<Body>| {
+            res.headers()
+                .get("x-dav-cond-debug")
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let res = dav.handle(put(Some("(<urn:uuid:x>)"))).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(debug(&res), None);
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .debug_conditions(true)
+            .build_handler();
+        let res = dav.handle(put(None)).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(debug(&res), None);
+        let res = dav
+            .handle(put(Some("(<urn:uuid:x>) (Not <DAV:no-lock>)")))
+            .await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let s = "matched=1; list0=fail(no-locksystem); list1=ok(!no-lock)";
+        assert_eq!(debug(&res).as_deref(), Some(s));
+        let res = dav.handle(put(Some("([\"nope\"])"))).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        let s = "matched=none; list0=fail(etag-mismatch)";
+        assert_eq!(debug(&res).as_deref(), Some(s));
+
+        // it shows what the handler saw: not a header that was stripped,
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .debug_conditions(true)
+            .strip_request_headers(vec![HeaderName::from_static("if")])
+            .build_handler();
+        let res = dav.handle(put(Some("(<urn:uuid:x>)"))).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(debug(&res), None);
+
+        // and nothing if the request failed before the If header was looked at.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .debug_conditions(true)
+            .build_handler();
+        let req = Request::delete("/missing")
+            .header("If", "(<urn:uuid:x>)")
+            .body(Body::empty())
+            .unwrap();
+        let res = dav.handle(req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(debug(&res), None);
+    }
+
+    #[tokio::test]
+    async fn test_ms_nocache_404() {
+        let ms_ua = "Microsoft-WebDAV-MiniRedir/10.0.19043";
+        let head = |dav: DavHandler, ua: &'static str| async move {
+            let req = Request::head("/missing")
+                .header("User-Agent", ua)
+                .body(Body::empty())
+                .unwrap();
+            dav.handle(req).await
         };
-        res
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let res = head(dav.clone(), ms_ua).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()["pragma"], "no-cache");
+        assert_eq!(res.headers()["progma"], "no-cache");
+        assert_eq!(res.headers()["expires"], "0");
+        let res = head(dav, "curl/8.0").await;
+        assert!(res.headers().get("pragma").is_none());
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .ms_nocache_404(false)
+            .build_handler();
+        let res = head(dav, ms_ua).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(res.headers().get("pragma").is_none());
+        assert!(res.headers().get("cache-control").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_client_quirks() {
+        use crate::testing::DavClient;
+
+        let ms_ua = "Microsoft-WebDAV-MiniRedir/10.0.19043";
+        async fn allprop(client: &DavClient, ua: &str) -> String {
+            let req = Request::builder()
+                .method("PROPFIND")
+                .uri("/file")
+                .header("Depth", "0")
+                .header("User-Agent", ua)
+                .body(Body::empty())
+                .unwrap();
+            let res = client.request(req).await;
+            assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+            String::from_utf8(res.body().to_vec()).unwrap()
+        }
+
+        // the defaults.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/file", "x").await, StatusCode::CREATED);
+        assert!(allprop(&client, ms_ua)
+            .await
+            .contains("Win32FileAttributes"));
+        assert!(!allprop(&client, "curl/8.0")
+            .await
+            .contains("Win32FileAttributes"));
+
+        // treat curl like Windows, and Windows like curl.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .client_quirks(|req, quirks| {
+                let ua = req.headers().get("user-agent");
+                if ua.is_some_and(|ua| ua.as_bytes().starts_with(b"curl/")) {
+                    ClientQuirks::from_user_agent("Microsoft")
+                } else {
+                    ClientQuirks {
+                        ms_404_nocache: false,
+                        ms_propfind_allprop_extras: false,
+                        ..quirks
+                    }
+                }
+            })
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/file", "x").await, StatusCode::CREATED);
+        assert!(!allprop(&client, ms_ua)
+            .await
+            .contains("Win32FileAttributes"));
+        assert!(allprop(&client, "curl/8.0")
+            .await
+            .contains("Win32FileAttributes"));
+
+        let head = |ua: &'static str| {
+            let req = Request::head("/missing")
+                .header("User-Agent", ua)
+                .body(Body::empty())
+                .unwrap();
+            client.request(req)
+        };
+        let res = head("curl/8.0").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()["pragma"], "no-cache");
+        let res = head(ms_ua).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(res.headers().get("pragma").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_html_errors() {
+        use crate::testing::DavClient;
+
+        let browser = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .html_errors(true)
+            .build_handler();
+        let client = DavClient::new(dav);
+        let request = |method: &str, path: &str, accept: Option<&str>| {
+            let mut req = Request::builder()
+                .method(method)
+                .uri(path)
+                .header("User-Agent", "Microsoft-WebDAV-MiniRedir/10.0.19043");
+            if let Some(accept) = accept {
+                req = req.header("Accept", accept);
+            }
+            client.request(req.body(Body::empty()).unwrap())
+        };
+
+        // a browser gets a page, with the path escaped.
+        let res = request("GET", "/a/%3Cscript%3E", Some(browser)).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()["content-type"], "text/html; charset=utf-8");
+        let len = res.headers()["content-length"].to_str().unwrap();
+        assert_eq!(len, res.body().len().to_string());
+        let page = std::str::from_utf8(res.body()).unwrap();
+        assert!(page.contains("<title>404 Not Found</title>"));
+        assert!(page.contains("/a/&lt;script&gt;"));
+        assert!(!page.contains("<script>"));
+        assert!(page.contains("<a href=\"/a/\">"));
+        assert_eq!(res.headers()["pragma"], "no-cache");
+
+        let res = request("HEAD", "/missing", Some(browser)).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_ne!(res.headers()["content-length"], "0");
+        assert!(res.body().is_empty());
+
+        // WebDAV clients, and other methods, get what they always got.
+        for (method, accept) in [
+            ("GET", None),
+            ("GET", Some("*/*")),
+            ("GET", Some("application/xml, text/html;q=0.5")),
+            ("DELETE", Some(browser)),
+        ] {
+            let res = request(method, "/missing", accept).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+            assert_eq!(res.headers()["content-length"], "0");
+            assert!(res.body().is_empty());
+            assert_eq!(res.headers()["pragma"], "no-cache");
+        }
+
+        // off by default.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let req = Request::get("/missing")
+            .header("Accept", browser)
+            .body(Body::empty())
+            .unwrap();
+        let res = DavClient::new(dav).request(req).await;
+        assert!(res.body().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        use crate::testing::{DavClient, Depth};
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        assert_eq!(
+            client.put_bytes("/dir/file.txt?v=1", "hello").await,
+            StatusCode::CREATED
+        );
+
+        let res = client.get("/dir/file.txt?v=123").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body().as_ref(), b"hello");
+
+        // the query does not end up in the hrefs of the members.
+        let ms = client.propfind("/dir/?x=y", Depth::One, &[]).await.unwrap();
+        assert_eq!(ms.hrefs(), vec!["/dir/", "/dir/file.txt"]);
+
+        let uri = "/dir/file.txt?v=123&sig=abc".parse().unwrap();
+        let path = DavPath::from_uri_and_prefix(&uri, "").unwrap();
+        assert_eq!(path.query(), Some("v=123&sig=abc"));
+        assert_eq!(path, DavPath::new("/dir/file.txt").unwrap());
+        assert_eq!(path.parent().query(), None);
+    }
+
+    #[tokio::test]
+    async fn test_lock_sweep_interval() {
+        use crate::ls::{DavLock, DavLockSystem};
+        use tokio::sync::Notify;
+
+        // Only counts the sweeps, the other methods are never called.
+        #[derive(Debug, Clone)]
+        struct SweepLs(Arc<Notify>);
+
+        impl DavLockSystem for SweepLs {
+            fn lock(
+                &self,
+                _: &DavPath,
+                _: Option<&str>,
+                _: Option<&xmltree::Element>,
+                _: Option<Duration>,
+                _: bool,
+                _: bool,
+            ) -> Result<DavLock, DavLock> {
+                unreachable!()
+            }
+            fn unlock(&self, _: &DavPath, _: &str) -> Result<(), ()> {
+                unreachable!()
+            }
+            fn refresh(&self, _: &DavPath, _: &str, _: Option<Duration>) -> Result<DavLock, ()> {
+                unreachable!()
+            }
+            fn check(
+                &self,
+                _: &DavPath,
+                _: Option<&str>,
+                _: bool,
+                _: bool,
+                _: Vec<&str>,
+            ) -> Result<(), DavLock> {
+                unreachable!()
+            }
+            fn discover(&self, _: &DavPath) -> Vec<DavLock> {
+                unreachable!()
+            }
+            fn delete(&self, _: &DavPath) -> Result<(), ()> {
+                unreachable!()
+            }
+            fn sweep(&self) {
+                self.0.notify_one();
+            }
+        }
+
+        let swept = Arc::new(Notify::new());
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(Box::new(SweepLs(swept.clone())))
+            .lock_sweep_interval(Duration::from_millis(1))
+            .build_handler();
+        for _ in 0..2 {
+            let sweep = tokio::time::timeout(Duration::from_secs(10), swept.notified());
+            assert!(sweep.await.is_ok(), "locksystem was not swept");
+        }
+        drop(dav);
+    }
+
+    #[tokio::test]
+    async fn test_precondition() {
+        use crate::testing::DavClient;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls2 = calls.clone();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .precondition(move |req, meta| {
+                calls2.fetch_add(1, Ordering::SeqCst);
+                let tag = req.headers().get("if-schedule-tag-match")?;
+                match meta {
+                    Some(m) if !m.is_dir() && tag == "\"1\"" => None,
+                    _ => Some(StatusCode::PRECONDITION_FAILED),
+                }
+            })
+            .build_handler();
+        let client = DavClient::new(dav);
+        let send = |method: &str, tag: Option<&str>, other: Option<(&str, &str)>| {
+            let mut req = Request::builder().method(method).uri("/file");
+            if let Some(tag) = tag {
+                req = req.header("If-Schedule-Tag-Match", tag);
+            }
+            if let Some((name, value)) = other {
+                req = req.header(name, value);
+            }
+            let body = if method == "PUT" { "x" } else { "" };
+            let req = req.body(Body::from(body)).unwrap();
+            let client = client.clone();
+            async move { client.request(req).await.status() }
+        };
+
+        // the resource must exist.
+        assert_eq!(
+            send("PUT", Some("\"1\""), None).await,
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(client.get("/file").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(send("PUT", None, None).await, StatusCode::CREATED);
+
+        assert_eq!(send("GET", Some("\"1\""), None).await, StatusCode::OK);
+        assert_eq!(
+            send("GET", Some("\"2\""), None).await,
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(
+            send("PUT", Some("\"2\""), None).await,
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(
+            send("DELETE", Some("\"2\""), None).await,
+            StatusCode::PRECONDITION_FAILED
+        );
+
+        // the built-in headers are evaluated first.
+        calls.store(0, Ordering::SeqCst);
+        let inm = Some(("If-None-Match", "*"));
+        assert_eq!(
+            send("PUT", Some("\"1\""), inm).await,
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            send("DELETE", Some("\"1\""), None).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_parent() {
+        use crate::testing::{DavClient, Depth};
+
+        let memfs = MemFs::new();
+        let setup = DavClient::new(
+            DavHandler::builder()
+                .filesystem(memfs.clone())
+                .build_handler(),
+        );
+        assert_eq!(setup.mkcol("/shared/").await, StatusCode::CREATED);
+        assert_eq!(setup.mkcol("/shared/team/").await, StatusCode::CREATED);
+        let file = "/shared/team/file.txt";
+        assert_eq!(setup.put_bytes(file, "hello").await, StatusCode::CREATED);
+
+        // the metadata of "/shared/" itself is forbidden.
+        let fs = MockFs::builder()
+            .filesystem(memfs)
+            .fail(MockOp::Metadata, "/shared", FsError::Forbidden)
+            .fail(MockOp::ReadDir, "/shared", FsError::Forbidden)
+            .build();
+        let client = DavClient::new(DavHandler::builder().filesystem(fs).build_handler());
+        assert_eq!(client.get("/shared/").await.status(), StatusCode::FORBIDDEN);
+
+        assert_eq!(client.get(file).await.body().as_ref(), b"hello");
+        let ms = client.propfind(file, Depth::Zero, &[]).await.unwrap();
+        assert_eq!(ms.hrefs(), vec![file]);
+        let ms = client.propfind("/shared/team/", Depth::One, &[]).await;
+        assert_eq!(ms.unwrap().hrefs().len(), 2);
+        assert_eq!(client.put_bytes(file, "bye").await, StatusCode::NO_CONTENT);
+        assert_eq!(client.get(file).await.body().as_ref(), b"bye");
+
+        let xml = concat!(
+            r#"<D:propertyupdate xmlns:D="DAV:" xmlns:X="urn:x">"#,
+            r#"<D:set><D:prop><X:color>red</X:color></D:prop></D:set>"#,
+            r#"</D:propertyupdate>"#,
+        );
+        let req = Request::builder().method("PROPPATCH").uri(file);
+        let res = client.request(req.body(Body::from(xml)).unwrap()).await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let ms = client.propfind(file, Depth::Zero, &["{urn:x}color"]).await;
+        assert_eq!(
+            ms.unwrap().responses[0]
+                .prop_text("{urn:x}color")
+                .as_deref(),
+            Some("red")
+        );
+
+        // a new member of the readable collection.
+        let copy = "/shared/team/copy.txt";
+        assert_eq!(client.copy(file, copy, false).await, StatusCode::CREATED);
+        assert_eq!(client.copy(file, copy, true).await, StatusCode::NO_CONTENT);
+
+        // a missing parent is still a conflict, an unreadable one is forbidden.
+        assert_eq!(
+            client.copy(file, "/nodir/x", false).await,
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            client.copy(file, "/shared/x", false).await,
+            StatusCode::FORBIDDEN
+        );
     }
 }
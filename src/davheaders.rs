@@ -5,12 +5,10 @@ use std::str::FromStr;
 use headers::Header;
 use http::header::{HeaderName, HeaderValue};
 use lazy_static::lazy_static;
-use regex::Regex;
 
 use crate::fs::DavMetaData;
 
 lazy_static! {
-    static ref RE_URL: Regex = Regex::new(r"https?://[^/]*([^#?]+).*$").unwrap();
     pub static ref DEPTH: HeaderName = HeaderName::from_static("depth");
     pub static ref TIMEOUT: HeaderName = HeaderName::from_static("timeout");
     pub static ref OVERWRITE: HeaderName = HeaderName::from_static("overwrite");
@@ -22,6 +20,8 @@ lazy_static! {
     pub static ref X_UPDATE_RANGE: HeaderName = HeaderName::from_static("x-update-range");
     pub static ref IF: HeaderName = HeaderName::from_static("if");
     pub static ref CONTENT_LANGUAGE: HeaderName = HeaderName::from_static("content-language");
+    pub static ref APPLY_TO_REDIRECT_REF: HeaderName =
+        HeaderName::from_static("apply-to-redirect-ref");
 }
 
 // helper.
@@ -237,6 +237,9 @@ impl Header for Timeout {
     }
 }
 
+// The URL path of the Destination header. It is still percent-encoded,
+// like the path of the request URI, so that both are decoded by DavPath.
+// That drops a query. A fragment is left for the COPY / MOVE handler.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Destination(pub String);
 
@@ -249,16 +252,32 @@ impl Header for Destination {
     where
         I: Iterator<Item = &'i HeaderValue>,
     {
-        let s = one(values)?.to_str().map_err(map_invalid)?;
-        if s.starts_with('/') {
-            return Ok(Destination(s.to_string()));
-        }
-        if let Some(caps) = RE_URL.captures(s) {
-            if let Some(path) = caps.get(1) {
-                return Ok(Destination(path.as_str().to_string()));
+        // Some clients send raw UTF-8 (an IRI). Encode every byte that
+        // is not printable ASCII, so that it decodes to itself.
+        let mut s = String::new();
+        for &b in one(values)?.as_bytes() {
+            if (32..=126).contains(&b) {
+                s.push(b as char);
+            } else {
+                s.push_str(&format!("%{:02X}", b));
             }
         }
-        Err(invalid())
+
+        // an absolute path, or an absolute http(s) URI.
+        let path = if s.starts_with('/') {
+            s
+        } else {
+            let (scheme, rest) = s.split_once("://").ok_or_else(invalid)?;
+            if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+                return Err(invalid());
+            }
+            match rest.find(['/', '?', '#']) {
+                Some(pos) if rest[pos..].starts_with('/') => rest[pos..].to_string(),
+                Some(pos) => format!("/{}", &rest[pos..]),
+                None => "/".to_string(),
+            }
+        };
+        Ok(Destination(path))
     }
 
     fn encode<E>(&self, values: &mut E)
@@ -283,8 +302,41 @@ impl Header for Overwrite {
     {
         let line = one(values)?;
         match line.as_bytes() {
-            b"F" => Ok(Overwrite(false)),
-            b"T" => Ok(Overwrite(true)),
+            b"F" | b"f" => Ok(Overwrite(false)),
+            b"T" | b"t" => Ok(Overwrite(true)),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let value = match self.0 {
+            true => "T",
+            false => "F",
+        };
+        values.extend(std::iter::once(HeaderValue::from_static(value)));
+    }
+}
+
+// RFC 4437
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApplyToRedirectRef(pub bool);
+
+impl Header for ApplyToRedirectRef {
+    fn name() -> &'static HeaderName {
+        &APPLY_TO_REDIRECT_REF
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let line = one(values)?;
+        match line.as_bytes() {
+            b"F" => Ok(ApplyToRedirectRef(false)),
+            b"T" => Ok(ApplyToRedirectRef(true)),
             _ => Err(invalid()),
         }
     }
@@ -308,32 +360,55 @@ pub struct ETag {
 }
 
 impl ETag {
-    #[allow(dead_code)]
     pub fn new(weak: bool, t: impl Into<String>) -> Result<ETag, headers::Error> {
         let t = t.into();
         if t.contains('\"') {
             Err(invalid())
         } else {
-            let w = if weak { "W/" } else { "" };
-            Ok(ETag {
-                tag: format!("{}\"{}\"", w, t),
-                weak,
-            })
+            let mut tag = String::with_capacity(t.len() + 4);
+            if weak {
+                tag.push_str("W/");
+            }
+            tag.push('"');
+            tag.push_str(&t);
+            tag.push('"');
+            Ok(ETag { tag, weak })
         }
     }
 
-    pub fn from_meta(meta: impl AsRef<dyn DavMetaData>) -> Option<ETag> {
-        let tag = meta.as_ref().etag()?;
-        Some(ETag {
-            tag: format!("\"{}\"", tag),
-            weak: false,
-        })
+    /// The ETag of a resource, as `DavMetaData::etag` and
+    /// `DavMetaData::etag_is_weak` report it. `None` if it has none,
+    /// or if it is not a valid entity-tag.
+    pub fn from_meta(meta: &dyn DavMetaData) -> Option<ETag> {
+        let tag = meta.etag()?;
+        if tag.ends_with('"') {
+            return tag.parse().ok();
+        }
+        ETag::new(meta.etag_is_weak(), tag).ok()
     }
 
     #[allow(dead_code)]
     pub fn is_weak(&self) -> bool {
         self.weak
     }
+
+    /// The entity-tag as it appears in a header, including quotes and the `W/` prefix.
+    pub fn as_str(&self) -> &str {
+        &self.tag
+    }
+
+    /// Weak comparison (RFC7232 2.3.2): the opaque tags are equal,
+    /// whether or not either or both of them are weak.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        let strip = |t: &ETag| {
+            if t.weak {
+                t.tag[2..].to_string()
+            } else {
+                t.tag.clone()
+            }
+        };
+        strip(self) == strip(other)
+    }
 }
 
 impl FromStr for ETag {
@@ -598,6 +673,89 @@ impl Header for XUpdateRange {
     }
 }
 
+/// Why a `Range` header can not be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeError {
+    /// Not a valid `bytes=` range set. The header should be ignored.
+    Invalid,
+    /// Valid, but none of the ranges overlap the file. Answer with a 416.
+    Unsatisfiable,
+}
+
+// parse a number, digits only.
+fn parse_u64(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Parse the value of a `Range` header for a file of `len` bytes (RFC 9110 14.1.2).
+///
+/// Returns the satisfiable ranges as `(first, last)` byte positions
+/// (inclusive), sorted, with overlapping and adjacent ranges merged.
+/// Ranges that start beyond the end of the file are left out, ranges
+/// that end beyond it are cut short.
+pub fn parse_ranges(header: &str, len: u64) -> Result<Vec<(u64, u64)>, RangeError> {
+    let mut parts = header.splitn(2, '=');
+    let unit = parts.next().unwrap_or_default().trim();
+    let set = match parts.next() {
+        Some(set) if unit.eq_ignore_ascii_case("bytes") => set,
+        _ => return Err(RangeError::Invalid),
+    };
+
+    let mut ranges = Vec::new();
+    let mut specs = 0;
+    for spec in set.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        specs += 1;
+        let (first, last) = match spec.find('-') {
+            Some(idx) => (&spec[..idx], &spec[idx + 1..]),
+            None => return Err(RangeError::Invalid),
+        };
+        let range = if first.is_empty() {
+            // suffix: the last `n` bytes.
+            let n = parse_u64(last).ok_or(RangeError::Invalid)?;
+            if n > 0 && len > 0 {
+                Some((len - n.min(len), len - 1))
+            } else {
+                None
+            }
+        } else {
+            let first = parse_u64(first).ok_or(RangeError::Invalid)?;
+            let last = if last.is_empty() {
+                u64::MAX
+            } else {
+                parse_u64(last).ok_or(RangeError::Invalid)?
+            };
+            if last < first {
+                return Err(RangeError::Invalid);
+            }
+            if first < len {
+                Some((first, last.min(len - 1)))
+            } else {
+                None
+            }
+        };
+        ranges.extend(range);
+    }
+    if specs == 0 {
+        return Err(RangeError::Invalid);
+    }
+    if ranges.is_empty() {
+        return Err(RangeError::Unsatisfiable);
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some(prev) if first <= prev.1.saturating_add(1) => prev.1 = prev.1.max(last),
+            _ => merged.push((first, last)),
+        }
+    }
+    Ok(merged)
+}
+
 // The "If" header contains IfLists, of which the results are ORed.
 #[derive(Debug, Clone, PartialEq)]
 pub struct If(pub Vec<IfList>);
@@ -740,12 +898,29 @@ impl Header for If {
     where
         I: Iterator<Item = &'i HeaderValue>,
     {
+        If::decode_max(one(values)?, usize::MAX)?.ok_or_else(invalid)
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let value = "[If header]";
+        values.extend(std::iter::once(HeaderValue::from_static(value)));
+    }
+}
+
+impl If {
+    /// Like `decode`, but stop parsing as soon as there are more than
+    /// `max` conditions in all lists together, and return `Ok(None)`.
+    pub fn decode_max(value: &HeaderValue, max: usize) -> Result<Option<If>, headers::Error> {
         // one big state machine.
         let mut if_lists = If(Vec::new());
         let mut cur_list = IfList::new();
+        let mut count = 0;
 
         let mut state = IfState::Start;
-        let mut input = one(values)?.as_bytes();
+        let mut input = value.as_bytes();
 
         loop {
             let (tok, rest) = get_token(input)?;
@@ -754,8 +929,14 @@ impl Header for If {
                 IfState::Start => match tok {
                     IfToken::ListOpen => IfState::List,
                     IfToken::Pointy(url) => {
-                        let u = url::Url::parse(&url).map_err(map_invalid)?;
-                        cur_list.resource_tag = Some(u);
+                        // an absolute URI, or an absolute path (RFC4918 10.4.2).
+                        let u = if url.starts_with('/') {
+                            let base = url::Url::parse("http://localhost/").unwrap();
+                            base.join(&url)
+                        } else {
+                            url::Url::parse(&url)
+                        };
+                        cur_list.resource_tag = Some(u.map_err(map_invalid)?);
                         IfState::RTag
                     }
                     IfToken::End => {
@@ -786,11 +967,19 @@ impl Header for If {
                             if !stok.contains(':') {
                                 IfState::Bad
                             } else {
+                                count += 1;
+                                if count > max {
+                                    return Ok(None);
+                                }
                                 cur_list.add(not, IfItem::StateToken(stok));
                                 IfState::List
                             }
                         }
                         IfToken::ETag(etag) => {
+                            count += 1;
+                            if count > max {
+                                return Ok(None);
+                            }
                             cur_list.add(not, IfItem::ETag(etag));
                             IfState::List
                         }
@@ -809,15 +998,7 @@ impl Header for If {
                 IfState::Bad => return Err(invalid()),
             };
         }
-        Ok(if_lists)
-    }
-
-    fn encode<E>(&self, values: &mut E)
-    where
-        E: Extend<HeaderValue>,
-    {
-        let value = "[If header]";
-        values.extend(std::iter::once(HeaderValue::from_static(value)));
+        Ok(Some(if_lists))
     }
 }
 
@@ -838,6 +1019,95 @@ mod tests {
         let mut iter = std::iter::once(&hdrval);
         let hdr = If::decode(&mut iter);
         assert!(hdr.is_ok());
+
+        // the same header has 5 conditions.
+        assert!(matches!(If::decode_max(&hdrval, 5), Ok(Some(_))));
+        assert!(matches!(If::decode_max(&hdrval, 4), Ok(None)));
+
+        // a resource tag can be an absolute path.
+        let hdrval = HeaderValue::from_static("</a/b> ([\"etag\"])");
+        let hdr = If::decode(&mut std::iter::once(&hdrval)).unwrap();
+        assert_eq!(hdr.0[0].resource_tag.as_ref().unwrap().path(), "/a/b");
+    }
+
+    #[test]
+    fn destination_header() {
+        use crate::davpath::DavPath;
+
+        // the value, and the path that it resolves to.
+        let table: &[(&[u8], Option<&str>)] = &[
+            (b"/a/b.txt", Some("/a/b.txt")),
+            (b"http://localhost/a/b.txt", Some("/a/b.txt")),
+            (b"HTTPS://example.com:8080/a/", Some("/a/")),
+            (b"http://localhost", Some("/")),
+            (b"http://localhost?x=1", Some("/")),
+            (b"/a%20b.txt", Some("/a b.txt")),
+            (b"/a b.txt", Some("/a b.txt")),
+            (b"/%C3%A4%20b.txt", Some("/\u{e4} b.txt")),
+            ("/\u{e4} b.txt".as_bytes(), Some("/\u{e4} b.txt")),
+            (
+                "http://localhost/d\u{ed}r/\u{e4}".as_bytes(),
+                Some("/d\u{ed}r/\u{e4}"),
+            ),
+            // decoded once, so this is a literal "%20".
+            (b"/a%2520b", Some("/a%20b")),
+            (b"/a/./b/../c", Some("/a/c")),
+            (b"//a//b", Some("/a/b")),
+            (b"/a/b?x=1", Some("/a/b")),
+            (b"/a/b#frag", None),
+            (b"http://localhost/a#frag", None),
+            (b"http://localhost#frag", None),
+            (b"ftp://localhost/a", None),
+            (b"a/b", None),
+            (b"/a%2fb", None),
+        ];
+        for (value, expected) in table {
+            let hdrval = HeaderValue::from_bytes(value).unwrap();
+            let path = Destination::decode(&mut std::iter::once(&hdrval))
+                .ok()
+                .and_then(|d| DavPath::new(&d.0).ok());
+            let path = path.map(|p| String::from_utf8(p.as_bytes().to_vec()).unwrap());
+            assert_eq!(path.as_deref(), *expected, "{:?}", hdrval);
+        }
+    }
+
+    #[test]
+    fn range_header() {
+        let r = |s: &str| parse_ranges(s, 1000);
+        assert_eq!(r("bytes=0-99"), Ok(vec![(0, 99)]));
+        assert_eq!(r("Bytes= 0-0 , -1"), Ok(vec![(0, 0), (999, 999)]));
+        // open ended and suffix ranges.
+        assert_eq!(r("bytes=900-"), Ok(vec![(900, 999)]));
+        assert_eq!(r("bytes=-100"), Ok(vec![(900, 999)]));
+        assert_eq!(r("bytes=-5000"), Ok(vec![(0, 999)]));
+        // cut short, or left out if they start beyond the end.
+        assert_eq!(r("bytes=990-2000"), Ok(vec![(990, 999)]));
+        assert_eq!(r("bytes=0-9,1000-1100"), Ok(vec![(0, 9)]));
+        // overlapping and adjacent ranges are merged, and sorted.
+        assert_eq!(r("bytes=50-99,0-49"), Ok(vec![(0, 99)]));
+        assert_eq!(r("bytes=0-10,5-20,30-40"), Ok(vec![(0, 20), (30, 40)]));
+        assert_eq!(r("bytes=500-,-600"), Ok(vec![(400, 999)]));
+        // unsatisfiable.
+        assert_eq!(r("bytes=1000-"), Err(RangeError::Unsatisfiable));
+        assert_eq!(r("bytes=-0"), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse_ranges("bytes=0-", 0), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse_ranges("bytes=-1", 0), Err(RangeError::Unsatisfiable));
+        // malformed.
+        for s in [
+            "bytes=10-5",
+            "bytes=",
+            "bytes=,",
+            "bytes=5",
+            "bytes=-",
+            "bytes=a-b",
+            "bytes=+1-2",
+            "bytes=0-1,x",
+            "bytes=99999999999999999999-",
+            "items=0-1",
+            "0-1",
+        ] {
+            assert_eq!(r(s), Err(RangeError::Invalid), "{}", s);
+        }
     }
 
     #[test]
@@ -849,5 +1119,9 @@ mod tests {
         assert!(t1 != t2);
         assert!(t2 != t3);
         assert!(t3 == t4);
+        assert!(t1.weak_eq(&t2));
+        assert!(!t2.weak_eq(&t3));
+        let t5 = ETag::from_str(r#""12345""#).unwrap();
+        assert!(t5.weak_eq(&t1));
     }
 }
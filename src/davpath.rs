@@ -1,5 +1,6 @@
 //! Utility module to handle the path part of an URL as a filesytem path.
 //!
+use std::borrow::Cow;
 use std::error::Error;
 use std::ffi::OsStr;
 #[cfg(target_os = "windows")]
@@ -27,6 +28,7 @@ const PATH_ENCODE_SET: &pct::AsciiSet = &pct::NON_ALPHANUMERIC
 pub struct DavPath {
     fullpath: Vec<u8>,
     pfxlen: Option<usize>,
+    query: Option<String>,
 }
 
 /// Reference to DavPath, no prefix.
@@ -90,10 +92,17 @@ fn encode_path(src: &[u8]) -> Vec<u8> {
         .into_bytes()
 }
 
+// the query part of a raw path (everything after ?, up to a fragment).
+fn split_query(rp: &str) -> Option<String> {
+    let query = &rp[rp.find('?')? + 1..];
+    let query = query.split('#').next().unwrap_or_default();
+    Some(query.to_string())
+}
+
 // make path safe:
 // - raw path before decoding can contain only printable ascii
 // - make sure path is absolute
-// - remove query part (everything after ?)
+// - remove query part (everything after ?), see split_query()
 // - merge consecutive slashes
 // - decode percent encoded bytes, fail on invalid encodings.
 // - process . and ..
@@ -118,37 +127,34 @@ fn normalize_path(rp: &[u8]) -> Result<Vec<u8>, ParseError> {
         return Err(ParseError::InvalidPath);
     }
 
-    // split up in segments
+    // decode the segments one by one, straight into the result.
     let isdir = matches!(rawpath.last(), Some(x) if *x == b'/');
-    let segments: Vec<Vec<u8>> = rawpath
-        .split(|c| *c == b'/')
-        .map(|segment| pct::percent_decode(segment).collect())
-        .collect();
-    let mut v: Vec<&[u8]> = Vec::new();
-    for segment in &segments {
+    let mut path = Vec::with_capacity(rawpath.len());
+    for segment in rawpath.split(|c| *c == b'/') {
+        let segment: Cow<[u8]> = pct::percent_decode(segment).into();
         match &segment[..] {
             b"." | b"" => {}
             b".." => {
-                if v.len() < 2 {
+                if path.is_empty() {
                     return Err(ParseError::ForbiddenPath);
                 }
-                v.pop();
-                v.pop();
+                let pos = path.iter().rposition(|&c| c == b'/').unwrap_or(0);
+                path.truncate(pos);
             }
             s => {
                 // a decoded segment can contain any value except '/' or '\0'
                 if s.iter().any(|x| *x == 0 || *x == b'/') {
                     return Err(ParseError::InvalidPath);
                 }
-                v.push(b"/");
-                v.push(s);
+                path.push(b'/');
+                path.extend_from_slice(s);
             }
         }
     }
-    if isdir || v.is_empty() {
-        v.push(b"/");
+    if isdir || path.is_empty() {
+        path.push(b'/');
     }
-    Ok(v.join(&b""[..]))
+    Ok(path)
 }
 
 /// Comparison ignores any trailing slash, so /foo == /foo/
@@ -169,10 +175,10 @@ impl PartialEq for DavPath {
 impl DavPath {
     /// from URL encoded path
     pub fn new(src: &str) -> Result<DavPath, ParseError> {
-        let path = normalize_path(src.as_bytes())?;
         Ok(DavPath {
-            fullpath: path.to_vec(),
+            fullpath: normalize_path(src.as_bytes())?,
             pfxlen: None,
+            query: split_query(src),
         })
     }
 
@@ -203,10 +209,10 @@ impl DavPath {
 
     /// from URL encoded path and non-encoded prefix.
     pub(crate) fn from_str_and_prefix(src: &str, prefix: &str) -> Result<DavPath, ParseError> {
-        let path = normalize_path(src.as_bytes())?;
         let mut davpath = DavPath {
-            fullpath: path.to_vec(),
+            fullpath: normalize_path(src.as_bytes())?,
             pfxlen: None,
+            query: split_query(src),
         };
         davpath.set_prefix(prefix)?;
         Ok(davpath)
@@ -221,8 +227,13 @@ impl DavPath {
             "*" => Ok(DavPath {
                 fullpath: b"*".to_vec(),
                 pfxlen: None,
+                query: None,
             }),
-            path if path.starts_with('/') => DavPath::from_str_and_prefix(path, prefix),
+            path if path.starts_with('/') => {
+                let mut davpath = DavPath::from_str_and_prefix(path, prefix)?;
+                davpath.query = uri.query().map(|q| q.to_string());
+                Ok(davpath)
+            }
             _ => Err(ParseError::InvalidPath),
         }
     }
@@ -232,6 +243,7 @@ impl DavPath {
         Ok(DavPath {
             fullpath: uri.path().as_bytes().to_vec(),
             pfxlen: None,
+            query: uri.query().map(|q| q.to_string()),
         })
     }
 
@@ -249,8 +261,22 @@ impl DavPath {
         }
     }
 
+    // Is `other` below this path. Compares whole segments, so "/dir"
+    // is not an ancestor of "/dir2".
+    pub(crate) fn is_ancestor_of(&self, other: &DavPath) -> bool {
+        let mut a = self.as_bytes();
+        if a.ends_with(b"/") {
+            a = &a[..a.len() - 1];
+        }
+        let b = other.as_bytes();
+        b.len() > a.len() + 1 && b.starts_with(a) && b[a.len()] == b'/'
+    }
+
     /// Add a segment to the end of the path.
+    ///
+    /// The query belongs to the original URL, so it is dropped.
     pub(crate) fn push_segment(&mut self, b: &[u8]) {
+        self.query = None;
         if !self.is_collection() {
             self.fullpath.push(b'/');
         }
@@ -280,6 +306,14 @@ impl DavPath {
         std::str::from_utf8(self.get_prefix()).unwrap()
     }
 
+    /// Return the query part of the URL (still URL encoded), if there was one.
+    ///
+    /// The query is never part of the filesystem path, and it is
+    /// ignored when comparing paths.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
     /// Return the parent directory.
     pub fn parent(&self) -> DavPath {
         let mut segs = self
@@ -295,6 +329,7 @@ impl DavPath {
         DavPath {
             pfxlen: self.pfxlen,
             fullpath: segs.join(&b'/').to_vec(),
+            query: None,
         }
     }
 }
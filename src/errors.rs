@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::io::{self, ErrorKind};
+use std::time::Duration;
 
 use http::StatusCode;
 
@@ -7,22 +8,44 @@ use crate::fs::FsError;
 
 pub(crate) type DavResult<T> = Result<T, DavError>;
 
+/// Why a request failed.
+///
+/// Returned by [`DavHandler::try_handle`](crate::DavHandler::try_handle).
 #[derive(Debug)]
-pub(crate) enum DavError {
-    XmlReadError,  // error reading/parsing xml
-    XmlParseError, // error interpreting xml
-    InvalidPath,   // error parsing path
-    IllegalPath,   // path not valid here
-    ForbiddenPath, // too many dotdots
+#[non_exhaustive]
+pub enum DavError {
+    /// Error reading the XML request body, or it is not well-formed XML.
+    XmlReadError,
+    /// The XML request body is well-formed, but it is not what was
+    /// expected, for example the wrong root element (422).
+    XmlParseError,
+    /// Error parsing the request path.
+    InvalidPath,
+    /// Path not valid here.
+    IllegalPath,
+    /// Path has too many dotdots.
+    ForbiddenPath,
+    /// Not a HTTP or webdav method.
     UnknownDavMethod,
+    /// Internal channel error.
     ChanError,
+    /// Invalid UTF-8.
     Utf8Error,
+    /// Request failed with this status.
     Status(StatusCode),
+    /// Request failed with this status, and the connection should be closed.
     StatusClose(StatusCode),
+    /// Error from the filesystem backend.
     FsError(FsError),
+    /// I/O error.
     IoError(io::Error),
+    /// Error reading XML.
     XmlReaderError(xml::reader::Error),
+    /// Error writing XML.
     XmlWriterError(xml::writer::Error),
+    /// An error with a human-readable detail, which is sent
+    /// as the response body. Use `DavError::with_detail` to create one.
+    Detail(Box<DavError>, String),
 }
 
 impl Error for DavError {
@@ -30,12 +53,13 @@ impl Error for DavError {
         "DAV error"
     }
 
-    fn cause(&self) -> Option<&dyn Error> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
         match *self {
             DavError::FsError(ref e) => Some(e),
             DavError::IoError(ref e) => Some(e),
             DavError::XmlReaderError(ref e) => Some(e),
             DavError::XmlWriterError(ref e) => Some(e),
+            DavError::Detail(ref e, _) => Some(&**e),
             _ => None,
         }
     }
@@ -46,7 +70,9 @@ impl std::fmt::Display for DavError {
         match *self {
             DavError::XmlReaderError(_) => write!(f, "XML parse error"),
             DavError::XmlWriterError(_) => write!(f, "XML generate error"),
-            DavError::IoError(_) => write!(f, "I/O error"),
+            DavError::IoError(ref e) => write!(f, "I/O error: {}", e),
+            DavError::FsError(ref e) => write!(f, "filesystem error: {}", e),
+            DavError::Detail(ref e, ref detail) => write!(f, "{}: {}", e, detail),
             _ => write!(f, "{:?}", self),
         }
     }
@@ -128,6 +154,7 @@ fn fserror_to_ioerror(e: FsError) -> io::Error {
         FsError::PathTooLong => io::Error::new(io::ErrorKind::Other, "PathTooLong"),
         FsError::TooLarge => io::Error::new(io::ErrorKind::Other, "TooLarge"),
         FsError::IsRemote => io::Error::new(io::ErrorKind::Other, "IsRemote"),
+        e @ FsError::Other { .. } => io::Error::new(io::ErrorKind::Other, e),
     }
 }
 
@@ -153,14 +180,71 @@ fn fserror_to_status(e: &FsError) -> StatusCode {
         FsError::PathTooLong => StatusCode::URI_TOO_LONG,
         FsError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
         FsError::IsRemote => StatusCode::BAD_GATEWAY,
+        FsError::Other { status_hint, .. } => status_hint
+            .filter(|s| s.is_client_error() || s.is_server_error())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
 
 impl DavError {
-    pub(crate) fn statuscode(&self) -> StatusCode {
+    /// Add a human-readable detail to this error. It is sent
+    /// as the body of the error response.
+    ///
+    /// ```
+    /// use dav_server::DavError;
+    /// use http::StatusCode;
+    ///
+    /// let err = DavError::from(StatusCode::CONFLICT).with_detail("parent is read-only");
+    /// assert_eq!(err.status(), StatusCode::CONFLICT);
+    /// assert_eq!(err.detail(), Some("parent is read-only"));
+    /// ```
+    pub fn with_detail(self, detail: impl Into<String>) -> DavError {
+        match self {
+            DavError::Detail(e, _) => DavError::Detail(e, detail.into()),
+            e => DavError::Detail(Box::new(e), detail.into()),
+        }
+    }
+
+    /// The detail that was added with `with_detail`, if any.
+    pub fn detail(&self) -> Option<&str> {
+        match *self {
+            DavError::Detail(_, ref detail) => Some(detail.as_str()),
+            _ => None,
+        }
+    }
+
+    /// The HTTP status that this error is turned into.
+    pub fn status(&self) -> StatusCode {
+        self.statuscode()
+    }
+
+    /// The filesystem error that caused this error, if any.
+    pub fn fs_error(&self) -> Option<&FsError> {
+        match *self {
+            DavError::FsError(ref e) => Some(e),
+            DavError::Detail(ref e, _) => e.fs_error(),
+            _ => None,
+        }
+    }
+
+    // The Retry-After that the filesystem asked for, if any.
+    pub(crate) fn retry_after(&self) -> Option<Duration> {
+        match self.fs_error() {
+            Some(FsError::Other { retry_after, .. }) => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Did a precondition (`If`, `If-Match` etc) fail.
+    pub fn is_precondition_failure(&self) -> bool {
+        self.statuscode() == StatusCode::PRECONDITION_FAILED
+    }
+
+    /// The HTTP status that this error is turned into. Same as `status()`.
+    pub fn statuscode(&self) -> StatusCode {
         match *self {
             DavError::XmlReadError => StatusCode::BAD_REQUEST,
-            DavError::XmlParseError => StatusCode::BAD_REQUEST,
+            DavError::XmlParseError => StatusCode::UNPROCESSABLE_ENTITY,
             DavError::InvalidPath => StatusCode::BAD_REQUEST,
             DavError::IllegalPath => StatusCode::BAD_GATEWAY,
             DavError::ForbiddenPath => StatusCode::FORBIDDEN,
@@ -173,10 +257,14 @@ impl DavError {
             DavError::StatusClose(e) => e,
             DavError::XmlReaderError(ref _e) => StatusCode::BAD_REQUEST,
             DavError::XmlWriterError(ref _e) => StatusCode::INTERNAL_SERVER_ERROR,
+            DavError::Detail(ref e, _) => e.statuscode(),
         }
     }
 
     pub(crate) fn must_close(&self) -> bool {
+        if let DavError::Detail(ref e, _) = *self {
+            return e.must_close();
+        }
         !matches!(
             self,
             &DavError::Status(_)
@@ -121,6 +121,10 @@ impl DavLockSystem for FakeLs {
         Vec::new()
     }
 
+    fn covers(&self, _path: &DavPath, _token: &str) -> bool {
+        true
+    }
+
     fn delete(&self, _path: &DavPath) -> Result<(), ()> {
         Ok(())
     }
@@ -6,12 +6,14 @@
 use std::fmt::Debug;
 use std::io::SeekFrom;
 use std::pin::Pin;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use futures_util::{future, Future, FutureExt, Stream, TryFutureExt};
 use http::StatusCode;
 
 use crate::davpath::DavPath;
+use crate::DavMethodSet;
 
 macro_rules! notimplemented {
     ($method:expr) => {
@@ -27,8 +29,85 @@ macro_rules! notimplemented_fut {
 
 /// Errors generated by a filesystem implementation.
 ///
-/// These are more result-codes than errors, really.
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// These are more result-codes than errors, really. A backend that
+/// has an error that does not fit one of the result-codes can wrap
+/// it in `FsError::Other`, so it is not lost:
+///
+/// ```
+/// use dav_server::fs::FsError;
+///
+/// #[derive(Debug)]
+/// struct QuotaServiceDown;
+///
+/// impl std::fmt::Display for QuotaServiceDown {
+///     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+///         write!(f, "quota service is down")
+///     }
+/// }
+///
+/// impl std::error::Error for QuotaServiceDown {}
+///
+/// let err = FsError::other(QuotaServiceDown);
+/// assert_eq!(err.to_string(), "quota service is down");
+/// ```
+///
+/// A request that fails with `FsError::Other` gets a
+/// `500 Internal Server Error` response:
+///
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use dav_server::{body::Body, davpath::DavPath, fs::*, DavHandler};
+/// use futures_util::future::{self, FutureExt};
+/// use http::{Request, StatusCode};
+///
+/// #[derive(Clone)]
+/// struct BrokenFs;
+///
+/// impl DavFileSystem for BrokenFs {
+///     fn open<'a>(&'a self, _: &'a DavPath, _: OpenOptions) -> FsFuture<'a, Box<dyn DavFile>> {
+///         future::ready(Err(FsError::other("backend unreachable"))).boxed()
+///     }
+///     fn read_dir<'a>(
+///         &'a self,
+///         _: &'a DavPath,
+///         _: ReadDirMeta,
+///     ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+///         future::ready(Err(FsError::other("backend unreachable"))).boxed()
+///     }
+///     fn metadata<'a>(&'a self, _: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+///         future::ready(Err(FsError::other("backend unreachable"))).boxed()
+///     }
+/// }
+///
+/// let dav = DavHandler::builder().filesystem(Box::new(BrokenFs)).build_handler();
+/// let req = Request::get("/file").body(Body::empty()).unwrap();
+/// let err = dav.try_handle(req).await.err().unwrap();
+/// assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+/// assert!(matches!(err.fs_error(), Some(FsError::Other { .. })));
+/// assert_eq!(err.to_string(), "filesystem error: backend unreachable");
+/// # }
+/// ```
+///
+/// A backend can ask for another status than 500, for example a
+/// `503 Service Unavailable` with a `Retry-After` header when it is
+/// being throttled by its storage provider:
+///
+/// ```
+/// use std::time::Duration;
+/// use dav_server::fs::FsError;
+/// use http::StatusCode;
+///
+/// let err = FsError::other("SlowDown: please reduce your request rate")
+///     .with_status_hint(StatusCode::SERVICE_UNAVAILABLE)
+///     .with_retry_after(Duration::from_secs(5));
+/// ```
+///
+/// The message of an `Other` error is logged at debug level, but not
+/// sent to the client, unless [`DavConfig::error_details`](crate::DavConfig::error_details)
+/// is enabled.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum FsError {
     /// Operation not implemented (501)
     NotImplemented,
@@ -50,6 +129,74 @@ pub enum FsError {
     TooLarge,
     /// Trying to MOVE over a mount boundary (EXDEV) (502)
     IsRemote,
+    /// Any other error (500, or `status_hint`). Use `FsError::other` to create one.
+    #[non_exhaustive]
+    Other {
+        /// The status to respond with instead of 500. Only client
+        /// and server error statuses are honored.
+        status_hint: Option<StatusCode>,
+        /// Sent as a `Retry-After` header, in whole seconds.
+        retry_after: Option<Duration>,
+        /// The error of the backend.
+        source: Arc<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl FsError {
+    /// Wrap a backend-specific error.
+    pub fn other(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> FsError {
+        FsError::Other {
+            status_hint: None,
+            retry_after: None,
+            source: Arc::from(err.into()),
+        }
+    }
+
+    /// Set the status hint of an `Other` error. Other errors are
+    /// returned unchanged.
+    pub fn with_status_hint(self, status: StatusCode) -> FsError {
+        match self {
+            FsError::Other {
+                retry_after,
+                source,
+                ..
+            } => FsError::Other {
+                status_hint: Some(status),
+                retry_after,
+                source,
+            },
+            e => e,
+        }
+    }
+
+    /// Set the `Retry-After` of an `Other` error. Other errors are
+    /// returned unchanged.
+    pub fn with_retry_after(self, after: Duration) -> FsError {
+        match self {
+            FsError::Other {
+                status_hint,
+                source,
+                ..
+            } => FsError::Other {
+                status_hint,
+                retry_after: Some(after),
+                source,
+            },
+            e => e,
+        }
+    }
+}
+
+// `Other` errors are only equal to themselves.
+impl PartialEq for FsError {
+    fn eq(&self, other: &FsError) -> bool {
+        match (self, other) {
+            (FsError::Other { source: a, .. }, FsError::Other { source: b, .. }) => {
+                Arc::ptr_eq(a, b)
+            }
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
 }
 /// The Result type.
 pub type FsResult<T> = std::result::Result<T, FsError>;
@@ -88,7 +235,7 @@ impl From<&std::io::Error> for FsError {
         match e.kind() {
             ErrorKind::NotFound => FsError::NotFound,
             ErrorKind::PermissionDenied => FsError::Forbidden,
-            _ => FsError::GeneralFailure,
+            _ => FsError::other(std::io::Error::new(e.kind(), e.to_string())),
         }
     }
 }
@@ -96,7 +243,11 @@ impl From<&std::io::Error> for FsError {
 #[cfg(any(feature = "memfs", feature = "localfs"))]
 impl From<std::io::Error> for FsError {
     fn from(e: std::io::Error) -> Self {
-        (&e).into()
+        match FsError::from(&e) {
+            // keep the original error.
+            FsError::Other { .. } => FsError::other(e),
+            other => other,
+        }
     }
 }
 /// A webdav property.
@@ -112,6 +263,15 @@ pub struct DavProp {
     pub xml: Option<Vec<u8>>,
 }
 
+/// A redirect reference (RFC 4437).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedirectRef {
+    /// The URI the reference points to.
+    pub target: String,
+    /// Permanent (301) or temporary (302) redirect.
+    pub permanent: bool,
+}
+
 /// Future returned by almost all of the DavFileSystem methods.
 pub type FsFuture<'a, T> = Pin<Box<dyn Future<Output = FsResult<T>> + Send + 'a>>;
 /// Convenience alias for a boxed Stream.
@@ -135,6 +295,17 @@ pub enum ReadDirMeta {
     None,
 }
 
+/// How much `DavFile::sync` writes to stable storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncLevel {
+    /// Nothing, only flush buffers, like `DavFile::flush`.
+    None,
+    /// The data, and the metadata needed to read it back (`fdatasync`).
+    Data,
+    /// The data and all metadata (`fsync`).
+    All,
+}
+
 /// The trait that defines a filesystem.
 pub trait DavFileSystem: Sync + Send + BoxCloneFs {
     /// Open a file.
@@ -148,6 +319,9 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
     ) -> FsFuture<FsStream<Box<dyn DavDirEntry>>>;
 
     /// Return the metadata of a file or directory.
+    ///
+    /// A path that ends in a `/` can only name a collection. If it names
+    /// a file, the handler treats it as not found, whatever is returned here.
     fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<Box<dyn DavMetaData>>;
 
     /// Return the metadata of a file, directory or symbolic link.
@@ -170,6 +344,41 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
         notimplemented_fut!("create_dir")
     }
 
+    /// Create a directory with extra resource types.
+    ///
+    /// Used by extended MKCOL (RFC 5689), and by COPY to keep the types
+    /// of a collection. The metadata of the new directory must return
+    /// `resourcetypes` from `DavMetaData::extra_resourcetypes`.
+    ///
+    /// The default implementation calls `create_dir` if there are no
+    /// extra types, and returns FsError::NotImplemented otherwise.
+    fn create_collection<'a>(
+        &'a self,
+        path: &'a DavPath,
+        resourcetypes: Vec<DavProp>,
+    ) -> FsFuture<'a, ()> {
+        if resourcetypes.is_empty() {
+            return self.create_dir(path);
+        }
+        notimplemented_fut!("create_collection")
+    }
+
+    /// Create a redirect reference (RFC 4437).
+    ///
+    /// The metadata of the new resource must return the reference
+    /// from `DavMetaData::redirect_ref`. If something exists at
+    /// `path` already, return `FsError::Exists`.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    fn create_redirect_ref<'a>(
+        &'a self,
+        path: &'a DavPath,
+        target: RedirectRef,
+    ) -> FsFuture<'a, ()> {
+        notimplemented_fut!("create_redirect_ref")
+    }
+
     /// Remove a directory.
     ///
     /// The default implementation returns FsError::NotImplemented.
@@ -210,6 +419,67 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
         notimplemented_fut!("copy")
     }
 
+    /// Rename a file or directory, unless the destination exists.
+    ///
+    /// Like `rename`, but if `to` exists this returns `FsError::Exists`.
+    /// This is used for MOVE with `Overwrite: F`. Implementations should
+    /// check and rename in one atomic step, so that two concurrent
+    /// requests cannot both succeed.
+    ///
+    /// The default implementation checks if `to` exists and then calls
+    /// `rename`, which is not atomic.
+    fn rename_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            match self.symlink_metadata(to).await {
+                Ok(_) => Err(FsError::Exists),
+                Err(FsError::NotFound) => self.rename(from, to).await,
+                Err(e) => Err(e),
+            }
+        }
+        .boxed()
+    }
+
+    /// Copy a file, unless the destination exists.
+    ///
+    /// Like `copy`, but if `to` exists this returns `FsError::Exists`.
+    /// This is used for COPY with `Overwrite: F`, and should be atomic,
+    /// just like `rename_no_replace`.
+    ///
+    /// The default implementation checks if `to` exists and then calls
+    /// `copy`, which is not atomic.
+    fn copy_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            match self.symlink_metadata(to).await {
+                Ok(_) => Err(FsError::Exists),
+                Err(FsError::NotFound) => self.copy(from, to).await,
+                Err(e) => Err(e),
+            }
+        }
+        .boxed()
+    }
+
+    /// Copy a directory and everything in it.
+    ///
+    /// `to` must not exist, if it does this returns `FsError::Exists`.
+    /// Like `copy`, this should also copy the DAV properties. Backends
+    /// that can copy a whole tree in one go (a server-side copy of a
+    /// prefix, reflinks) can implement this to make a COPY of a large
+    /// collection a lot faster. If it fails halfway, it should remove
+    /// what it has copied.
+    ///
+    /// COPY only uses this when there are no reserved paths below `from`.
+    /// An async COPY job reports the whole tree as one step. Files that
+    /// this filesystem hides from listings should not be copied.
+    ///
+    /// The default implementation returns FsError::NotImplemented. COPY
+    /// then creates the collections and copies the files one by one.
+    /// It does the same if this returns NotImplemented after all, so a
+    /// backend can also refuse trees it cannot copy faithfully.
+    #[allow(unused_variables)]
+    fn copy_recursive<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        notimplemented_fut!("copy_recursive")
+    }
+
     /// Set the access time of a file / directory.
     ///
     /// The default implementation returns FsError::NotImplemented.
@@ -278,6 +548,42 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
     fn get_quota(&self) -> FsFuture<(u64, Option<u64>)> {
         notimplemented_fut!("get_quota`")
     }
+
+    /// Get the change tag of a collection.
+    ///
+    /// This is an opaque string that must change whenever a member of
+    /// the collection is added, removed, or changed. It may change more
+    /// often than that. Sync clients use it to see if they need to look
+    /// at the collection again (`getctag`, in the
+    /// `http://calendarserver.org/ns/` namespace).
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    fn get_ctag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        notimplemented_fut!("get_ctag")
+    }
+
+    /// The methods that make sense on this filesystem.
+    ///
+    /// Used as the set of allowed methods if `DavConfig::methods` was not set.
+    ///
+    /// The default implementation returns `None` (all methods).
+    fn allowed_methods(&self) -> Option<DavMethodSet> {
+        None
+    }
+
+    /// Check that the backend can serve requests.
+    ///
+    /// Used by the readiness check on `DavConfig::health_path`, which
+    /// gives up after a short timeout. It should be cheap.
+    ///
+    /// The default implementation gets the metadata of the root.
+    fn healthcheck(&self) -> FsFuture<'_, ()> {
+        Box::pin(async move {
+            let root = DavPath::new("/").map_err(|_| FsError::GeneralFailure)?;
+            self.metadata(&root).await.map(|_| ())
+        })
+    }
 }
 
 // BoxClone trait.
@@ -337,9 +643,20 @@ pub trait DavFile: Debug + Send + Sync {
     fn metadata(&mut self) -> FsFuture<Box<dyn DavMetaData>>;
     fn write_buf(&mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<()>;
     fn write_bytes(&mut self, buf: bytes::Bytes) -> FsFuture<()>;
+    /// Read at most `count` bytes. Fewer is fine, an empty result means end of file.
     fn read_bytes(&mut self, count: usize) -> FsFuture<bytes::Bytes>;
     fn seek(&mut self, pos: SeekFrom) -> FsFuture<u64>;
     fn flush(&mut self) -> FsFuture<()>;
+    /// Truncate or extend the file to `len` bytes, like `std::fs::File::set_len`.
+    /// The file position is not changed. Default: `FsError::NotImplemented`.
+    fn set_len(&mut self, _len: u64) -> FsFuture<'_, ()> {
+        notimplemented_fut!("set_len")
+    }
+    /// Flush the file, and write it to stable storage as far as `level` asks.
+    /// Default: `flush()`, for backends without a notion of stable storage.
+    fn sync(&mut self, _level: SyncLevel) -> FsFuture<'_, ()> {
+        self.flush()
+    }
     fn redirect_url(&mut self) -> FsFuture<Option<String>> {
         future::ready(Ok(None)).boxed()
     }
@@ -354,10 +671,22 @@ pub trait DavMetaData: Debug + BoxCloneMd + Send + Sync {
     /// File or directory (aka collection).
     fn is_dir(&self) -> bool;
 
-    /// Simplistic implementation of `etag()`
+    /// The entity tag of the resource.
+    ///
+    /// The handler uses it as it is, wherever it sends or compares an
+    /// ETag: in `GET`, `HEAD` and `PUT` responses, as `getetag` in
+    /// `PROPFIND`, and to evaluate `If-Match`, `If-None-Match`, `If-Range`
+    /// and `If` headers. So a backend that already has tags, like the
+    /// ETag of an object store or a content hash, should return those.
+    /// The tag is quoted by the handler, unless it already is (`"abc"` or
+    /// `W/"abc"`). Otherwise it must not contain a `"`. For collections,
+    /// the handler computes an ETag from the members instead, unless
+    /// [`hashed_collection_etags`](crate::DavConfig::hashed_collection_etags)
+    /// is turned off.
     ///
-    /// Returns a simple etag that basically is `\<length\>-\<timestamp_in_ms\>`
-    /// with the numbers in hex. Enough for most implementations.
+    /// The default implementation returns a simple etag that basically is
+    /// `\<length\>-\<timestamp_in_ms\>` with the numbers in hex. Enough
+    /// for most implementations.
     fn etag(&self) -> Option<String> {
         if let Ok(t) = self.modified() {
             if let Ok(t) = t.duration_since(UNIX_EPOCH) {
@@ -373,6 +702,16 @@ pub trait DavMetaData: Debug + BoxCloneMd + Send + Sync {
         None
     }
 
+    /// Is the tag that `etag()` returns a weak validator. Default: `false`.
+    ///
+    /// A tag is weak if it does not change with every change of the
+    /// content, for example if it is derived from a timestamp with a
+    /// granularity of a second. It is then sent with a `W/` prefix, and
+    /// never matches `If-Match` and `If-Range`, which need a strong tag.
+    fn etag_is_weak(&self) -> bool {
+        false
+    }
+
     /// Is this a file and not a directory. Default: `!s_dir()`.
     fn is_file(&self) -> bool {
         !self.is_dir()
@@ -407,6 +746,18 @@ pub trait DavMetaData: Debug + BoxCloneMd + Send + Sync {
     fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    /// The target if this is a redirect reference (RFC 4437). Default: `None`.
+    fn redirect_ref(&self) -> Option<RedirectRef> {
+        None
+    }
+
+    /// Values of the `resourcetype` property besides `D:collection`,
+    /// like `C:calendar` for a CalDAV calendar. Only the name and the
+    /// namespace of each `DavProp` are used. Default: none.
+    fn extra_resourcetypes(&self) -> Vec<DavProp> {
+        Vec::new()
+    }
 }
 
 // generic Clone, calls implementation-specific box_clone().
@@ -497,13 +848,19 @@ impl std::error::Error for FsError {
     fn description(&self) -> &str {
         "DavFileSystem error"
     }
-    fn cause(&self) -> Option<&dyn std::error::Error> {
-        None
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FsError::Other { source, .. } => Some(&**source),
+            _ => None,
+        }
     }
 }
 
 impl std::fmt::Display for FsError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            FsError::Other { source, .. } => write!(f, "{}", source),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
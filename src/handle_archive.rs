@@ -0,0 +1,1044 @@
+// Download of a collection as one archive file.
+//
+// A GET on a collection can send the whole subtree as a tar, tar.gz or
+// zip file, see `DavConfig::archive_download`. The archive is built while
+// it is sent: the tree is walked with read_dir, and the files are opened
+// and read one at a time, so only the zip central directory is kept
+// in memory.
+//
+// Zip members are "stored", and the CRC and the sizes follow the data
+// in a data descriptor, so nothing has to be known up front. Members of
+// 4 GiB and over, and offsets beyond 4 GiB, use the zip64 extensions.
+// The tar is POSIX pax: names of more than 100 bytes, and sizes of 8 GiB
+// and over, go into a pax extended header.
+//
+use std::cmp;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+#[cfg(feature = "compression")]
+use flate2::{write::GzEncoder, Compression};
+use futures_util::StreamExt;
+use http::{HeaderValue, Request, Response, StatusCode};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+use crate::async_stream::AsyncStream;
+use crate::body::Body;
+use crate::davhandler::lock_owner;
+use crate::davpath::DavPath;
+use crate::errors::*;
+use crate::fs::*;
+use crate::handle_gethead::AUTOINDEX_TYPES;
+use crate::negotiate::negotiate;
+use crate::util::systemtime_to_offsetdatetime;
+
+const READ_BUF_SIZE: usize = 16384;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::Zip => "application/zip",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+}
+
+impl crate::DavInner {
+    // The archive a GET on the collection `path` asks for, if any.
+    // A "download" query wins over the Accept header.
+    pub(crate) fn archive_format(
+        &self,
+        req: &Request<()>,
+        path: &DavPath,
+    ) -> Option<ArchiveFormat> {
+        let formats = self.archive_download?;
+        let mut enabled = Vec::new();
+        if formats.zip {
+            enabled.push(ArchiveFormat::Zip);
+        }
+        if formats.tar {
+            enabled.push(ArchiveFormat::Tar);
+        }
+        if formats.tar_gz && cfg!(feature = "compression") {
+            enabled.push(ArchiveFormat::TarGz);
+        }
+
+        let download = path
+            .query()
+            .into_iter()
+            .flat_map(|q| q.split('&'))
+            .find_map(|p| p.strip_prefix("download="));
+        if let Some(download) = download {
+            let format = match download {
+                "zip" => ArchiveFormat::Zip,
+                "tar" => ArchiveFormat::Tar,
+                "tar.gz" | "tgz" => ArchiveFormat::TarGz,
+                _ => return None,
+            };
+            return enabled.into_iter().find(|&f| f == format);
+        }
+
+        let accept = req
+            .headers()
+            .get_all("accept")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        let accept = Some(accept.as_str()).filter(|a| !a.is_empty());
+        // the archives are other representations of the collection, next
+        // to the directory index, which is what a client gets if it does
+        // not say.
+        let mut offers = AUTOINDEX_TYPES.to_vec();
+        offers.extend(enabled.iter().map(|f| f.content_type()));
+        let ct = negotiate(accept, &offers)?;
+        enabled.into_iter().find(|f| f.content_type() == ct)
+    }
+
+    pub(crate) async fn handle_archive(
+        &self,
+        req: &Request<()>,
+        head: bool,
+        format: ArchiveFormat,
+    ) -> DavResult<Response<Body>> {
+        let path = self.path(req);
+        let meta = self.fs.metadata(&path).await?;
+
+        // the archive has no validators, only the If header applies.
+        let (matched, tokens) = self.dav_if_match(&path).await;
+        if !matched {
+            return Err(DavError::Status(StatusCode::PRECONDITION_FAILED));
+        }
+
+        // like a GET of a file, optionally keep out of what someone else
+        // has locked: the collection as a whole, or files in it.
+        let ls = match self.ls {
+            Some(ref ls) if self.get_respects_locks.unwrap_or(false) => Some(ls.clone()),
+            _ => None,
+        };
+        let principal = self.principal.clone();
+        let locked = move |path: &DavPath| match ls {
+            Some(ref ls) => ls
+                .discover(path)
+                .iter()
+                .any(|l| !l.shared && !lock_owner(l, principal.as_deref(), &tokens)),
+            None => false,
+        };
+        if locked(&path) {
+            return Err(DavError::Status(StatusCode::LOCKED));
+        }
+
+        // the name of the download, and of the top directory in it.
+        let name = match path.file_name_bytes() {
+            b"" => "archive".to_string(),
+            n => String::from_utf8_lossy(n).to_string(),
+        };
+        let filename = format!("{}.{}", name, format.extension());
+        let fallback = filename
+            .chars()
+            .map(|c| match c {
+                ' '..='~' if c != '"' && c != '\\' => c,
+                _ => '_',
+            })
+            .collect::<String>();
+        let disposition = format!(
+            "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+            fallback,
+            utf8_percent_encode(&filename, NON_ALPHANUMERIC)
+        );
+
+        let mut res = Response::new(Body::empty());
+        let headers = res.headers_mut();
+        headers.insert(
+            "Content-Type",
+            HeaderValue::from_static(format.content_type()),
+        );
+        headers.insert("Content-Disposition", disposition.parse().unwrap());
+        headers.insert("Vary", HeaderValue::from_static("Accept"));
+        *res.status_mut() = StatusCode::OK;
+        if head {
+            return Ok(res);
+        }
+
+        let fs = self.fs.clone();
+        let reserved = self.reserved.clone();
+        let read_buf_size = self.read_buf_size.unwrap_or(READ_BUF_SIZE);
+        let limits = self.limits();
+        let mut top = name.into_bytes();
+        top.push(b'/');
+
+        *res.body_mut() = Body::from(AsyncStream::new(|mut tx| async move {
+            let zero = [0; 4096];
+            let mut w = ArchiveWriter::new(format);
+            let mut entries = 0;
+            let mut size = 0;
+            // the status has been sent, all we can do is abort.
+            let exceeded = |limit: &str| {
+                debug!("archive: limit {} exceeded", limit);
+                io::Error::from(io::ErrorKind::Other)
+            };
+
+            // depth first, the files of a directory before its subdirectories.
+            let mut todo = vec![(path, top, meta.modified().ok(), 0)];
+            while let Some((dir, name, mtime, depth)) = todo.pop() {
+                entries += 1;
+                if entries > limits.archive_entries {
+                    return Err(exceeded("archive_entries"));
+                }
+                w.add_dir(&name, mtime);
+                let mut dirents = match fs.read_dir(&dir, ReadDirMeta::DataSymlink).await {
+                    Ok(dirents) => dirents,
+                    Err(_e) => {
+                        debug!("archive: read_dir {:?}: {:?}", dir, _e);
+                        continue;
+                    }
+                };
+                let mut subdirs = Vec::new();
+                while let Some(dirent) = dirents.next().await {
+                    let meta = match dirent.metadata().await {
+                        Ok(meta) if !meta.is_symlink() => meta,
+                        _ => continue,
+                    };
+                    let segment = dirent.name();
+                    let mut epath = dir.clone();
+                    epath.push_segment(&segment);
+                    let mut ename = name.clone();
+                    ename.extend_from_slice(&segment);
+
+                    if reserved.contains(&epath) {
+                        continue;
+                    }
+                    if meta.is_dir() {
+                        if depth >= limits.archive_depth {
+                            return Err(exceeded("archive_depth"));
+                        }
+                        epath.add_slash();
+                        ename.push(b'/');
+                        subdirs.push((epath, ename, meta.modified().ok(), depth + 1));
+                        continue;
+                    }
+                    if !meta.is_file() || locked(&epath) {
+                        continue;
+                    }
+                    entries += 1;
+                    size += meta.len();
+                    if entries > limits.archive_entries {
+                        return Err(exceeded("archive_entries"));
+                    }
+                    if size > limits.archive_size {
+                        return Err(exceeded("archive_size"));
+                    }
+                    let mut file = match fs.open(&epath, OpenOptions::read()).await {
+                        Ok(file) => file,
+                        Err(_e) => {
+                            debug!("archive: open {:?}: {:?}", epath, _e);
+                            continue;
+                        }
+                    };
+
+                    // the size is in the tar header, so send exactly that many
+                    // bytes. Like GET, pad with zeroes if the file got shorter.
+                    let len = meta.len();
+                    let exec = meta.executable().unwrap_or(false);
+                    w.begin_file(&ename, len, meta.modified().ok(), exec);
+                    let mut count = len;
+                    while count > 0 {
+                        let blen = cmp::min(count, read_buf_size as u64) as usize;
+                        let mut buf = file.read_bytes(blen).await?;
+                        buf.truncate(blen);
+                        if buf.is_empty() {
+                            let n = cmp::min(count, 4096) as usize;
+                            buf = Bytes::copy_from_slice(&zero[..n]);
+                        }
+                        count -= buf.len() as u64;
+                        w.file_data(&buf);
+                        let out = w.take();
+                        if !out.is_empty() {
+                            tx.send(out).await;
+                        }
+                    }
+                    w.end_file();
+                }
+                todo.extend(subdirs.into_iter().rev());
+
+                let out = w.take();
+                if !out.is_empty() {
+                    tx.send(out).await;
+                }
+            }
+
+            w.finish();
+            tx.send(w.take()).await;
+            Ok::<_, io::Error>(())
+        }));
+
+        Ok(res)
+    }
+}
+
+// A zip central directory entry.
+struct ZipEntry {
+    name: Vec<u8>,
+    dos_time: (u16, u16),
+    crc: u32,
+    size: u64,
+    offset: u64,
+    is_dir: bool,
+    exec: bool,
+}
+
+impl ZipEntry {
+    // the sizes are 64 bits in the local header and the data descriptor.
+    fn zip64(&self) -> bool {
+        self.size >= 0xffff_ffff
+    }
+
+    fn flags(&self) -> u16 {
+        let utf8 = if std::str::from_utf8(&self.name).is_ok() {
+            0x0800
+        } else {
+            0
+        };
+        let descriptor = if self.is_dir { 0 } else { 0x0008 };
+        utf8 | descriptor
+    }
+
+    fn version_needed(&self) -> u16 {
+        if self.zip64() || self.offset >= 0xffff_ffff {
+            45
+        } else {
+            20
+        }
+    }
+}
+
+// Builds an archive, one entry at a time. Output is collected in an
+// internal buffer and picked up with `take`.
+struct ArchiveWriter {
+    format: ArchiveFormat,
+    buf: Vec<u8>,
+    #[cfg(feature = "compression")]
+    gz: Option<GzEncoder<Vec<u8>>>,
+    // number of bytes written to the (uncompressed) archive.
+    offset: u64,
+    // the zip member that is being written.
+    current: Option<ZipEntry>,
+    crc: Crc,
+    entries: Vec<ZipEntry>,
+}
+
+impl ArchiveWriter {
+    fn new(format: ArchiveFormat) -> ArchiveWriter {
+        ArchiveWriter {
+            format,
+            buf: Vec::new(),
+            #[cfg(feature = "compression")]
+            gz: match format {
+                ArchiveFormat::TarGz => Some(GzEncoder::new(Vec::new(), Compression::default())),
+                _ => None,
+            },
+            offset: 0,
+            current: None,
+            crc: Crc::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        self.offset += data.len() as u64;
+        #[cfg(feature = "compression")]
+        if let Some(ref mut gz) = self.gz {
+            // writing to a Vec cannot fail.
+            let _ = gz.write_all(data);
+            return;
+        }
+        self.buf.extend_from_slice(data);
+    }
+
+    // the output so far.
+    fn take(&mut self) -> Bytes {
+        #[cfg(feature = "compression")]
+        if let Some(ref mut gz) = self.gz {
+            return Bytes::from(std::mem::take(gz.get_mut()));
+        }
+        Bytes::from(std::mem::take(&mut self.buf))
+    }
+
+    fn add_dir(&mut self, name: &[u8], mtime: Option<SystemTime>) {
+        match self.format {
+            ArchiveFormat::Zip => {
+                let entry = ZipEntry {
+                    name: name.to_vec(),
+                    dos_time: dos_time(mtime),
+                    crc: 0,
+                    size: 0,
+                    offset: self.offset,
+                    is_dir: true,
+                    exec: false,
+                };
+                self.zip_local_header(&entry);
+                self.entries.push(entry);
+            }
+            _ => self.tar_header(name, 0, mtime, 0o755, b'5'),
+        }
+    }
+
+    fn begin_file(&mut self, name: &[u8], size: u64, mtime: Option<SystemTime>, exec: bool) {
+        match self.format {
+            ArchiveFormat::Zip => {
+                let entry = ZipEntry {
+                    name: name.to_vec(),
+                    dos_time: dos_time(mtime),
+                    crc: 0,
+                    size,
+                    offset: self.offset,
+                    is_dir: false,
+                    exec,
+                };
+                self.zip_local_header(&entry);
+                self.crc = Crc::new();
+                self.current = Some(entry);
+            }
+            _ => {
+                let mode = if exec { 0o755 } else { 0o644 };
+                self.tar_header(name, size, mtime, mode, b'0');
+            }
+        }
+    }
+
+    fn file_data(&mut self, data: &[u8]) {
+        if self.format == ArchiveFormat::Zip {
+            self.crc.update(data);
+        }
+        self.write(data);
+    }
+
+    fn end_file(&mut self) {
+        match self.format {
+            ArchiveFormat::Zip => {
+                let mut entry = match self.current.take() {
+                    Some(entry) => entry,
+                    None => return,
+                };
+                entry.crc = self.crc.sum();
+                let mut d = Vec::new();
+                put_u32(&mut d, 0x0807_4b50);
+                put_u32(&mut d, entry.crc);
+                if entry.zip64() {
+                    put_u64(&mut d, entry.size);
+                    put_u64(&mut d, entry.size);
+                } else {
+                    put_u32(&mut d, entry.size as u32);
+                    put_u32(&mut d, entry.size as u32);
+                }
+                self.write(&d);
+                self.entries.push(entry);
+            }
+            _ => {
+                // pad the data to a whole block.
+                let pad = (512 - (self.offset % 512) as usize) % 512;
+                self.write(&[0u8; 512][..pad]);
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        match self.format {
+            ArchiveFormat::Zip => self.zip_central_directory(),
+            _ => {
+                self.write(&[0u8; 1024]);
+                #[cfg(feature = "compression")]
+                if let Some(ref mut gz) = self.gz {
+                    let _ = gz.try_finish();
+                }
+            }
+        }
+    }
+
+    // A ustar header, preceded by a pax header if the name or the size
+    // does not fit.
+    fn tar_header(
+        &mut self,
+        name: &[u8],
+        size: u64,
+        mtime: Option<SystemTime>,
+        mode: u32,
+        typeflag: u8,
+    ) {
+        let mtime = mtime
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| cmp::min(d.as_secs(), 0o777_7777_7777));
+        let big = size > 0o777_7777_7777;
+
+        if name.len() > 100 || big {
+            let mut records = Vec::new();
+            if name.len() > 100 {
+                pax_record(&mut records, "path", name);
+            }
+            if big {
+                pax_record(&mut records, "size", size.to_string().as_bytes());
+            }
+            let block = tar_block(b"././@PaxHeader", records.len() as u64, mtime, 0o644, b'x');
+            self.write(&block);
+            self.write(&records);
+            self.end_file();
+        }
+
+        let block = tar_block(
+            &name[..cmp::min(name.len(), 100)],
+            size,
+            mtime,
+            mode,
+            typeflag,
+        );
+        self.write(&block);
+    }
+
+    fn zip_local_header(&mut self, e: &ZipEntry) {
+        let mut h = Vec::new();
+        put_u32(&mut h, 0x0403_4b50);
+        put_u16(&mut h, e.version_needed());
+        put_u16(&mut h, e.flags());
+        put_u16(&mut h, 0);
+        put_u16(&mut h, e.dos_time.0);
+        put_u16(&mut h, e.dos_time.1);
+        // crc and sizes follow in the data descriptor.
+        put_u32(&mut h, 0);
+        let size = if e.zip64() { 0xffff_ffff } else { 0 };
+        put_u32(&mut h, size);
+        put_u32(&mut h, size);
+        put_u16(&mut h, e.name.len() as u16);
+        put_u16(&mut h, if e.zip64() { 20 } else { 0 });
+        h.extend_from_slice(&e.name);
+        if e.zip64() {
+            put_u16(&mut h, 0x0001);
+            put_u16(&mut h, 16);
+            put_u64(&mut h, 0);
+            put_u64(&mut h, 0);
+        }
+        self.write(&h);
+    }
+
+    fn zip_central_directory(&mut self) {
+        let start = self.offset;
+        let entries = std::mem::take(&mut self.entries);
+        for e in &entries {
+            let mut extra = Vec::new();
+            if e.zip64() {
+                put_u64(&mut extra, e.size);
+                put_u64(&mut extra, e.size);
+            }
+            if e.offset >= 0xffff_ffff {
+                put_u64(&mut extra, e.offset);
+            }
+
+            let mut h = Vec::new();
+            put_u32(&mut h, 0x0201_4b50);
+            // made by: unix, 4.5
+            put_u16(&mut h, (3 << 8) | 45);
+            put_u16(&mut h, e.version_needed());
+            put_u16(&mut h, e.flags());
+            put_u16(&mut h, 0);
+            put_u16(&mut h, e.dos_time.0);
+            put_u16(&mut h, e.dos_time.1);
+            put_u32(&mut h, e.crc);
+            let size = cmp::min(e.size, 0xffff_ffff) as u32;
+            put_u32(&mut h, size);
+            put_u32(&mut h, size);
+            put_u16(&mut h, e.name.len() as u16);
+            let extra_len = if extra.is_empty() { 0 } else { extra.len() + 4 };
+            put_u16(&mut h, extra_len as u16);
+            // comment length, disk number, internal attributes.
+            put_u16(&mut h, 0);
+            put_u16(&mut h, 0);
+            put_u16(&mut h, 0);
+            let attrs = match (e.is_dir, e.exec) {
+                (true, _) => (0o40755 << 16) | 0x10,
+                (false, true) => 0o100755 << 16,
+                (false, false) => 0o100644 << 16,
+            };
+            put_u32(&mut h, attrs);
+            put_u32(&mut h, cmp::min(e.offset, 0xffff_ffff) as u32);
+            h.extend_from_slice(&e.name);
+            if !extra.is_empty() {
+                put_u16(&mut h, 0x0001);
+                put_u16(&mut h, extra.len() as u16);
+                h.extend_from_slice(&extra);
+            }
+            self.write(&h);
+        }
+        let count = entries.len() as u64;
+        let size = self.offset - start;
+
+        let mut h = Vec::new();
+        if count >= 0xffff || size >= 0xffff_ffff || start >= 0xffff_ffff {
+            // zip64 end of central directory record, and its locator.
+            let end64 = self.offset;
+            put_u32(&mut h, 0x0606_4b50);
+            put_u64(&mut h, 44);
+            put_u16(&mut h, (3 << 8) | 45);
+            put_u16(&mut h, 45);
+            put_u32(&mut h, 0);
+            put_u32(&mut h, 0);
+            put_u64(&mut h, count);
+            put_u64(&mut h, count);
+            put_u64(&mut h, size);
+            put_u64(&mut h, start);
+            put_u32(&mut h, 0x0706_4b50);
+            put_u32(&mut h, 0);
+            put_u64(&mut h, end64);
+            put_u32(&mut h, 1);
+        }
+        put_u32(&mut h, 0x0605_4b50);
+        put_u16(&mut h, 0);
+        put_u16(&mut h, 0);
+        put_u16(&mut h, cmp::min(count, 0xffff) as u16);
+        put_u16(&mut h, cmp::min(count, 0xffff) as u16);
+        put_u32(&mut h, cmp::min(size, 0xffff_ffff) as u32);
+        put_u32(&mut h, cmp::min(start, 0xffff_ffff) as u32);
+        put_u16(&mut h, 0);
+        self.write(&h);
+    }
+}
+
+// One 512 byte ustar header block.
+fn tar_block(name: &[u8], size: u64, mtime: u64, mode: u32, typeflag: u8) -> [u8; 512] {
+    let mut b = [0u8; 512];
+    b[..name.len()].copy_from_slice(name);
+    b[100..108].copy_from_slice(format!("{:07o}\0", mode).as_bytes());
+    b[108..116].copy_from_slice(b"0000000\0");
+    b[116..124].copy_from_slice(b"0000000\0");
+    if size > 0o777_7777_7777 {
+        // GNU base-256, the real size is in the pax header as well.
+        b[124] = 0x80;
+        b[128..136].copy_from_slice(&size.to_be_bytes());
+    } else {
+        b[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+    }
+    b[136..148].copy_from_slice(format!("{:011o}\0", mtime).as_bytes());
+    b[156] = typeflag;
+    b[257..265].copy_from_slice(b"ustar\x0000");
+
+    // the checksum is calculated with the checksum field set to spaces.
+    b[148..156].copy_from_slice(b"        ");
+    let sum = b.iter().map(|&c| c as u32).sum::<u32>();
+    b[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    b
+}
+
+// "<len> <key>=<value>\n", where len includes its own digits.
+fn pax_record(out: &mut Vec<u8>, key: &str, value: &[u8]) {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    let _ = write!(out, "{} {}=", len, key);
+    out.extend_from_slice(value);
+    out.push(b'\n');
+}
+
+// MS-DOS time and date, in UTC.
+fn dos_time(t: Option<SystemTime>) -> (u16, u16) {
+    let tm = match t {
+        Some(t) => systemtime_to_offsetdatetime(t),
+        None => return (0, (1 << 5) | 1),
+    };
+    if tm.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    if tm.year() > 2107 {
+        return (0xbf7d, 0xff9f);
+    }
+    let time = ((tm.hour() as u16) << 11) | ((tm.minute() as u16) << 5) | (tm.second() as u16 / 2);
+    let date =
+        (((tm.year() - 1980) as u16) << 9) | ((u8::from(tm.month()) as u16) << 5) | tm.day() as u16;
+    (time, date)
+}
+
+fn put_u16(v: &mut Vec<u8>, n: u16) {
+    v.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_u32(v: &mut Vec<u8>, n: u32) {
+    v.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_u64(v: &mut Vec<u8>, n: u64) {
+    v.extend_from_slice(&n.to_le_bytes());
+}
+
+// The CRC-32 of zip members (IEEE 802.3), so that zip does not need
+// the "compression" feature.
+struct Crc(u32);
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+impl Crc {
+    fn new() -> Crc {
+        Crc(0xffff_ffff)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.0 = CRC_TABLE[((self.0 ^ b as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    fn sum(&self) -> u32 {
+        !self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::memls::MemLs;
+    use crate::testing::DavClient;
+    use crate::testing::Depth;
+    use crate::{ArchiveFormats, DavConfig, DavHandler, Limits};
+
+    // (name, content) of all entries in a tar file.
+    fn untar(data: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut entries = Vec::new();
+        let mut pax_path = None;
+        let mut pos = 0;
+        while pos + 512 <= data.len() {
+            let h = &data[pos..pos + 512];
+            if h.iter().all(|&c| c == 0) {
+                break;
+            }
+            let mut b = h.to_vec();
+            b[148..156].copy_from_slice(b"        ");
+            let sum = b.iter().map(|&c| c as u32).sum::<u32>();
+            let chksum = std::str::from_utf8(&h[148..154]).unwrap();
+            assert_eq!(u32::from_str_radix(chksum, 8).unwrap(), sum);
+
+            let size = std::str::from_utf8(&h[124..135]).unwrap();
+            let size = usize::from_str_radix(size, 8).unwrap();
+            let end = h[..100].iter().position(|&c| c == 0).unwrap_or(100);
+            let name = String::from_utf8(h[..end].to_vec()).unwrap();
+            let body = data[pos + 512..pos + 512 + size].to_vec();
+            pos += 512 + size.div_ceil(512) * 512;
+            if h[156] == b'x' {
+                let rec = String::from_utf8(body).unwrap();
+                let path = rec.split_once(" path=").unwrap().1;
+                pax_path = Some(path.trim_end_matches('\n').to_string());
+                continue;
+            }
+            entries.push((pax_path.take().unwrap_or(name), body));
+        }
+        assert_eq!(pos + 1024, data.len());
+        entries
+    }
+
+    fn u16_at(data: &[u8], pos: usize) -> usize {
+        u16::from_le_bytes(data[pos..pos + 2].try_into().unwrap()) as usize
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> usize {
+        u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()) as usize
+    }
+
+    // (name, content) of all entries in a zip file, from the central directory.
+    fn unzip(data: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let eocd = data.len() - 22;
+        assert_eq!(u32_at(data, eocd), 0x0605_4b50);
+        let count = u16_at(data, eocd + 10);
+        let mut pos = u32_at(data, eocd + 16);
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            assert_eq!(u32_at(data, pos), 0x0201_4b50);
+            let crc = u32_at(data, pos + 16) as u32;
+            let size = u32_at(data, pos + 24);
+            let nlen = u16_at(data, pos + 28);
+            let offset = u32_at(data, pos + 42);
+            let name = String::from_utf8(data[pos + 46..pos + 46 + nlen].to_vec()).unwrap();
+            pos += 46 + nlen + u16_at(data, pos + 30) + u16_at(data, pos + 32);
+
+            assert_eq!(u32_at(data, offset), 0x0403_4b50);
+            let start = offset + 30 + u16_at(data, offset + 26) + u16_at(data, offset + 28);
+            let body = data[start..start + size].to_vec();
+            let mut c = Crc::new();
+            c.update(&body);
+            assert_eq!(c.sum(), crc);
+            if !name.ends_with('/') {
+                assert_eq!(u32_at(data, start + size), 0x0807_4b50);
+                assert_eq!(u32_at(data, start + size + 4) as u32, crc);
+            }
+            entries.push((name, body));
+        }
+        assert_eq!(pos, eocd);
+        entries
+    }
+
+    async fn setup(config: DavConfig) -> DavClient {
+        let client = DavClient::new(config.filesystem(MemFs::new()).build_handler());
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        assert_eq!(client.mkcol("/dir/sub/").await, StatusCode::CREATED);
+        assert_eq!(
+            client.put_bytes("/dir/a.txt", "hello").await,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            client.put_bytes("/dir/sub/b.txt", "world").await,
+            StatusCode::CREATED
+        );
+        let long = format!("/dir/sub/{}.txt", "x".repeat(120));
+        assert_eq!(
+            client.put_bytes(&long, vec![7u8; 1000]).await,
+            StatusCode::CREATED
+        );
+        client
+    }
+
+    async fn get(client: &DavClient, path: &str, accept: &str) -> Response<Bytes> {
+        let req = Request::get(path)
+            .header("Accept", accept)
+            .body(crate::body::Body::empty())
+            .unwrap();
+        client.request(req).await
+    }
+
+    fn sorted(mut entries: Vec<(String, Vec<u8>)>) -> Vec<(String, Vec<u8>)> {
+        entries.sort();
+        entries
+    }
+
+    fn expected() -> Vec<(String, Vec<u8>)> {
+        sorted(vec![
+            ("dir/".to_string(), vec![]),
+            ("dir/a.txt".to_string(), b"hello".to_vec()),
+            ("dir/sub/".to_string(), vec![]),
+            ("dir/sub/b.txt".to_string(), b"world".to_vec()),
+            (format!("dir/sub/{}.txt", "x".repeat(120)), vec![7u8; 1000]),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_archive_download() {
+        let client = setup(DavHandler::builder().archive_download(ArchiveFormats::all())).await;
+
+        let res = get(&client, "/dir/?download=tar", "*/*").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-type"], "application/x-tar");
+        assert_eq!(
+            res.headers()["content-disposition"],
+            "attachment; filename=\"dir.tar\"; filename*=UTF-8''dir%2Etar"
+        );
+        assert!(res.headers().get("content-length").is_none());
+        assert_eq!(sorted(untar(res.body())), expected());
+
+        let res = get(&client, "/dir/", "application/zip").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-type"], "application/zip");
+        assert_eq!(sorted(unzip(res.body())), expected());
+
+        #[cfg(feature = "compression")]
+        {
+            use std::io::Read;
+
+            let res = get(&client, "/dir/?download=tar.gz", "text/html").await;
+            assert_eq!(res.headers()["content-type"], "application/gzip");
+            let mut tar = Vec::new();
+            flate2::read::GzDecoder::new(&res.body()[..])
+                .read_to_end(&mut tar)
+                .unwrap();
+            assert_eq!(sorted(untar(&tar)), expected());
+        }
+
+        // the query survives the redirect to the collection.
+        let res = get(&client, "/dir?download=zip", "*/*").await;
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(res.headers()["location"], "/dir/?download=zip");
+
+        // a wildcard does not ask for an archive.
+        let res = get(&client, "/dir/", "*/*").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_archive_formats() {
+        let client = setup(DavHandler::builder().archive_download(ArchiveFormats {
+            zip: true,
+            ..ArchiveFormats::default()
+        }))
+        .await;
+        let res = get(&client, "/dir/?download=tar", "*/*").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = get(&client, "/dir/", "application/x-tar, application/zip;q=0.5").await;
+        assert_eq!(res.headers()["content-type"], "application/zip");
+
+        // the directory index is offered too, and wins on a tie.
+        let res = get(&client, "/dir/", "text/html;q=0.5, application/zip").await;
+        assert_eq!(res.headers()["content-type"], "application/zip");
+        let res = get(&client, "/dir/", "text/html, application/zip;q=0.5").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = get(&client, "/dir/", "text/html, application/zip").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        let res = get(&client, "/?download=zip", "application/zip").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_archive_limits() {
+        // a truncated zip has no end of central directory record.
+        let complete = |limits: Limits| async move {
+            let config = DavHandler::builder()
+                .archive_download(ArchiveFormats::all())
+                .limits(limits);
+            let client = setup(config).await;
+            let res = get(&client, "/dir/", "application/zip").await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let data = res.body();
+            data.len() >= 22 && u32_at(data, data.len() - 22) == 0x0605_4b50
+        };
+        let limits = Limits::default();
+        assert!(complete(limits).await);
+
+        // 2 directories and 3 files.
+        for (entries, ok) in [(4, false), (5, true)] {
+            let limits = Limits {
+                archive_entries: entries,
+                ..limits
+            };
+            assert_eq!(complete(limits).await, ok, "entries {}", entries);
+        }
+        for (depth, ok) in [(0, false), (1, true)] {
+            let limits = Limits {
+                archive_depth: depth,
+                ..limits
+            };
+            assert_eq!(complete(limits).await, ok, "depth {}", depth);
+        }
+        for (size, ok) in [(1009, false), (1010, true)] {
+            let limits = Limits {
+                archive_size: size,
+                ..limits
+            };
+            assert_eq!(complete(limits).await, ok, "size {}", size);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_archive_locks() {
+        let config = DavHandler::builder()
+            .archive_download(ArchiveFormats::all())
+            .locksystem(MemLs::new())
+            .get_respects_locks(true);
+        let client = setup(config).await;
+        let names = |res: Response<Bytes>| {
+            let entries = unzip(res.body());
+            entries.into_iter().map(|(n, _)| n).collect::<Vec<_>>()
+        };
+
+        // a file locked by someone else is left out.
+        let token = client.lock("/dir/a.txt", Depth::Zero).await.unwrap();
+        let res = get(&client, "/dir/", "application/zip").await;
+        assert!(!names(res).contains(&"dir/a.txt".to_string()));
+
+        // but not for the owner of the lock.
+        let req = Request::get("/dir/")
+            .header("Accept", "application/zip")
+            .header("If", format!("</dir/a.txt> (<{}>)", token))
+            .body(crate::body::Body::empty())
+            .unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(names(res).contains(&"dir/a.txt".to_string()));
+        assert_eq!(
+            client.unlock("/dir/a.txt", &token).await,
+            StatusCode::NO_CONTENT
+        );
+
+        // a locked collection is not sent at all.
+        client.lock("/dir/", Depth::Infinity).await.unwrap();
+        let res = get(&client, "/dir/", "application/zip").await;
+        assert_eq!(res.status(), StatusCode::LOCKED);
+    }
+
+    #[test]
+    fn test_crc() {
+        let mut c = Crc::new();
+        assert_eq!(c.sum(), 0);
+        c.update(b"12345");
+        c.update(b"6789");
+        assert_eq!(c.sum(), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_zip64() {
+        let mut w = ArchiveWriter::new(ArchiveFormat::Zip);
+        w.begin_file(b"big", 5 << 30, None, false);
+        w.end_file();
+        let data = w.take();
+        // the local header has a zip64 extra field, the data descriptor
+        // 64 bit sizes.
+        assert_eq!(u32_at(&data, 18), 0xffff_ffff);
+        assert_eq!(u16_at(&data, 28), 20);
+        assert_eq!(u16_at(&data, 33), 0x0001);
+        assert_eq!(data.len(), 33 + 20 + 24);
+        assert_eq!(
+            &data[data.len() - 16..data.len() - 8],
+            &(5u64 << 30).to_le_bytes()
+        );
+
+        // pretend that member took 5 GiB of archive.
+        w.offset = 6 << 30;
+        w.finish();
+        let data = w.take();
+        // central directory, zip64 end record, locator, end record.
+        let cd_len = 46 + 3 + 4 + 16;
+        assert_eq!(data.len(), cd_len + 56 + 20 + 22);
+        assert_eq!(u32_at(&data, cd_len), 0x0606_4b50);
+        assert_eq!(u32_at(&data, cd_len + 56), 0x0706_4b50);
+        let eocd = cd_len + 56 + 20;
+        assert_eq!(u32_at(&data, eocd + 12), cd_len);
+        assert_eq!(u32_at(&data, eocd + 16), 0xffff_ffff);
+    }
+}
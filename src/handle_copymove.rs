@@ -1,45 +1,92 @@
+use std::io::Cursor;
+
 use futures_util::{future::BoxFuture, FutureExt, StreamExt};
 use headers::HeaderMapExt;
 use http::{Request, Response, StatusCode};
+use xmltree::Element;
 
 use crate::async_stream::AsyncStream;
 use crate::body::Body;
-use crate::conditional::*;
 use crate::davheaders::{self, Depth};
 use crate::davpath::DavPath;
 use crate::errors::*;
 use crate::fs::*;
 use crate::multierror::{multi_error, MultiError};
-use crate::{util::DavMethod, DavResult};
+use crate::util::{dav_xml_error, limit_error, DavMethod, NS_DAV_SERVER_URI};
+use crate::xmltree_ext::*;
+use crate::DavResult;
+
+// Options of a COPY that are the same for every resource it copies.
+#[derive(Clone, Copy)]
+pub(crate) struct CopyOptions {
+    pub depth: Depth,
+    pub overwrite: bool,
+    // propertybehavior "omit": failing to copy a property is not an error.
+    pub omit: bool,
+}
+
+// The 403 for a COPY or MOVE between a collection and one of its
+// members. There is no standard DAV:error element for this.
+fn overlap_error(condition: &str) -> Response<Body> {
+    let xml = format!(r#"<S:{} xmlns:S="{}"/>"#, condition, NS_DAV_SERVER_URI);
+    let mut res = Response::new(dav_xml_error(&xml));
+    *res.status_mut() = StatusCode::FORBIDDEN;
+    res.headers_mut().insert(
+        "content-type",
+        "application/xml; charset=utf-8".parse().unwrap(),
+    );
+    res
+}
+
+// parse the body of a COPY or MOVE request, which can contain a
+// RFC 2518 propertybehavior element. Returns whether it is "omit".
+//
+// "keepalive" asks for the live properties to stay live at the
+// destination. They are computed by the server, so they always are.
+fn parse_propertybehavior(xmldata: &[u8]) -> DavResult<bool> {
+    if xmldata.is_empty() {
+        return Ok(false);
+    }
+    let tree = Element::parse2(Cursor::new(xmldata))?;
+    if tree.name != "propertybehavior" {
+        return Err(DavError::XmlParseError);
+    }
+    let behavior = tree.child_elems_iter().next().map(|e| e.name.as_str());
+    match behavior {
+        Some("omit") => Ok(true),
+        Some("keepalive") => Ok(false),
+        _ => Err(DavError::XmlParseError),
+    }
+}
 
 // map_err helper.
-async fn add_status<'a>(
+async fn add_status<'a, T>(
     m_err: &'a mut MultiError,
     path: &'a DavPath,
     e: impl Into<DavError> + 'static,
-) -> DavResult<()> {
+) -> DavResult<T> {
     let daverror = e.into();
-    if let Err(x) = m_err.add_status(path, daverror.statuscode()).await {
+    if let Err(x) = m_err.add_error(path, &daverror).await {
         return Err(x.into());
     }
     Err(daverror)
 }
 
 impl crate::DavInner {
+    // Returns whether an existing destination was replaced. That
+    // is only meaningful for the top-level destination.
     pub(crate) fn do_copy<'a>(
         &'a self,
         source: &'a DavPath,
         topdest: &'a DavPath,
         dest: &'a DavPath,
-        depth: Depth,
+        opts: CopyOptions,
         multierror: &'a mut MultiError,
-    ) -> BoxFuture<'a, DavResult<()>> {
+    ) -> BoxFuture<'a, DavResult<bool>> {
         async move {
-            // when doing "COPY /a/b /a/b/c make sure we don't recursively
-            // copy /a/b/c/ into /a/b/c.
-            if source == topdest {
-                return Ok(());
-            }
+            let CopyOptions {
+                depth, overwrite, ..
+            } = opts;
 
             // source must exist.
             let meta = match self.fs.metadata(source).await {
@@ -47,10 +94,32 @@ impl crate::DavInner {
                 Ok(m) => m,
             };
 
+            // For the destination itself, let the filesystem tell us
+            // atomically whether it exists: with Overwrite: F that is
+            // an error, otherwise we need to know it for the status.
+            let top = dest == topdest;
+
             // if it's a file we can overwrite it.
             if !meta.is_dir() {
-                return match self.fs.copy(source, dest).await {
-                    Ok(_) => Ok(()),
+                let res = if top {
+                    match self.fs.copy_no_replace(source, dest).await {
+                        Err(FsError::Exists) if overwrite => {
+                            self.fs.copy(source, dest).await.map(|_| true)
+                        }
+                        res => res.map(|_| false),
+                    }
+                } else {
+                    self.fs.copy(source, dest).await.map(|_| false)
+                };
+                multierror.progress(source);
+                return match res {
+                    Ok(replaced) => {
+                        self.copy_props(source, dest, opts.omit, multierror).await?;
+                        Ok(replaced)
+                    }
+                    Err(FsError::Exists) if top => {
+                        add_status(multierror, source, StatusCode::PRECONDITION_FAILED).await
+                    }
                     Err(e) => {
                         debug!("do_copy: self.fs.copy error: {:?}", e);
                         add_status(multierror, source, e).await
@@ -58,19 +127,47 @@ impl crate::DavInner {
                 };
             }
 
+            // let the filesystem copy the whole tree if it can. It only
+            // does that for a new destination, and it would not leave
+            // out reserved resources.
+            if top && depth == Depth::Infinity && !self.reserved.overlaps(source) {
+                match self.fs.copy_recursive(source, dest).await {
+                    Ok(()) => {
+                        multierror.progress(source);
+                        return Ok(false);
+                    }
+                    Err(FsError::Exists) if !overwrite => {
+                        return add_status(multierror, source, StatusCode::PRECONDITION_FAILED)
+                            .await;
+                    }
+                    Err(FsError::NotImplemented) | Err(FsError::Exists) => {}
+                    Err(e) => {
+                        debug!("do_copy: self.fs.copy_recursive error: {:?}", e);
+                        return add_status(multierror, source, e).await;
+                    }
+                }
+            }
+
             // Copying a directory onto an existing directory with Depth 0
-            // is not an error. It means "only copy properties" (which
-            // we do not do yet).
-            if let Err(e) = self.fs.create_dir(dest).await {
+            // is not an error. It means "only copy properties".
+            let mut replaced = false;
+            let types = meta.extra_resourcetypes();
+            if let Err(e) = self.fs.create_collection(dest, types).await {
+                if top && !overwrite && e == FsError::Exists {
+                    return add_status(multierror, source, StatusCode::PRECONDITION_FAILED).await;
+                }
                 if depth != Depth::Zero || e != FsError::Exists {
                     debug!("do_copy: self.fs.create_dir({}) error: {:?}", dest, e);
                     return add_status(multierror, dest, e).await;
                 }
+                replaced = true;
             }
+            multierror.progress(source);
+            self.copy_props(source, dest, opts.omit, multierror).await?;
 
             // only recurse when Depth > 0.
             if depth == Depth::Zero {
-                return Ok(());
+                return Ok(replaced);
             }
 
             let mut entries = match self.fs.read_dir(source, ReadDirMeta::DataSymlink).await {
@@ -83,7 +180,7 @@ impl crate::DavInner {
 
             // If we encounter errors, just print them, and keep going.
             // Last seen error is returned from function.
-            let mut retval = Ok::<_, DavError>(());
+            let mut retval = Ok::<_, DavError>(replaced);
             while let Some(dirent) = entries.next().await {
                 // NOTE: dirent.metadata() behaves like symlink_metadata()
                 let meta = match dirent.metadata().await {
@@ -100,11 +197,15 @@ impl crate::DavInner {
                     nsrc.add_slash();
                     ndest.add_slash();
                 }
-                // recurse.
-                if let Err(e) = self
-                    .do_copy(&nsrc, topdest, &ndest, depth, multierror)
-                    .await
+                // reserved members are not copied, and not copied to.
+                if (self.is_reserved_path(&nsrc) && !self.is_reserved_path(source))
+                    || (self.is_reserved_path(&ndest) && !self.is_reserved_path(dest))
                 {
+                    continue;
+                }
+                multierror.found();
+                // recurse.
+                if let Err(e) = self.do_copy(&nsrc, topdest, &ndest, opts, multierror).await {
                     retval = Err(e);
                 }
             }
@@ -114,6 +215,55 @@ impl crate::DavInner {
         .boxed()
     }
 
+    // Copy the dead properties of `source` to `dest`, if the filesystem
+    // has them and did not copy them along already. With
+    // propertybehavior "omit" a failure is only logged.
+    async fn copy_props(
+        &self,
+        source: &DavPath,
+        dest: &DavPath,
+        omit: bool,
+        multierror: &mut MultiError,
+    ) -> DavResult<()> {
+        if !self.fs.have_props(source).await {
+            return Ok(());
+        }
+        let res = match self.fs.get_props(source, true).await {
+            Ok(props) => {
+                if props.is_empty() {
+                    return Ok(());
+                }
+                let have = self.fs.get_props(dest, true).await.unwrap_or_default();
+                let copied = |p: &DavProp| {
+                    have.iter()
+                        .any(|h| h.name == p.name && h.namespace == p.namespace && h.xml == p.xml)
+                };
+                let patch: Vec<_> = props
+                    .into_iter()
+                    .filter(|p| !copied(p))
+                    .map(|p| (true, p))
+                    .collect();
+                if patch.is_empty() {
+                    return Ok(());
+                }
+                self.fs.patch_props(dest, patch).await
+            }
+            Err(e) => Err(e),
+        };
+        let status = match res {
+            Ok(st) => match st.into_iter().find(|(s, _)| !s.is_success()) {
+                Some((s, _)) => s,
+                None => return Ok(()),
+            },
+            Err(e) => DavError::from(e).statuscode(),
+        };
+        debug!("copy_props: {} -> {}: {}", source, dest, status);
+        if omit {
+            return Ok(());
+        }
+        add_status(multierror, source, status).await
+    }
+
     // Right now we handle MOVE with a simple RENAME. RFC4918 #9.9.2 talks
     // about "partially failed moves", which means that we might have to
     // try to move directories with increasing granularity to move as much
@@ -127,16 +277,30 @@ impl crate::DavInner {
     // .. so for perfect compliance we might have to process all resources
     // one-by-one anyway. But seriously, who cares.
     //
+    // Returns whether an existing destination was replaced.
     pub(crate) async fn do_move<'a>(
         &'a self,
         source: &'a DavPath,
         dest: &'a DavPath,
+        overwrite: bool,
         multierror: &'a mut MultiError,
-    ) -> DavResult<()> {
-        if let Err(e) = self.fs.rename(source, dest).await {
-            add_status(multierror, source, e).await
-        } else {
-            Ok(())
+    ) -> DavResult<bool> {
+        // let the filesystem tell us atomically whether the destination
+        // exists: with Overwrite: F that is an error, otherwise we
+        // need to know it for the status.
+        let res = match self.fs.rename_no_replace(source, dest).await {
+            Err(FsError::Exists) if overwrite => self.fs.rename(source, dest).await.map(|_| true),
+            res => res.map(|_| false),
+        };
+        match res {
+            Ok(replaced) => {
+                multierror.progress(source);
+                Ok(replaced)
+            }
+            Err(FsError::Exists) if !overwrite => {
+                add_status(multierror, source, StatusCode::PRECONDITION_FAILED).await
+            }
+            Err(e) => add_status(multierror, source, e).await,
         }
     }
 
@@ -144,32 +308,58 @@ impl crate::DavInner {
         self,
         req: &Request<()>,
         method: DavMethod,
+        xmldata: &[u8],
     ) -> DavResult<Response<Body>> {
+        let omit = parse_propertybehavior(xmldata)?;
+
         // get and check headers.
-        let overwrite = req
-            .headers()
-            .typed_get::<davheaders::Overwrite>()
-            .map_or(true, |o| o.0);
-        let depth = match req.headers().typed_get::<Depth>() {
-            Some(Depth::Infinity) | None => Depth::Infinity,
-            Some(Depth::Zero) if method == DavMethod::Copy => Depth::Zero,
+        let overwrite = match req.headers().typed_try_get::<davheaders::Overwrite>() {
+            Ok(Some(o)) => o.0,
+            Ok(None) => self.default_overwrite.unwrap_or(true),
+            Err(_) => return Err(StatusCode::BAD_REQUEST.into()),
+        };
+        let depth = match req.headers().typed_try_get::<Depth>() {
+            Ok(Some(Depth::Infinity)) | Ok(None) => Depth::Infinity,
+            Ok(Some(Depth::Zero)) if method == DavMethod::Copy => Depth::Zero,
             _ => return Err(StatusCode::BAD_REQUEST.into()),
         };
 
         // decode and validate destination.
+        if let Some(dest) = req.headers().get(&*davheaders::DESTINATION) {
+            if dest.len() > self.limits().destination_len {
+                return Ok(limit_error(StatusCode::BAD_REQUEST, "destination_len"));
+            }
+        }
         let dest = match req.headers().typed_get::<davheaders::Destination>() {
-            Some(dest) => DavPath::from_str_and_prefix(&dest.0, &self.prefix)?,
+            Some(mut dest) => {
+                // a fragment does not identify a different resource.
+                let strip = self.strip_destination_fragment.unwrap_or(true);
+                match dest.0.find('#') {
+                    Some(pos) if strip => dest.0.truncate(pos),
+                    Some(_) => return Err(StatusCode::BAD_REQUEST.into()),
+                    None => {}
+                }
+                DavPath::from_str_and_prefix(&dest.0, self.prefix())?
+            }
             None => return Err(StatusCode::BAD_REQUEST.into()),
         };
+        if self.is_reserved_path(&dest) {
+            return Err(StatusCode::FORBIDDEN.into());
+        }
 
         // for MOVE, tread with care- if the path ends in "/" but it actually
         // is a symlink, we want to move the symlink, not what it points to.
+        // If symlinks are hidden, the symlink does not exist.
         let mut path = self.path(req);
         let meta = if method == DavMethod::Move {
             let meta = self.fs.symlink_metadata(&path).await?;
             if meta.is_symlink() {
-                let m2 = self.fs.metadata(&path).await?;
-                path.add_slash_if(m2.is_dir());
+                if self.hide_symlinks.unwrap_or(true) {
+                    return Err(FsError::NotFound.into());
+                }
+                if let Ok(m2) = self.fs.metadata(&path).await {
+                    path.add_slash_if(m2.is_dir());
+                }
             }
             meta
         } else {
@@ -177,9 +367,24 @@ impl crate::DavInner {
         };
         path.add_slash_if(meta.is_dir());
 
-        // parent of the destination must exist.
-        if !self.has_parent(&dest).await {
-            return Err(StatusCode::CONFLICT.into());
+        // replacing `dest`, or moving `path`, would take the reserved
+        // paths below them along.
+        if self.reserved.prefix_below(&dest)
+            || (method == DavMethod::Move && self.reserved.prefix_below(&path))
+        {
+            return Err(StatusCode::FORBIDDEN.into());
+        }
+
+        // a collection cannot be copied or moved into itself, and
+        // not onto a collection that it is in, as that would delete it.
+        if path == dest {
+            return Err(StatusCode::FORBIDDEN.into());
+        }
+        if meta.is_dir() && depth != Depth::Zero && path.is_ancestor_of(&dest) {
+            return Ok(overlap_error("destination-in-source"));
+        }
+        if dest.is_ancestor_of(&path) && overwrite {
+            return Ok(overlap_error("source-in-destination"));
         }
 
         // for the destination, also check if it's a symlink. If we are going
@@ -200,49 +405,82 @@ impl crate::DavInner {
             Err(e) => (false, Err(e)),
         };
 
-        // check if overwrite is "F"
+        // check if overwrite is "F". This is checked again, atomically,
+        // when the destination is created.
         let exists = dmeta.is_ok();
         if !overwrite && exists {
             return Err(StatusCode::PRECONDITION_FAILED.into());
         }
-
-        // check if source == dest
-        if path == dest {
-            return Err(StatusCode::FORBIDDEN.into());
+        // a new destination needs an existing parent. An existing one
+        // does not, it might be in a collection that we cannot read.
+        if !exists {
+            self.check_parent(&dest).await?;
+            self.check_windows_name(&dest)?;
         }
 
-        // check If and If-* headers for source URL
-        let tokens = match if_match_get_tokens(req, Some(&meta), &self.fs, &self.ls, &path).await {
+        // check If and If-* headers. The If-* headers are about the source
+        // URL. Lists in the If header that are tagged with the destination
+        // URL are evaluated against the destination.
+        let tokens = match self.if_match_get_tokens(req, Some(&*meta), &path).await {
             Ok(t) => t,
-            Err(s) => return Err(s.into()),
+            Err(s) => return Ok(self.precondition_response(s, &path, Some(&*meta)).await),
         };
 
         // check locks. since we cancel the entire operation if there is
         // a conflicting lock, we do not return a 207 multistatus, but
-        // just a simple status.
+        // just a 423 that names the lock.
         if let Some(ref locksystem) = self.ls {
             let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             let principal = self.principal.as_deref();
             if method == DavMethod::Move {
                 // for MOVE check if source path is locked
-                if let Err(_l) = locksystem.check(&path, principal, false, true, t.clone()) {
-                    return Err(StatusCode::LOCKED.into());
+                if let Err(l) = locksystem.check(&path, principal, false, true, t.clone()) {
+                    return self.locked_response(&l, &tokens);
                 }
             }
             // for MOVE and COPY check if destination is locked
-            if let Err(_l) = locksystem.check(&dest, principal, false, true, t) {
-                return Err(StatusCode::LOCKED.into());
+            if let Err(l) = locksystem.check(&dest, principal, false, true, t.clone()) {
+                return self.locked_response(&l, &tokens);
             }
+            // a new destination is a new member of its parent collection,
+            // which a depth 0 lock on the collection protects as well.
+            if !exists {
+                if let Err(l) = locksystem.check(&dest.parent(), principal, false, false, t) {
+                    return self.locked_response(&l, &tokens);
+                }
+            }
+        }
+        if self.dry_run {
+            return Ok(Response::new(Body::empty()));
         }
 
+        // a COPY or MOVE of a whole collection can take longer than the
+        // client is willing to wait. With async_copymove it continues
+        // in the background.
+        let job = if meta.is_dir() && depth == Depth::Infinity {
+            self.start_job(req, &path)?
+        } else {
+            None
+        };
         let req_path = path.clone();
 
+        let progress = job.clone();
         let items = AsyncStream::new(|tx| {
             async move {
-                let mut multierror = MultiError::new(tx);
+                let details = self.error_details.unwrap_or(false);
+                let mut multierror = MultiError::new(tx, details).with_job(progress);
 
-                // see if we need to delete the destination first.
-                if overwrite && exists && depth != Depth::Zero && !dest_is_file {
+                // see if we need to delete the destination first (RFC4918 9.8.4,
+                // 9.9.3). A file that replaces a file does not need that, the
+                // filesystem does it atomically. A collection is deleted first,
+                // unless it only gets the properties of a Depth 0 COPY.
+                let delete_first = if dest_is_file {
+                    meta.is_dir()
+                } else {
+                    !meta.is_dir() || depth != Depth::Zero
+                };
+                let mut deleted = false;
+                if overwrite && exists && delete_first {
                     trace!("handle_copymove: deleting destination {}", dest);
                     if self
                         .delete_items(&mut multierror, Depth::Infinity, dmeta.unwrap(), &dest)
@@ -255,40 +493,653 @@ impl crate::DavInner {
                     if let Some(ref locksystem) = self.ls {
                         let _ = locksystem.delete(&dest);
                     }
+                    deleted = true;
                 }
 
-                // COPY or MOVE.
-                if method == DavMethod::Copy {
-                    if self
-                        .do_copy(&path, &dest, &dest, depth, &mut multierror)
+                // COPY or MOVE. The status depends on whether the destination
+                // existed, as reported by the filesystem operation itself.
+                let res = if method == DavMethod::Copy {
+                    let opts = CopyOptions {
+                        depth,
+                        overwrite,
+                        omit,
+                    };
+                    self.do_copy(&path, &dest, &dest, opts, &mut multierror)
                         .await
-                        .is_ok()
-                    {
-                        let s = if exists {
-                            StatusCode::NO_CONTENT
-                        } else {
-                            StatusCode::CREATED
-                        };
-                        let _ = multierror.add_status(&path, s).await;
-                    }
                 } else {
-                    // move and if successful, remove locks at old location.
-                    if self.do_move(&path, &dest, &mut multierror).await.is_ok() {
-                        if let Some(ref locksystem) = self.ls {
+                    self.do_move(&path, &dest, overwrite, &mut multierror).await
+                };
+                if let Ok(replaced) = res {
+                    // locks do not go with the resource (RFC4918 7.5, 9.8.5, 9.9.1).
+                    // After a MOVE the locks on the source are stale, and the
+                    // destination is a new resource that is only covered by
+                    // the depth infinity locks of its parents, if any.
+                    if let Some(ref locksystem) = self.ls {
+                        if method == DavMethod::Move {
                             locksystem.delete(&path).ok();
                         }
-                        let s = if exists {
-                            StatusCode::NO_CONTENT
-                        } else {
-                            StatusCode::CREATED
-                        };
-                        let _ = multierror.add_status(&path, s).await;
+                        locksystem.delete(&dest).ok();
                     }
+                    let s = if replaced || deleted {
+                        StatusCode::NO_CONTENT
+                    } else {
+                        StatusCode::CREATED
+                    };
+                    let _ = multierror.add_status(&path, s).await;
+                } else if deleted {
+                    // the source has reported its error. Do not pretend that
+                    // nothing happened, the destination is gone.
+                    let msg = "destination was deleted, but could not be replaced";
+                    let _ = multierror.add_described(&dest, StatusCode::OK, msg).await;
                 }
                 Ok::<_, DavError>(())
             }
         });
 
-        multi_error(req_path, items).await
+        let job = match job {
+            Some(job) => job,
+            None => return multi_error(req_path, items).await,
+        };
+        let res = job.accepted();
+        tokio::spawn(async move {
+            let res = multi_error(req_path, items).await;
+            job.finish(res).await;
+        });
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::testfs::{MockAction, MockFs, MockOp};
+    use crate::testing::DavClient;
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn test_move_no_overwrite_race() {
+        // yield to other tasks after every metadata lookup, so that
+        // concurrent requests interleave between their checks and their writes.
+        let fs = MockFs::builder()
+            .rule(MockOp::Metadata, "*", MockAction::Yield, None)
+            .build();
+        let dav = DavHandler::builder().filesystem(fs).build_handler();
+        let client = DavClient::new(dav);
+        let move_to_dest = |from: &str| {
+            Request::builder()
+                .method("MOVE")
+                .uri(from)
+                .header("Destination", "/dest")
+                .header("Overwrite", "F")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for _ in 0..10 {
+            assert_eq!(client.put_bytes("/a", "a").await, StatusCode::CREATED);
+            assert_eq!(client.put_bytes("/b", "b").await, StatusCode::CREATED);
+
+            // both pass the existence check, only one may win.
+            let (ra, rb) = tokio::join!(
+                client.request(move_to_dest("/a")),
+                client.request(move_to_dest("/b")),
+            );
+            let mut status = [ra.status(), rb.status()];
+            status.sort();
+            assert_eq!(
+                status,
+                [StatusCode::CREATED, StatusCode::PRECONDITION_FAILED]
+            );
+
+            // the loser is still there.
+            let loser = if ra.status() == StatusCode::CREATED {
+                "/b"
+            } else {
+                "/a"
+            };
+            assert_eq!(client.get(loser).await.status(), StatusCode::OK);
+            assert_eq!(client.delete(loser).await, StatusCode::NO_CONTENT);
+            assert_eq!(client.delete("/dest").await, StatusCode::NO_CONTENT);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_copymove_status() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/a", "a").await, StatusCode::CREATED);
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+
+        // new destination: 201, existing destination: 204.
+        assert_eq!(client.copy("/a", "/b", true).await, StatusCode::CREATED);
+        assert_eq!(client.copy("/a", "/b", true).await, StatusCode::NO_CONTENT);
+        assert_eq!(client.move_("/b", "/c", true).await, StatusCode::CREATED);
+        assert_eq!(client.copy("/a", "/b", true).await, StatusCode::CREATED);
+        assert_eq!(client.move_("/b", "/c", true).await, StatusCode::NO_CONTENT);
+        assert_eq!(
+            client.copy("/dir/", "/dir2/", true).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            client.copy("/dir/", "/dir2/", true).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            client.move_("/dir2/", "/dir/", true).await,
+            StatusCode::NO_CONTENT
+        );
+
+        // Overwrite: F.
+        assert_eq!(
+            client.copy("/a", "/c", false).await,
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(client.get("/c").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_copy_dead_props() {
+        use crate::testing::Depth as D;
+
+        // no properties can be stored on paths under "/ro".
+        let fs = MockFs::builder()
+            .fail(MockOp::PatchProps, "/ro*", FsError::Forbidden)
+            .build();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        let client = DavClient::new(dav);
+        let proppatch = |path: &'static str| {
+            let xml = concat!(
+                r#"<D:propertyupdate xmlns:D="DAV:" xmlns:X="urn:x">"#,
+                r#"<D:set><D:prop><X:color>red</X:color></D:prop></D:set>"#,
+                r#"</D:propertyupdate>"#,
+            );
+            let req = Request::builder().method("PROPPATCH").uri(path);
+            client.request(req.body(Body::from(xml)).unwrap())
+        };
+        let copy =
+            |from: &'static str, to: &'static str, depth: &'static str, body: &'static str| {
+                let req = Request::builder()
+                    .method("COPY")
+                    .uri(from)
+                    .header("Destination", format!("http://localhost{}", to))
+                    .header("Depth", depth);
+                client.request(req.body(Body::from(body)).unwrap())
+            };
+        let color = |path: &'static str| {
+            let client = &client;
+            async move {
+                let ms = client
+                    .propfind(path, D::Zero, &["{urn:x}color"])
+                    .await
+                    .unwrap();
+                ms.responses[0].prop_text("{urn:x}color")
+            }
+        };
+
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        assert_eq!(
+            client.put_bytes("/dir/file", "x").await,
+            StatusCode::CREATED
+        );
+        assert_eq!(proppatch("/dir/").await.status(), StatusCode::MULTI_STATUS);
+        assert_eq!(
+            proppatch("/dir/file").await.status(),
+            StatusCode::MULTI_STATUS
+        );
+
+        // collections and their members keep their dead properties.
+        // MemFs copies those of a file along, so only the collection
+        // is patched.
+        let patches = fs.calls(MockOp::PatchProps);
+        assert_eq!(
+            copy("/dir/", "/dir2/", "infinity", "").await.status(),
+            StatusCode::CREATED
+        );
+        assert_eq!(color("/dir2/").await.as_deref(), Some("red"));
+        assert_eq!(color("/dir2/file").await.as_deref(), Some("red"));
+        assert_eq!(fs.calls(MockOp::PatchProps), patches + 1);
+
+        // and a file that the filesystem copied with its properties
+        // does not need a patch that would fail.
+        let res = copy("/dir/file", "/rofile", "0", "").await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(color("/rofile").await.as_deref(), Some("red"));
+
+        // Depth 0 onto an existing collection copies only the properties.
+        assert_eq!(client.mkcol("/dir3/").await, StatusCode::CREATED);
+        assert_eq!(
+            copy("/dir/", "/dir3/", "0", "").await.status(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(color("/dir3/").await.as_deref(), Some("red"));
+
+        // if they cannot be copied, that is an error, unless "omit" was asked for.
+        let res = copy("/dir/", "/rodir/", "0", "").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let omit = r#"<D:propertybehavior xmlns:D="DAV:"><D:omit/></D:propertybehavior>"#;
+        let res = copy("/dir/", "/rodir2/", "0", omit).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(color("/rodir2/").await, None);
+
+        let keepalive = concat!(
+            r#"<D:propertybehavior xmlns:D="DAV:">"#,
+            r#"<D:keepalive>*</D:keepalive></D:propertybehavior>"#
+        );
+        let res = copy("/dir/", "/dir4/", "infinity", keepalive).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(color("/dir4/file").await.as_deref(), Some("red"));
+
+        let res = copy("/dir/", "/dir5/", "infinity", "<D:bogus xmlns:D=\"DAV:\"/>").await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_destination_query() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/a", "x").await, StatusCode::CREATED);
+        assert_eq!(client.mkcol("/foo/").await, StatusCode::CREATED);
+
+        let to = "http://localhost/b?token=123";
+        assert_eq!(client.copy("/a", to, false).await, StatusCode::CREATED);
+        assert_eq!(
+            client.move_("/b", "/c?x=y", false).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(client.get("/c").await.body().as_ref(), b"x");
+        assert_eq!(client.get("/b").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            client.copy("/a", "/foo/bar.txt?x=1", false).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(client.get("/foo/bar.txt").await.status(), StatusCode::OK);
+
+        // an encoded "?" or "#" is part of the name.
+        assert_eq!(
+            client.copy("/a", "/foo/b%3Fx%23y.txt", false).await,
+            StatusCode::CREATED
+        );
+        let res = client.get("/foo/b%3Fx%23y.txt").await;
+        assert_eq!(res.body().as_ref(), b"x");
+
+        // a fragment is dropped as well.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/a", "x").await, StatusCode::CREATED);
+        let to = "http://localhost/b?token=123#frag";
+        assert_eq!(client.copy("/a", to, false).await, StatusCode::CREATED);
+        assert_eq!(
+            client.move_("/b", "/c#frag", false).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(client.get("/c").await.body().as_ref(), b"x");
+
+        // unless that is disabled.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_destination_fragment(false)
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/a", "x").await, StatusCode::CREATED);
+        let to = "http://localhost/b?token=123#frag";
+        assert_eq!(client.copy("/a", to, false).await, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_destination_encoding() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        let move_ = |from: &str, to: &[u8]| {
+            let req = Request::builder()
+                .method("MOVE")
+                .uri(from)
+                .header("Destination", http::HeaderValue::from_bytes(to).unwrap())
+                .body(Body::empty())
+                .unwrap();
+            client.request(req)
+        };
+
+        assert_eq!(
+            client.put_bytes("/a%20b.txt", "x").await,
+            StatusCode::CREATED
+        );
+        // the same resource, spelled differently.
+        let res = move_("/a%20b.txt", b"http://localhost/a b.txt").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // raw UTF-8, and percent-encoded UTF-8.
+        let res = move_("/a%20b.txt", "http://localhost/\u{e4} b.txt".as_bytes()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let res = move_("/%C3%A4%20b.txt", b"/%C3%B6%20b.txt").await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(client.get("/%C3%B6%20b.txt").await.status(), StatusCode::OK);
+        assert_eq!(
+            client.get("/%C3%A4%20b.txt").await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        let res = move_("/%C3%B6%20b.txt", b"/c.txt#frag").await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(client.get("/c.txt").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_copymove_overlap() {
+        for method in ["COPY", "MOVE"] {
+            let dav = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .build_handler();
+            let client = DavClient::new(dav);
+            assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+            assert_eq!(client.mkcol("/dir/sub/").await, StatusCode::CREATED);
+            assert_eq!(
+                client.put_bytes("/dir/sub/file", "x").await,
+                StatusCode::CREATED
+            );
+            let send = |from: &str, to: &str, headers: &[(&str, &str)]| {
+                let mut req = Request::builder()
+                    .method(method)
+                    .uri(from)
+                    .header("Destination", to);
+                for (name, value) in headers {
+                    req = req.header(*name, *value);
+                }
+                client.request(req.body(Body::empty()).unwrap())
+            };
+
+            // the same resource.
+            let res = send("/dir/", "/dir", &[("Overwrite", "F")]).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "{}", method);
+
+            // into itself.
+            let res = send("/dir/", "/dir/sub/new/", &[]).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "{}", method);
+            let body = String::from_utf8_lossy(res.body());
+            assert!(body.contains(r#"<S:destination-in-source xmlns:S="urn:dav-server:"/>"#));
+
+            // onto a collection that it is in.
+            let res = send("/dir/sub/", "/dir/", &[]).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "{}", method);
+            let body = String::from_utf8_lossy(res.body());
+            assert!(body.contains("source-in-destination"));
+            let res = send("/dir/sub/", "/dir/", &[("Overwrite", "F")]).await;
+            assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+            assert_eq!(client.get("/dir/sub/file").await.status(), StatusCode::OK);
+
+            // a sibling with the same prefix is not related.
+            let res = send("/dir/", "/dir2/", &[]).await;
+            assert_eq!(res.status(), StatusCode::CREATED, "{}", method);
+            assert_eq!(client.get("/dir2/sub/file").await.status(), StatusCode::OK);
+        }
+
+        // a COPY of only the collection itself is fine.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        let req = Request::builder()
+            .method("COPY")
+            .uri("/dir/")
+            .header("Destination", "/dir/copy/")
+            .header("Depth", "0")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(client.request(req).await.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_move_overwrite_collection() {
+        use crate::testfs::{MockFs, MockOp};
+        use crate::testing::{Depth, Multistatus};
+
+        let fs = MemFs::new();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        let client = DavClient::new(dav);
+        let setup = || async {
+            assert!(client.put_bytes("/file", "data").await.is_success());
+            assert!(client.mkcol("/dir/").await.is_success());
+            assert!(client.put_bytes("/dir/member", "x").await.is_success());
+        };
+
+        // a file onto a collection that is not empty.
+        setup().await;
+        assert_eq!(
+            client.move_("/file", "/dir/", false).await,
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(
+            client.move_("/file", "/dir", true).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(client.get("/dir").await.body().as_ref(), b"data");
+        let ms = client.propfind("/", Depth::One, &[]).await.unwrap();
+        assert_eq!(ms.hrefs(), ["/", "/dir"]);
+        assert_eq!(client.delete("/dir").await, StatusCode::NO_CONTENT);
+
+        // a collection onto a file.
+        setup().await;
+        assert_eq!(
+            client.move_("/dir/", "/file", false).await,
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(
+            client.move_("/dir/", "/file", true).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(client.get("/file/member").await.body().as_ref(), b"x");
+        let ms = client.propfind("/", Depth::One, &[]).await.unwrap();
+        assert_eq!(ms.hrefs(), ["/", "/file/"]);
+
+        // if the move fails after the destination was deleted, say so.
+        let fs = MockFs::builder()
+            .filesystem(fs)
+            .fail(MockOp::Rename, "/file/", FsError::Forbidden)
+            .build();
+        let dav = DavHandler::builder().filesystem(fs).build_handler();
+        let client = DavClient::new(dav);
+        assert!(client.put_bytes("/dest", "keep").await.is_success());
+        let req = Request::builder()
+            .method("MOVE")
+            .uri("/file/")
+            .header("Destination", "/dest")
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let ms = Multistatus::parse(res.body()).unwrap();
+        assert_eq!(ms.hrefs(), ["/file/", "/dest"]);
+        assert_eq!(
+            ms.response("/file/").unwrap().status,
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(ms.response("/dest").unwrap().status, Some(StatusCode::OK));
+        assert!(String::from_utf8_lossy(res.body()).contains("destination was deleted"));
+        assert_eq!(client.get("/dest").await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_copymove_locks() {
+        use crate::ls::DavLockSystem;
+        use crate::memls::MemLs;
+        use crate::testing::Depth;
+
+        let ls = MemLs::new();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(ls.clone())
+            .build_handler();
+        let client = DavClient::new(dav);
+        let discover = |path: &str| ls.discover(&DavPath::new(path).unwrap());
+        // `token` is for the resource `locked`.
+        let request = |method: &str, from: &str, to: &str, locked: &str, token: &str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(from)
+                .header("Destination", to)
+                .header("If", format!("<{}> (<{}>)", locked, token))
+                .body(Body::empty())
+                .unwrap();
+            client.request(req)
+        };
+
+        // MOVE leaves the lock on the source behind, and removes it.
+        assert_eq!(client.put_bytes("/a", "a").await, StatusCode::CREATED);
+        let token = client.lock("/a", Depth::Zero).await.unwrap();
+        let res = request("MOVE", "/a", "/b", "/a", &token).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert!(discover("/a").is_empty());
+        assert!(discover("/b").is_empty());
+        assert_eq!(client.put_bytes("/a", "a").await, StatusCode::CREATED);
+
+        // a resource that is replaced by COPY or MOVE loses its locks.
+        assert_eq!(client.put_bytes("/c", "c").await, StatusCode::CREATED);
+        for method in ["COPY", "MOVE"] {
+            let token = client.lock("/b", Depth::Zero).await.unwrap();
+            let res = request(method, "/c", "/b", "/b", &token).await;
+            assert_eq!(res.status(), StatusCode::NO_CONTENT);
+            assert!(discover("/b").is_empty());
+        }
+
+        // but stays covered by the lock of its parent.
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        let token = client.lock("/dir/", Depth::Infinity).await.unwrap();
+        let res = request("COPY", "/a", "/dir/a", "/dir/", &token).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(discover("/dir/a").len(), 1);
+        assert_eq!(client.put_bytes("/dir/a", "x").await, StatusCode::LOCKED);
+    }
+
+    #[tokio::test]
+    async fn test_destination_conditions() {
+        use crate::memls::MemLs;
+        use crate::testing::Depth;
+
+        let fs = MemFs::new();
+        let ls = MemLs::new();
+        let client = |principal: &str| {
+            let dav = DavHandler::builder()
+                .filesystem(fs.clone())
+                .locksystem(ls.clone())
+                .principal(principal)
+                .build_handler();
+            DavClient::new(dav)
+        };
+        let (alice, bob) = (client("alice"), client("bob"));
+        let copy = |to: &str, cond: Option<String>| {
+            let mut req = Request::builder()
+                .method("COPY")
+                .uri("/src")
+                .header("Destination", format!("http://localhost{}", to));
+            if let Some(cond) = cond {
+                req = req.header("If", cond);
+            }
+            bob.request(req.body(Body::empty()).unwrap())
+        };
+
+        assert_eq!(bob.put_bytes("/src", "new").await, StatusCode::CREATED);
+        assert_eq!(bob.put_bytes("/dest", "old").await, StatusCode::CREATED);
+        assert_eq!(bob.mkcol("/dir/").await, StatusCode::CREATED);
+
+        // the destination is locked by somebody else.
+        let token = alice.lock("/dest", Depth::Zero).await.unwrap();
+        let res = copy("/dest", None).await;
+        assert_eq!(res.status(), StatusCode::LOCKED);
+        let body = String::from_utf8_lossy(res.body());
+        assert!(body.contains("<D:lock-token-submitted><D:href>/dest</D:href>"));
+        let res = copy("/dest", Some(format!("</dest> (<{}>)", token))).await;
+        assert_eq!(res.status(), StatusCode::LOCKED);
+        assert_eq!(alice.unlock("/dest", &token).await, StatusCode::NO_CONTENT);
+
+        // a new member of a collection with a depth 0 lock.
+        let token = alice.lock("/dir/", Depth::Zero).await.unwrap();
+        let res = copy("/dir/new", None).await;
+        assert_eq!(res.status(), StatusCode::LOCKED);
+        let body = String::from_utf8_lossy(res.body());
+        assert!(body.contains("<D:href>/dir/</D:href>"));
+        assert_eq!(alice.unlock("/dir/", &token).await, StatusCode::NO_CONTENT);
+        assert_eq!(copy("/dir/new", None).await.status(), StatusCode::CREATED);
+
+        // an etag condition tagged with the destination.
+        let etag = bob.get("/dest").await.headers()["etag"].clone();
+        let etag = etag.to_str().unwrap();
+        let res = copy("/dest", Some("</dest> ([\"nope\"])".to_string())).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(bob.get("/dest").await.body().as_ref(), b"old");
+        let res = copy(
+            "/dest",
+            Some(format!("<http://localhost/dest> ([{}])", etag)),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(bob.get("/dest").await.body().as_ref(), b"new");
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_header() {
+        let request = |method: &str, overwrite: Option<&str>| {
+            let mut req = Request::builder()
+                .method(method)
+                .uri("/src")
+                .header("Destination", "/dest");
+            if let Some(o) = overwrite {
+                req = req.header("Overwrite", o);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/src", "src").await, StatusCode::CREATED);
+        assert_eq!(client.put_bytes("/dest", "dest").await, StatusCode::CREATED);
+
+        // case-insensitive.
+        for o in ["f", "F"] {
+            let res = client.request(request("COPY", Some(o))).await;
+            assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        }
+        let res = client.request(request("COPY", Some("t"))).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        // anything else is an error, and nothing happens.
+        for o in ["yes", "TRUE", ""] {
+            let res = client.request(request("MOVE", Some(o))).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+        assert_eq!(client.get("/src").await.status(), StatusCode::OK);
+
+        // absent is "T" by default.
+        assert_eq!(
+            client.put_bytes("/dest", "dest").await,
+            StatusCode::NO_CONTENT
+        );
+        let res = client.request(request("MOVE", None)).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(client.get("/dest").await.body().as_ref(), b"src");
+
+        // unless configured otherwise.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .default_overwrite(false)
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/src", "src").await, StatusCode::CREATED);
+        assert_eq!(client.put_bytes("/dest", "dest").await, StatusCode::CREATED);
+        let res = client.request(request("MOVE", None)).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(client.get("/dest").await.body().as_ref(), b"dest");
+        let res = client.request(request("MOVE", Some("T"))).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
     }
 }
@@ -4,7 +4,6 @@ use http::{Request, Response, StatusCode};
 
 use crate::async_stream::AsyncStream;
 use crate::body::Body;
-use crate::conditional::if_match_get_tokens;
 use crate::davheaders::Depth;
 use crate::davpath::DavPath;
 use crate::errors::*;
@@ -14,8 +13,9 @@ use crate::DavResult;
 
 // map_err helper.
 async fn add_status<'a>(m_err: &'a mut MultiError, path: &'a DavPath, e: FsError) -> DavError {
-    let status = DavError::FsError(e).statuscode();
-    if let Err(x) = m_err.add_status(path, status).await {
+    let err = DavError::FsError(e);
+    let status = err.statuscode();
+    if let Err(x) = m_err.add_error(path, &err).await {
         return x.into();
     }
     DavError::Status(status)
@@ -24,11 +24,12 @@ async fn add_status<'a>(m_err: &'a mut MultiError, path: &'a DavPath, e: FsError
 // map_err helper for directories, the result statuscode
 // mappings are not 100% the same.
 async fn dir_status<'a>(res: &'a mut MultiError, path: &'a DavPath, e: FsError) -> DavError {
-    let status = match e {
-        FsError::Exists => StatusCode::CONFLICT,
-        e => DavError::FsError(e).statuscode(),
+    let err = match e {
+        FsError::Exists => DavError::Status(StatusCode::CONFLICT),
+        e => DavError::FsError(e),
     };
-    if let Err(x) = res.add_status(path, status).await {
+    let status = err.statuscode();
+    if let Err(x) = res.add_error(path, &err).await {
         return x.into();
     }
     DavError::Status(status)
@@ -66,21 +67,23 @@ impl crate::DavInner {
 
             let mut result = Ok(());
             while let Some(dirent) = entries.next().await {
-                // if metadata() fails, skip to next entry.
+                let mut npath = path.clone();
+                npath.push_segment(&dirent.name());
+
+                // if metadata() fails, report the entry and skip to the next one.
                 // NOTE: dirent.metadata == symlink_metadata (!)
                 let meta = match dirent.metadata().await {
                     Ok(m) => m,
                     Err(e) => {
-                        result = Err(add_status(res, path, e).await);
+                        result = Err(add_status(res, &npath, e).await);
                         continue;
                     }
                 };
-
-                let mut npath = path.clone();
-                npath.push_segment(&dirent.name());
                 npath.add_slash_if(meta.is_dir());
 
-                // do the actual work. If this fails with a non-fs related error,
+                // do the actual work. A failed entry has already reported
+                // itself (and none of its descendants), so go on with the
+                // next one. If this fails with a non-fs related error,
                 // return immediately.
                 if let Err(e) = self.delete_items(res, depth, meta, &npath).await {
                     match e {
@@ -108,9 +111,9 @@ impl crate::DavInner {
     pub(crate) async fn handle_delete(self, req: &Request<()>) -> DavResult<Response<Body>> {
         // RFC4918 9.6.1 DELETE for Collections.
         // Note that allowing Depth: 0 is NOT RFC compliant.
-        let depth = match req.headers().typed_get::<Depth>() {
-            Some(Depth::Infinity) | None => Depth::Infinity,
-            Some(Depth::Zero) => Depth::Zero,
+        let depth = match req.headers().typed_try_get::<Depth>() {
+            Ok(Some(Depth::Infinity)) | Ok(None) => Depth::Infinity,
+            Ok(Some(Depth::Zero)) => Depth::Zero,
             _ => return Err(DavError::Status(StatusCode::BAD_REQUEST)),
         };
 
@@ -124,10 +127,10 @@ impl crate::DavInner {
         path.add_slash_if(meta.is_dir());
 
         // check the If and If-* headers.
-        let tokens_res = if_match_get_tokens(req, Some(&meta), &self.fs, &self.ls, &path).await;
+        let tokens_res = self.if_match_get_tokens(req, Some(&*meta), &path).await;
         let tokens = match tokens_res {
             Ok(t) => t,
-            Err(s) => return Err(DavError::Status(s)),
+            Err(s) => return Ok(self.precondition_response(s, &path, Some(&*meta)).await),
         };
 
         // check locks. since we cancel the entire operation if there is
@@ -136,17 +139,20 @@ impl crate::DavInner {
         if let Some(ref locksystem) = self.ls {
             let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             let principal = self.principal.as_deref();
-            if let Err(_l) = locksystem.check(&path, principal, false, true, t) {
-                return Err(DavError::Status(StatusCode::LOCKED));
+            if let Err(l) = locksystem.check(&path, principal, false, true, t) {
+                return Err(DavError::Status(self.lock_status(&l, &tokens)));
             }
         }
+        if self.dry_run {
+            return Ok(Response::new(Body::empty()));
+        }
 
         let req_path = path.clone();
 
         let items = AsyncStream::new(|tx| {
             async move {
                 // turn the Sink into something easier to pass around.
-                let mut multierror = MultiError::new(tx);
+                let mut multierror = MultiError::new(tx, self.error_details.unwrap_or(false));
 
                 // now delete the path recursively.
                 let fut = self.delete_items(&mut multierror, depth, meta, &path);
@@ -165,3 +171,148 @@ impl crate::DavInner {
         multi_error(req_path, items).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::testfs::{MockFs, MockOp};
+    use crate::testing::{DavClient, Multistatus};
+    use crate::DavHandler;
+
+    // MemFs that refuses to remove anything named "keep" or "keepdir",
+    // and to list the contents of "unreadable". Removing "throttled"
+    // fails like a storage provider that is busy.
+    fn sticky_fs() -> Box<MockFs> {
+        let throttled = FsError::other("SlowDown: reduce your request rate")
+            .with_status_hint(StatusCode::SERVICE_UNAVAILABLE)
+            .with_retry_after(std::time::Duration::from_secs(5));
+        let mut builder = MockFs::builder()
+            .fail(MockOp::ReadDir, "*/unreadable", FsError::Forbidden)
+            .fail(MockOp::RemoveFile, "*/throttled", throttled);
+        for op in [MockOp::RemoveDir, MockOp::RemoveFile] {
+            builder = builder.fail(op, "*/keep", FsError::Forbidden).fail(
+                op,
+                "*/keepdir",
+                FsError::Forbidden,
+            );
+        }
+        builder.build()
+    }
+
+    #[tokio::test]
+    async fn test_delete_partial_failure() {
+        let dav = DavHandler::builder()
+            .filesystem(sticky_fs())
+            .build_handler();
+        let client = DavClient::new(dav);
+        for path in [
+            "/dir/",
+            "/dir/sub/",
+            "/dir/sub/keepdir/",
+            "/dir/unreadable/",
+        ] {
+            assert_eq!(client.mkcol(path).await, StatusCode::CREATED);
+        }
+        for path in [
+            "/dir/a",
+            "/dir/keep",
+            "/dir/z",
+            "/dir/sub/b",
+            "/dir/sub/keepdir/x",
+            "/dir/unreadable/keep",
+        ] {
+            assert_eq!(client.put_bytes(path, "x").await, StatusCode::CREATED);
+        }
+
+        // one response per failed member, none for its ancestors or descendants.
+        let req = Request::delete("/dir/").body(Body::empty()).unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let ms = Multistatus::parse(res.body()).unwrap();
+        let mut hrefs = ms.hrefs();
+        hrefs.sort_unstable();
+        assert_eq!(
+            hrefs,
+            ["/dir/keep", "/dir/sub/keepdir/", "/dir/unreadable/"]
+        );
+        for resp in &ms.responses {
+            assert_eq!(resp.status, Some(StatusCode::FORBIDDEN));
+        }
+
+        // everything else is gone.
+        for path in ["/dir/a", "/dir/z", "/dir/sub/b", "/dir/sub/keepdir/x"] {
+            assert_eq!(client.get(path).await.status(), StatusCode::NOT_FOUND);
+        }
+        assert_eq!(client.get("/dir/keep").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_delete_status_hint() {
+        let fs = sticky_fs();
+        let client = DavClient::new(DavHandler::builder().filesystem(fs.clone()).build_handler());
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        for path in ["/throttled", "/dir/a", "/dir/throttled"] {
+            assert_eq!(client.put_bytes(path, "x").await, StatusCode::CREATED);
+        }
+
+        // the status hint and Retry-After make it into the response.
+        let req = Request::delete("/throttled").body(Body::empty()).unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()["retry-after"], "5");
+
+        // and the multistatus, but the message only if allowed.
+        let req = Request::delete("/dir/").body(Body::empty()).unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let ms = Multistatus::parse(res.body()).unwrap();
+        let resp = ms.response("/dir/throttled").unwrap();
+        assert_eq!(resp.status, Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!String::from_utf8_lossy(res.body()).contains("SlowDown"));
+
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .error_details(true)
+            .build_handler();
+        let req = Request::delete("/dir/").body(Body::empty()).unwrap();
+        let res = DavClient::new(dav).request(req).await;
+        let body = String::from_utf8_lossy(res.body());
+        let description =
+            "<D:responsedescription>SlowDown: reduce your request rate</D:responsedescription>";
+        assert!(body.contains(description), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_delete_if_match() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        let delete = |etag: &str| {
+            Request::delete("/file")
+                .header("If-Match", etag)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        assert_eq!(client.put_bytes("/file", "x").await, StatusCode::CREATED);
+        let stale = client.get("/file").await.headers()["etag"].clone();
+        assert_eq!(
+            client.put_bytes("/file", "xy").await,
+            StatusCode::NO_CONTENT
+        );
+        let etag = client.get("/file").await.headers()["etag"].clone();
+        assert_ne!(stale, etag);
+
+        // a stale ETag does not delete.
+        let res = client.request(delete(stale.to_str().unwrap())).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(client.get("/file").await.status(), StatusCode::OK);
+
+        // the current one does.
+        let res = client.request(delete(etag.to_str().unwrap())).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(client.get("/file").await.status(), StatusCode::NOT_FOUND);
+    }
+}
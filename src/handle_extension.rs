@@ -0,0 +1,157 @@
+use std::error::Error as StdError;
+use std::io;
+use std::sync::Arc;
+
+use bytes::{Buf, Bytes};
+use futures_channel::mpsc;
+use futures_util::future::{self, Either};
+use futures_util::StreamExt;
+use http::{Request, Response};
+use http_body::Body as HttpBody;
+
+use crate::async_stream::AsyncStream;
+use crate::body::Body;
+use crate::davpath::DavPath;
+use crate::{DavResult, ExtensionHandler};
+
+impl crate::DavInner {
+    // the handler the application registered for this method, if any.
+    pub(crate) fn extension_handler(
+        &self,
+        method: &http::Method,
+    ) -> Option<Arc<dyn ExtensionHandler>> {
+        self.extension_methods
+            .as_ref()?
+            .get(method.as_str())
+            .cloned()
+    }
+
+    pub(crate) async fn handle_extension<ReqBody, ReqData, ReqError>(
+        &self,
+        handler: Arc<dyn ExtensionHandler>,
+        req: Request<()>,
+        body: ReqBody,
+    ) -> DavResult<Response<Body>>
+    where
+        ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+    {
+        // a reserved handler can be outside of the prefix.
+        let path = match DavPath::from_uri_and_prefix(req.uri(), self.prefix()) {
+            Ok(path) => path,
+            Err(_) => DavPath::from_uri(req.uri())?,
+        };
+
+        // The request body is passed to the handler through a channel,
+        // so the handler does not need to know its type.
+        let (mut tx, mut rx) = mpsc::channel::<io::Result<Bytes>>(1);
+        let reqbody = Body::from(AsyncStream::new(|mut out| async move {
+            while let Some(data) = rx.next().await {
+                out.send(data?).await;
+            }
+            Ok(())
+        }));
+        let copy = async move {
+            pin_utils::pin_mut!(body);
+            while let Some(data) = body.data().await {
+                let data = data
+                    .map(|mut buf| buf.copy_to_bytes(buf.remaining()))
+                    .map_err(|e| io::Error::new(io::ErrorKind::UnexpectedEof, e));
+                let failed = data.is_err();
+                if future::poll_fn(|cx| tx.poll_ready(cx)).await.is_err()
+                    || tx.start_send(data).is_err()
+                    || failed
+                {
+                    break;
+                }
+            }
+        };
+        pin_utils::pin_mut!(copy);
+
+        // once the handler is done, stop reading the body.
+        let handle = handler.handle(path, req.map(|_| reqbody));
+        let res = match future::select(copy, handle).await {
+            Either::Left(((), handle)) => handle.await,
+            Either::Right((res, _)) => res,
+        };
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use futures_util::future::BoxFuture;
+    use futures_util::{FutureExt, StreamExt};
+    use http::{Request, Response, StatusCode};
+
+    use crate::async_stream::AsyncStream;
+    use crate::body::Body;
+    use crate::davpath::DavPath;
+    use crate::memfs::MemFs;
+    use crate::{DavHandler, ExtensionHandler};
+
+    // answers with the path and the request body.
+    struct Echo;
+
+    impl ExtensionHandler for Echo {
+        fn handle(&self, path: DavPath, req: Request<Body>) -> BoxFuture<'_, Response<Body>> {
+            async move {
+                let mut body = req.into_body();
+                let mut data = Vec::new();
+                while let Some(chunk) = body.next().await {
+                    data.extend_from_slice(&chunk.unwrap());
+                }
+                Response::builder()
+                    .header("X-Path", path.as_url_string())
+                    .body(Body::from(Bytes::from(data)))
+                    .unwrap()
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extension_method() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_prefix("/dav")
+            .extension_method("REPORT", Arc::new(Echo))
+            .build_handler();
+
+        let body = Body::from(AsyncStream::new(|mut tx| async move {
+            for chunk in ["<report", "/>"] {
+                tx.send(Bytes::from(chunk)).await;
+            }
+            Ok::<_, io::Error>(())
+        }));
+        let req = Request::builder()
+            .method("REPORT")
+            .uri("/dav/cal/")
+            .body(body)
+            .unwrap();
+        let res = dav.handle(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-path"], "/cal/");
+        let mut body = res.into_body();
+        assert_eq!(body.next().await.unwrap().unwrap(), "<report/>");
+
+        // methods nobody handles are not implemented.
+        let req = Request::builder()
+            .method("SEARCH")
+            .uri("/dav/")
+            .body(Body::empty())
+            .unwrap();
+        let res = dav.handle(req).await;
+        assert_eq!(res.status(), StatusCode::NOT_IMPLEMENTED);
+
+        let req = Request::options("/dav/").body(Body::empty()).unwrap();
+        let res = dav.handle(req).await;
+        let allow = res.headers()["allow"].to_str().unwrap();
+        assert!(allow.split(',').any(|m| m == "REPORT"), "{}", allow);
+    }
+}
@@ -1,9 +1,10 @@
 use std::cmp;
+use std::convert::TryFrom;
 use std::io::Write;
 
 use futures_util::StreamExt;
 use headers::HeaderMapExt;
-use http::{status::StatusCode, Request, Response};
+use http::{status::StatusCode, HeaderValue, Request, Response, Uri};
 
 use bytes::Bytes;
 
@@ -14,8 +15,10 @@ use crate::davheaders;
 use crate::davpath::DavPath;
 use crate::errors::*;
 use crate::fs::*;
+use crate::handle_props::stored_davprops;
+use crate::negotiate::negotiate;
 use crate::util::systemtime_to_offsetdatetime;
-use crate::DavMethod;
+use crate::{DavMethod, DirectoryGetMode};
 
 struct Range {
     start: u64,
@@ -27,6 +30,10 @@ const BOUNDARY_START: &str = "\n--BOUNDARY\n";
 const BOUNDARY_END: &str = "\n--BOUNDARY--\n";
 
 const READ_BUF_SIZE: usize = 16384;
+const READ_AHEAD: usize = 2;
+
+// What a directory index can be sent as, preferred first.
+pub(crate) const AUTOINDEX_TYPES: &[&str] = &["text/html"];
 
 impl crate::DavInner {
     pub(crate) async fn handle_get(&self, req: &Request<()>) -> DavResult<Response<Body>> {
@@ -35,6 +42,15 @@ impl crate::DavInner {
 
         // check if it's a directory.
         let meta = self.fs.metadata(&path).await?;
+
+        // a redirect reference answers GET with the redirect, even
+        // with "Apply-To-Redirect-Ref: T" (RFC 4437 6).
+        if self.redirectrefs.unwrap_or(false) {
+            if let Some(r) = meta.redirect_ref() {
+                return Ok(self.redirect_response(&r));
+            }
+        }
+
         if meta.is_dir() {
             //
             // This is a directory. If the path doesn't end in "/", send a redir.
@@ -44,15 +60,27 @@ impl crate::DavInner {
             if !path.is_collection() {
                 let mut res = Response::new(Body::empty());
                 path.add_slash();
-                res.headers_mut().insert(
-                    "Location",
-                    path.with_prefix().as_url_string().parse().unwrap(),
-                );
+                let mut location = path.with_prefix().as_url_string();
+                if let Some(query) = req.uri().query() {
+                    location.push('?');
+                    location.push_str(query);
+                }
+                res.headers_mut()
+                    .insert("Location", location.parse().unwrap());
                 res.headers_mut().typed_insert(headers::ContentLength(0));
-                *res.status_mut() = StatusCode::FOUND;
+                *res.status_mut() = if self.permanent_collection_redirect.unwrap_or(false) {
+                    StatusCode::PERMANENT_REDIRECT
+                } else {
+                    StatusCode::FOUND
+                };
                 return Ok(res);
             }
 
+            // a download of the whole collection as one archive?
+            if let Some(format) = self.archive_format(req, &path) {
+                return self.handle_archive(req, head, format).await;
+            }
+
             // If indexfile was set, use it.
             if let Some(indexfile) = self.indexfile.as_ref() {
                 path.push_segment(indexfile.as_bytes());
@@ -62,20 +90,59 @@ impl crate::DavInner {
             }
         }
 
-        // double check, is it a regular file.
+        // optionally, an exclusive lock keeps other principals out.
+        if self.get_respects_locks.unwrap_or(false) {
+            if let Some(ref locksystem) = self.ls {
+                let locks = locksystem.discover(&path);
+                let (_, tokens) = self.dav_if_match(&path).await;
+                if locks
+                    .iter()
+                    .any(|l| !l.shared && !self.is_lock_owner(l, &tokens))
+                {
+                    return Err(DavError::Status(StatusCode::LOCKED));
+                }
+            }
+        }
+
+        // redirect instead of serving the content? This wins over
+        // Range and the conditional headers, and the file is not
+        // opened at all.
+        if let Some(ref handler) = self.redirect_handler {
+            if let Some((status, url)) = handler(&path, &*meta).filter(|_| meta.is_file()) {
+                let location = HeaderValue::from_str(&url.to_string());
+                let location = match location {
+                    Ok(location) if status.is_redirection() => location,
+                    _ => {
+                        error!("redirect_handler: invalid redirect {} {}", status, url);
+                        return Err(DavError::Status(StatusCode::INTERNAL_SERVER_ERROR));
+                    }
+                };
+                let mut res = Response::new(Body::empty());
+                self.validators(&path, &*meta)
+                    .await
+                    .insert(res.headers_mut());
+                res.headers_mut().insert("Location", location);
+                res.headers_mut().typed_insert(headers::ContentLength(0));
+                *res.status_mut() = status;
+                return Ok(res);
+            }
+        }
+
+        // double check, is it a regular file. An empty file is opened
+        // as well, `open` might refuse it.
         let mut file = self.fs.open(&path, OpenOptions::read()).await?;
-        #[allow(unused_mut)]
-        let mut meta = file.metadata().await?;
+        let meta = file.metadata().await?;
         if !meta.is_file() {
             return Err(DavError::Status(StatusCode::METHOD_NOT_ALLOWED));
         }
 
         let len = meta.len();
         let mut curpos = 0u64;
-        let file_etag = davheaders::ETag::from_meta(&meta);
+        let validators = self.validators(&path, &*meta).await;
+        let file_etag = validators.etag.clone();
 
         let mut ranges = Vec::new();
-        let mut do_range = match req.headers().typed_try_get::<davheaders::IfRange>() {
+        let do_range = match req.headers().typed_try_get::<davheaders::IfRange>() {
             Ok(Some(r)) => conditional::ifrange_match(&r, file_etag.as_ref(), meta.modified().ok()),
             Ok(None) => true,
             Err(_) => false,
@@ -85,28 +152,19 @@ impl crate::DavInner {
         let mut no_body = false;
 
         // set Last-Modified and ETag headers.
-        if let Ok(modified) = meta.modified() {
-            res.headers_mut()
-                .typed_insert(headers::LastModified::from(modified));
-        }
-        if let Some(etag) = file_etag {
-            res.headers_mut().typed_insert(etag);
-        }
+        validators.insert(res.headers_mut());
 
-        match self.redirect {
-            Some(redirect) => {
-                if redirect {
-                    match file.redirect_url().await? {
-                        Some(url) => {
-                            res.headers_mut().insert("Location", url.parse().unwrap());
-                            *res.status_mut() = StatusCode::FOUND;
-                            return Ok(res);
-                        }
-                        None => {}
-                    }
-                }
+        // or does the file itself redirect elsewhere.
+        if self.redirect_handler.is_none() && self.redirect.unwrap_or(false) {
+            let url = file.redirect_url().await?;
+            let url = url.and_then(|url| url.parse::<Uri>().ok());
+            if let Some(location) = url.and_then(|url| HeaderValue::from_str(&url.to_string()).ok())
+            {
+                res.headers_mut().insert("Location", location);
+                res.headers_mut().typed_insert(headers::ContentLength(0));
+                *res.status_mut() = StatusCode::FOUND;
+                return Ok(res);
             }
-            None => {}
         }
 
         // Apache always adds an Accept-Ranges header, even with partial
@@ -115,49 +173,44 @@ impl crate::DavInner {
         res.headers_mut()
             .typed_insert(headers::AcceptRanges::bytes());
 
-        // handle the if-headers.
-        if let Some(s) = conditional::if_match(req, Some(&meta), &self.fs, &self.ls, &path).await {
+        // handle the if-headers. A 304 or 412 keeps the validators set
+        // above, but not the Content-Type or Content-Length.
+        if let Some(s) = self.if_match(req, Some(&*meta), &path).await {
             *res.status_mut() = s;
-            no_body = true;
-            do_range = false;
-        }
-
-        // see if we want to get one or more ranges.
-        if do_range {
-            if let Some(r) = req.headers().typed_get::<headers::Range>() {
-                trace!("handle_gethead: range header {:?}", r);
-                use std::ops::Bound::*;
-                for range in r.iter() {
-                    let (start, mut count, valid) = match range {
-                        (Included(s), Included(e)) if e >= s => (s, e - s + 1, true),
-                        (Included(s), Unbounded) if s <= len => (s, len - s, true),
-                        (Unbounded, Included(n)) if n <= len => (len - n, n, true),
-                        _ => (0, 0, false),
-                    };
-                    if !valid || start >= len {
-                        let r = format!("bytes */{}", len);
-                        res.headers_mut()
-                            .insert("Content-Range", r.parse().unwrap());
-                        *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
-                        ranges.clear();
-                        no_body = true;
-                        break;
-                    }
-                    if start + count > len {
-                        count = len - start;
-                    }
-                    ranges.push(Range { start, count });
+            if s != StatusCode::NOT_MODIFIED {
+                res.headers_mut().typed_insert(headers::ContentLength(0));
+            }
+            return Ok(res);
+        }
+
+        // see if we want to get one or more ranges. An invalid
+        // Range header is ignored, and so is any Range on an empty
+        // file: "bytes=0-" is not worth a 416 there.
+        let range_hdr = req.headers().get(http::header::RANGE);
+        if let Some(r) = range_hdr.filter(|_| do_range && len > 0) {
+            trace!("handle_gethead: range header {:?}", r);
+            match davheaders::parse_ranges(r.to_str().unwrap_or_default(), len) {
+                Ok(r) => {
+                    ranges.extend(r.into_iter().map(|(first, last)| Range {
+                        start: first,
+                        count: last - first + 1,
+                    }));
+                }
+                Err(davheaders::RangeError::Unsatisfiable) => {
+                    let r = format!("bytes */{}", len);
+                    res.headers_mut()
+                        .insert("Content-Range", r.parse().unwrap());
+                    *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                    no_body = true;
                 }
+                Err(davheaders::RangeError::Invalid) => {}
             }
         }
 
         if !ranges.is_empty() {
             // seek to beginning of the first range.
-            if file
-                .seek(std::io::SeekFrom::Start(ranges[0].start))
-                .await
-                .is_err()
-            {
+            let seeked = file.seek(std::io::SeekFrom::Start(ranges[0].start)).await;
+            if seeked.is_err() {
                 let r = format!("bytes */{}", len);
                 res.headers_mut()
                     .insert("Content-Range", r.parse().unwrap());
@@ -194,27 +247,47 @@ impl crate::DavInner {
             });
         }
 
+        // a getcontenttype or getcontentlanguage set with PROPPATCH wins.
+        let props = ["getcontenttype", "getcontentlanguage"];
+        let [mime, lang] = stored_davprops(&*self.fs, &path, props).await;
+        if let Some(lang) = lang {
+            if let Ok(lang) = davheaders::ContentLanguage::try_from(lang.as_str()) {
+                res.headers_mut().typed_insert(lang);
+            }
+        }
+
+        // a stored type that does not fit in a header falls back to
+        // the type guessed from the name.
+        let with_charset = |mime: &str| match self.default_charset {
+            Some(ref charset) if mime.starts_with("text/") && !mime.contains("charset=") => {
+                format!("{}; charset={}", mime, charset)
+            }
+            _ => mime.to_string(),
+        };
+        let guessed = path.get_mime_type_str();
+        let content_type = [mime.as_deref(), Some(guessed)]
+            .iter()
+            .flatten()
+            .find_map(|mime| HeaderValue::from_str(&with_charset(mime)).ok())
+            .unwrap_or_else(|| HeaderValue::from_static(guessed));
+
         // set content-length and start if we're not doing multipart.
-        let content_type = path.get_mime_type_str();
         if ranges.len() <= 1 {
             res.headers_mut()
-                .typed_insert(davheaders::ContentType(content_type.to_owned()));
-            let notmod = res.status() == StatusCode::NOT_MODIFIED;
-            let len = if head || !no_body || notmod {
-                ranges[0].count
-            } else {
-                0
-            };
+                .insert("Content-Type", content_type.clone());
+            let len = if head || !no_body { ranges[0].count } else { 0 };
             res.headers_mut().typed_insert(headers::ContentLength(len));
         }
 
-        if head || no_body {
+        // an empty file gets an empty body, not an empty stream.
+        if head || no_body || len == 0 {
             return Ok(res);
         }
 
         // now just loop and send data.
         let read_buf_size = self.read_buf_size.unwrap_or(READ_BUF_SIZE);
-        *res.body_mut() = Body::from(AsyncStream::new(|mut tx| {
+        let read_ahead = self.read_ahead.unwrap_or(READ_AHEAD);
+        let strm = AsyncStream::new(|mut tx| {
             async move {
                 let zero = [0; 4096];
 
@@ -245,7 +318,8 @@ impl crate::DavInner {
                             range.start + range.count - 1,
                             len
                         );
-                        let _ = writeln!(hdrs, "Content-Type: {}", content_type);
+                        let ct = String::from_utf8_lossy(content_type.as_bytes());
+                        let _ = writeln!(hdrs, "Content-Type: {}", ct);
                         let _ = writeln!(hdrs);
                         tx.send(Bytes::from(hdrs)).await;
                     }
@@ -254,6 +328,8 @@ impl crate::DavInner {
                     while count > 0 {
                         let blen = cmp::min(count, read_buf_size as u64) as usize;
                         let mut buf = file.read_bytes(blen).await?;
+                        // never send more than was asked for.
+                        buf.truncate(blen);
                         if buf.is_empty() {
                             // this is a cop out. if the file got truncated, just
                             // return zeroed bytes instead of file content.
@@ -272,11 +348,28 @@ impl crate::DavInner {
                 }
                 Ok::<(), std::io::Error>(())
             }
-        }));
+        });
+        *res.body_mut() = Body::from(strm.read_ahead(read_ahead));
 
         Ok(res)
     }
 
+    // GET on a collection with DirectoryGetMode::Propfind.
+    async fn directory_propfind(&self, req: &Request<()>, head: bool) -> DavResult<Response<Body>> {
+        let mut r = Request::new(());
+        *r.uri_mut() = req.uri().clone();
+        r.headers_mut()
+            .insert("Depth", HeaderValue::from_static("1"));
+        let mut res = self.clone().handle_propfind(&r, b"").await?;
+        if res.status() == StatusCode::MULTI_STATUS {
+            *res.status_mut() = StatusCode::OK;
+        }
+        if head {
+            *res.body_mut() = Body::empty();
+        }
+        Ok(res)
+    }
+
     pub(crate) async fn handle_autoindex(
         &self,
         req: &Request<()>,
@@ -294,20 +387,123 @@ impl crate::DavInner {
         // Only allow index generation if explicitly set to true, _or_ if it was
         // unset, and PROPFIND is explicitly allowed.
         if !self.autoindex.unwrap_or(allow_propfind) {
-            debug!(
-                "method {} not allowed on request {}",
-                req.method(),
-                req.uri()
-            );
-            return Err(DavError::StatusClose(StatusCode::METHOD_NOT_ALLOWED));
+            debug!("no directory index for request {}", req.uri());
+            return Err(match self.directory_get_mode {
+                Some(DirectoryGetMode::NotFound) => DavError::Status(StatusCode::NOT_FOUND),
+                Some(DirectoryGetMode::Forbidden) | None => DavError::Status(StatusCode::FORBIDDEN),
+                Some(DirectoryGetMode::MethodNotAllowed) => {
+                    DavError::StatusClose(StatusCode::METHOD_NOT_ALLOWED)
+                }
+                Some(DirectoryGetMode::Redirect(ref location)) => {
+                    // checked by the builder.
+                    let location = HeaderValue::from_str(location).unwrap();
+                    res.headers_mut().insert("Location", location);
+                    res.headers_mut().typed_insert(headers::ContentLength(0));
+                    *res.status_mut() = StatusCode::FOUND;
+                    return Ok(res);
+                }
+                Some(DirectoryGetMode::Propfind) => {
+                    return self.directory_propfind(req, head).await;
+                }
+            });
+        }
+
+        // pick a representation. The response depends on Accept even if
+        // there is only one, since it can be a 406.
+        res.headers_mut()
+            .insert("Vary", HeaderValue::from_static("Accept"));
+        let accept = req
+            .headers()
+            .get_all("accept")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        let accept = Some(accept.as_str()).filter(|a| !a.is_empty());
+        let content_type = match negotiate(accept, AUTOINDEX_TYPES) {
+            Some(ct) => ct,
+            None => {
+                let types = format!("{}\n", AUTOINDEX_TYPES.join("\n"));
+                res.headers_mut()
+                    .insert("Content-Type", HeaderValue::from_static("text/plain"));
+                res.headers_mut()
+                    .typed_insert(headers::ContentLength(types.len() as u64));
+                *res.status_mut() = StatusCode::NOT_ACCEPTABLE;
+                if !head {
+                    *res.body_mut() = Body::from(types);
+                }
+                return Ok(res);
+            }
+        };
+
+        // the validators, and the if-headers. A 304 or 412 does not
+        // need the listing.
+        let sort = IndexSort::from_query(path.query());
+        let meta = self.fs.metadata(&path).await?;
+        let validators = self.validators(&path, &*meta).await;
+        validators.insert(res.headers_mut());
+        let status = match self.dav_if_match(&path).await {
+            (false, _) => Some(StatusCode::PRECONDITION_FAILED),
+            (true, _) => conditional::http_if_match_etag(
+                req,
+                Some(&*meta),
+                validators.etag.as_ref(),
+                self.precondition.as_deref(),
+            ),
+        };
+        if let Some(s) = status {
+            *res.status_mut() = s;
+            if s != StatusCode::NOT_MODIFIED {
+                res.headers_mut().typed_insert(headers::ContentLength(0));
+            }
+            return Ok(res);
         }
 
         // read directory or bail.
-        let mut entries = self.fs.read_dir(&path, ReadDirMeta::Data).await?;
+        let mut entries = self.fs.read_dir(&path, self.readdir_meta()).await?;
+
+        // transform all entries into a dirent struct.
+        struct Dirent {
+            path: String,
+            name: String,
+            // None if it could not be read.
+            meta: Option<Box<dyn DavMetaData>>,
+        }
+
+        let mut dirents: Vec<Dirent> = Vec::new();
+        while let Some(dirent) = entries.next().await {
+            let mut name = dirent.name();
+            let meta = match dirent.metadata().await {
+                Ok(meta) => Some(meta),
+                Err(e) => match self.unreadable_status(e) {
+                    Some(_) => None,
+                    None => continue,
+                },
+            };
+            let mut npath = path.clone();
+            npath.push_segment(&name);
+            if meta.as_ref().map(|m| m.is_dir()).unwrap_or(false) {
+                name.push(b'/');
+                npath.add_slash();
+            }
+            let listed = match meta {
+                Some(ref meta) => self.is_listed(&npath, &**meta),
+                None => !self.is_reserved_path(&npath),
+            };
+            if !listed || name.starts_with(b".") {
+                continue;
+            }
+            dirents.push(Dirent {
+                path: npath.with_prefix().as_url_string(),
+                name: String::from_utf8_lossy(&name).to_string(),
+                meta,
+            });
+        }
 
         // start output
+        let content_type = format!("{}; charset=utf-8", content_type);
         res.headers_mut()
-            .insert("Content-Type", "text/html; charset=utf-8".parse().unwrap());
+            .insert("Content-Type", content_type.parse().unwrap());
         *res.status_mut() = StatusCode::OK;
         if head {
             return Ok(res);
@@ -316,45 +512,21 @@ impl crate::DavInner {
         // now just loop and send data.
         *res.body_mut() = Body::from(AsyncStream::new(|mut tx| {
             async move {
-                // transform all entries into a dirent struct.
-                struct Dirent {
-                    path: String,
-                    name: String,
-                    meta: Box<dyn DavMetaData>,
-                }
-
-                let mut dirents: Vec<Dirent> = Vec::new();
-                while let Some(dirent) = entries.next().await {
-                    let mut name = dirent.name();
-                    if name.starts_with(b".") {
-                        continue;
-                    }
-                    let mut npath = path.clone();
-                    npath.push_segment(&name);
-                    if let Ok(meta) = dirent.metadata().await {
-                        if meta.is_dir() {
-                            name.push(b'/');
-                            npath.add_slash();
-                        }
-                        dirents.push(Dirent {
-                            path: npath.with_prefix().as_url_string(),
-                            name: String::from_utf8_lossy(&name).to_string(),
-                            meta,
-                        });
-                    }
-                }
-
-                // now we can sort the dirent struct.
+                // now we can sort the dirent struct. Collections come first,
+                // in either order.
                 dirents.sort_by(|a, b| {
-                    let adir = a.meta.is_dir();
-                    let bdir = b.meta.is_dir();
-                    if adir && !bdir {
-                        std::cmp::Ordering::Less
-                    } else if bdir && !adir {
-                        std::cmp::Ordering::Greater
-                    } else {
-                        (a.name).cmp(&b.name)
-                    }
+                    let adir = a.meta.as_ref().map(|m| m.is_dir()).unwrap_or(false);
+                    let bdir = b.meta.as_ref().map(|m| m.is_dir()).unwrap_or(false);
+                    let size = |d: &Dirent| d.meta.as_ref().map(|m| m.len()).unwrap_or(0);
+                    let date = |d: &Dirent| d.meta.as_ref().and_then(|m| m.modified().ok());
+                    let ord = match sort.key {
+                        SortKey::Name => cmp::Ordering::Equal,
+                        SortKey::Size => size(a).cmp(&size(b)),
+                        SortKey::Date => date(a).cmp(&date(b)),
+                    };
+                    let ord = ord.then_with(|| a.name.cmp(&b.name));
+                    bdir.cmp(&adir)
+                        .then(if sort.desc { ord.reverse() } else { ord })
                 });
 
                 // and output html
@@ -399,10 +571,13 @@ impl crate::DavInner {
                 w.push_str(
                     "\
                     <table>\n\
-                    <tr>\n\
-                      <th class=\"left mw20\">Name</th>\n\
-                      <th class=\"left\">Last modified</th>\n\
-                      <th>Size</th>\n\
+                    <tr>\n",
+                );
+                w.push_str(&sort.header(SortKey::Name, "Name", "left mw20"));
+                w.push_str(&sort.header(SortKey::Date, "Last modified", "left"));
+                w.push_str(&sort.header(SortKey::Size, "Size", ""));
+                w.push_str(
+                    "\
                     </tr>\n\
                     <tr><th colspan=\"3\"><hr></th></tr>\n\
                     <tr>\n\
@@ -415,27 +590,23 @@ impl crate::DavInner {
                 tx.send(Bytes::from(w)).await;
 
                 for dirent in &dirents {
-                    let modified = match dirent.meta.modified() {
-                        Ok(t) => {
-                            let tm = systemtime_to_offsetdatetime(t);
-                            format!(
-                                "{:04}-{:02}-{:02} {:02}:{:02}",
-                                tm.year(),
-                                tm.month(),
-                                tm.day(),
-                                tm.hour(),
-                                tm.minute(),
-                            )
-                        }
-                        Err(_) => "".to_string(),
-                    };
-                    let size = match dirent.meta.is_file() {
-                        true => display_size(dirent.meta.len()),
-                        false => "[DIR]    ".to_string(),
+                    let (modified, size, bytes) = match dirent.meta {
+                        Some(ref meta) if meta.is_file() => (
+                            display_modified(&**meta),
+                            display_size(meta.len()),
+                            format!(" title=\"{} bytes\"", meta.len()),
+                        ),
+                        Some(ref meta) => (
+                            display_modified(&**meta),
+                            "[DIR]    ".to_string(),
+                            String::new(),
+                        ),
+                        // we could not read it.
+                        None => ("".to_string(), "-".to_string(), String::new()),
                     };
                     let name = htmlescape::encode_minimal(&dirent.name);
-                    let s = format!("<tr><td><a href=\"{}\">{}</a></td><td class=\"mono\">{}</td><td class=\"mono\" align=\"right\">{}</td></tr>",
-                         dirent.path, name, modified, size);
+                    let s = format!("<tr><td><a href=\"{}\">{}</a></td><td class=\"mono\">{}</td><td class=\"mono\" align=\"right\"{}>{}</td></tr>",
+                         dirent.path, name, modified, bytes, size);
                     tx.send(Bytes::from(s)).await;
                 }
 
@@ -452,6 +623,82 @@ impl crate::DavInner {
     }
 }
 
+// The column that the autoindex is sorted on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Name,
+    Size,
+    Date,
+}
+
+// How to sort the autoindex. From the query, like `?sort=size&order=desc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IndexSort {
+    key: SortKey,
+    desc: bool,
+}
+
+impl IndexSort {
+    fn from_query(query: Option<&str>) -> IndexSort {
+        let mut sort = IndexSort {
+            key: SortKey::Name,
+            desc: false,
+        };
+        for param in query.into_iter().flat_map(|q| q.split('&')) {
+            match param {
+                "sort=name" => sort.key = SortKey::Name,
+                "sort=size" => sort.key = SortKey::Size,
+                "sort=date" => sort.key = SortKey::Date,
+                "order=asc" => sort.desc = false,
+                "order=desc" => sort.desc = true,
+                _ => {}
+            }
+        }
+        sort
+    }
+
+    // The header of a column, with a link that sorts on it. For the
+    // column that is sorted on already, it reverses the order.
+    fn header(&self, key: SortKey, label: &str, class: &str) -> String {
+        let name = match key {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Date => "date",
+        };
+        let (order, arrow) = match (key == self.key, self.desc) {
+            (true, false) => ("desc", " &uarr;"),
+            (true, true) => ("asc", " &darr;"),
+            (false, _) => ("asc", ""),
+        };
+        let class = match class {
+            "" => String::new(),
+            class => format!(" class=\"{}\"", class),
+        };
+        format!(
+            "  <th{}><a href=\"?sort={}&amp;order={}\">{}</a>{}</th>\n",
+            class, name, order, label, arrow
+        )
+    }
+}
+
+fn display_modified(meta: &dyn DavMetaData) -> String {
+    match meta.modified() {
+        Ok(t) => {
+            let tm = systemtime_to_offsetdatetime(t);
+            format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                tm.year(),
+                tm.month() as u8,
+                tm.day(),
+                tm.hour(),
+                tm.minute(),
+                tm.second(),
+            )
+        }
+        Err(_) => "".to_string(),
+    }
+}
+
 fn display_size(size: u64) -> String {
     let (formatted, unit) = ["KiB", "MiB", "GiB", "TiB", "PiB"]
         .iter()
@@ -495,3 +742,723 @@ fn display_path(path: &DavPath) -> String {
 
     dpath
 }
+
+#[cfg(test)]
+mod tests {
+
+    use bytes::Bytes;
+
+    use crate::body::Body;
+    use crate::davpath::DavPath;
+    use crate::fs::*;
+    use crate::memfs::MemFs;
+    use crate::testfs::{MockFs, MockOp};
+    use crate::testing::{DavClient, Depth, Multistatus};
+    use crate::DavHandler;
+    use http::{Request, Response, StatusCode};
+
+    async fn req(
+        dav: &DavHandler,
+        method: &str,
+        uri: &str,
+        inm: Option<&str>,
+    ) -> (StatusCode, Option<String>) {
+        let mut r = Request::builder().method(method).uri(uri);
+        if let Some(inm) = inm {
+            r = r.header("If-None-Match", inm);
+        }
+        let resp = dav.handle(r.body(Body::empty()).unwrap()).await;
+        let etag = resp
+            .headers()
+            .get("etag")
+            .map(|v| v.to_str().unwrap().to_string());
+        (resp.status(), etag)
+    }
+
+    #[tokio::test]
+    async fn test_collection_etag() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .build_handler();
+
+        assert_eq!(
+            req(&dav, "GET", "/dir/", None).await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            req(&dav, "MKCOL", "/dir/", None).await.0,
+            StatusCode::CREATED
+        );
+
+        let (status, etag1) = req(&dav, "GET", "/dir/", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let etag1 = etag1.unwrap();
+        assert!(etag1.starts_with("W/\""), "{}", etag1);
+        let (status, _) = req(&dav, "GET", "/dir/", Some(&etag1)).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+        req(&dav, "PUT", "/dir/a", None).await;
+        let etag2 = req(&dav, "GET", "/dir/", None).await.1.unwrap();
+        assert_ne!(etag1, etag2);
+        let (status, _) = req(&dav, "GET", "/dir/", Some(&etag1)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let r = Request::builder()
+            .method("MOVE")
+            .uri("/dir/a")
+            .header("Destination", "/dir/b")
+            .body(Body::empty())
+            .unwrap();
+        dav.handle(r).await;
+        let etag3 = req(&dav, "GET", "/dir/", None).await.1.unwrap();
+        assert_ne!(etag2, etag3);
+
+        req(&dav, "DELETE", "/dir/b", None).await;
+        let etag4 = req(&dav, "GET", "/dir/", None).await.1.unwrap();
+        assert_ne!(etag3, etag4);
+    }
+
+    #[tokio::test]
+    async fn test_collection_etag_roundtrip() {
+        // the getetag of a collection is the ETag of GET, and the
+        // conditional headers accept it, with or without hashed
+        // collection ETags.
+        for hashed in [false, true] {
+            let dav = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .autoindex(true)
+                .hashed_collection_etags(hashed)
+                .build_handler();
+            let client = DavClient::new(dav.clone());
+            assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+            assert_eq!(client.put_bytes("/dir/a", "a").await, StatusCode::CREATED);
+
+            let ms = client.propfind("/dir/", Depth::Zero, &["getetag"]).await;
+            let ms = ms.unwrap();
+            let etag = ms.response("/dir/").unwrap().prop_text("getetag").unwrap();
+            assert_eq!(req(&dav, "GET", "/dir/", None).await.1, Some(etag.clone()));
+
+            let get = |value: &str| {
+                let r = Request::get("/dir/").header("If-Match", value);
+                dav.handle(r.body(Body::empty()).unwrap())
+            };
+            // a hashed collection ETag is weak, and never matches If-Match.
+            let status = if hashed {
+                StatusCode::PRECONDITION_FAILED
+            } else {
+                StatusCode::OK
+            };
+            assert_eq!(get(&etag).await.status(), status);
+            assert_eq!(
+                get("\"other\"").await.status(),
+                StatusCode::PRECONDITION_FAILED
+            );
+
+            // If-None-Match uses the weak comparison.
+            let r = Request::get("/dir/").header("If-None-Match", &etag);
+            let res = dav.handle(r.body(Body::empty()).unwrap()).await;
+            assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+            // the If header compares like If-Match.
+            let r = Request::delete("/dir/")
+                .header("If", format!("([{}])", etag))
+                .body(Body::empty())
+                .unwrap();
+            let status = if hashed {
+                StatusCode::PRECONDITION_FAILED
+            } else {
+                StatusCode::NO_CONTENT
+            };
+            assert_eq!(dav.handle(r).await.status(), status);
+        }
+    }
+
+    // the validators of a 304 must be those of the 200, and it must
+    // not describe a body.
+    fn assert_not_modified(ok: &Response<Body>, notmod: &Response<Body>) {
+        assert_eq!(ok.status(), StatusCode::OK);
+        assert_eq!(notmod.status(), StatusCode::NOT_MODIFIED);
+        for h in ["etag", "last-modified", "cache-control", "vary"] {
+            assert_eq!(ok.headers().get(h), notmod.headers().get(h), "{}", h);
+        }
+        assert!(ok.headers().contains_key("etag"));
+        assert!(notmod.headers().get("content-length").is_none());
+        assert!(notmod.headers().get("content-type").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_not_modified_headers() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .build_handler();
+
+        let r = Request::put("/file").body(Body::from("hello")).unwrap();
+        assert_eq!(dav.handle(r).await.status(), StatusCode::CREATED);
+
+        for uri in ["/file", "/"] {
+            let r = Request::get(uri).body(Body::empty()).unwrap();
+            let ok = dav.handle(r).await;
+            let etag = ok.headers().get("etag").unwrap().clone();
+            let r = Request::get(uri)
+                .header("If-None-Match", etag)
+                .body(Body::empty())
+                .unwrap();
+            let notmod = dav.handle(r).await;
+            assert_not_modified(&ok, &notmod);
+        }
+
+        // a failed If-Match on a state-changing method returns the current validators.
+        let r = Request::get("/file").body(Body::empty()).unwrap();
+        let etag = dav.handle(r).await.headers().get("etag").unwrap().clone();
+        let r = Request::delete("/file")
+            .header("If-Match", "\"nope\"")
+            .body(Body::empty())
+            .unwrap();
+        let res = dav.handle(r).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(res.headers().get("etag"), Some(&etag));
+    }
+
+    #[tokio::test]
+    async fn test_precondition_failed_collection() {
+        // a 412 on a collection carries the ETag that GET sends.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .hashed_collection_etags(true)
+            .build_handler();
+        let client = DavClient::new(dav.clone());
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        assert_eq!(client.put_bytes("/dir/a", "a").await, StatusCode::CREATED);
+        let etag = req(&dav, "GET", "/dir/", None).await.1.unwrap();
+
+        for method in ["DELETE", "PROPPATCH", "MOVE"] {
+            let r = Request::builder()
+                .method(method)
+                .uri("/dir/")
+                .header("Destination", "/other/")
+                .header("If-Match", "\"nope\"")
+                .body(Body::empty())
+                .unwrap();
+            let res = dav.handle(r).await;
+            assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED, "{}", method);
+            assert_eq!(res.headers()["etag"], etag.as_str(), "{}", method);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_default_charset() {
+        let content_type = |dav: DavHandler, uri: &'static str| async move {
+            let r = Request::get(uri).body(Body::empty()).unwrap();
+            let res = dav.handle(r).await;
+            res.headers()["content-type"].to_str().unwrap().to_string()
+        };
+        let fs = MemFs::new();
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .autoindex(true)
+            .build_handler();
+        for name in ["/a.txt", "/b.bin"] {
+            let r = Request::put(name).body(Body::from("x")).unwrap();
+            assert_eq!(dav.handle(r).await.status(), StatusCode::CREATED);
+        }
+
+        assert_eq!(content_type(dav.clone(), "/a.txt").await, "text/plain");
+        assert_eq!(
+            content_type(dav.clone(), "/").await,
+            "text/html; charset=utf-8"
+        );
+
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .autoindex(true)
+            .default_charset("utf-8")
+            .build_handler();
+        assert_eq!(
+            content_type(dav.clone(), "/a.txt").await,
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            content_type(dav.clone(), "/b.bin").await,
+            "application/octet-stream"
+        );
+        assert_eq!(
+            content_type(dav.clone(), "/").await,
+            "text/html; charset=utf-8"
+        );
+
+        // a stored type that is not a valid header value is ignored.
+        let prop = DavProp {
+            name: "getcontenttype".to_string(),
+            prefix: Some("D".to_string()),
+            namespace: Some("DAV:".to_string()),
+            xml: Some(
+                "<D:getcontenttype xmlns:D=\"DAV:\">text/plain\x7f</D:getcontenttype>".into(),
+            ),
+        };
+        let path = DavPath::new("/b.bin").unwrap();
+        fs.patch_props(&path, vec![(true, prop)]).await.unwrap();
+        assert_eq!(
+            content_type(dav.clone(), "/b.bin").await,
+            "application/octet-stream"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_filesystem_etag() {
+        let client = |tag: &'static str, weak: bool| {
+            let fs = MockFs::builder().etag("/file", tag, weak).build();
+            DavClient::new(DavHandler::builder().filesystem(fs).build_handler())
+        };
+        let get = |client: &DavClient, name: &'static str, value: &'static str| {
+            let req = Request::get("/file")
+                .header(name, value)
+                .header("Range", "bytes=0-0");
+            let client = client.clone();
+            async move {
+                client
+                    .request(req.body(Body::empty()).unwrap())
+                    .await
+                    .status()
+            }
+        };
+        let etag = |res: &Response<Bytes>| res.headers()["etag"].to_str().unwrap().to_string();
+
+        let c = client("s3-0123", false);
+        let res = c
+            .request(Request::put("/file").body(Body::from("hello")).unwrap())
+            .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(etag(&res), "\"s3-0123\"");
+        assert_eq!(etag(&c.get("/file").await), "\"s3-0123\"");
+        let res = c
+            .request(Request::head("/file").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(etag(&res), "\"s3-0123\"");
+        let ms = c
+            .propfind("/file", Depth::Zero, &["getetag"])
+            .await
+            .unwrap();
+        let getetag = ms.responses[0].prop_text("getetag");
+        assert_eq!(getetag.as_deref(), Some("\"s3-0123\""));
+
+        assert_eq!(
+            get(&c, "If-Match", "\"s3-0123\"").await,
+            StatusCode::PARTIAL_CONTENT
+        );
+        assert_eq!(
+            get(&c, "If-Match", "\"other\"").await,
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(
+            get(&c, "If-Range", "\"s3-0123\"").await,
+            StatusCode::PARTIAL_CONTENT
+        );
+        assert_eq!(get(&c, "If-Range", "\"other\"").await, StatusCode::OK);
+        assert_eq!(
+            get(&c, "If", "([\"s3-0123\"])").await,
+            StatusCode::PARTIAL_CONTENT
+        );
+        let req = Request::put("/file").header("If-Match", "\"s3-0123\"");
+        let res = c.request(req.body(Body::from("x")).unwrap()).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        // a quoted tag is used as it is.
+        let c = client("W/\"abc\"", false);
+        assert_eq!(c.put_bytes("/file", "hello").await, StatusCode::CREATED);
+        assert_eq!(etag(&c.get("/file").await), "W/\"abc\"");
+
+        // a weak tag never matches If-Match and If-Range.
+        let c = client("mtime-1", true);
+        assert_eq!(c.put_bytes("/file", "hello").await, StatusCode::CREATED);
+        assert_eq!(etag(&c.get("/file").await), "W/\"mtime-1\"");
+        assert_eq!(
+            get(&c, "If-Match", "W/\"mtime-1\"").await,
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(get(&c, "If-Range", "W/\"mtime-1\"").await, StatusCode::OK);
+        assert_eq!(
+            get(&c, "If-None-Match", "\"mtime-1\"").await,
+            StatusCode::NOT_MODIFIED
+        );
+
+        // collections too, without hashed collection ETags.
+        let fs = MockFs::builder().etag("/dir", "dir-0123", false).build();
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .autoindex(true)
+            .hashed_collection_etags(false)
+            .build_handler();
+        let c = DavClient::new(dav.clone());
+        assert_eq!(c.mkcol("/dir/").await, StatusCode::CREATED);
+        assert_eq!(etag(&c.get("/dir/").await), "\"dir-0123\"");
+        let ms = c.propfind("/", Depth::One, &["getetag"]).await.unwrap();
+        let getetag = ms.response("/dir/").unwrap().prop_text("getetag");
+        assert_eq!(getetag.as_deref(), Some("\"dir-0123\""));
+        let req = Request::get("/dir/").header("If-Match", "\"dir-0123\"");
+        let res = c.request(req.body(Body::empty()).unwrap()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let req = Request::delete("/dir/").header("If", "([\"dir-0123\"])");
+        let res = c.request(req.body(Body::empty()).unwrap()).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_read_size() {
+        let fs = MockFs::builder().build();
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .read_buf_size(4096)
+            .build_handler();
+        let client = DavClient::new(dav);
+        let data = vec![b'x'; 1024 * 1024];
+        let status = client.put_bytes("/file", data).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let res = client.get("/file").await;
+        assert_eq!(res.body().len(), 1024 * 1024);
+        let reads = fs.take_reads();
+        assert!(reads.iter().all(|&(req, got)| req <= 4096 && got <= req));
+        assert_eq!(reads.iter().map(|r| r.1).sum::<usize>(), 1024 * 1024);
+
+        // a range is read, and nothing past its end.
+        let res = client.get_range("/file", 1000, 1999).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.body().len(), 1000);
+        let reads = fs.take_reads();
+        assert_eq!(reads.iter().map(|r| r.1).sum::<usize>(), 1000);
+    }
+
+    #[tokio::test]
+    async fn test_read_ahead() {
+        use futures_util::StreamExt;
+
+        let fs = MockFs::builder().build();
+        let data = (0..1024 * 1024)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let client = DavClient::new(DavHandler::builder().filesystem(fs.clone()).build_handler());
+        assert_eq!(
+            client.put_bytes("/file", data.clone()).await,
+            StatusCode::CREATED
+        );
+
+        for (read_ahead, reads) in [(None, 3), (Some(0), 1), (Some(4), 5)] {
+            let mut config = DavHandler::builder()
+                .filesystem(fs.clone())
+                .read_buf_size(4096);
+            if let Some(n) = read_ahead {
+                config = config.read_ahead(n);
+            }
+            let dav = config.build_handler();
+
+            // while the first chunk is out, the next ones are read already.
+            let req = Request::get("/file").body(Body::empty()).unwrap();
+            let mut body = dav.handle(req).await.into_body();
+            let first = body.next().await.unwrap().unwrap();
+            assert_eq!(&first[..], &data[..4096]);
+            assert_eq!(fs.take_reads().len(), reads, "{:?}", read_ahead);
+
+            // and the chunks arrive in order.
+            let mut got = first.to_vec();
+            while let Some(chunk) = body.next().await {
+                got.extend_from_slice(&chunk.unwrap());
+            }
+            assert!(got == data);
+            fs.take_reads();
+
+            // ranges still read only what they need.
+            let client = DavClient::new(dav.clone());
+            let res = client.get_range("/file", 5000, 14999).await;
+            assert_eq!(&res.body()[..], &data[5000..15000]);
+            let reads = fs.take_reads();
+            assert_eq!(reads.iter().map(|r| r.1).sum::<usize>(), 10000);
+
+            // a client that goes away drops the file, and the reads stop.
+            let req = Request::get("/file").body(Body::empty()).unwrap();
+            let mut body = dav.handle(req).await.into_body();
+            body.next().await.unwrap().unwrap();
+            assert_eq!(fs.open_files(), 1);
+            drop(body);
+            assert_eq!(fs.open_files(), 0);
+            fs.take_reads();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_autoindex_accept() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .build_handler();
+        let client = DavClient::new(dav);
+        let get = |accept: &'static str| {
+            let req = Request::get("/").header("Accept", accept);
+            client.request(req.body(Body::empty()).unwrap())
+        };
+
+        for accept in ["*/*", "application/*;q=0.5, text/html", "text/*"] {
+            let res = get(accept).await;
+            assert_eq!(res.status(), StatusCode::OK, "{}", accept);
+            assert_eq!(res.headers()["content-type"], "text/html; charset=utf-8");
+            assert_eq!(res.headers()["vary"], "Accept");
+        }
+
+        let res = get("application/json, text/html;q=0").await;
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(res.headers()["vary"], "Accept");
+        assert_eq!(&res.body()[..], b"text/html\n");
+    }
+
+    #[tokio::test]
+    async fn test_autoindex_sort() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .build_handler();
+        let client = DavClient::new(dav);
+        // the date of the files, which can roll over while they are created.
+        let today = || {
+            let now = crate::util::systemtime_to_offsetdatetime(std::time::SystemTime::now());
+            format!(
+                "{:04}-{:02}-{:02}T",
+                now.year(),
+                now.month() as u8,
+                now.day()
+            )
+        };
+        let before = today();
+        for (name, data) in [("/b", "bbb"), ("/a", "aaaaaaaaaa"), ("/c", "c")] {
+            assert_eq!(client.put_bytes(name, data).await, StatusCode::CREATED);
+        }
+        assert_eq!(client.mkcol("/d/").await, StatusCode::CREATED);
+
+        // the names, in the order they are listed.
+        let client = &client;
+        let index = |query: &'static str| async move {
+            let req = Request::get(format!("/{}", query))
+                .header("Accept", "text/html")
+                .body(Body::empty())
+                .unwrap();
+            let res = client.request(req).await;
+            let body = String::from_utf8_lossy(res.body()).to_string();
+            let mut names = ["a", "b", "c", "d/"]
+                .iter()
+                .map(|n| (body.find(&format!(">{}</a>", n)).unwrap(), *n))
+                .collect::<Vec<_>>();
+            names.sort();
+            let names = names.into_iter().map(|(_, n)| n).collect::<Vec<_>>();
+            (names, body)
+        };
+
+        let (names, body) = index("").await;
+        assert_eq!(names, ["d/", "a", "b", "c"]);
+        assert!(body.contains("<a href=\"?sort=name&amp;order=desc\">Name</a> &uarr;"));
+        assert!(body.contains("title=\"10 bytes\">10 B<"));
+        assert!(body.contains(&before) || body.contains(&today()));
+
+        let (names, _) = index("?order=desc").await;
+        assert_eq!(names, ["d/", "c", "b", "a"]);
+        let (names, body) = index("?sort=size&order=desc").await;
+        assert_eq!(names, ["d/", "a", "b", "c"]);
+        assert!(body.contains("<a href=\"?sort=size&amp;order=asc\">Size</a> &darr;"));
+        let (names, _) = index("?sort=size").await;
+        assert_eq!(names, ["d/", "c", "b", "a"]);
+    }
+
+    #[tokio::test]
+    async fn test_directory_get_mode() {
+        use crate::DirectoryGetMode as M;
+
+        let modes = [
+            (None, StatusCode::FORBIDDEN),
+            (Some(M::Forbidden), StatusCode::FORBIDDEN),
+            (Some(M::NotFound), StatusCode::NOT_FOUND),
+            (Some(M::MethodNotAllowed), StatusCode::METHOD_NOT_ALLOWED),
+            (Some(M::Redirect("index.html".into())), StatusCode::FOUND),
+            (Some(M::Propfind), StatusCode::OK),
+        ];
+        for (mode, status) in modes {
+            let mut config = DavHandler::builder().filesystem(MemFs::new());
+            if let Some(mode) = mode.clone() {
+                config = config.directory_get_mode(mode);
+            }
+            let client = DavClient::new(config.build_handler());
+            assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+            assert_eq!(client.put_bytes("/dir/a", "a").await, StatusCode::CREATED);
+            let res = client.get("/dir/").await;
+            assert_eq!(res.status(), status, "{:?}", mode);
+
+            match mode {
+                Some(M::Redirect(_)) => assert_eq!(res.headers()["location"], "index.html"),
+                Some(M::Propfind) => {
+                    let ms = Multistatus::parse(res.body()).unwrap();
+                    assert_eq!(ms.hrefs(), ["/dir/", "/dir/a"]);
+                    let head = Request::head("/dir/").body(Body::empty()).unwrap();
+                    let res = client.request(head).await;
+                    assert_eq!(res.status(), StatusCode::OK);
+                    assert!(res.body().is_empty());
+                }
+                _ => assert!(res.headers().get("location").is_none()),
+            }
+        }
+
+        // autoindex wins.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .directory_get_mode(M::NotFound)
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.get("/").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_handler() {
+        let fs = MockFs::builder().build();
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .redirect_handler(|path, meta| {
+                let url = format!("https://cdn.example.com{}", path.as_url_string());
+                match meta.len() {
+                    0 => None,
+                    1 => Some((StatusCode::FOUND, url.parse().unwrap())),
+                    2 => Some((StatusCode::TEMPORARY_REDIRECT, url.parse().unwrap())),
+                    _ => Some((StatusCode::OK, url.parse().unwrap())),
+                }
+            })
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/empty", "").await, StatusCode::CREATED);
+        assert_eq!(client.put_bytes("/one", "1").await, StatusCode::CREATED);
+        assert_eq!(client.put_bytes("/two", "22").await, StatusCode::CREATED);
+        assert_eq!(client.put_bytes("/three", "333").await, StatusCode::CREATED);
+        let opened = fs.calls(MockOp::Open);
+
+        // None falls through to serving the file.
+        let res = client.get("/empty").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get("location").is_none());
+
+        let res = client.get("/one").await;
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(res.headers()["location"], "https://cdn.example.com/one");
+
+        // HEAD too, and the redirect wins over a Range.
+        for method in ["GET", "HEAD"] {
+            let req = Request::builder()
+                .method(method)
+                .uri("/two")
+                .header("Range", "bytes=0-0")
+                .body(Body::empty())
+                .unwrap();
+            let res = client.request(req).await;
+            assert_eq!(res.status(), StatusCode::TEMPORARY_REDIRECT);
+            assert_eq!(res.headers()["location"], "https://cdn.example.com/two");
+        }
+
+        // the redirect is decided before the file is opened.
+        assert_eq!(fs.calls(MockOp::Open), opened + 1);
+
+        // a status that is not a redirect is a bug in the handler.
+        let res = client.get("/three").await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(res.headers().get("location").is_none());
+
+        // PROPFIND is not affected.
+        let req = Request::builder()
+            .method("PROPFIND")
+            .uri("/two")
+            .header("Depth", "0")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(client.request(req).await.status(), StatusCode::MULTI_STATUS);
+    }
+
+    #[tokio::test]
+    async fn test_collection_redirect() {
+        for (permanent, status) in [
+            (None, StatusCode::FOUND),
+            (Some(false), StatusCode::FOUND),
+            (Some(true), StatusCode::PERMANENT_REDIRECT),
+        ] {
+            let mut config = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .strip_prefix("/dav")
+                .autoindex(true);
+            if let Some(permanent) = permanent {
+                config = config.permanent_collection_redirect(permanent);
+            }
+            let client = DavClient::new(config.build_handler());
+            assert_eq!(client.mkcol("/dav/a%20b/").await, StatusCode::CREATED);
+
+            for method in ["GET", "HEAD"] {
+                let req = Request::builder()
+                    .method(method)
+                    .uri("/dav/a%20b?sort=name")
+                    .body(Body::empty())
+                    .unwrap();
+                let res = client.request(req).await;
+                assert_eq!(res.status(), status);
+                assert_eq!(res.headers()["location"], "/dav/a%20b/?sort=name");
+            }
+
+            // PROPFIND is not redirected.
+            let req = Request::builder()
+                .method("PROPFIND")
+                .uri("/dav/a%20b")
+                .header("Depth", "0")
+                .body(Body::empty())
+                .unwrap();
+            let res = client.request(req).await;
+            assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+            assert_eq!(res.headers()["content-location"], "/dav/a%20b/");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_empty_file() {
+        use http_body::Body as _;
+
+        let fs = MemFs::new();
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        fs.open(&DavPath::new("/empty").unwrap(), oo).await.unwrap();
+
+        let fs = MockFs::builder().filesystem(fs).build();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+
+        for (method, range) in [
+            ("GET", None),
+            ("HEAD", None),
+            ("GET", Some("bytes=0-")),
+            ("GET", Some("bytes=-10")),
+        ] {
+            let mut req = Request::builder().method(method).uri("/empty");
+            if let Some(range) = range {
+                req = req.header("Range", range);
+            }
+            let res = dav.handle(req.body(Body::empty()).unwrap()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()["content-length"], "0");
+            assert!(res.headers().contains_key("etag"));
+            assert!(res.headers().contains_key("last-modified"));
+            assert!(!res.headers().contains_key("content-range"));
+            assert_eq!(res.body().size_hint().exact(), Some(0));
+        }
+        assert_eq!(fs.calls(MockOp::Read), 0);
+
+        // the file is still opened, and can refuse that.
+        let fs = MockFs::builder()
+            .filesystem(fs)
+            .fail(MockOp::Open, "/empty", FsError::Forbidden)
+            .build();
+        let dav = DavHandler::builder().filesystem(fs).build_handler();
+        let res = dav
+            .handle(Request::get("/empty").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,129 @@
+// Health endpoint for liveness and readiness probes, see
+// `DavConfig::health_path`.
+//
+// It is answered before anything else, without a lookup in the
+// namespace that is served, so it keeps working if the backend is gone.
+// Only the readiness part, `DavFileSystem::healthcheck`, touches the
+// backend, with a timeout.
+//
+use std::time::Duration;
+
+use headers::HeaderMapExt;
+use http::{HeaderValue, Method, Request, Response, StatusCode};
+
+use crate::body::Body;
+use crate::util::in_url_path;
+use crate::DavResult;
+
+const HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+impl crate::DavInner {
+    // Is the request path `path` the health path, or below it.
+    pub(crate) fn in_health_path(&self, path: &str) -> bool {
+        match self.health_path {
+            Some(ref health) => in_url_path(health, path),
+            None => false,
+        }
+    }
+
+    pub(crate) async fn handle_health(&self, req: &Request<()>) -> DavResult<Response<Body>> {
+        let mut res = Response::new(Body::empty());
+
+        // nothing below the health path exists.
+        let health = self.health_path.as_deref().unwrap_or("");
+        if req.uri().path().trim_end_matches('/') != health.trim_end_matches('/') {
+            *res.status_mut() = StatusCode::NOT_FOUND;
+            return Ok(res);
+        }
+
+        let head = req.method() == Method::HEAD;
+        if !head && req.method() != Method::GET {
+            res.headers_mut()
+                .insert("Allow", HeaderValue::from_static("GET, HEAD"));
+            *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            return Ok(res);
+        }
+
+        let backend = match tokio::time::timeout(HEALTHCHECK_TIMEOUT, self.fs.healthcheck()).await {
+            Ok(Ok(())) => "ok",
+            Ok(Err(_e)) => {
+                debug!("healthcheck failed: {:?}", _e);
+                "failed"
+            }
+            Err(_) => "timeout",
+        };
+        let (status, code) = match backend {
+            "ok" => ("ok", StatusCode::OK),
+            _ => ("degraded", StatusCode::SERVICE_UNAVAILABLE),
+        };
+        let uptime = self.started.map_or(0, |t| t.elapsed().as_secs());
+        let body = format!(
+            "{{\"status\":\"{}\",\"version\":\"{}\",\"uptime\":{},\"backend\":\"{}\"}}\n",
+            status,
+            env!("CARGO_PKG_VERSION"),
+            uptime,
+            backend
+        );
+
+        let h = res.headers_mut();
+        h.insert("Content-Type", HeaderValue::from_static("application/json"));
+        h.insert("Cache-Control", HeaderValue::from_static("no-store"));
+        h.typed_insert(headers::ContentLength(body.len() as u64));
+        *res.status_mut() = code;
+        if !head {
+            *res.body_mut() = Body::from(body);
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::testing::{DavClient, Depth};
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn test_health_path() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .health_path("/dav/healthz")
+            .strip_prefix("/dav")
+            .build_handler();
+        let client = DavClient::new(dav);
+
+        let res = client.get("/dav/healthz").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-type"], "application/json");
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert!(body.starts_with("{\"status\":\"ok\",\"version\":\""));
+        assert!(body.ends_with(",\"backend\":\"ok\"}\n"));
+
+        // not part of the namespace.
+        assert_eq!(
+            client.put_bytes("/dav/healthz", "x").await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(client.mkcol("/dav/healthz/x/").await, StatusCode::NOT_FOUND);
+        assert_eq!(client.put_bytes("/dav/a", "a").await, StatusCode::CREATED);
+        assert_eq!(
+            client.copy("/dav/a", "/dav/healthz", true).await,
+            StatusCode::FORBIDDEN
+        );
+
+        // a file that is there anyway is not listed.
+        let fs = MemFs::new();
+        let plain = DavClient::new(DavHandler::builder().filesystem(fs.clone()).build_handler());
+        assert_eq!(plain.put_bytes("/healthz", "x").await, StatusCode::CREATED);
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .health_path("/healthz")
+            .build_handler();
+        let client = DavClient::new(dav);
+        let ms = client.propfind("/", Depth::One, &[]).await.unwrap();
+        assert_eq!(ms.hrefs(), vec!["/"]);
+        let ms = plain.propfind("/", Depth::One, &[]).await.unwrap();
+        assert_eq!(ms.hrefs(), vec!["/", "/healthz"]);
+    }
+}
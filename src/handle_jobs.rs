@@ -0,0 +1,444 @@
+// Background COPY and MOVE jobs, see `DavConfig::async_copymove`.
+//
+// `handle_copymove` does all its checks in the request, then starts
+// a job. A spawned task drives the multistatus stream of the operation
+// to the end and keeps the response, so the client can pick it up at
+// the status resource of the job later.
+//
+// Jobs live in memory, in the config of the handler. A finished job is
+// forgotten after `JOB_GRACE`, or earlier to make room for a new job
+// when there are `DavConfig::max_jobs` already.
+//
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+use futures_util::StreamExt;
+use headers::HeaderMapExt;
+use http::{header, HeaderValue, Method, Request, Response, StatusCode};
+
+use crate::body::Body;
+use crate::davpath::DavPath;
+use crate::DavResult;
+
+// How long the response of a finished job is kept.
+const JOB_GRACE: Duration = Duration::from_secs(600);
+
+// The default for `DavConfig::max_jobs`.
+const MAX_JOBS: usize = 64;
+
+#[derive(Default)]
+pub(crate) struct Jobs {
+    jobs: Mutex<HashMap<String, Arc<Job>>>,
+}
+
+pub(crate) struct Job {
+    id: String,
+    // URL of the status resource.
+    location: String,
+    method: String,
+    href: String,
+    principal: Option<Arc<str>>,
+    state: Mutex<JobState>,
+}
+
+#[derive(Default)]
+struct JobState {
+    done: u64,
+    total: Option<u64>,
+    current: Option<String>,
+    result: Option<JobResult>,
+}
+
+// The response of a finished job.
+struct JobResult {
+    status: StatusCode,
+    content_type: Option<HeaderValue>,
+    retry_after: Option<HeaderValue>,
+    body: Bytes,
+    finished: Instant,
+}
+
+impl Jobs {
+    // Add a job, unless there are `max` already. To make room, the job
+    // that finished first is forgotten early. Running jobs are kept.
+    fn insert(&self, job: Arc<Job>, max: usize) -> bool {
+        let mut jobs = self.jobs.lock().unwrap();
+        prune(&mut jobs);
+        if jobs.len() >= max {
+            let oldest = jobs
+                .iter()
+                .filter_map(|(id, job)| {
+                    Some((job.state.lock().unwrap().result.as_ref()?.finished, id))
+                })
+                .min()
+                .map(|(_, id)| id.clone());
+            match oldest {
+                Some(id) => jobs.remove(&id),
+                None => return false,
+            };
+        }
+        jobs.insert(job.id.clone(), job);
+        true
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<Job>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        prune(&mut jobs);
+        jobs.get(id).cloned()
+    }
+}
+
+// forget the jobs that finished more than JOB_GRACE ago.
+fn prune(jobs: &mut HashMap<String, Arc<Job>>) {
+    jobs.retain(|_, job| match job.state.lock().unwrap().result {
+        Some(ref r) => r.finished.elapsed() < JOB_GRACE,
+        None => true,
+    });
+}
+
+// A string as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl Job {
+    // Called for every resource that has been copied or moved.
+    pub fn progress(&self, path: &DavPath) {
+        let mut state = self.state.lock().unwrap();
+        state.done += 1;
+        state.current = Some(path.with_prefix().as_url_string());
+    }
+
+    // Called for every resource that is found in the tree.
+    pub fn found(&self, count: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.total = Some(state.total.unwrap_or(0) + count);
+    }
+
+    // Keep the response of the operation.
+    pub async fn finish(&self, res: DavResult<Response<Body>>) {
+        let result = match res {
+            Ok(res) => {
+                let (parts, mut body) = res.into_parts();
+                let mut data = BytesMut::new();
+                while let Some(chunk) = body.next().await {
+                    match chunk {
+                        Ok(chunk) => data.extend_from_slice(&chunk),
+                        Err(_e) => {
+                            debug!("job {}: reading response: {:?}", self.id, _e);
+                            break;
+                        }
+                    }
+                }
+                JobResult {
+                    status: parts.status,
+                    content_type: parts.headers.get(header::CONTENT_TYPE).cloned(),
+                    retry_after: parts.headers.get(header::RETRY_AFTER).cloned(),
+                    body: data.freeze(),
+                    finished: Instant::now(),
+                }
+            }
+            Err(e) => JobResult {
+                status: e.statuscode(),
+                content_type: None,
+                retry_after: e.retry_after().map(|d| HeaderValue::from(d.as_secs())),
+                body: Bytes::new(),
+                finished: Instant::now(),
+            },
+        };
+        debug!("job {}: finished: {}", self.id, result.status);
+        self.state.lock().unwrap().result = Some(result);
+    }
+
+    // The progress of a running job, as JSON.
+    fn report(&self, state: &JobState) -> String {
+        let total = match state.total {
+            Some(total) => total.to_string(),
+            None => "null".to_string(),
+        };
+        let current = match state.current {
+            Some(ref current) => json_string(current),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"state\":\"running\",\"method\":{},\"href\":{},\"done\":{},\"total\":{},\"current\":{}}}\n",
+            json_string(&self.method),
+            json_string(&self.href),
+            state.done,
+            total,
+            current
+        )
+    }
+
+    fn json_response(&self, status: StatusCode, state: &JobState, head: bool) -> Response<Body> {
+        let body = self.report(state);
+        let mut res = Response::new(Body::empty());
+        let h = res.headers_mut();
+        h.insert("Content-Type", HeaderValue::from_static("application/json"));
+        h.insert("Cache-Control", HeaderValue::from_static("no-store"));
+        h.typed_insert(headers::ContentLength(body.len() as u64));
+        *res.status_mut() = status;
+        if !head {
+            *res.body_mut() = Body::from(body);
+        }
+        res
+    }
+
+    // The response to the COPY or MOVE that started the job.
+    pub fn accepted(&self) -> Response<Body> {
+        let state = self.state.lock().unwrap();
+        let mut res = self.json_response(StatusCode::ACCEPTED, &state, false);
+        if let Ok(location) = HeaderValue::from_str(&self.location) {
+            res.headers_mut().insert("Location", location);
+        }
+        res
+    }
+
+    // The response to a GET or HEAD of the status resource.
+    fn status(&self, head: bool) -> Response<Body> {
+        let state = self.state.lock().unwrap();
+        let result = match state.result {
+            Some(ref result) => result,
+            None => return self.json_response(StatusCode::OK, &state, head),
+        };
+        let mut res = Response::new(Body::empty());
+        let h = res.headers_mut();
+        if let Some(ref ct) = result.content_type {
+            h.insert("Content-Type", ct.clone());
+        }
+        if let Some(ref after) = result.retry_after {
+            h.insert("Retry-After", after.clone());
+        }
+        h.insert("Cache-Control", HeaderValue::from_static("no-store"));
+        h.typed_insert(headers::ContentLength(result.body.len() as u64));
+        *res.status_mut() = result.status;
+        if !head {
+            *res.body_mut() = Body::from(result.body.clone());
+        }
+        res
+    }
+}
+
+impl crate::DavInner {
+    // Start a background job for a COPY or MOVE of `path`. Returns `None`
+    // if background jobs are not enabled, or there is no tokio runtime
+    // to run them on, and 503 if there are too many jobs.
+    pub(crate) fn start_job(
+        &self,
+        req: &Request<()>,
+        path: &DavPath,
+    ) -> DavResult<Option<Arc<Job>>> {
+        let (jobs, base) = match (&self.jobs, &self.async_copymove) {
+            (Some(jobs), Some(base)) => (jobs, base),
+            _ => return Ok(None),
+        };
+        if tokio::runtime::Handle::try_current().is_err() {
+            debug!("async_copymove: not running on a tokio runtime");
+            return Ok(None);
+        }
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let job = Arc::new(Job {
+            location: format!("{}/{}", base.trim_end_matches('/'), id),
+            id,
+            method: req.method().to_string(),
+            href: path.with_prefix().as_url_string(),
+            principal: self.principal.clone(),
+            state: Mutex::new(JobState::default()),
+        });
+        if !jobs.insert(job.clone(), self.max_jobs.unwrap_or(MAX_JOBS)) {
+            debug!("async_copymove: too many jobs");
+            return Err(StatusCode::SERVICE_UNAVAILABLE.into());
+        }
+        debug!("job {}: {} {}", job.id, job.method, job.href);
+        job.found(1);
+        Ok(Some(job))
+    }
+
+    pub(crate) fn handle_job(&self, req: &Request<()>) -> DavResult<Response<Body>> {
+        let mut res = Response::new(Body::empty());
+
+        // the status path itself is not a resource, and a job of another
+        // principal does not exist either.
+        let base = self.async_copymove.as_deref().unwrap_or("");
+        let id = req
+            .uri()
+            .path()
+            .trim_end_matches('/')
+            .strip_prefix(base.trim_end_matches('/'))
+            .and_then(|rest| rest.strip_prefix('/'))
+            .unwrap_or("");
+        let job = match self.jobs {
+            Some(ref jobs) if !id.is_empty() => jobs.get(id),
+            _ => None,
+        };
+        let job = match job {
+            Some(job) if job.principal == self.principal => job,
+            _ => {
+                *res.status_mut() = StatusCode::NOT_FOUND;
+                return Ok(res);
+            }
+        };
+
+        let head = req.method() == Method::HEAD;
+        if !head && req.method() != Method::GET {
+            res.headers_mut()
+                .insert("Allow", HeaderValue::from_static("GET, HEAD"));
+            *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            return Ok(res);
+        }
+        Ok(job.status(head))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testfs::{MockFs, MockOp};
+    use crate::testing::DavClient;
+    use crate::{DavConfig, DavHandler};
+    use tokio::sync::Semaphore;
+
+    fn copymove(method: &str, path: &str, dest: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .header("Destination", dest)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    // Wait until the progress report contains `until`, or without
+    // `until`, until the job is finished.
+    async fn poll(client: &DavClient, location: &str, until: Option<&str>) -> Response<Bytes> {
+        for _ in 0..500 {
+            let res = client.get(location).await;
+            let body = std::str::from_utf8(res.body()).unwrap();
+            if res.status() != StatusCode::OK || until.is_some_and(|u| body.contains(u)) {
+                return res;
+            }
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        panic!("job did not get to {:?}", until);
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("/a b/"), "\"/a b/\"");
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+
+    #[tokio::test]
+    async fn test_async_copymove() {
+        // copying a file waits for a permit of `gate`.
+        let gate = Arc::new(Semaphore::new(0));
+        let fs = MockFs::builder()
+            .wait(MockOp::Copy, "/src/*", gate.clone())
+            .build();
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .principal("alice")
+            .async_copymove("/.jobs")
+            .build_handler();
+        let client = DavClient::new(dav.clone());
+        assert_eq!(client.mkcol("/src/").await, StatusCode::CREATED);
+        for f in ["/src/a", "/src/b", "/src/c"] {
+            assert_eq!(client.put_bytes(f, f).await, StatusCode::CREATED);
+        }
+
+        // a file is copied right away.
+        gate.add_permits(1);
+        let res = client.request(copymove("COPY", "/src/a", "/a")).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        // a collection in the background.
+        let res = client.request(copymove("COPY", "/src/", "/dst/")).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        assert_eq!(res.headers()["content-type"], "application/json");
+        let location = res.headers()["location"].to_str().unwrap().to_string();
+        assert!(location.starts_with("/.jobs/"));
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert!(body.starts_with("{\"state\":\"running\",\"method\":\"COPY\",\"href\":\"/src/\""));
+
+        // the collection is done, the first file is underway.
+        let res = poll(&client, &location, Some("\"done\":1,")).await;
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert!(body.contains("\"total\":2,"));
+        assert!(body.contains("\"current\":\"/src/\""));
+        assert_eq!(res.headers()["cache-control"], "no-store");
+
+        // only for the principal that started it, and read-only.
+        let bob = DavConfig::new().principal("bob");
+        let get = Request::get(&location).body(Body::empty()).unwrap();
+        let res = dav.handle_with(bob, get).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            client.put_bytes(&location, "x").await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(client.get("/.jobs/").await.status(), StatusCode::NOT_FOUND);
+
+        // done: the status the COPY would have had.
+        gate.add_permits(3);
+        let res = poll(&client, &location, None).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert!(res.body().is_empty());
+        assert_eq!(client.get("/dst/c").await.body().as_ref(), b"/src/c");
+
+        let res = client.request(copymove("MOVE", "/dst/", "/moved/")).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let location = res.headers()["location"].to_str().unwrap().to_string();
+        let res = poll(&client, &location, None).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(client.get("/moved/b").await.status(), StatusCode::OK);
+
+        // the status path is not part of the namespace.
+        let res = client.request(copymove("COPY", "/a", "/.jobs/a")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_max_jobs() {
+        let gate = Arc::new(Semaphore::new(0));
+        let fs = MockFs::builder()
+            .wait(MockOp::Copy, "/src/*", gate.clone())
+            .build();
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .async_copymove("/.jobs")
+            .max_jobs(1)
+            .retry_after(Duration::from_secs(5))
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.mkcol("/src/").await, StatusCode::CREATED);
+        assert_eq!(client.put_bytes("/src/a", "a").await, StatusCode::CREATED);
+
+        // while a job is running, there is no room for another one.
+        let res = client.request(copymove("COPY", "/src/", "/one/")).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let first = res.headers()["location"].to_str().unwrap().to_string();
+        let res = client.request(copymove("COPY", "/src/", "/two/")).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()["retry-after"], "5");
+
+        // a finished job makes room.
+        gate.add_permits(2);
+        let res = poll(&client, &first, None).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let res = client.request(copymove("COPY", "/src/", "/two/")).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        assert_eq!(client.get(&first).await.status(), StatusCode::NOT_FOUND);
+    }
+}
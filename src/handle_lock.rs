@@ -8,7 +8,6 @@ use http::{Request, Response};
 use xmltree::{self, Element};
 
 use crate::body::Body;
-use crate::conditional::{dav_if_match, if_match};
 use crate::davheaders::{self, DavTimeout};
 use crate::davpath::DavPath;
 use crate::errors::*;
@@ -42,7 +41,7 @@ impl crate::DavInner {
         // lock refresh?
         if xmldata.is_empty() {
             // get locktoken
-            let (_, tokens) = dav_if_match(req, &self.fs, &self.ls, &path).await;
+            let (_, tokens) = self.dav_if_match(&path).await;
             if tokens.len() != 1 {
                 return Err(SC::BAD_REQUEST.into());
             }
@@ -68,14 +67,14 @@ impl crate::DavInner {
         }
 
         // handle Depth:
-        let deep = match req.headers().typed_get::<davheaders::Depth>() {
-            Some(davheaders::Depth::Infinity) | None => true,
-            Some(davheaders::Depth::Zero) => false,
+        let deep = match req.headers().typed_try_get::<davheaders::Depth>() {
+            Ok(Some(davheaders::Depth::Infinity)) | Ok(None) => true,
+            Ok(Some(davheaders::Depth::Zero)) => false,
             _ => return Err(SC::BAD_REQUEST.into()),
         };
 
         // handle the if-headers.
-        if let Some(s) = if_match(req, meta.as_ref(), &self.fs, &self.ls, &path).await {
+        if let Some(s) = self.if_match(req, meta.as_deref(), &path).await {
             return Err(s.into());
         }
 
@@ -140,6 +139,13 @@ impl crate::DavInner {
         };
         let shared = shared.unwrap();
 
+        // with lock-null resources, only the name is locked. It still
+        // has to be a name that can be created.
+        let lock_null = meta.is_none() && self.lock_null_resources.unwrap_or(false);
+        if lock_null {
+            self.check_parent(&path).await?;
+        }
+
         // create lock
         let timeout = get_timeout(req, false, shared);
         let principal = self.principal.as_deref();
@@ -151,7 +157,7 @@ impl crate::DavInner {
         // try to create file if it doesn't exist.
         let create = oo.create;
         let create_new = oo.create_new;
-        if meta.is_none() {
+        if meta.is_none() && !lock_null {
             match self.fs.open(&path, oo).await {
                 Ok(_) => {}
                 Err(FsError::NotFound) | Err(FsError::Exists) => {
@@ -175,7 +181,7 @@ impl crate::DavInner {
         let ct = "application/xml; charset=utf-8".to_owned();
         res.headers_mut().typed_insert(davheaders::LockToken(lt));
         res.headers_mut().typed_insert(davheaders::ContentType(ct));
-        if meta.is_none() {
+        if meta.is_none() && !lock_null {
             *res.status_mut() = SC::CREATED;
         } else {
             *res.status_mut() = SC::OK;
@@ -211,6 +217,23 @@ impl crate::DavInner {
             self.fixpath(&mut res, &mut path, meta);
         }
 
+        // with strict_lock_owner, only the owner of the lock may remove it.
+        if self.strict_lock_owner.unwrap_or(false) {
+            let locks = locksystem.discover(&path);
+            if let Some(lock) = locks.iter().find(|l| l.token == token) {
+                if !self.is_lock_owner(lock, &[token.to_string()]) {
+                    return Err(SC::FORBIDDEN.into());
+                }
+            }
+        }
+
+        if self.dry_run {
+            if !locksystem.discover(&path).iter().any(|l| l.token == token) {
+                return Err(SC::CONFLICT.into());
+            }
+            return Ok(res);
+        }
+
         match locksystem.unlock(&path, token) {
             Ok(_) => {
                 *res.status_mut() = SC::NO_CONTENT;
@@ -340,3 +363,341 @@ fn build_lock_prop(lock: &DavLock, full: bool) -> Element {
 
     prop
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::davpath::DavPath;
+    use crate::ls::DavLockSystem;
+    use crate::memfs::MemFs;
+    use crate::memls::MemLs;
+    use crate::testing::{DavClient, Depth};
+    use crate::DavHandler;
+    use http::{Request, StatusCode};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_lock_depth() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        for path in ["/deep/", "/deep/sub/", "/shallow/"] {
+            assert_eq!(client.mkcol(path).await, StatusCode::CREATED);
+        }
+
+        // Depth: 1 is not allowed on LOCK, and neither is garbage.
+        assert_eq!(
+            client.lock("/deep/", Depth::One).await,
+            Err(StatusCode::BAD_REQUEST)
+        );
+        let req = Request::builder()
+            .method("LOCK")
+            .uri("/deep/")
+            .header("Depth", "2")
+            .body(Body::from(
+                r#"<?xml version="1.0" encoding="utf-8" ?>
+                <D:lockinfo xmlns:D="DAV:">
+                <D:lockscope><D:exclusive/></D:lockscope>
+                <D:locktype><D:write/></D:locktype>
+                </D:lockinfo>"#,
+            ))
+            .unwrap();
+        assert_eq!(client.request(req).await.status(), StatusCode::BAD_REQUEST);
+
+        // A depth-infinity lock covers all descendants.
+        let token = client.lock("/deep/", Depth::Infinity).await.unwrap();
+        assert_eq!(
+            client.put_bytes("/deep/sub/file", "x").await,
+            StatusCode::LOCKED
+        );
+        assert_eq!(
+            client.lock("/deep/sub/", Depth::Zero).await,
+            Err(StatusCode::LOCKED)
+        );
+        let req = Request::put("/deep/sub/file")
+            .header("If", format!("(<{}>)", token))
+            .body(Body::from("x"))
+            .unwrap();
+        assert_eq!(client.request(req).await.status(), StatusCode::CREATED);
+
+        // The lock is reported on descendants too.
+        let ms = client
+            .propfind("/deep/sub/file", Depth::Zero, &["lockdiscovery"])
+            .await
+            .unwrap();
+        let ld = ms.responses[0].prop("lockdiscovery").unwrap();
+        let ld = xmltree_text(ld);
+        assert!(ld.contains(&token));
+
+        // A depth-0 lock only covers the collection itself.
+        let token = client.lock("/shallow/", Depth::Zero).await.unwrap();
+        let ms = client
+            .propfind("/shallow/", Depth::Zero, &["lockdiscovery"])
+            .await
+            .unwrap();
+        assert!(xmltree_text(ms.responses[0].prop("lockdiscovery").unwrap()).contains(&token));
+        // locking an unmapped url creates it.
+        let token2 = client.lock("/shallow/file", Depth::Zero).await.unwrap();
+        assert_ne!(token, token2);
+        let ms = client
+            .propfind("/shallow/file", Depth::Zero, &["lockdiscovery"])
+            .await
+            .unwrap();
+        assert!(!xmltree_text(ms.responses[0].prop("lockdiscovery").unwrap()).contains(&token));
+    }
+
+    #[tokio::test]
+    async fn test_lock_then_put() {
+        use crate::davpath::DavPath;
+        use crate::ls::DavLockSystem;
+
+        let ls = MemLs::new();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(ls.clone())
+            .build_handler();
+        let client = DavClient::new(dav);
+        let ua = "Microsoft-WebDAV-MiniRedir/10.0.19045";
+        let request = |method: &str, path: &str, token: &str, body: &'static str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(path)
+                .header("User-Agent", ua)
+                .header("If", format!("(<{}>)", token))
+                .body(Body::from(body))
+                .unwrap();
+            client.request(req)
+        };
+        let locks = |path: &str| ls.discover(&DavPath::new(path).unwrap()).len();
+
+        // LOCK creates an empty file, that PUT then fills.
+        let token = client.lock("/new.txt", Depth::Zero).await.unwrap();
+        assert_eq!(client.get("/new.txt").await.body().len(), 0);
+        let res = request("PUT", "/new.txt", &token, "hello").await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(locks("/new.txt"), 1);
+        let proppatch = concat!(
+            r#"<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:schemas-microsoft-com:">"#,
+            r#"<D:set><D:prop><Z:Win32FileAttributes>00000020</Z:Win32FileAttributes>"#,
+            r#"</D:prop></D:set></D:propertyupdate>"#
+        );
+        let res = request("PROPPATCH", "/new.txt", &token, proppatch).await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        assert_eq!(
+            client.unlock("/new.txt", &token).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(locks("/new.txt"), 0);
+        assert_eq!(client.get("/new.txt").await.body().as_ref(), b"hello");
+
+        // Explorer: a zero-length PUT, LOCK, PUT with the data, UNLOCK.
+        assert_eq!(client.put_bytes("/copy.txt", "").await, StatusCode::CREATED);
+        let token = client.lock("/copy.txt", Depth::Zero).await.unwrap();
+        let res = request("PUT", "/copy.txt", &token, "data").await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let res = request("PUT", "/copy.txt", &token, "").await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            client.unlock("/copy.txt", &token).await,
+            StatusCode::NO_CONTENT
+        );
+        let res = client.get("/copy.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_lock_null_resources() {
+        use crate::davpath::DavPath;
+        use crate::ls::DavLockSystem;
+
+        let ls = MemLs::new();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(ls.clone())
+            .lock_null_resources(true)
+            .build_handler();
+        let client = DavClient::new(dav);
+        let locks = |path: &str| ls.discover(&DavPath::new(path).unwrap()).len();
+
+        // LOCK only reserves the name, PUT creates the file.
+        let token = client.lock("/new.txt", Depth::Zero).await.unwrap();
+        assert_eq!(client.get("/new.txt").await.status(), StatusCode::NOT_FOUND);
+        let res = client.propfind("/new.txt", Depth::Zero, &[]).await;
+        assert_eq!(res.err(), Some(StatusCode::NOT_FOUND));
+        let ms = client.propfind("/", Depth::One, &[]).await.unwrap();
+        assert!(ms.response("/new.txt").is_none());
+        assert_eq!(client.delete("/new.txt").await, StatusCode::NOT_FOUND);
+        assert_eq!(client.put_bytes("/new.txt", "x").await, StatusCode::LOCKED);
+        let res = client.request(put_with_token("/new.txt", &token)).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(locks("/new.txt"), 1);
+        assert_eq!(
+            client.unlock("/new.txt", &token).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(client.get("/new.txt").await.body().as_ref(), b"x");
+
+        // without a PUT, nothing is left behind.
+        let token = client.lock("/gone.txt", Depth::Zero).await.unwrap();
+        assert_eq!(
+            client.unlock("/gone.txt", &token).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            client.get("/gone.txt").await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        // the parent has to exist.
+        assert_eq!(
+            client.lock("/nodir/file", Depth::Zero).await,
+            Err(StatusCode::CONFLICT)
+        );
+        assert_eq!(locks("/nodir/file"), 0);
+    }
+
+    // two clients, alice and bob, sharing one filesystem and locksystem.
+    fn two_clients(strict: bool) -> (DavClient, DavClient) {
+        let fs = MemFs::new();
+        let ls = MemLs::new();
+        let client = |principal: &str| {
+            let dav = DavHandler::builder()
+                .filesystem(fs.clone())
+                .locksystem(ls.clone())
+                .principal(principal)
+                .strict_lock_owner(strict)
+                .build_handler();
+            DavClient::new(dav)
+        };
+        (client("alice"), client("bob"))
+    }
+
+    fn put_with_token(path: &str, token: &str) -> Request<Body> {
+        Request::put(path)
+            .header("If", format!("(<{}>)", token))
+            .body(Body::from("x"))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_lock_owner() {
+        // default: a token of somebody else's lock does not help.
+        let (alice, bob) = two_clients(false);
+        let token = alice.lock("/file", Depth::Zero).await.unwrap();
+        let res = bob.request(put_with_token("/file", &token)).await;
+        assert_eq!(res.status(), StatusCode::LOCKED);
+        assert_eq!(bob.unlock("/file", &token).await, StatusCode::NO_CONTENT);
+
+        // strict: both are forbidden for bob, and allowed for alice.
+        let (alice, bob) = two_clients(true);
+        let token = alice.lock("/file", Depth::Zero).await.unwrap();
+        let res = bob.request(put_with_token("/file", &token)).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(bob.put_bytes("/file", "x").await, StatusCode::LOCKED);
+        assert_eq!(bob.unlock("/file", &token).await, StatusCode::FORBIDDEN);
+
+        let res = alice.request(put_with_token("/file", &token)).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(alice.unlock("/file", &token).await, StatusCode::NO_CONTENT);
+        assert_eq!(bob.put_bytes("/file", "x").await, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_put_locked() {
+        let (alice, bob) = two_clients(false);
+        assert_eq!(alice.mkcol("/dir/").await, StatusCode::CREATED);
+        assert_eq!(
+            alice.put_bytes("/dir/file", "old").await,
+            StatusCode::CREATED
+        );
+
+        // a locked file can only be written with the token.
+        let token = alice.lock("/dir/file", Depth::Zero).await.unwrap();
+        let req = Request::put("/dir/file").body(Body::from("bob")).unwrap();
+        let res = bob.request(req).await;
+        assert_eq!(res.status(), StatusCode::LOCKED);
+        assert_eq!(res.headers()["connection"], "close");
+        assert_eq!(
+            alice.put_bytes("/dir/file", "new").await,
+            StatusCode::LOCKED
+        );
+        assert_eq!(bob.get("/dir/file").await.body().as_ref(), b"old");
+        let res = alice.request(put_with_token("/dir/file", &token)).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(bob.get("/dir/file").await.body().as_ref(), b"x");
+        assert_eq!(
+            alice.unlock("/dir/file", &token).await,
+            StatusCode::NO_CONTENT
+        );
+
+        // and so can a new member of a locked collection.
+        let token = alice.lock("/dir/", Depth::Infinity).await.unwrap();
+        assert_eq!(bob.put_bytes("/dir/new", "bob").await, StatusCode::LOCKED);
+        assert_eq!(bob.get("/dir/new").await.status(), StatusCode::NOT_FOUND);
+        let res = alice.request(put_with_token("/dir/new", &token)).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_get_respects_locks() {
+        let fs = MemFs::new();
+        let ls = MemLs::new();
+        let client = |principal: &str, respect: bool| {
+            let dav = DavHandler::builder()
+                .filesystem(fs.clone())
+                .locksystem(ls.clone())
+                .principal(principal)
+                .get_respects_locks(respect)
+                .build_handler();
+            DavClient::new(dav)
+        };
+        let alice = client("alice", true);
+        assert_eq!(alice.put_bytes("/file", "x").await, StatusCode::CREATED);
+        let token = alice.lock("/file", Depth::Zero).await.unwrap();
+
+        // by default a write lock does not keep anybody from reading.
+        let res = client("bob", false).get("/file").await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // with the option, only the owner can read.
+        let bob = client("bob", true);
+        assert_eq!(bob.get("/file").await.status(), StatusCode::LOCKED);
+        let req = Request::head("/file").body(Body::empty()).unwrap();
+        assert_eq!(bob.request(req).await.status(), StatusCode::LOCKED);
+        assert_eq!(alice.get("/file").await.status(), StatusCode::OK);
+        assert_eq!(alice.unlock("/file", &token).await, StatusCode::NO_CONTENT);
+        assert_eq!(bob.get("/file").await.status(), StatusCode::OK);
+
+        // without principals, only the client with the token can read.
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .locksystem(ls.clone())
+            .get_respects_locks(true)
+            .build_handler();
+        let anon = DavClient::new(dav);
+        let token = anon.lock("/file", Depth::Zero).await.unwrap();
+        assert_eq!(anon.get("/file").await.status(), StatusCode::LOCKED);
+        let req = Request::get("/file")
+            .header("If", format!("(<{}>)", token))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(anon.request(req).await.status(), StatusCode::OK);
+
+        // a lock that has timed out does not count.
+        let path = DavPath::new("/file").unwrap();
+        ls.unlock(&path, &token).unwrap();
+        ls.lock(&path, None, None, Some(Duration::ZERO), false, false)
+            .unwrap();
+        assert_eq!(anon.get("/file").await.status(), StatusCode::OK);
+    }
+
+    // serialize an element, to search it for a token.
+    fn xmltree_text(e: &xmltree::Element) -> String {
+        let mut buf = Vec::new();
+        e.write(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+}
@@ -1,19 +1,100 @@
+use std::io::Cursor;
+
 use headers::HeaderMapExt;
 use http::{Request, Response, StatusCode};
+use xmltree::Element;
 
 use crate::body::Body;
-use crate::conditional::*;
 use crate::davheaders;
 use crate::fs::*;
+use crate::handle_props::{element_to_davprop, element_to_davprop_full};
+use crate::xmltree_ext::ElementExt;
 use crate::{DavError, DavResult};
 
+const NS_DAV_URI: &str = "DAV:";
+
+// The resourcetypes (without DAV:collection) and the dead properties
+// that an extended MKCOL (RFC 5689) body sets.
+type MkcolBody = (Vec<DavProp>, Vec<(bool, DavProp)>);
+
+fn parse_mkcol(xmldata: &[u8]) -> DavResult<MkcolBody> {
+    let tree = Element::parse2(Cursor::new(xmldata))?;
+    if tree.name != "mkcol" || tree.namespace.as_deref() != Some(NS_DAV_URI) {
+        return Err(DavError::Status(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+    }
+    let mut resourcetypes = Vec::new();
+    let mut props = Vec::new();
+    let mut collection = false;
+    let is_dav =
+        |e: &Element, name: &str| e.name == name && e.namespace.as_deref() == Some(NS_DAV_URI);
+    for prop in tree
+        .child_elems_iter()
+        .filter(|e| is_dav(e, "set"))
+        .flat_map(|e| e.child_elems_iter())
+        .filter(|e| is_dav(e, "prop"))
+        .flat_map(|e| e.child_elems_iter())
+    {
+        if is_dav(prop, "resourcetype") {
+            for t in prop.child_elems_iter() {
+                if is_dav(t, "collection") {
+                    collection = true;
+                } else {
+                    resourcetypes.push(element_to_davprop(t));
+                }
+            }
+        } else if prop.namespace.as_deref() == Some(NS_DAV_URI) && prop.name != "displayname" {
+            // other live properties cannot be set.
+            return Err(DavError::Status(StatusCode::FORBIDDEN));
+        } else {
+            props.push((true, element_to_davprop_full(prop)));
+        }
+    }
+    // a resourcetype that is set must at least say "collection".
+    if !collection && !resourcetypes.is_empty() {
+        return Err(DavError::Status(StatusCode::FORBIDDEN));
+    }
+    Ok((resourcetypes, props))
+}
+
+// Is the body XML. Clients that leave out the Content-Type get
+// the benefit of the doubt.
+fn is_xml_body(req: &Request<()>) -> bool {
+    match req.headers().typed_get::<davheaders::ContentType>() {
+        Some(ct) => {
+            let mime = ct.0.split(';').next().unwrap_or_default().trim();
+            mime.eq_ignore_ascii_case("application/xml") || mime.eq_ignore_ascii_case("text/xml")
+        }
+        None => true,
+    }
+}
+
 impl crate::DavInner {
-    pub(crate) async fn handle_mkcol(&self, req: &Request<()>) -> DavResult<Response<Body>> {
+    pub(crate) async fn handle_mkcol(
+        &self,
+        req: &Request<()>,
+        xmldata: &[u8],
+    ) -> DavResult<Response<Body>> {
+        // RFC 4918 9.3, a body that is not understood is refused. The only
+        // one we know is the XML of extended MKCOL. An empty body is fine,
+        // whatever its Content-Type says.
+        let (resourcetypes, props) = if xmldata.is_empty() {
+            (Vec::new(), Vec::new())
+        } else if self.extended_mkcol.unwrap_or(false) && is_xml_body(req) {
+            parse_mkcol(xmldata)?
+        } else {
+            return Err(DavError::Status(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+        };
+
         let mut path = self.path(req);
         let meta = self.fs.metadata(&path).await;
+        if meta.is_err() {
+            self.check_windows_name(&path)?;
+        }
 
         // check the If and If-* headers.
-        let res = if_match_get_tokens(req, meta.as_ref().ok(), &self.fs, &self.ls, &path).await;
+        let res = self
+            .if_match_get_tokens(req, meta.as_deref().ok(), &path)
+            .await;
         let tokens = match res {
             Ok(t) => t,
             Err(s) => return Err(DavError::Status(s)),
@@ -23,18 +104,32 @@ impl crate::DavInner {
         if let Some(ref locksystem) = self.ls {
             let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             let principal = self.principal.as_deref();
-            if let Err(_l) = locksystem.check(&path, principal, false, false, t) {
-                return Err(DavError::Status(StatusCode::LOCKED));
+            if let Err(l) = locksystem.check(&path, principal, false, false, t) {
+                return Err(DavError::Status(self.lock_status(&l, &tokens)));
             }
         }
 
         let mut res = Response::new(Body::empty());
 
-        match self.fs.create_dir(&path).await {
+        let typed = !resourcetypes.is_empty();
+        let created = if self.dry_run {
+            let mut oo = OpenOptions::write();
+            oo.create = true;
+            oo.create_new = true;
+            self.dry_run_open(&path, meta.is_ok(), &oo).await
+        } else {
+            self.fs.create_collection(&path, resourcetypes).await
+        };
+        match created {
             // RFC 4918 9.3.1 MKCOL Status Codes.
             Err(FsError::Exists) => return Err(DavError::Status(StatusCode::METHOD_NOT_ALLOWED)),
             Err(FsError::NotFound) => return Err(DavError::Status(StatusCode::CONFLICT)),
+            // RFC 5689 3.3, a resourcetype that is not supported.
+            Err(FsError::NotImplemented) if typed => {
+                return Err(DavError::Status(StatusCode::FORBIDDEN))
+            }
             Err(e) => return Err(DavError::FsError(e)),
+            Ok(()) if self.dry_run => return Ok(res),
             Ok(()) => {
                 if path.is_collection() {
                     path.add_slash();
@@ -46,6 +141,80 @@ impl crate::DavInner {
             }
         }
 
+        // MKCOL is all or nothing, so if the properties cannot be
+        // set, the collection is removed again.
+        if !props.is_empty() {
+            let ok = match self.fs.patch_props(&path, props).await {
+                Ok(st) => st.iter().all(|(s, _)| *s == StatusCode::OK),
+                Err(_) => false,
+            };
+            if !ok {
+                let _ = self.fs.remove_dir(&path).await;
+                return Err(DavError::Status(StatusCode::FORBIDDEN));
+            }
+        }
+
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::testing::DavClient;
+    use crate::DavHandler;
+    use http::{Request, StatusCode};
+
+    async fn mkcol(client: &DavClient, path: &str, ct: Option<&str>, body: &str) -> StatusCode {
+        let mut req = Request::builder().method("MKCOL").uri(path);
+        if let Some(ct) = ct {
+            req = req.header("Content-Type", ct);
+        }
+        let req = req.body(Body::from(body.to_string())).unwrap();
+        client.request(req).await.status()
+    }
+
+    #[tokio::test]
+    async fn test_mkcol_body() {
+        const XML: &str = r#"<D:mkcol xmlns:D="DAV:"><D:set><D:prop>
+            <D:displayname>Dir</D:displayname>
+            </D:prop></D:set></D:mkcol>"#;
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .extended_mkcol(true)
+            .build_handler();
+        let client = DavClient::new(dav);
+
+        // no body, also with a Content-Type.
+        assert_eq!(mkcol(&client, "/a/", None, "").await, StatusCode::CREATED);
+        let ct = Some("application/octet-stream");
+        assert_eq!(mkcol(&client, "/b/", ct, "").await, StatusCode::CREATED);
+
+        // an XML body.
+        let ct = Some("application/xml; charset=utf-8");
+        assert_eq!(mkcol(&client, "/c/", ct, XML).await, StatusCode::CREATED);
+        let ct = Some("text/xml");
+        assert_eq!(mkcol(&client, "/d/", ct, XML).await, StatusCode::CREATED);
+
+        // anything else is not understood.
+        let ct = Some("application/octet-stream");
+        assert_eq!(
+            mkcol(&client, "/e/", ct, "data").await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(client.mkcol("/e/").await, StatusCode::CREATED);
+
+        // without extended MKCOL, XML is not understood either.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        let ct = Some("application/xml");
+        assert_eq!(
+            mkcol(&client, "/c/", ct, XML).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(mkcol(&client, "/b/", ct, "").await, StatusCode::CREATED);
+    }
+}
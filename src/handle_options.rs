@@ -1,10 +1,18 @@
 use headers::HeaderMapExt;
-use http::{Request, Response};
+use http::{HeaderValue, Request, Response};
 
 use crate::body::Body;
 use crate::util::{dav_method, DavMethod};
 use crate::DavResult;
 
+// add a method to the value of an Allow header.
+fn push_method(allow: &mut String, method: &str) {
+    if !allow.is_empty() {
+        allow.push(',');
+    }
+    allow.push_str(method);
+}
+
 impl crate::DavInner {
     pub(crate) async fn handle_options(&self, req: &Request<()>) -> DavResult<Response<Body>> {
         let mut res = Response::new(Body::empty());
@@ -14,9 +22,14 @@ impl crate::DavInner {
         // We could simply not report webdav level 2 support if self.allow doesn't
         // contain LOCK/UNLOCK. However we do advertise support, since there might
         // be LOCK/UNLOCK support in another part of the URL space.
-        let dav = "1,2,3,sabredav-partialupdate";
-        h.insert("DAV", dav.parse().unwrap());
-        h.insert("MS-Author-Via", "DAV".parse().unwrap());
+        let redirectrefs = self.redirectrefs.unwrap_or(false);
+        let dav = if redirectrefs {
+            "1,2,3,sabredav-partialupdate,redirectrefs"
+        } else {
+            "1,2,3,sabredav-partialupdate"
+        };
+        h.insert("DAV", HeaderValue::from_static(dav));
+        h.insert("MS-Author-Via", HeaderValue::from_static("DAV"));
         h.typed_insert(headers::ContentLength(0));
 
         // Helper to add method to array if method is in fact
@@ -25,27 +38,37 @@ impl crate::DavInner {
         // for DavMethodNotAllowed.
         let method = dav_method(req.method()).unwrap_or(DavMethod::Options);
         let islock = |m| m == DavMethod::Lock || m == DavMethod::Unlock;
-        let mm = |v: &mut Vec<String>, m: &str, y: DavMethod| {
+        let mm = |v: &mut String, m: &str, y: DavMethod| {
             if (y == DavMethod::Options || (y != method || islock(y) != islock(method)))
                 && (!islock(y) || self.ls.is_some())
+                && (y != DavMethod::MkRedirectRef || redirectrefs)
                 && self.allow.map(|x| x.contains(y)).unwrap_or(true)
             {
-                v.push(m.to_string());
+                push_method(v, m);
             }
         };
 
+        // "OPTIONS *" is about the server as a whole, so it gets the
+        // methods of all resources, and there is nothing to look up.
         let path = self.path(req);
-        let meta = self.fs.metadata(&path).await;
-        let is_unmapped = meta.is_err();
-        let is_file = meta.map(|m| m.is_file()).unwrap_or_default();
         let is_star = path.is_star() && method == DavMethod::Options;
+        let (is_unmapped, is_file) = if is_star {
+            (false, false)
+        } else {
+            let meta = self.fs.metadata(&path).await;
+            (meta.is_err(), meta.map(|m| m.is_file()).unwrap_or_default())
+        };
 
-        let mut v = Vec::new();
+        let mut v = String::with_capacity(128);
         if is_unmapped && !is_star {
+            // something can only be created in an existing collection.
             mm(&mut v, "OPTIONS", DavMethod::Options);
-            mm(&mut v, "MKCOL", DavMethod::MkCol);
-            mm(&mut v, "PUT", DavMethod::Put);
-            mm(&mut v, "LOCK", DavMethod::Lock);
+            if self.check_parent(&path).await.is_ok() {
+                mm(&mut v, "MKCOL", DavMethod::MkCol);
+                mm(&mut v, "PUT", DavMethod::Put);
+                mm(&mut v, "LOCK", DavMethod::Lock);
+                mm(&mut v, "MKREDIRECTREF", DavMethod::MkRedirectRef);
+            }
         } else {
             if is_file || is_star {
                 mm(&mut v, "HEAD", DavMethod::Head);
@@ -53,10 +76,17 @@ impl crate::DavInner {
                 mm(&mut v, "PATCH", DavMethod::Patch);
                 mm(&mut v, "PUT", DavMethod::Put);
             }
+            if !is_file {
+                mm(&mut v, "POST", DavMethod::Post);
+            }
+            if is_star {
+                mm(&mut v, "MKCOL", DavMethod::MkCol);
+                mm(&mut v, "MKREDIRECTREF", DavMethod::MkRedirectRef);
+            }
             mm(&mut v, "OPTIONS", DavMethod::Options);
             mm(&mut v, "PROPFIND", DavMethod::PropFind);
             mm(&mut v, "COPY", DavMethod::Copy);
-            if path.as_url_string() != "/" {
+            if path.as_bytes() != b"/" {
                 mm(&mut v, "MOVE", DavMethod::Move);
                 mm(&mut v, "DELETE", DavMethod::Delete);
             }
@@ -64,7 +94,14 @@ impl crate::DavInner {
             mm(&mut v, "UNLOCK", DavMethod::Unlock);
         }
 
-        let a = v.join(",").parse().unwrap();
+        if let Some(ref methods) = self.extension_methods {
+            for m in methods.keys() {
+                push_method(&mut v, m);
+            }
+        }
+
+        // extension method names were checked by the builder.
+        let a = HeaderValue::from_str(&v).unwrap();
         res.headers_mut().insert("allow", a);
 
         Ok(res)
@@ -2,6 +2,7 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::io::{self, Cursor};
+use std::time::SystemTime;
 
 use futures_util::{future::BoxFuture, FutureExt, StreamExt};
 use headers::HeaderMapExt;
@@ -16,23 +17,24 @@ use xmltree::{Element, XMLNode};
 
 use crate::async_stream::AsyncStream;
 use crate::body::Body;
-use crate::conditional::if_match_get_tokens;
+use crate::conditional::Validators;
 use crate::davheaders;
 use crate::davpath::*;
 use crate::errors::*;
 use crate::fs::*;
 use crate::handle_lock::{list_lockdiscovery, list_supportedlock};
-use crate::ls::*;
-use crate::util::MemBuffer;
-use crate::util::{dav_xml_error, systemtime_to_httpdate, systemtime_to_rfc3339};
-use crate::{DavInner, DavResult};
+use crate::util::{buffer_body, MemBuffer};
+use crate::util::{dav_xml_error, limit_error, systemtime_to_httpdate, systemtime_to_rfc3339};
+use crate::{ClientQuirks, CreationDateFallback, DavInner, DavResult};
 
 const NS_APACHE_URI: &str = "http://apache.org/dav/props/";
+const NS_CALSERVER_URI: &str = "http://calendarserver.org/ns/";
 const NS_DAV_URI: &str = "DAV:";
 const NS_MS_URI: &str = "urn:schemas-microsoft-com:";
 
 // list returned by PROPFIND <propname/>.
 const PROPNAME_STR: &[&str] = &[
+    "D:add-member",
     "D:creationdate",
     "D:displayname",
     "D:getcontentlanguage",
@@ -41,6 +43,8 @@ const PROPNAME_STR: &[&str] = &[
     "D:getetag",
     "D:getlastmodified",
     "D:lockdiscovery",
+    "D:redirect-lifetime",
+    "D:reftarget",
     "D:resourcetype",
     "D:supportedlock",
     "D:quota-available-bytes",
@@ -59,6 +63,8 @@ const ALLPROP_STR: &[&str] = &[
     "D:getetag",
     "D:getlastmodified",
     "D:lockdiscovery",
+    "D:redirect-lifetime",
+    "D:reftarget",
     "D:resourcetype",
     "D:supportedlock",
 ];
@@ -81,6 +87,21 @@ const MS_ALLPROP_STR: &[&str] = &[
     "Z:Win32LastModifiedTime",
 ];
 
+// live properties that are computed by the server, in the DAV: namespace.
+// PROPPATCH answers them with 403 and DAV:cannot-modify-protected-property.
+const PROTECTED_STR: &[&str] = &[
+    "add-member",
+    "creationdate",
+    "getcontentlength",
+    "getetag",
+    "getlastmodified",
+    "lockdiscovery",
+    "quota-available-bytes",
+    "quota-used-bytes",
+    "resourcetype",
+    "supportedlock",
+];
+
 lazy_static! {
     static ref ALLPROP: Vec<Element> = init_staticprop(ALLPROP_STR);
     static ref MS_ALLPROP: Vec<Element> = init_staticprop(MS_ALLPROP_STR);
@@ -100,10 +121,12 @@ struct PropWriter {
     tx: Option<Sender>,
     name: String,
     props: Vec<Element>,
-    fs: Box<dyn DavFileSystem>,
-    ls: Option<Box<dyn DavLockSystem>>,
-    useragent: String,
+    // the handler, for the filesystem, the locksystem and the validators.
+    dav: DavInner,
+    quirks: ClientQuirks,
+    prefixes: Prefixes,
     q_cache: QuotaCache,
+    creationdate_fallback: CreationDateFallback,
 }
 
 #[derive(Default, Clone, Copy)]
@@ -113,6 +136,89 @@ struct QuotaCache {
     q_total: Option<u64>,
 }
 
+// The namespace prefixes of a multistatus response. The well-known
+// namespaces have a fixed prefix, any other namespace gets the next
+// "nsN", so that a namespace has the same prefix in every response.
+#[derive(Default)]
+struct Prefixes {
+    // namespace => (prefix, declared on the multistatus element)
+    map: HashMap<String, (String, bool)>,
+    next: usize,
+}
+
+impl Prefixes {
+    // The prefix for `ns`, and whether it is declared on the root.
+    fn get(&mut self, ns: &str) -> (String, bool) {
+        if ns == NS_DAV_URI {
+            return ("D".to_string(), true);
+        }
+        if let Some(p) = self.map.get(ns) {
+            return p.clone();
+        }
+        let pfx = match ns {
+            NS_APACHE_URI => "A".to_string(),
+            NS_CALSERVER_URI => "CS".to_string(),
+            NS_MS_URI => "Z".to_string(),
+            _ => {
+                self.next += 1;
+                format!("ns{}", self.next)
+            }
+        };
+        self.map.insert(ns.to_string(), (pfx.clone(), false));
+        (pfx, false)
+    }
+
+    // Declare `ns` on the root. Returns the prefix if it was not yet.
+    fn declare(&mut self, ns: &str) -> Option<String> {
+        let (pfx, declared) = self.get(ns);
+        if declared {
+            return None;
+        }
+        self.map.insert(ns.to_string(), (pfx.clone(), true));
+        Some(pfx)
+    }
+}
+
+// Give `elem` and its children the prefix of their namespace, and drop
+// the namespace declarations they came with. Namespaces that are not
+// declared on the root are added to `undeclared`.
+fn set_prefixes(
+    elem: &mut Element,
+    prefixes: &mut Prefixes,
+    undeclared: &mut Vec<(String, String)>,
+) {
+    elem.namespaces = None;
+    if let Some(ns) = elem.namespace.as_deref().filter(|ns| !ns.is_empty()) {
+        let (pfx, declared) = prefixes.get(ns);
+        if !declared && !undeclared.iter().any(|(_, n)| n == ns) {
+            undeclared.push((pfx.clone(), ns.to_string()));
+        }
+        elem.prefix = Some(pfx);
+    }
+    for node in elem.children.iter_mut() {
+        if let XMLNode::Element(e) = node {
+            set_prefixes(e, prefixes, undeclared);
+        }
+    }
+}
+
+// The namespaces of `elem` and its children, in document order.
+fn element_namespaces<'a>(elem: &'a Element, namespaces: &mut Vec<&'a str>) {
+    if let Some(ns) = elem.namespace.as_deref().filter(|ns| !ns.is_empty()) {
+        namespaces.push(ns);
+    }
+    for node in &elem.children {
+        if let XMLNode::Element(e) = node {
+            element_namespaces(e, namespaces);
+        }
+    }
+}
+
+// is the element in the DAV: namespace, whatever its prefix.
+fn is_dav(e: &Element) -> bool {
+    e.namespace.as_deref() == Some(NS_DAV_URI)
+}
+
 fn init_staticprop(p: &[&str]) -> Vec<Element> {
     let mut v = Vec::new();
     for a in p {
@@ -143,8 +249,8 @@ impl DavInner {
             .typed_insert(headers::CacheControl::new().with_no_cache());
         res.headers_mut().typed_insert(headers::Pragma::no_cache());
 
-        let depth = match req.headers().typed_get::<davheaders::Depth>() {
-            Some(davheaders::Depth::Infinity) | None => {
+        let depth = match req.headers().typed_try_get::<davheaders::Depth>() {
+            Ok(Some(davheaders::Depth::Infinity)) | Ok(None) => {
                 if req.headers().typed_get::<davheaders::XLitmus>().is_none() {
                     let ct = "application/xml; charset=utf-8".to_owned();
                     res.headers_mut().typed_insert(davheaders::ContentType(ct));
@@ -154,38 +260,53 @@ impl DavInner {
                 }
                 davheaders::Depth::Infinity
             }
-            Some(d) => d,
+            Ok(Some(d)) => d,
+            Err(_) => return Err(DavError::Status(StatusCode::BAD_REQUEST)),
         };
 
         // path and meta
         let mut path = self.path(req);
         let meta = self.fs.metadata(&path).await?;
+        if let Some(r) = self.follow_redirect_ref(req, &*meta) {
+            return Ok(self.redirect_response(&r));
+        }
         let meta = self.fixpath(&mut res, &mut path, meta);
 
+        // the properties are the children of DAV:prop or DAV:include.
+        let limits = self.limits();
+        if too_many_elements(xmldata, 3, limits.props) {
+            return Ok(limit_error(StatusCode::BAD_REQUEST, "props"));
+        }
+
+        // an empty body (RFC4918 9.1), or one with only whitespace, is "allprop".
         let mut root = None;
-        if !xmldata.is_empty() {
+        if !xmldata.iter().all(|b| b.is_ascii_whitespace()) {
             root = match Element::parse(Cursor::new(xmldata)) {
                 Ok(t) => {
-                    if t.name == "propfind" && t.namespace.as_deref() == Some("DAV:") {
+                    if t.name == "propfind" && is_dav(&t) {
                         Some(t)
                     } else {
                         return Err(DavError::XmlParseError);
                     }
                 }
-                Err(_) => return Err(DavError::XmlParseError),
+                Err(_) => return Err(DavError::XmlReadError),
             };
         }
 
         let (name, props) = match root {
             None => ("allprop", Vec::new()),
-            Some(mut elem) => {
-                let includes = elem
-                    .take_child("includes")
-                    .map_or(Vec::new(), |n| n.take_child_elems());
-                match elem
-                    .child_elems_into_iter()
-                    .find(|e| e.name == "propname" || e.name == "prop" || e.name == "allprop")
-                {
+            Some(elem) => {
+                // match on namespace URI, not on prefix.
+                let mut found = None;
+                let mut includes = Vec::new();
+                for e in elem.child_elems_into_iter().filter(is_dav) {
+                    match e.name.as_str() {
+                        "include" => includes = e.take_child_elems(),
+                        "propname" | "prop" | "allprop" if found.is_none() => found = Some(e),
+                        _ => {}
+                    }
+                }
+                match found {
                     Some(elem) => match elem.name.as_str() {
                         "propname" => ("propname", Vec::new()),
                         "prop" => ("prop", elem.take_child_elems()),
@@ -199,7 +320,10 @@ impl DavInner {
 
         trace!("propfind: type request: {}", name);
 
-        let mut pw = PropWriter::new(req, &mut res, name, props, &self.fs, self.ls.as_ref())?;
+        let mut pw = PropWriter::new(self.quirks, &mut res, name, props, &self)?;
+        if let Some(fallback) = self.creationdate_fallback {
+            pw.creationdate_fallback = fallback;
+        }
 
         *res.body_mut() = Body::from(AsyncStream::new(|tx| async move {
             pw.set_tx(tx);
@@ -208,13 +332,25 @@ impl DavInner {
             pw.flush().await?;
 
             if is_dir && depth != davheaders::Depth::Zero {
-                let _ = self.propfind_directory(&path, depth, &mut pw).await;
+                let mut left = limits.propfind_members;
+                let r = self
+                    .propfind_directory(&path, depth, &mut pw, &mut left)
+                    .await;
+                if let Err(DavError::Status(StatusCode::INSUFFICIENT_STORAGE)) = r {
+                    pw.write_limitresponse(&path)?;
+                    pw.flush().await?;
+                }
             }
             pw.close().await?;
 
             Ok(())
         }));
 
+        // a finite depth multistatus is sent with a Content-Length.
+        if depth != davheaders::Depth::Infinity {
+            buffer_body(&mut res).await?;
+        }
+
         Ok(res)
     }
 
@@ -223,13 +359,10 @@ impl DavInner {
         path: &'a DavPath,
         depth: davheaders::Depth,
         propwriter: &'a mut PropWriter,
+        left: &'a mut usize,
     ) -> BoxFuture<'a, DavResult<()>> {
         async move {
-            let readdir_meta = match self.hide_symlinks {
-                Some(true) | None => ReadDirMeta::DataSymlink,
-                Some(false) => ReadDirMeta::Data,
-            };
-            let mut entries = match self.fs.read_dir(path, readdir_meta).await {
+            let mut entries = match self.fs.read_dir(path, self.readdir_meta()).await {
                 Ok(entries) => entries,
                 Err(e) => {
                     // if we cannot read_dir, just skip it.
@@ -244,7 +377,16 @@ impl DavInner {
                 let meta = match dirent.metadata().await {
                     Ok(meta) => meta,
                     Err(e) => {
-                        trace!("metadata error on {}. Skipping {:?}", npath, e);
+                        trace!("metadata error on {}: {:?}", npath, e);
+                        let status = match self.unreadable_status(e) {
+                            Some(status) if !self.is_reserved_path(&npath) => status,
+                            _ => continue,
+                        };
+                        if *left == 0 {
+                            return Err(StatusCode::INSUFFICIENT_STORAGE.into());
+                        }
+                        *left -= 1;
+                        propwriter.write_statusresponse(&npath, status)?;
                         continue;
                     }
                 };
@@ -254,11 +396,20 @@ impl DavInner {
                 if meta.is_dir() {
                     npath.add_slash();
                 }
+                if self.is_reserved_path(&npath) {
+                    continue;
+                }
+                // cut the listing off at the limit.
+                if *left == 0 {
+                    return Err(StatusCode::INSUFFICIENT_STORAGE.into());
+                }
+                *left -= 1;
                 let is_dir = meta.is_dir();
                 propwriter.write_props(&npath, meta).await?;
                 propwriter.flush().await?;
                 if depth == davheaders::Depth::Infinity && is_dir {
-                    self.propfind_directory(&npath, depth, propwriter).await?;
+                    self.propfind_directory(&npath, depth, propwriter, left)
+                        .await?;
                 }
             }
             Ok(())
@@ -289,9 +440,15 @@ impl DavInner {
                             StatusCode::FORBIDDEN
                         }
                     }
-                    "displayname" => {
-                        if prop.get_text().is_none() || prop.has_child_elems() {
-                            return StatusCode::CONFLICT;
+                    "getcontenttype" => {
+                        // stored as a dead property, used by GET.
+                        match prop.get_text() {
+                            Some(s) if !prop.has_child_elems() => {
+                                if s.trim().parse::<mime_guess::mime::Mime>().is_err() {
+                                    return StatusCode::CONFLICT;
+                                }
+                            }
+                            _ => return StatusCode::CONFLICT,
                         }
                         if can_deadprop {
                             StatusCode::CONTINUE
@@ -299,13 +456,15 @@ impl DavInner {
                             StatusCode::FORBIDDEN
                         }
                     }
-                    "getlastmodified" => {
-                        // we might allow setting modified time
-                        // by using utimes() on unix. Not yet though.
+                    "displayname" => {
                         if prop.get_text().is_none() || prop.has_child_elems() {
                             return StatusCode::CONFLICT;
                         }
-                        StatusCode::FORBIDDEN
+                        if can_deadprop {
+                            StatusCode::CONTINUE
+                        } else {
+                            StatusCode::FORBIDDEN
+                        }
                     }
                     _ => StatusCode::FORBIDDEN,
                 }
@@ -345,13 +504,13 @@ impl DavInner {
     }
 
     // In general, live properties cannot be removed, with the
-    // exception of getcontentlanguage and displayname.
+    // exception of getcontentlanguage, getcontenttype and displayname.
     fn liveprop_remove(&self, prop: &Element, can_deadprop: bool) -> StatusCode {
         match prop.namespace.as_deref() {
             Some(NS_DAV_URI) => match prop.name.as_str() {
-                "getcontentlanguage" | "displayname" => {
+                "getcontentlanguage" | "getcontenttype" | "displayname" => {
                     if can_deadprop {
-                        StatusCode::OK
+                        StatusCode::CONTINUE
                     } else {
                         StatusCode::FORBIDDEN
                     }
@@ -376,42 +535,56 @@ impl DavInner {
         let meta = self.fixpath(&mut res, &mut path, meta);
 
         // check the If and If-* headers.
-        let tokens = match if_match_get_tokens(req, Some(&meta), &self.fs, &self.ls, &path).await {
+        let tokens = match self.if_match_get_tokens(req, Some(&*meta), &path).await {
             Ok(t) => t,
-            Err(s) => return Err(s.into()),
+            Err(s) => return Ok(self.precondition_response(s, &path, Some(&*meta)).await),
         };
 
         // if locked check if we hold that lock.
         if let Some(ref locksystem) = self.ls {
             let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             let principal = self.principal.as_deref();
-            if let Err(_l) = locksystem.check(&path, principal, false, false, t) {
-                return Err(StatusCode::LOCKED.into());
+            if let Err(l) = locksystem.check(&path, principal, false, false, t) {
+                return Err(self.lock_status(&l, &tokens).into());
             }
         }
+        if self.dry_run {
+            return Ok(res);
+        }
 
         trace!(target: "xml", "proppatch input:\n{}]\n",
                std::string::String::from_utf8_lossy(xmldata));
 
+        // the properties are the children of DAV:set/DAV:prop and
+        // DAV:remove/DAV:prop.
+        if too_many_elements(xmldata, 4, self.limits().props) {
+            return Ok(limit_error(StatusCode::BAD_REQUEST, "props"));
+        }
+
         // parse xml
         let tree = Element::parse2(Cursor::new(xmldata))?;
-        if tree.name != "propertyupdate" {
+        if tree.name != "propertyupdate" || !is_dav(&tree) {
             return Err(DavError::XmlParseError);
         }
 
         let mut patch = Vec::new();
         let mut ret = Vec::new();
+        let mut protected = Vec::new();
         let can_deadprop = self.fs.have_props(&path).await;
 
         // walk over the element tree and feed "set" and "remove" items to
         // the liveprop_set/liveprop_remove functions. If skipped by those,
         // gather .them in the "patch" Vec to be processed as dead properties.
-        for elem in tree.child_elems_iter() {
+        for elem in tree.child_elems_iter().filter(|e| is_dav(e)) {
             for n in elem
                 .child_elems_iter()
-                .filter(|e| e.name == "prop")
+                .filter(|e| e.name == "prop" && is_dav(e))
                 .flat_map(|e| e.child_elems_iter())
             {
+                if is_protected(n) && (elem.name == "set" || elem.name == "remove") {
+                    protected.push(davprop_to_element(element_to_davprop(n)));
+                    continue;
+                }
                 match elem.name.as_str() {
                     "set" => match self.liveprop_set(n, can_deadprop) {
                         StatusCode::CONTINUE => patch.push((true, element_to_davprop_full(n))),
@@ -427,7 +600,7 @@ impl DavInner {
         }
 
         // if any set/remove failed, stop processing here.
-        if ret.iter().any(|&(ref s, _)| s != &StatusCode::OK) {
+        if !protected.is_empty() || ret.iter().any(|&(ref s, _)| s != &StatusCode::OK) {
             ret = ret
                 .into_iter()
                 .map(|(s, p)| {
@@ -460,14 +633,24 @@ impl DavInner {
             v.push(davprop_to_element(prop));
         }
 
-        // And reply.
-        let mut pw = PropWriter::new(req, &mut res, "propertyupdate", Vec::new(), &self.fs, None)?;
+        // And reply. The namespaces are declared in the order of the
+        // response, so that the prefixes are always the same.
+        let mut codes = hm.keys().copied().collect::<Vec<_>>();
+        codes.sort();
+        let props = codes
+            .iter()
+            .flat_map(|code| &hm[code])
+            .chain(&protected)
+            .cloned()
+            .collect();
+        let mut pw = PropWriter::new(self.quirks, &mut res, "propertyupdate", props, &self)?;
         *res.body_mut() = Body::from(AsyncStream::new(|tx| async move {
             pw.set_tx(tx);
-            pw.write_propresponse(&path, hm)?;
+            pw.write_propresponse(&path, hm, protected)?;
             pw.close().await?;
             Ok::<_, io::Error>(())
         }));
+        buffer_body(&mut res).await?;
 
         Ok(res)
     }
@@ -475,12 +658,11 @@ impl DavInner {
 
 impl PropWriter {
     pub fn new(
-        req: &Request<()>,
+        quirks: ClientQuirks,
         res: &mut Response<Body>,
         name: &str,
         mut props: Vec<Element>,
-        fs: &Box<dyn DavFileSystem>,
-        ls: Option<&Box<dyn DavLockSystem>>,
+        dav: &DavInner,
     ) -> DavResult<PropWriter> {
         let contenttype = "application/xml; charset=utf-8".parse().unwrap();
         res.headers_mut().insert("content-type", contenttype);
@@ -501,16 +683,10 @@ impl PropWriter {
             standalone: None,
         })?;
 
-        // user-agent header.
-        let ua = match req.headers().get("user-agent") {
-            Some(s) => s.to_str().unwrap_or(""),
-            None => "",
-        };
-
         if name != "prop" && name != "propertyupdate" {
             let mut v = Vec::new();
             let iter = if name == "allprop" {
-                if ua.contains("Microsoft") {
+                if quirks.ms_propfind_allprop_extras {
                     MS_ALLPROP.iter()
                 } else {
                     ALLPROP.iter()
@@ -529,24 +705,19 @@ impl PropWriter {
             props.append(&mut v);
         }
 
-        // check the prop namespaces to see what namespaces
-        // we need to put in the preamble.
+        // declare the namespaces of the props we know of in the
+        // preamble, so that the responses do not have to. Only the
+        // namespaces in the values of dead properties that a PROPFIND
+        // finds are declared where they are used.
+        let mut prefixes = Prefixes::default();
+        let mut namespaces = Vec::new();
+        for prop in &props {
+            element_namespaces(prop, &mut namespaces);
+        }
         let mut ev = XmlWEvent::start_element("D:multistatus").ns("D", NS_DAV_URI);
-        if name != "propertyupdate" {
-            let mut a = false;
-            let mut m = false;
-            for prop in &props {
-                match prop.namespace.as_deref() {
-                    Some(NS_APACHE_URI) => a = true,
-                    Some(NS_MS_URI) => m = true,
-                    _ => {}
-                }
-            }
-            if a {
-                ev = ev.ns("A", NS_APACHE_URI);
-            }
-            if m {
-                ev = ev.ns("Z", NS_MS_URI);
+        for ns in namespaces {
+            if let Some(pfx) = prefixes.declare(ns) {
+                ev = ev.ns(pfx, ns.to_string());
             }
         }
         emitter.write(ev)?;
@@ -556,10 +727,11 @@ impl PropWriter {
             tx: None,
             name: name.to_string(),
             props,
-            fs: fs.clone(),
-            ls: ls.cloned(),
-            useragent: ua.to_string(),
+            dav: dav.clone(),
+            quirks,
+            prefixes,
             q_cache: Default::default(),
+            creationdate_fallback: CreationDateFallback::StatusChanged,
         })
     }
 
@@ -597,6 +769,25 @@ impl PropWriter {
         })
     }
 
+    // creation time, or a substitute if the filesystem doesn't have it.
+    fn creation_time(&self, meta: &dyn DavMetaData) -> Option<SystemTime> {
+        if let Ok(time) = meta.created() {
+            return Some(time);
+        }
+        match self.creationdate_fallback {
+            CreationDateFallback::Omit => None,
+            CreationDateFallback::Modified => meta.modified().ok(),
+            CreationDateFallback::StatusChanged => {
+                // use ctime instead - apache seems to do this.
+                let ctime = meta.status_changed().ok()?;
+                match meta.modified() {
+                    Ok(mtime) if mtime < ctime => Some(mtime),
+                    _ => Some(ctime),
+                }
+            }
+        }
+    }
+
     async fn get_quota<'a>(
         &'a self,
         qc: &'a mut QuotaCache,
@@ -605,7 +796,7 @@ impl PropWriter {
     ) -> FsResult<(u64, Option<u64>)> {
         // do lookup only once.
         match qc.q_state {
-            0 => match self.fs.get_quota().await {
+            0 => match self.dav.fs.get_quota().await {
                 Err(e) => {
                     qc.q_state = 1;
                     return Err(e);
@@ -640,6 +831,7 @@ impl PropWriter {
         path: &'a DavPath,
         meta: &'a dyn DavMetaData,
         qc: &'a mut QuotaCache,
+        validators: &'a mut Option<Validators>,
         docontent: bool,
     ) -> DavResult<StatusElement> {
         // in some cases, a live property might be stored in the
@@ -651,19 +843,21 @@ impl PropWriter {
             Some(NS_DAV_URI) => {
                 pfx = "D";
                 match prop.name.as_str() {
-                    "creationdate" => {
-                        if let Ok(time) = meta.created() {
-                            let tm = systemtime_to_rfc3339(time);
-                            return self.build_elem(docontent, pfx, prop, tm);
+                    "add-member" if meta.is_dir() => {
+                        // RFC5995: POST to the collection itself adds a member.
+                        let mut elem = prop.clone();
+                        if docontent {
+                            let href =
+                                Element::new2("D:href").text(path.with_prefix().as_url_string());
+                            elem.children.push(XMLNode::Element(href));
                         }
-                        // use ctime instead - apache seems to do this.
-                        if let Ok(ctime) = meta.status_changed() {
-                            let mut time = ctime;
-                            if let Ok(mtime) = meta.modified() {
-                                if mtime < ctime {
-                                    time = mtime;
-                                }
-                            }
+                        return Ok(StatusElement {
+                            status: StatusCode::OK,
+                            element: elem,
+                        });
+                    }
+                    "creationdate" => {
+                        if let Some(time) = self.creation_time(meta) {
                             let tm = systemtime_to_rfc3339(time);
                             return self.build_elem(docontent, pfx, prop, tm);
                         }
@@ -672,8 +866,13 @@ impl PropWriter {
                         try_deadprop = true;
                     }
                     "getetag" => {
-                        if let Some(etag) = meta.etag() {
-                            return self.build_elem(docontent, pfx, prop, etag);
+                        // must agree with the ETag that GET sends.
+                        let v = match validators {
+                            Some(v) => v,
+                            None => validators.insert(self.dav.validators(path, meta).await),
+                        };
+                        if let Some(ref etag) = v.etag {
+                            return self.build_elem(docontent, pfx, prop, etag.as_str());
                         }
                     }
                     "getcontentlength" => {
@@ -682,6 +881,11 @@ impl PropWriter {
                         }
                     }
                     "getcontenttype" => {
+                        if let Some(ct) =
+                            stored_davprop(&*self.dav.fs, path, "getcontenttype").await
+                        {
+                            return self.build_elem(docontent, pfx, prop, ct);
+                        }
                         return if meta.is_dir() {
                             self.build_elem(docontent, pfx, prop, "httpd/unix-directory")
                         } else {
@@ -689,8 +893,11 @@ impl PropWriter {
                         };
                     }
                     "getlastmodified" => {
-                        if let Ok(time) = meta.modified() {
-                            let tm = systemtime_to_httpdate(time);
+                        let v = match validators {
+                            Some(v) => v,
+                            None => validators.insert(self.dav.validators(path, meta).await),
+                        };
+                        if let Some(tm) = v.last_modified_str() {
                             return self.build_elem(docontent, pfx, prop, tm);
                         }
                     }
@@ -700,21 +907,63 @@ impl PropWriter {
                             let dir = Element::new2("D:collection");
                             elem.children.push(XMLNode::Element(dir));
                         }
+                        if meta.redirect_ref().is_some() && docontent {
+                            let rr = Element::new2("D:redirectref");
+                            elem.children.push(XMLNode::Element(rr));
+                        }
+                        if docontent {
+                            for t in meta.extra_resourcetypes() {
+                                let t = davprop_to_element(DavProp { xml: None, ..t });
+                                elem.children.push(XMLNode::Element(t));
+                            }
+                        }
                         return Ok(StatusElement {
                             status: StatusCode::OK,
                             element: elem,
                         });
                     }
+                    "reftarget" => {
+                        // RFC4437 10.2
+                        if let Some(r) = meta.redirect_ref() {
+                            let mut elem = prop.clone();
+                            if docontent {
+                                let href = Element::new2("D:href").text(r.target);
+                                elem.children.push(XMLNode::Element(href));
+                            }
+                            return Ok(StatusElement {
+                                status: StatusCode::OK,
+                                element: elem,
+                            });
+                        }
+                    }
+                    "redirect-lifetime" => {
+                        // RFC4437 10.1
+                        if let Some(r) = meta.redirect_ref() {
+                            let mut elem = prop.clone();
+                            if docontent {
+                                let name = if r.permanent {
+                                    "D:permanent"
+                                } else {
+                                    "D:temporary"
+                                };
+                                elem.children.push(XMLNode::Element(Element::new2(name)));
+                            }
+                            return Ok(StatusElement {
+                                status: StatusCode::OK,
+                                element: elem,
+                            });
+                        }
+                    }
                     "supportedlock" => {
                         return Ok(StatusElement {
                             status: StatusCode::OK,
-                            element: list_supportedlock(self.ls.as_ref()),
+                            element: list_supportedlock(self.dav.ls.as_ref()),
                         });
                     }
                     "lockdiscovery" => {
                         return Ok(StatusElement {
                             status: StatusCode::OK,
-                            element: list_lockdiscovery(self.ls.as_ref(), path),
+                            element: list_lockdiscovery(self.dav.ls.as_ref(), path),
                         });
                     }
                     "quota-available-bytes" => {
@@ -726,9 +975,7 @@ impl PropWriter {
                     "quota-used-bytes" => {
                         let qc = qc;
                         if let Ok((used, _)) = self.get_quota(qc, path, meta).await {
-                            let used = if self.useragent.contains("WebDAVFS") {
-                                // Need this on MacOs, otherwise the value is off
-                                // by a factor of 10 or so .. ?!?!!?
+                            let used = if self.quirks.osx_dscaring {
                                 format!("{:014}", used)
                             } else {
                                 used.to_string()
@@ -748,22 +995,21 @@ impl PropWriter {
                     }
                 }
             }
+            Some(NS_CALSERVER_URI) => {
+                pfx = "CS";
+                if prop.name.as_str() != "getctag" {
+                    try_deadprop = true;
+                } else if meta.is_dir() {
+                    if let Ok(ctag) = self.dav.fs.get_ctag(path).await {
+                        return self.build_elem(docontent, pfx, prop, ctag);
+                    }
+                }
+            }
             Some(NS_MS_URI) => {
                 pfx = "Z";
                 match prop.name.as_str() {
                     "Win32CreationTime" => {
-                        if let Ok(time) = meta.created() {
-                            let tm = systemtime_to_httpdate(time);
-                            return self.build_elem(docontent, pfx, prop, tm);
-                        }
-                        // use ctime instead - apache seems to do this.
-                        if let Ok(ctime) = meta.status_changed() {
-                            let mut time = ctime;
-                            if let Ok(mtime) = meta.modified() {
-                                if mtime < ctime {
-                                    time = mtime;
-                                }
-                            }
+                        if let Some(time) = self.creation_time(meta) {
                             let tm = systemtime_to_httpdate(time);
                             return self.build_elem(docontent, pfx, prop, tm);
                         }
@@ -807,10 +1053,10 @@ impl PropWriter {
             }
         }
 
-        if try_deadprop && self.name == "prop" && self.fs.have_props(path).await {
+        if try_deadprop && self.name == "prop" && self.dav.fs.have_props(path).await {
             // asking for a specific property.
             let dprop = element_to_davprop(prop);
-            if let Ok(xml) = self.fs.get_prop(path, dprop).await {
+            if let Ok(xml) = self.dav.fs.get_prop(path, dprop).await {
                 if let Ok(e) = Element::parse(Cursor::new(xml)) {
                     return Ok(StatusElement {
                         status: StatusCode::OK,
@@ -843,9 +1089,11 @@ impl PropWriter {
         // Get properties one-by-one
         let do_content = self.name != "propname";
         let mut qc = self.q_cache;
+        // computed once for getetag and getlastmodified.
+        let mut validators = None;
         for p in &self.props {
             let res = self
-                .build_prop(p, path, &*meta, &mut qc, do_content)
+                .build_prop(p, path, &*meta, &mut qc, &mut validators, do_content)
                 .await?;
             if res.status == StatusCode::OK || (self.name != "propname" && self.name != "allprop") {
                 add_sc_elem(&mut props, res.status, res.element);
@@ -854,21 +1102,32 @@ impl PropWriter {
         self.q_cache = qc;
 
         // and list the dead properties as well.
-        if (self.name == "propname" || self.name == "allprop") && self.fs.have_props(path).await {
-            if let Ok(v) = self.fs.get_props(path, do_content).await {
+        if (self.name == "propname" || self.name == "allprop") && self.dav.fs.have_props(path).await
+        {
+            if let Ok(mut v) = self.dav.fs.get_props(path, do_content).await {
+                // in a fixed order, so that new namespaces get the
+                // same prefix every time.
+                v.sort_by(|a, b| (&a.namespace, &a.name).cmp(&(&b.namespace, &b.name)));
+                // a stored getcontenttype was already listed above.
                 v.into_iter()
+                    .filter(|p| {
+                        p.name != "getcontenttype" || p.namespace.as_deref() != Some(NS_DAV_URI)
+                    })
                     .map(davprop_to_element)
                     .for_each(|e| add_sc_elem(&mut props, StatusCode::OK, e));
             }
         }
 
-        Ok::<(), DavError>(self.write_propresponse(path, props)?)
+        Ok::<(), DavError>(self.write_propresponse(path, props, Vec::new())?)
     }
 
+    // `protected` are the properties a PROPPATCH tried to change that
+    // cannot be changed. They get a 403 propstat of their own.
     pub fn write_propresponse(
         &mut self,
         path: &DavPath,
-        props: HashMap<StatusCode, Vec<Element>>,
+        mut props: HashMap<StatusCode, Vec<Element>>,
+        protected: Vec<Element>,
     ) -> Result<(), DavError> {
         self.emitter.write(XmlWEvent::start_element("D:response"))?;
         let p = path.with_prefix().as_url_string();
@@ -876,24 +1135,99 @@ impl PropWriter {
             .text(p)
             .write_ev(&mut self.emitter)?;
 
-        let mut keys = props.keys().collect::<Vec<_>>();
+        let mut keys = props.keys().copied().collect::<Vec<_>>();
         keys.sort();
+        let mut protected = Some(protected).filter(|p| !p.is_empty());
         for status in keys {
-            let v = props.get(status).unwrap();
-            self.emitter.write(XmlWEvent::start_element("D:propstat"))?;
-            self.emitter.write(XmlWEvent::start_element("D:prop"))?;
-            for i in v.iter() {
-                i.write_ev(&mut self.emitter)?;
+            if status > StatusCode::FORBIDDEN {
+                if let Some(p) = protected.take() {
+                    self.write_propstat(p, StatusCode::FORBIDDEN, true)?;
+                }
             }
-            self.emitter.write(XmlWEvent::end_element())?;
-            Element::new2("D:status")
-                .text("HTTP/1.1 ".to_string() + &status.to_string())
-                .write_ev(&mut self.emitter)?;
+            let v = props.remove(&status).unwrap_or_default();
+            self.write_propstat(v, status, false)?;
+        }
+        if let Some(p) = protected {
+            self.write_propstat(p, StatusCode::FORBIDDEN, true)?;
+        }
+
+        self.emitter.write(XmlWEvent::end_element())?; // response
+
+        Ok(())
+    }
+
+    fn write_propstat(
+        &mut self,
+        props: Vec<Element>,
+        status: StatusCode,
+        protected: bool,
+    ) -> Result<(), DavError> {
+        self.emitter.write(XmlWEvent::start_element("D:propstat"))?;
+        self.emitter.write(XmlWEvent::start_element("D:prop"))?;
+        for i in props {
+            self.prefixed(i).write_ev(&mut self.emitter)?;
+        }
+        self.emitter.write(XmlWEvent::end_element())?;
+        Element::new2("D:status")
+            .text("HTTP/1.1 ".to_string() + &status.to_string())
+            .write_ev(&mut self.emitter)?;
+        if protected {
+            self.emitter.write(XmlWEvent::start_element("D:error"))?;
+            Element::new2("D:cannot-modify-protected-property").write_ev(&mut self.emitter)?;
             self.emitter.write(XmlWEvent::end_element())?;
         }
+        self.emitter.write(XmlWEvent::end_element())?;
+        Ok(())
+    }
 
+    // `elem` with the prefixes of this multistatus. The namespaces
+    // that the root does not declare are declared on `elem`.
+    fn prefixed(&mut self, mut elem: Element) -> Element {
+        let mut undeclared = Vec::new();
+        set_prefixes(&mut elem, &mut self.prefixes, &mut undeclared);
+        for (pfx, ns) in undeclared {
+            elem = elem.ns(pfx, ns);
+        }
+        elem
+    }
+
+    // The response for a collection whose listing was cut off
+    // at Limits::propfind_members (RFC 5323 5.3.1).
+    pub fn write_limitresponse(&mut self, path: &DavPath) -> Result<(), DavError> {
+        self.emitter.write(XmlWEvent::start_element("D:response"))?;
+        let p = path.with_prefix().as_url_string();
+        Element::new2("D:href")
+            .text(p)
+            .write_ev(&mut self.emitter)?;
+        let status = StatusCode::INSUFFICIENT_STORAGE;
+        Element::new2("D:status")
+            .text("HTTP/1.1 ".to_string() + &status.to_string())
+            .write_ev(&mut self.emitter)?;
+        self.emitter.write(XmlWEvent::start_element("D:error"))?;
+        Element::new2("D:number-of-matches-within-limits").write_ev(&mut self.emitter)?;
+        self.emitter.write(XmlWEvent::end_element())?;
+        Element::new2("D:responsedescription")
+            .text("propfind_members")
+            .write_ev(&mut self.emitter)?;
         self.emitter.write(XmlWEvent::end_element())?; // response
+        Ok(())
+    }
 
+    // The response for a member of a collection that could not be read.
+    pub fn write_statusresponse(
+        &mut self,
+        path: &DavPath,
+        status: StatusCode,
+    ) -> Result<(), DavError> {
+        self.emitter.write(XmlWEvent::start_element("D:response"))?;
+        let p = path.with_prefix().as_url_string();
+        Element::new2("D:href")
+            .text(p)
+            .write_ev(&mut self.emitter)?;
+        Element::new2("D:status")
+            .text("HTTP/1.1 ".to_string() + &status.to_string())
+            .write_ev(&mut self.emitter)?;
+        self.emitter.write(XmlWEvent::end_element())?; // response
         Ok(())
     }
 
@@ -914,7 +1248,7 @@ fn add_sc_elem(hm: &mut HashMap<StatusCode, Vec<Element>>, sc: StatusCode, e: El
     hm.get_mut(&sc).unwrap().push(e)
 }
 
-fn element_to_davprop_full(elem: &Element) -> DavProp {
+pub(crate) fn element_to_davprop_full(elem: &Element) -> DavProp {
     let mut emitter = EventWriter::new(Cursor::new(Vec::new()));
     elem.write_ev(&mut emitter).ok();
     let xml = emitter.into_inner().into_inner();
@@ -926,7 +1260,7 @@ fn element_to_davprop_full(elem: &Element) -> DavProp {
     }
 }
 
-fn element_to_davprop(elem: &Element) -> DavProp {
+pub(crate) fn element_to_davprop(elem: &Element) -> DavProp {
     DavProp {
         name: elem.name.clone(),
         prefix: elem.prefix.clone(),
@@ -935,6 +1269,63 @@ fn element_to_davprop(elem: &Element) -> DavProp {
     }
 }
 
+// The text of a DAV: property that is stored as a dead property,
+// like a getcontenttype that was set with PROPPATCH.
+pub(crate) async fn stored_davprop(
+    fs: &dyn DavFileSystem,
+    path: &DavPath,
+    name: &str,
+) -> Option<String> {
+    if !fs.have_props(path).await {
+        return None;
+    }
+    let prop = DavProp {
+        name: name.to_string(),
+        prefix: Some("D".to_string()),
+        namespace: Some(NS_DAV_URI.to_string()),
+        xml: None,
+    };
+    let xml = fs.get_prop(path, prop).await.ok()?;
+    davprop_text(&xml)
+}
+
+// Like `stored_davprop`, for several properties that are fetched
+// from the filesystem at once.
+pub(crate) async fn stored_davprops<const N: usize>(
+    fs: &dyn DavFileSystem,
+    path: &DavPath,
+    names: [&str; N],
+) -> [Option<String>; N] {
+    let mut res = [(); N].map(|_| None);
+    if !fs.have_props(path).await {
+        return res;
+    }
+    let props = fs.get_props(path, true).await.unwrap_or_default();
+    for prop in props {
+        if prop.namespace.as_deref() != Some(NS_DAV_URI) {
+            continue;
+        }
+        if let Some(i) = names.iter().position(|&n| n == prop.name) {
+            res[i] = prop.xml.as_deref().and_then(davprop_text);
+        }
+    }
+    res
+}
+
+fn davprop_text(xml: &[u8]) -> Option<String> {
+    let elem = Element::parse(Cursor::new(xml)).ok()?;
+    let text = elem.get_text()?.trim().to_string();
+    Some(text).filter(|t| !t.is_empty())
+}
+
+fn is_protected(prop: &Element) -> bool {
+    match prop.namespace.as_deref() {
+        Some(NS_DAV_URI) => PROTECTED_STR.contains(&prop.name.as_str()),
+        Some(NS_CALSERVER_URI) => prop.name == "getctag",
+        _ => false,
+    }
+}
+
 fn davprop_to_element(prop: DavProp) -> Element {
     if let Some(xml) = prop.xml {
         return Element::parse2(Cursor::new(xml)).unwrap();
@@ -948,3 +1339,1011 @@ fn davprop_to_element(prop: DavProp) -> Element {
     elem.namespace = prop.namespace.clone();
     elem
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::davpath::DavPath;
+    use crate::fs::*;
+    use crate::memfs::MemFs;
+    use crate::testfs::{MockFs, MockOp};
+    use crate::testing::{DavClient, Depth, Multistatus};
+    use crate::{DavHandler, UnreadableEntries};
+    use http::{Request, StatusCode};
+
+    #[tokio::test]
+    async fn test_multistatus_content_length() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        assert_eq!(
+            client.put_bytes("/dir/file", "x").await,
+            StatusCode::CREATED
+        );
+
+        for depth in ["0", "1"] {
+            let req = Request::builder()
+                .method("PROPFIND")
+                .uri("/dir/")
+                .header("Depth", depth)
+                .body(Body::empty())
+                .unwrap();
+            let res = client.request(req).await;
+            assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+            let len = res.body().len().to_string();
+            assert_eq!(res.headers()["content-length"], len.as_str());
+        }
+
+        // Depth: infinity is streamed.
+        let req = Request::builder()
+            .method("PROPFIND")
+            .uri("/dir/")
+            .header("Depth", "infinity")
+            .header("X-Litmus", "props: 1 (propfind_invalid2)")
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        assert!(res.headers().get("content-length").is_none());
+
+        let xml = r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:test">
+            <D:set><D:prop><Z:color>red</Z:color></D:prop></D:set>
+            </D:propertyupdate>"#;
+        let req = Request::builder()
+            .method("PROPPATCH")
+            .uri("/dir/file")
+            .body(Body::from(xml))
+            .unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let len = res.body().len().to_string();
+        assert_eq!(res.headers()["content-length"], len.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_propfind_depth1_file() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/file", "x").await, StatusCode::CREATED);
+
+        // the members of a non-collection are just the resource itself.
+        let ms = client
+            .propfind("/file", Depth::One, &["getcontentlength"])
+            .await
+            .unwrap();
+        assert_eq!(ms.hrefs(), ["/file"]);
+        let resp = ms.response("/file").unwrap();
+        assert_eq!(resp.prop_text("getcontentlength").as_deref(), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn test_propfind_hrefs() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        assert_eq!(client.mkcol("/dir/sub/").await, StatusCode::CREATED);
+        assert_eq!(
+            client.put_bytes("/dir/file", "x").await,
+            StatusCode::CREATED
+        );
+
+        // the request-URI comes first, the members in any order.
+        let hrefs = |ms: &Multistatus| {
+            let mut hrefs: Vec<String> = ms.hrefs().iter().map(|h| h.to_string()).collect();
+            hrefs[1..].sort();
+            hrefs
+        };
+        // the collection itself is listed once, always with a slash.
+        for path in ["/dir", "/dir/"] {
+            let ms = client.propfind(path, Depth::One, &[]).await.unwrap();
+            assert_eq!(hrefs(&ms), ["/dir/", "/dir/file", "/dir/sub/"]);
+        }
+        let ms = client.propfind("/dir/sub", Depth::One, &[]).await.unwrap();
+        assert_eq!(hrefs(&ms), ["/dir/sub/"]);
+        let ms = client.propfind("/dir/file", Depth::One, &[]).await.unwrap();
+        assert_eq!(hrefs(&ms), ["/dir/file"]);
+
+        // a file never has a trailing slash.
+        let res = client.propfind("/dir/file/", Depth::One, &[]).await;
+        assert_eq!(res.err(), Some(StatusCode::NOT_FOUND));
+        assert_eq!(
+            client.get("/dir/file/").await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_trailing_slash_localfs() {
+        use crate::localfs::LocalFs;
+        use crate::testing::tempdir;
+
+        // a real backend, where "/file/" is looked up by the OS.
+        let dir = tempdir("slash");
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        assert_eq!(
+            client.put_bytes("/dir/file", "x").await,
+            StatusCode::CREATED
+        );
+
+        let ms = client.propfind("/dir", Depth::One, &[]).await.unwrap();
+        assert_eq!(ms.hrefs(), ["/dir/", "/dir/file"]);
+        let res = client.propfind("/dir/file/", Depth::Zero, &[]).await;
+        assert_eq!(res.err(), Some(StatusCode::NOT_FOUND));
+        assert_eq!(
+            client.get("/dir/file/").await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(client.delete("/dir/file/").await, StatusCode::NOT_FOUND);
+        assert_eq!(client.get("/dir/file").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_validators_consistent() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        let header = |res: &http::Response<_>, name: &str| {
+            res.headers()
+                .get(name)
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+
+        let put = Request::put("/file").body(Body::from("hello")).unwrap();
+        let res = client.request(put).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let etag = header(&res, "etag").unwrap();
+        let modified = header(&res, "last-modified").unwrap();
+        assert!(etag.starts_with('"') && etag.ends_with('"'), "{}", etag);
+
+        let ms = client
+            .propfind("/file", Depth::Zero, &["getetag", "getlastmodified"])
+            .await
+            .unwrap();
+        let resp = ms.response("/file").unwrap();
+        assert_eq!(resp.prop_text("getetag"), Some(etag.clone()));
+        assert_eq!(resp.prop_text("getlastmodified"), Some(modified.clone()));
+
+        for method in ["GET", "HEAD"] {
+            let req = Request::builder()
+                .method(method)
+                .uri("/file")
+                .body(Body::empty())
+                .unwrap();
+            let res = client.request(req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(header(&res, "etag"), Some(etag.clone()));
+            assert_eq!(header(&res, "last-modified"), Some(modified.clone()));
+        }
+
+        let req = Request::get("/file")
+            .header("If-None-Match", etag.as_str())
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(header(&res, "etag"), Some(etag.clone()));
+        assert_eq!(header(&res, "last-modified"), Some(modified));
+    }
+
+    #[tokio::test]
+    async fn test_validators_consistent_collection() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .hashed_collection_etags(true)
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        assert_eq!(client.put_bytes("/dir/a", "a").await, StatusCode::CREATED);
+
+        let res = client.get("/dir/").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let etag = res.headers()["etag"].to_str().unwrap().to_string();
+        let modified = res.headers()["last-modified"].to_str().unwrap();
+
+        // the same on the collection itself, and as a member of its parent.
+        for (path, depth) in [("/dir/", Depth::Zero), ("/", Depth::One)] {
+            let props = &["getetag", "getlastmodified"];
+            let ms = client.propfind(path, depth, props).await.unwrap();
+            let resp = ms.response("/dir/").unwrap();
+            assert_eq!(resp.prop_text("getetag").as_deref(), Some(etag.as_str()));
+            assert_eq!(resp.prop_text("getlastmodified").as_deref(), Some(modified));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_getctag() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        assert_eq!(client.mkcol("/dir/sub/").await, StatusCode::CREATED);
+        assert_eq!(
+            client.put_bytes("/dir/file", "x").await,
+            StatusCode::CREATED
+        );
+
+        const GETCTAG: &str = "{http://calendarserver.org/ns/}getctag";
+        let ctag = |path: &'static str| {
+            let client = &client;
+            async move {
+                let ms = client
+                    .propfind(path, Depth::Zero, &[GETCTAG])
+                    .await
+                    .unwrap();
+                let resp = ms.response(path).unwrap();
+                (resp.prop_status(GETCTAG), resp.prop_text(GETCTAG))
+            }
+        };
+        let (status, ctag1) = ctag("/dir/").await;
+        assert_eq!(status, Some(StatusCode::OK));
+        assert!(ctag1.is_some());
+
+        // reading does not change it, changing a member (even deeper down) does.
+        assert_eq!(client.get("/dir/file").await.status(), StatusCode::OK);
+        assert_eq!(ctag("/dir/").await.1, ctag1);
+        assert_eq!(
+            client.put_bytes("/dir/file", "y").await,
+            StatusCode::NO_CONTENT
+        );
+        let ctag2 = ctag("/dir/").await.1;
+        assert_ne!(ctag2, ctag1);
+        assert_eq!(
+            client.put_bytes("/dir/sub/x", "y").await,
+            StatusCode::CREATED
+        );
+        let ctag3 = ctag("/dir/").await.1;
+        assert_ne!(ctag3, ctag2);
+        assert_eq!(client.delete("/dir/file").await, StatusCode::NO_CONTENT);
+        assert_ne!(ctag("/dir/").await.1, ctag3);
+
+        // files do not have one, and it is not in allprop.
+        assert_eq!(ctag("/dir/sub/x").await.0, Some(StatusCode::NOT_FOUND));
+        let ms = client.propfind("/dir/", Depth::Zero, &[]).await.unwrap();
+        assert_eq!(ms.response("/dir/").unwrap().prop_status(GETCTAG), None);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_depth() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+
+        // an invalid Depth is an error, not the default.
+        for method in ["PROPFIND", "COPY", "MOVE", "DELETE"] {
+            for depth in ["2", "one", ""] {
+                let req = Request::builder()
+                    .method(method)
+                    .uri("/dir/")
+                    .header("Depth", depth)
+                    .header("Destination", "/copy/")
+                    .body(Body::empty())
+                    .unwrap();
+                let res = client.request(req).await;
+                assert_eq!(
+                    res.status(),
+                    StatusCode::BAD_REQUEST,
+                    "{} {:?}",
+                    method,
+                    depth
+                );
+            }
+        }
+        assert_eq!(client.get("/dir/").await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_propfind_empty_body() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/file", "x").await, StatusCode::CREATED);
+
+        for body in ["", " \r\n\t"] {
+            let req = Request::builder()
+                .method("PROPFIND")
+                .uri("/file")
+                .header("Depth", "0")
+                .body(Body::from(body))
+                .unwrap();
+            let res = client.request(req).await;
+            assert_eq!(res.status(), StatusCode::MULTI_STATUS, "{:?}", body);
+            let ms = Multistatus::parse(res.body()).unwrap();
+            let resp = ms.response("/file").unwrap();
+            for prop in [
+                "creationdate",
+                "getcontentlength",
+                "getcontenttype",
+                "getetag",
+                "getlastmodified",
+                "resourcetype",
+                "supportedlock",
+            ] {
+                assert!(resp.prop(prop).is_some(), "{:?}: {}", body, prop);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_namespace_prefixes() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/file", "x").await, StatusCode::CREATED);
+
+        let propfind = [
+            r#"<d:propfind xmlns:d="DAV:"><d:prop><d:getetag/><d:resourcetype/></d:prop></d:propfind>"#,
+            r#"<dav:propfind xmlns:dav="DAV:"><dav:prop><dav:getetag/><dav:resourcetype/></dav:prop></dav:propfind>"#,
+            r#"<propfind xmlns="DAV:"><prop><getetag/><resourcetype/></prop></propfind>"#,
+        ];
+        for body in propfind {
+            let req = Request::builder()
+                .method("PROPFIND")
+                .uri("/file")
+                .header("Depth", "0")
+                .body(Body::from(body))
+                .unwrap();
+            let res = client.request(req).await;
+            assert_eq!(res.status(), StatusCode::MULTI_STATUS, "{}", body);
+            let ms = Multistatus::parse(res.body()).unwrap();
+            let resp = ms.response("/file").unwrap();
+            assert!(resp.prop("getetag").is_some(), "{}", body);
+            assert!(resp.prop("resourcetype").is_some(), "{}", body);
+        }
+
+        let proppatch = [
+            r#"<d:propertyupdate xmlns:d="DAV:" xmlns:x="urn:x"><d:set><d:prop><x:a>1</x:a></d:prop></d:set></d:propertyupdate>"#,
+            r#"<dav:propertyupdate xmlns:dav="DAV:"><dav:set><dav:prop><b xmlns="urn:x">2</b></dav:prop></dav:set></dav:propertyupdate>"#,
+            r#"<propertyupdate xmlns="DAV:"><set><prop><c xmlns="urn:x">3</c></prop></set></propertyupdate>"#,
+        ];
+        for body in proppatch {
+            let req = Request::builder()
+                .method("PROPPATCH")
+                .uri("/file")
+                .body(Body::from(body))
+                .unwrap();
+            let res = client.request(req).await;
+            assert_eq!(res.status(), StatusCode::MULTI_STATUS, "{}", body);
+            let ms = Multistatus::parse(res.body()).unwrap();
+            let resp = ms.response("/file").unwrap();
+            assert_eq!(resp.propstat.len(), 1, "{}", body);
+            assert_eq!(resp.propstat[0].status, StatusCode::OK, "{}", body);
+        }
+        let ms = client
+            .propfind("/file", Depth::Zero, &["{urn:x}a", "{urn:x}b", "{urn:x}c"])
+            .await
+            .unwrap();
+        let resp = ms.response("/file").unwrap();
+        assert_eq!(resp.prop_text("{urn:x}a").as_deref(), Some("1"));
+        assert_eq!(resp.prop_text("{urn:x}b").as_deref(), Some("2"));
+        assert_eq!(resp.prop_text("{urn:x}c").as_deref(), Some("3"));
+
+        // allprop with include.
+        let body = r#"<D:propfind xmlns:D="DAV:"><D:allprop/><D:include><D:add-member/></D:include></D:propfind>"#;
+        let req = Request::builder()
+            .method("PROPFIND")
+            .uri("/")
+            .header("Depth", "0")
+            .body(Body::from(body))
+            .unwrap();
+        let res = client.request(req).await;
+        let ms = Multistatus::parse(res.body()).unwrap();
+        let resp = ms.response("/").unwrap();
+        assert!(resp.prop("add-member").is_some());
+        assert!(resp.prop("getlastmodified").is_some());
+
+        // propname lists add-member for collections only.
+        let body = r#"<D:propfind xmlns:D="DAV:"><D:propname/></D:propfind>"#;
+        let req = Request::builder()
+            .method("PROPFIND")
+            .uri("/")
+            .header("Depth", "1")
+            .body(Body::from(body))
+            .unwrap();
+        let res = client.request(req).await;
+        let ms = Multistatus::parse(res.body()).unwrap();
+        assert!(ms.response("/").unwrap().prop("add-member").is_some());
+        assert!(ms.response("/file").unwrap().prop("add-member").is_none());
+
+        // the element names must be in the DAV: namespace.
+        let body = r#"<propfind xmlns="DAV:"><prop xmlns="urn:x"><getetag/></prop></propfind>"#;
+        let req = Request::builder()
+            .method("PROPFIND")
+            .uri("/file")
+            .header("Depth", "0")
+            .body(Body::from(body))
+            .unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_xml_errors() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        let propfind = |body: &'static str| {
+            Request::builder()
+                .method("PROPFIND")
+                .uri("/")
+                .header("Depth", "0")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        // well-formed, but the wrong root element.
+        let body = r#"<D:propertyupdate xmlns:D="DAV:"><D:set/></D:propertyupdate>"#;
+        let res = client.request(propfind(body)).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = String::from_utf8_lossy(res.body());
+        assert!(body.contains(r#"<D:error xmlns:D="DAV:"/>"#), "{}", body);
+
+        // well-formed, but without prop, propname or allprop.
+        let body = r#"<D:propfind xmlns:D="DAV:"><D:foo/></D:propfind>"#;
+        let res = client.request(propfind(body)).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // not well-formed.
+        let res = client.request(propfind("<D:propfind xmlns:D=")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_propfind_limits() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .limits(crate::Limits {
+                propfind_members: 3,
+                props: 2,
+                ..Default::default()
+            })
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        for name in ["a", "b", "c", "d"] {
+            let path = format!("/dir/{}", name);
+            assert_eq!(client.put_bytes(&path, "x").await, StatusCode::CREATED);
+        }
+
+        // the listing is cut off, and ends with a 507 for the collection.
+        let ms = client.propfind("/dir/", Depth::One, &[]).await.unwrap();
+        assert_eq!(ms.hrefs().len(), 5);
+        let resp = &ms.responses[4];
+        assert_eq!(resp.href, "/dir/");
+        assert_eq!(resp.status, Some(StatusCode::INSUFFICIENT_STORAGE));
+        let ms = client.propfind("/dir/a", Depth::One, &[]).await.unwrap();
+        assert_eq!(ms.hrefs(), vec!["/dir/a"]);
+
+        // too many properties.
+        let ms = client.propfind("/dir/a", Depth::Zero, &["getetag", "getcontentlength"]);
+        assert!(ms.await.is_ok());
+        let ms = client.propfind("/dir/a", Depth::Zero, &["getetag", "getcontentlength", "x"]);
+        assert_eq!(ms.await.unwrap_err(), StatusCode::BAD_REQUEST);
+        let body = concat!(
+            r#"<D:propertyupdate xmlns:D="DAV:" xmlns:X="urn:x"><D:set><D:prop>"#,
+            "<X:a>1</X:a><X:b>2</X:b></D:prop></D:set>",
+            "<D:remove><D:prop><X:c/></D:prop></D:remove></D:propertyupdate>"
+        );
+        let req = Request::builder()
+            .method("PROPPATCH")
+            .uri("/dir/a")
+            .body(Body::from(body))
+            .unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = String::from_utf8_lossy(res.body());
+        assert!(body.contains(">props</S:limit-exceeded>"), "{}", body);
+
+        // a large body is refused while it is parsed: what comes after
+        // the limit, here a body that is not well-formed, is not looked at.
+        for (method, head, tail) in [
+            ("PROPFIND", r#"<D:propfind xmlns:D="DAV:"><D:prop>"#, "<"),
+            (
+                "PROPPATCH",
+                r#"<D:propertyupdate xmlns:D="DAV:"><D:set><D:prop>"#,
+                "<",
+            ),
+        ] {
+            let body = format!("{}{}{}", head, "<x/>".repeat(10001), tail);
+            let req = Request::builder()
+                .method(method)
+                .uri("/")
+                .header("Depth", "0")
+                .body(Body::from(body))
+                .unwrap();
+            let dav = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .build_handler();
+            let res = DavClient::new(dav).request(req).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", method);
+            let body = String::from_utf8_lossy(res.body());
+            assert!(body.contains(">props</S:limit-exceeded>"), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_namespace_declarations() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        let request = |method: &str, path: &str, depth: &str, body: &'static str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(path)
+                .header("Depth", depth)
+                .body(Body::from(body))
+                .unwrap();
+            let client = client.clone();
+            async move {
+                let res = client.request(req).await;
+                assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+                String::from_utf8(res.body().to_vec()).unwrap()
+            }
+        };
+        let proppatch = [
+            (
+                "/a",
+                r#"<D:propertyupdate xmlns:D="DAV:" xmlns:x="urn:x"><D:set><D:prop><x:p>1</x:p><y:q xmlns:y="urn:y"><y:r/></y:q></D:prop></D:set></D:propertyupdate>"#,
+            ),
+            (
+                "/b",
+                r#"<propertyupdate xmlns="DAV:"><set><prop><q xmlns="urn:y"><z:r xmlns:z="urn:z"/></q><p xmlns="urn:x">2</p></prop></set></propertyupdate>"#,
+            ),
+        ];
+        let head = r#"<?xml version="1.0" encoding="utf-8"?>"#;
+        let expect = [
+            concat!(
+                r#"<D:multistatus xmlns:D="DAV:" xmlns:ns1="urn:x" xmlns:ns2="urn:y">"#,
+                r#"<D:response><D:href>/a</D:href><D:propstat><D:prop>"#,
+                r#"<ns1:p></ns1:p><ns2:q></ns2:q></D:prop>"#,
+                r#"<D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>"#,
+                r#"</D:multistatus>"#,
+            ),
+            concat!(
+                r#"<D:multistatus xmlns:D="DAV:" xmlns:ns1="urn:y" xmlns:ns2="urn:x">"#,
+                r#"<D:response><D:href>/b</D:href><D:propstat><D:prop>"#,
+                r#"<ns1:q></ns1:q><ns2:p></ns2:p></D:prop>"#,
+                r#"<D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>"#,
+                r#"</D:multistatus>"#,
+            ),
+        ];
+        for (&(path, body), expect) in proppatch.iter().zip(expect.iter()) {
+            assert_eq!(client.put_bytes(path, "x").await, StatusCode::CREATED);
+            let res = request("PROPPATCH", path, "0", body).await;
+            assert_eq!(res, format!("{}{}", head, expect));
+        }
+
+        // the requested properties are declared on the root, the
+        // namespaces inside their values where they are used.
+        let body = concat!(
+            r#"<D:propfind xmlns:D="DAV:"><D:prop>"#,
+            r#"<a:p xmlns:a="urn:x"/><b:q xmlns:b="urn:y"/><c:s xmlns:c="urn:s"/>"#,
+            r#"</D:prop></D:propfind>"#
+        );
+        let expect = concat!(
+            r#"<D:multistatus xmlns:D="DAV:" xmlns:ns1="urn:x" xmlns:ns2="urn:y" xmlns:ns3="urn:s">"#,
+            r#"<D:response><D:href>/b</D:href>"#,
+            r#"<D:propstat><D:prop><ns1:p>2</ns1:p>"#,
+            r#"<ns2:q xmlns:ns4="urn:z"><ns4:r></ns4:r></ns2:q></D:prop>"#,
+            r#"<D:status>HTTP/1.1 200 OK</D:status></D:propstat>"#,
+            r#"<D:propstat><D:prop><ns3:s></ns3:s></D:prop>"#,
+            r#"<D:status>HTTP/1.1 404 Not Found</D:status></D:propstat>"#,
+            r#"</D:response></D:multistatus>"#,
+        );
+        let res = request("PROPFIND", "/b", "0", body).await;
+        assert_eq!(res, format!("{}{}", head, expect));
+
+        // dead properties that are only found while listing get their
+        // prefixes in a fixed order.
+        let body = r#"<D:propfind xmlns:D="DAV:"><D:propname/></D:propfind>"#;
+        let expect = concat!(
+            r#"<D:multistatus xmlns:A="http://apache.org/dav/props/" xmlns:D="DAV:" "#,
+            r#"xmlns:Z="urn:schemas-microsoft-com:">"#,
+            r#"<D:response><D:href>/b</D:href><D:propstat><D:prop>"#,
+            r#"<D:creationdate></D:creationdate><D:getcontentlength></D:getcontentlength>"#,
+            r#"<D:getcontenttype></D:getcontenttype><D:getetag></D:getetag>"#,
+            r#"<D:getlastmodified></D:getlastmodified><D:lockdiscovery></D:lockdiscovery>"#,
+            r#"<D:resourcetype></D:resourcetype><D:supportedlock></D:supportedlock>"#,
+            r#"<ns1:p xmlns:ns1="urn:x"></ns1:p><ns2:q xmlns:ns2="urn:y"></ns2:q></D:prop>"#,
+            r#"<D:status>HTTP/1.1 200 OK</D:status></D:propstat>"#,
+            r#"</D:response></D:multistatus>"#,
+        );
+        let res = request("PROPFIND", "/b", "0", body).await;
+        assert_eq!(res, format!("{}{}", head, expect));
+    }
+
+    #[tokio::test]
+    async fn test_proppatch_protected() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/file", "x").await, StatusCode::CREATED);
+
+        let xml = r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:test">
+            <D:set><D:prop>
+              <D:getcontentlength>10</D:getcontentlength>
+              <D:displayname>name</D:displayname>
+              <Z:color>red</Z:color>
+            </D:prop></D:set>
+            <D:remove><D:prop><D:getetag/></D:prop></D:remove>
+            </D:propertyupdate>"#;
+        let req = Request::builder()
+            .method("PROPPATCH")
+            .uri("/file")
+            .body(Body::from(xml))
+            .unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let body = String::from_utf8_lossy(res.body()).to_string();
+        let ms = Multistatus::parse(res.body()).unwrap();
+        let resp = ms.response("/file").unwrap();
+        assert_eq!(
+            resp.prop_status("getcontentlength"),
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(resp.prop_status("getetag"), Some(StatusCode::FORBIDDEN));
+        assert_eq!(
+            resp.prop_status("displayname"),
+            Some(StatusCode::FAILED_DEPENDENCY)
+        );
+        assert_eq!(
+            resp.prop_status("{urn:test}color"),
+            Some(StatusCode::FAILED_DEPENDENCY)
+        );
+        assert_eq!(
+            body.matches("<D:cannot-modify-protected-property").count(),
+            1
+        );
+
+        // nothing was changed.
+        let ms = client
+            .propfind("/file", Depth::Zero, &["getcontentlength", "displayname"])
+            .await
+            .unwrap();
+        let resp = ms.response("/file").unwrap();
+        assert_eq!(resp.prop_text("getcontentlength").as_deref(), Some("1"));
+        assert_eq!(resp.prop_status("displayname"), Some(StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn test_extra_resourcetypes() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .extended_mkcol(true)
+            .build_handler();
+        let client = DavClient::new(dav);
+
+        let xml = r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:mkcol xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+            <D:set><D:prop>
+              <D:resourcetype><D:collection/><C:calendar/><x:photo-album xmlns:x="urn:x"/></D:resourcetype>
+              <D:displayname>Photos</D:displayname>
+            </D:prop></D:set>
+            </D:mkcol>"#;
+        let req = Request::builder()
+            .method("MKCOL")
+            .uri("/cal/")
+            .body(Body::from(xml))
+            .unwrap();
+        assert_eq!(client.request(req).await.status(), StatusCode::CREATED);
+
+        // COPY keeps the types.
+        assert_eq!(
+            client.copy("/cal/", "/copy/", false).await,
+            StatusCode::CREATED
+        );
+
+        for path in ["/cal/", "/copy/"] {
+            let ms = client
+                .propfind(path, Depth::Zero, &["resourcetype", "displayname"])
+                .await
+                .unwrap();
+            let resp = ms.response(path).unwrap();
+            let rt = resp.prop("resourcetype").unwrap();
+            let types = rt
+                .children
+                .iter()
+                .filter_map(|c| c.as_element())
+                .map(|e| format!("{{{}}}{}", e.namespace.as_deref().unwrap(), e.name))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                types,
+                [
+                    "{DAV:}collection",
+                    "{urn:ietf:params:xml:ns:caldav}calendar",
+                    "{urn:x}photo-album"
+                ]
+            );
+            assert_eq!(resp.prop_text("displayname").as_deref(), Some("Photos"));
+        }
+
+        // a resourcetype without collection is refused.
+        let xml = r#"<D:mkcol xmlns:D="DAV:"><D:set><D:prop>
+            <D:resourcetype><x:photo-album xmlns:x="urn:x"/></D:resourcetype>
+            </D:prop></D:set></D:mkcol>"#;
+        let req = Request::builder()
+            .method("MKCOL")
+            .uri("/bad/")
+            .body(Body::from(xml))
+            .unwrap();
+        assert_eq!(client.request(req).await.status(), StatusCode::FORBIDDEN);
+        let res = client.propfind("/bad/", Depth::Zero, &[]).await;
+        assert_eq!(res.err(), Some(StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn test_content_type_language() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(
+            client.put_bytes("/doc.xml", "<x/>").await,
+            StatusCode::CREATED
+        );
+        let proppatch = |xml: &'static str| {
+            let req = Request::builder()
+                .method("PROPPATCH")
+                .uri("/doc.xml")
+                .body(Body::from(xml))
+                .unwrap();
+            let client = &client;
+            async move { Multistatus::parse(client.request(req).await.body()).unwrap() }
+        };
+
+        // not a MIME type.
+        let ms = proppatch(
+            r#"<D:propertyupdate xmlns:D="DAV:"><D:set><D:prop>
+            <D:getcontenttype>not a type</D:getcontenttype>
+            </D:prop></D:set></D:propertyupdate>"#,
+        )
+        .await;
+        let resp = ms.response("/doc.xml").unwrap();
+        assert_eq!(
+            resp.prop_status("getcontenttype"),
+            Some(StatusCode::CONFLICT)
+        );
+
+        let ms = proppatch(
+            r#"<D:propertyupdate xmlns:D="DAV:"><D:set><D:prop>
+            <D:getcontenttype>application/xslt+xml</D:getcontenttype>
+            <D:getcontentlanguage>nl</D:getcontentlanguage>
+            </D:prop></D:set></D:propertyupdate>"#,
+        )
+        .await;
+        let resp = ms.response("/doc.xml").unwrap();
+        assert_eq!(resp.prop_status("getcontenttype"), Some(StatusCode::OK));
+        assert_eq!(resp.prop_status("getcontentlanguage"), Some(StatusCode::OK));
+
+        let props = ["getcontenttype", "getcontentlanguage"];
+        let ms = client
+            .propfind("/doc.xml", Depth::Zero, &props)
+            .await
+            .unwrap();
+        let resp = ms.response("/doc.xml").unwrap();
+        assert_eq!(
+            resp.prop_text("getcontenttype").as_deref(),
+            Some("application/xslt+xml")
+        );
+        assert_eq!(resp.prop_text("getcontentlanguage").as_deref(), Some("nl"));
+
+        // allprop lists getcontenttype once.
+        let ms = client.propfind("/doc.xml", Depth::Zero, &[]).await.unwrap();
+        let resp = ms.response("/doc.xml").unwrap();
+        let count = resp
+            .propstat
+            .iter()
+            .flat_map(|ps| ps.props.iter())
+            .filter(|p| p.name == "getcontenttype")
+            .count();
+        assert_eq!(count, 1);
+
+        let res = client.get("/doc.xml").await;
+        assert_eq!(res.headers()["content-type"], "application/xslt+xml");
+        assert_eq!(res.headers()["content-language"], "nl");
+
+        // removing them brings back the defaults.
+        let ms = proppatch(
+            r#"<D:propertyupdate xmlns:D="DAV:"><D:remove><D:prop>
+            <D:getcontenttype/><D:getcontentlanguage/>
+            </D:prop></D:remove></D:propertyupdate>"#,
+        )
+        .await;
+        let resp = ms.response("/doc.xml").unwrap();
+        assert_eq!(resp.prop_status("getcontenttype"), Some(StatusCode::OK));
+        let res = client.get("/doc.xml").await;
+        assert_eq!(res.headers()["content-type"], "text/xml");
+        assert!(res.headers().get("content-language").is_none());
+    }
+
+    // LocalFs, as if it did not record a birth time, with a file whose
+    // modification time is after its status change. Returns the MockFs
+    // and the ctime and mtime of the file.
+    async fn creationdate_fs(
+        name: &str,
+    ) -> (Box<MockFs>, std::time::SystemTime, std::time::SystemTime) {
+        use crate::localfs::LocalFs;
+        use crate::testing::tempdir;
+
+        let dir = tempdir(name);
+        let local = LocalFs::new(&dir, false, false, false);
+        let fs = MockFs::builder()
+            .filesystem(local.clone())
+            .no_created("*")
+            .build();
+        let client = DavClient::new(DavHandler::builder().filesystem(fs.clone()).build_handler());
+        assert_eq!(client.put_bytes("/file", "x").await, StatusCode::CREATED);
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(3600);
+        let file = std::fs::File::options()
+            .write(true)
+            .open(dir.join("file"))
+            .unwrap();
+        file.set_modified(later).unwrap();
+        let meta = local
+            .metadata(&DavPath::new("/file").unwrap())
+            .await
+            .unwrap();
+        let mtime = meta.modified().unwrap();
+        let ctime = meta.status_changed().unwrap();
+        assert!(ctime < mtime);
+        (fs, ctime, mtime)
+    }
+
+    async fn creationdate(
+        fs: &MockFs,
+        fallback: Option<crate::CreationDateFallback>,
+    ) -> Option<String> {
+        let mut config = DavHandler::builder().filesystem(Box::new(fs.clone()));
+        if let Some(fallback) = fallback {
+            config = config.creationdate_fallback(fallback);
+        }
+        let client = DavClient::new(config.build_handler());
+        let ms = client
+            .propfind("/file", Depth::Zero, &["creationdate"])
+            .await
+            .unwrap();
+        ms.response("/file").unwrap().prop_text("creationdate")
+    }
+
+    #[tokio::test]
+    async fn test_creationdate_fallback() {
+        use crate::util::systemtime_to_rfc3339;
+        use crate::CreationDateFallback;
+
+        let (fs, ctime, mtime) = creationdate_fs("creationdate").await;
+        for (fallback, expect) in [
+            (
+                CreationDateFallback::StatusChanged,
+                Some(systemtime_to_rfc3339(ctime.min(mtime))),
+            ),
+            (
+                CreationDateFallback::Modified,
+                Some(systemtime_to_rfc3339(mtime)),
+            ),
+            (CreationDateFallback::Omit, None),
+        ] {
+            assert_eq!(
+                creationdate(&fs, Some(fallback)).await,
+                expect,
+                "{:?}",
+                fallback
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_creationdate_fallback_default() {
+        use crate::util::systemtime_to_rfc3339;
+
+        // the default is the ctime fallback, as before the option existed.
+        let (fs, ctime, mtime) = creationdate_fs("creationdate-default").await;
+        let expect = Some(systemtime_to_rfc3339(ctime.min(mtime)));
+        assert_eq!(creationdate(&fs, None).await, expect);
+    }
+
+    #[tokio::test]
+    async fn test_content_type_no_props() {
+        use crate::localfs::LocalFs;
+        use crate::testing::tempdir;
+
+        let dir = tempdir("ctype");
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.put_bytes("/a.txt", "x").await, StatusCode::CREATED);
+
+        let xml = r#"<D:propertyupdate xmlns:D="DAV:"><D:set><D:prop>
+            <D:getcontenttype>text/html</D:getcontenttype>
+            </D:prop></D:set></D:propertyupdate>"#;
+        let req = Request::builder()
+            .method("PROPPATCH")
+            .uri("/a.txt")
+            .body(Body::from(xml))
+            .unwrap();
+        let res = client.request(req).await;
+        let ms = Multistatus::parse(res.body()).unwrap();
+        let resp = ms.response("/a.txt").unwrap();
+        assert_eq!(
+            resp.prop_status("getcontenttype"),
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            client.get("/a.txt").await.headers()["content-type"],
+            "text/plain"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_entries() {
+        let fs = MemFs::new();
+        for name in ["/file1", "/locked", "/gone", "/file2"] {
+            let mut oo = OpenOptions::write();
+            oo.create = true;
+            fs.open(&DavPath::new(name).unwrap(), oo).await.unwrap();
+        }
+
+        for policy in [UnreadableEntries::Skip, UnreadableEntries::Report] {
+            // "gone" was removed after the directory was read.
+            let mockfs = MockFs::builder()
+                .filesystem(fs.clone())
+                .fail(MockOp::Metadata, "/locked", FsError::Forbidden)
+                .fail(MockOp::Metadata, "/gone", FsError::NotFound)
+                .build();
+            let dav = DavHandler::builder()
+                .filesystem(mockfs)
+                .unreadable_entries(policy)
+                .autoindex(true)
+                .build_handler();
+            let client = DavClient::new(dav);
+
+            let ms = client.propfind("/", Depth::One, &[]).await.unwrap();
+            let mut hrefs = ms.hrefs();
+            hrefs.sort();
+            let index = client
+                .request(
+                    Request::get("/")
+                        .header("Accept", "text/html")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await;
+            let index = String::from_utf8_lossy(index.body()).to_string();
+            assert!(index.contains("file1") && index.contains("file2"));
+            assert!(!index.contains("gone"));
+
+            if policy == UnreadableEntries::Skip {
+                assert_eq!(hrefs, ["/", "/file1", "/file2"]);
+                assert!(!index.contains("locked"));
+            } else {
+                assert_eq!(hrefs, ["/", "/file1", "/file2", "/locked"]);
+                let locked = ms.response("/locked").unwrap();
+                assert_eq!(locked.status, Some(StatusCode::FORBIDDEN));
+                assert!(locked.propstat.is_empty());
+                assert!(index.contains("locked"));
+            }
+        }
+    }
+}
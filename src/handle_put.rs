@@ -1,21 +1,26 @@
 use std::any::Any;
 use std::error::Error as StdError;
 use std::io;
+#[cfg(feature = "compression")]
+use std::io::Write;
 
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use headers::HeaderMapExt;
 use http::StatusCode as SC;
 use http::{self, Request, Response};
 use http_body::Body as HttpBody;
 
 use crate::body::Body;
-use crate::conditional::if_match_get_tokens;
 use crate::davheaders;
+use crate::davpath::DavPath;
 use crate::fs::*;
-use crate::{DavError, DavResult};
+use crate::util::limit_error;
+use crate::{DavError, DavResult, Durability};
 
 const SABRE: &str = "application/x-sabredav-partialupdate";
 
+const WRITE_BUF_SIZE: usize = 16384;
+
 // This is a nice hack. If the type 'E' is actually an io::Error or a Box<io::Error>,
 // convert it back into a real io::Error. If it is a DavError or a Box<DavError>,
 // use its Into<io::Error> impl. Otherwise just wrap the error in io::Error::new.
@@ -52,7 +57,229 @@ where
     }
 }
 
+// Decompresses a request body with a Content-Encoding on the fly.
+#[cfg(feature = "compression")]
+struct Decoder {
+    codec: Codec,
+    // how many more decompressed bytes are allowed.
+    left: u64,
+}
+
+#[cfg(feature = "compression")]
+enum Codec {
+    Gzip(flate2::write::GzDecoder<Vec<u8>>),
+    Deflate(flate2::write::ZlibDecoder<Vec<u8>>),
+}
+
+#[cfg(feature = "compression")]
+impl Decoder {
+    // "deflate" in HTTP is the zlib format (RFC 9110 8.4.1.2).
+    fn from_request(req: &Request<()>, limit: u64) -> Option<Decoder> {
+        let enc = req.headers().get(http::header::CONTENT_ENCODING)?;
+        let codec = match enc.to_str().ok()?.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Codec::Gzip(flate2::write::GzDecoder::new(Vec::new())),
+            "deflate" => Codec::Deflate(flate2::write::ZlibDecoder::new(Vec::new())),
+            _ => return None,
+        };
+        Some(Decoder { codec, left: limit })
+    }
+
+    // feed compressed data, return what has been decompressed so far.
+    fn decode(&mut self, data: &[u8]) -> DavResult<Bytes> {
+        let out = match self.codec {
+            Codec::Gzip(ref mut d) => d.write_all(data).map(|_| std::mem::take(d.get_mut())),
+            Codec::Deflate(ref mut d) => d.write_all(data).map(|_| std::mem::take(d.get_mut())),
+        };
+        let out = out.map_err(bad_encoding)?;
+        // a small body can decompress to a huge one, so the limit
+        // is on the decompressed size.
+        self.left = self.left.checked_sub(out.len() as u64).ok_or(TOO_LARGE)?;
+        Ok(Bytes::from(out))
+    }
+
+    // end of input. Fails if the compressed stream was incomplete.
+    fn finish(self) -> DavResult<Bytes> {
+        let out = match self.codec {
+            Codec::Gzip(d) => d.finish(),
+            Codec::Deflate(d) => d.finish(),
+        };
+        let out = out.map_err(bad_encoding)?;
+        if out.len() as u64 > self.left {
+            return Err(TOO_LARGE);
+        }
+        Ok(Bytes::from(out))
+    }
+}
+
+// the decompressed body exceeds `Limits::decompressed_body`.
+#[cfg(feature = "compression")]
+const TOO_LARGE: DavError = DavError::StatusClose(SC::PAYLOAD_TOO_LARGE);
+
+#[cfg(feature = "compression")]
+fn bad_encoding(e: io::Error) -> DavError {
+    debug!("copy_body: decompressing body: {}", e);
+    DavError::StatusClose(SC::BAD_REQUEST)
+}
+
+// Without the "compression" feature, bodies are always stored as sent.
+#[cfg(not(feature = "compression"))]
+enum Decoder {}
+
+#[cfg(not(feature = "compression"))]
+impl Decoder {
+    fn decode(&mut self, _data: &[u8]) -> DavResult<Bytes> {
+        match *self {}
+    }
+
+    fn finish(self) -> DavResult<Bytes> {
+        match self {}
+    }
+}
+
+// write a chunk of the body to the file. Chunks smaller than `bufsize`
+// are gathered in `pending` and written in one go, larger chunks are
+// written as-is.
+async fn write_chunk<B>(
+    file: &mut Box<dyn DavFile>,
+    pending: &mut BytesMut,
+    mut buf: B,
+    bufsize: usize,
+) -> DavResult<()>
+where
+    B: Buf + Send + 'static,
+{
+    if buf.remaining() < bufsize {
+        pending.put(buf);
+        if pending.len() >= bufsize {
+            file.write_bytes(pending.split().freeze()).await?;
+        }
+        return Ok(());
+    }
+    if !pending.is_empty() {
+        file.write_bytes(pending.split().freeze()).await?;
+    }
+    // The `Buf` might actually be a `Bytes`.
+    let b = {
+        let b: &mut dyn std::any::Any = &mut buf;
+        b.downcast_mut::<Bytes>()
+    };
+    if let Some(bytes) = b {
+        let bytes = std::mem::replace(bytes, Bytes::new());
+        file.write_bytes(bytes).await?;
+    } else {
+        file.write_buf(Box::new(buf)).await?;
+    }
+    Ok(())
+}
+
+// loop, read body, write to file. Stops as soon as more than `limit`
+// bytes have been read, and returns the number of bytes read.
+//
+// The file is not flushed, so that the caller can check the count
+// first: a filesystem that stages writes only commits on flush.
+//
+// With a `decoder` the body is decompressed before it is written.
+// `limit` and the returned count are still about the body as sent, the
+// decoder has its own limit on the decompressed size.
+async fn copy_body<ReqBody, ReqData, ReqError>(
+    file: &mut Box<dyn DavFile>,
+    body: ReqBody,
+    limit: Option<u64>,
+    bufsize: usize,
+    mut decoder: Option<Decoder>,
+) -> DavResult<u64>
+where
+    ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+    ReqData: Buf + Send + 'static,
+    ReqError: StdError + Send + Sync + 'static,
+{
+    pin_utils::pin_mut!(body);
+
+    let mut total = 0u64;
+    let mut pending = BytesMut::new();
+
+    while let Some(data) = body.data().await {
+        let mut buf = data.map_err(|e| to_ioerror(e))?;
+        let buflen = buf.remaining();
+        total += buflen as u64;
+        // consistency check.
+        if matches!(limit, Some(l) if total > l) {
+            break;
+        }
+        match decoder {
+            Some(ref mut decoder) => {
+                let data = decoder.decode(&buf.copy_to_bytes(buflen))?;
+                write_chunk(file, &mut pending, data, bufsize).await?;
+            }
+            None => write_chunk(file, &mut pending, buf, bufsize).await?,
+        }
+    }
+    if let Some(decoder) = decoder {
+        let data = decoder.finish()?;
+        write_chunk(file, &mut pending, data, bufsize).await?;
+    }
+    if !pending.is_empty() {
+        file.write_bytes(pending.freeze()).await?;
+    }
+    Ok(total)
+}
+
+// the response for a failed upload. One that decompressed to more
+// than the limit gets a body that names it, like the other limits.
+fn upload_error(e: DavError) -> DavResult<Response<Body>> {
+    match e {
+        DavError::StatusClose(SC::PAYLOAD_TOO_LARGE) => {
+            let mut res = limit_error(SC::PAYLOAD_TOO_LARGE, "decompressed_body");
+            res.headers_mut()
+                .insert("connection", http::HeaderValue::from_static("close"));
+            Ok(res)
+        }
+        e => Err(e),
+    }
+}
+
+// the number of bytes in the range `first..=last`, if that fits in a u64.
+fn range_len(first: u64, last: u64) -> Option<u64> {
+    (last - first).checked_add(1)
+}
+
+// after a failed partial write, at least do not leave the file
+// longer than it was. Best effort, errors are ignored.
+async fn restore_len(file: &mut Box<dyn DavFile>, len: Option<u64>) {
+    if let Some(len) = len {
+        let _ = file.set_len(len).await;
+    }
+}
+
 impl crate::DavInner {
+    fn write_buf_size(&self) -> usize {
+        self.write_buf_size.unwrap_or(WRITE_BUF_SIZE)
+    }
+
+    // sync an uploaded file as far as the config asks.
+    async fn sync_file(&self, file: &mut Box<dyn DavFile>) -> DavResult<()> {
+        let level = SyncLevel::from(self.durability.unwrap_or(Durability::None));
+        if level != SyncLevel::None {
+            file.sync(level).await?;
+        }
+        Ok(())
+    }
+
+    // decompresses the body, if it has a Content-Encoding and the
+    // config asks for it.
+    #[cfg(feature = "compression")]
+    fn decoder(&self, req: &Request<()>) -> Option<Decoder> {
+        match self.decompress_uploads {
+            Some(true) => Decoder::from_request(req, self.limits().decompressed_body),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn decoder(&self, _req: &Request<()>) -> Option<Decoder> {
+        None
+    }
+
     pub(crate) async fn handle_put<ReqBody, ReqData, ReqError>(
         self,
         req: &Request<()>,
@@ -67,6 +294,7 @@ impl crate::DavInner {
         let mut count = 0;
         let mut have_count = false;
         let mut do_range = false;
+        let mut complete_len = None;
 
         let mut oo = OpenOptions::write();
         oo.create = true;
@@ -80,6 +308,7 @@ impl crate::DavInner {
             .headers()
             .get("X-Expected-Entity-Length")
             .and_then(|v| v.to_str().ok())
+            .filter(|_| self.quirks.finder_expected_length)
         {
             // macOS Finder, see https://evertpot.com/260/
             if let Ok(len) = n.parse() {
@@ -96,6 +325,9 @@ impl crate::DavInner {
 
         let path = self.path(req);
         let meta = self.fs.metadata(&path).await;
+        if meta.is_err() {
+            self.check_windows_name(&path)?;
+        }
 
         // close connection on error.
         let mut res = Response::new(Body::empty());
@@ -119,12 +351,16 @@ impl crate::DavInner {
                 .ok_or(DavError::StatusClose(SC::BAD_REQUEST))?;
             match r {
                 davheaders::XUpdateRange::FromTo(b, e) => {
-                    if b > e || e - b + 1 != count {
+                    if b > e || range_len(b, e) != Some(count) {
                         return Err(DavError::StatusClose(SC::RANGE_NOT_SATISFIABLE));
                     }
                     start = b;
                 }
                 davheaders::XUpdateRange::AllFrom(b) => {
+                    // writing past the end would leave a gap.
+                    if matches!(meta, Ok(ref m) if b > m.len()) {
+                        return Err(DavError::StatusClose(SC::RANGE_NOT_SATISFIABLE));
+                    }
                     start = b;
                 }
                 davheaders::XUpdateRange::Last(n) => {
@@ -147,40 +383,60 @@ impl crate::DavInner {
         match req.headers().typed_try_get::<headers::ContentRange>() {
             Ok(Some(range)) => {
                 if let Some((b, e)) = range.bytes_range() {
-                    if b > e {
+                    // the last byte must be inside the complete length.
+                    if b > e || matches!(range.bytes_len(), Some(len) if e >= len) {
                         return Err(DavError::StatusClose(SC::RANGE_NOT_SATISFIABLE));
                     }
+                    let len =
+                        range_len(b, e).ok_or(DavError::StatusClose(SC::RANGE_NOT_SATISFIABLE))?;
 
                     if have_count {
-                        if e - b + 1 != count {
+                        if len != count {
                             return Err(DavError::StatusClose(SC::RANGE_NOT_SATISFIABLE));
                         }
                     } else {
-                        count = e - b + 1;
+                        count = len;
                         have_count = true;
                     }
                     start = b;
                     do_range = true;
                     oo.truncate = false;
+                    complete_len = range.bytes_len();
                 }
             }
             Ok(None) => {}
             Err(_) => return Err(DavError::StatusClose(SC::BAD_REQUEST)),
         }
 
+        // Decompress "Content-Encoding: gzip" bodies, but not for partial
+        // updates, where the offsets are about the stored file.
+        let decoder = if do_range { None } else { self.decoder(req) };
+        let decompress = decoder.is_some();
+        if decompress {
+            // the length is that of the compressed body.
+            oo.size = None;
+        }
+
         // check the If and If-* headers.
-        let tokens = if_match_get_tokens(req, meta.as_ref().ok(), &self.fs, &self.ls, &path);
+        let tokens = self.if_match_get_tokens(req, meta.as_deref().ok(), &path);
         let tokens = match tokens.await {
             Ok(t) => t,
-            Err(s) => return Err(DavError::StatusClose(s)),
+            Err(s) => {
+                let mut res = self
+                    .precondition_response(s, &path, meta.as_deref().ok())
+                    .await;
+                res.headers_mut()
+                    .insert("connection", "close".parse().unwrap());
+                return Ok(res);
+            }
         };
 
         // if locked check if we hold that lock.
         if let Some(ref locksystem) = self.ls {
             let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
             let principal = self.principal.as_deref();
-            if let Err(_l) = locksystem.check(&path, principal, false, false, t) {
-                return Err(DavError::StatusClose(SC::LOCKED));
+            if let Err(l) = locksystem.check(&path, principal, false, false, t) {
+                return Err(DavError::StatusClose(self.lock_status(&l, &tokens)));
             }
         }
 
@@ -200,22 +456,45 @@ impl crate::DavInner {
             oo.create_new = true;
         }
 
+        // an open that failed because the file did or did not exist.
         let create = oo.create;
         let create_new = oo.create_new;
-        let mut file = match self.fs.open(&path, oo).await {
-            Ok(f) => f,
-            Err(FsError::NotFound) | Err(FsError::Exists) => {
+        let open_error = |e| match e {
+            FsError::NotFound | FsError::Exists => {
                 let s = if !create || create_new {
                     SC::PRECONDITION_FAILED
                 } else {
                     SC::CONFLICT
                 };
-                return Err(DavError::StatusClose(s));
+                DavError::StatusClose(s)
+            }
+            e => DavError::FsError(e),
+        };
+        if self.dry_run {
+            return match self.dry_run_open(&path, meta.is_ok(), &oo).await {
+                Ok(()) => Ok(Response::new(Body::empty())),
+                Err(e) => Err(open_error(e)),
+            };
+        }
+
+        // Whether the file existed is decided by open() itself, not by
+        // the metadata lookup above: first try to create the file, and
+        // only if it exists, open it.
+        let (opened, created) = if create && !create_new {
+            let mut oo_new = oo.clone();
+            oo_new.create_new = true;
+            match self.fs.open(&path, oo_new).await {
+                Err(FsError::Exists) => (self.fs.open(&path, oo).await, false),
+                opened => (opened, true),
             }
-            Err(e) => return Err(DavError::FsError(e)),
+        } else {
+            (self.fs.open(&path, oo).await, create_new)
         };
+        let mut file = opened.map_err(open_error)?;
 
+        let mut orig_len = None;
         if do_range {
+            orig_len = file.metadata().await.ok().map(|m| m.len());
             // seek to beginning of requested data.
             if file.seek(std::io::SeekFrom::Start(start)).await.is_err() {
                 return Err(DavError::StatusClose(SC::RANGE_NOT_SATISFIABLE));
@@ -225,64 +504,602 @@ impl crate::DavInner {
         res.headers_mut()
             .typed_insert(headers::AcceptRanges::bytes());
 
-        pin_utils::pin_mut!(body);
-
-        // loop, read body, write to file.
-        let mut total = 0u64;
-
-        while let Some(data) = body.data().await {
-            let mut buf = data.map_err(|e| to_ioerror(e))?;
-            let buflen = buf.remaining();
-            total += buflen as u64;
-            // consistency check.
-            if have_count && total > count {
-                break;
+        let limit = if have_count { Some(count) } else { None };
+        let bufsize = self.write_buf_size();
+        // on a failed or short upload, remove a file that we created,
+        // unless configured otherwise. A half decompressed one always.
+        // Without a flush, a filesystem that stages writes discards them.
+        let keep = self.keep_partial_uploads.unwrap_or(false);
+        let remove = created && (decompress || !keep);
+        let written = match copy_body(&mut file, body, limit, bufsize, decoder).await {
+            Ok(total) if have_count && total > count => {
+                error!("PUT file: sender is sending more bytes than expected");
+                Err(DavError::StatusClose(SC::BAD_REQUEST))
             }
-            // The `Buf` might actually be a `Bytes`.
-            let b = {
-                let b: &mut dyn std::any::Any = &mut buf;
-                b.downcast_mut::<Bytes>()
-            };
-            if let Some(bytes) = b {
-                let bytes = std::mem::replace(bytes, Bytes::new());
-                file.write_bytes(bytes).await?;
-            } else {
-                file.write_buf(Box::new(buf)).await?;
+            Ok(total) if have_count && total < count => {
+                error!("PUT file: premature EOF on input");
+                Err(DavError::StatusClose(SC::BAD_REQUEST))
             }
+            res => res,
+        };
+        if let Err(e) = written {
+            restore_len(&mut file, orig_len).await;
+            if remove {
+                drop(file);
+                let _ = self.fs.remove_file(&path).await;
+            } else if keep {
+                let _ = file.flush().await;
+            }
+            return upload_error(e);
         }
         file.flush().await?;
 
-        if have_count && total > count {
-            error!("PUT file: sender is sending more bytes than expected");
-            return Err(DavError::StatusClose(SC::BAD_REQUEST));
-        }
-
-        if have_count && total < count {
-            error!("PUT file: premature EOF on input");
-            return Err(DavError::StatusClose(SC::BAD_REQUEST));
+        // "Content-Range: bytes x-y/len" sets the final size of the file.
+        if let Some(len) = complete_len {
+            if file.metadata().await?.len() > len {
+                match file.set_len(len).await {
+                    Ok(()) | Err(FsError::NotImplemented) => {}
+                    Err(e) => return Err(DavError::FsError(e)),
+                }
+            }
         }
+        self.sync_file(&mut file).await?;
 
         // Report whether we created or updated the file.
-        *res.status_mut() = match meta {
-            Ok(_) => SC::NO_CONTENT,
-            Err(_) => {
-                res.headers_mut().typed_insert(headers::ContentLength(0));
-                SC::CREATED
-            }
+        *res.status_mut() = if created {
+            res.headers_mut().typed_insert(headers::ContentLength(0));
+            SC::CREATED
+        } else {
+            SC::NO_CONTENT
         };
 
         // no errors, connection may be kept open.
         res.headers_mut().remove(http::header::CONNECTION);
 
-        if let Ok(m) = file.metadata().await {
-            if let Some(etag) = davheaders::ETag::from_meta(&m) {
-                res.headers_mut().typed_insert(etag);
+        self.put_validators(&mut res, &mut file, &path).await;
+        Ok(res)
+    }
+
+    // Add the ETag and Last-Modified of the file as it is after the
+    // write. If the metadata cannot be read, the write still succeeded,
+    // so the headers are left out instead.
+    async fn put_validators(
+        &self,
+        res: &mut Response<Body>,
+        file: &mut Box<dyn DavFile>,
+        path: &DavPath,
+    ) {
+        let meta = match file.metadata().await {
+            Ok(meta) => meta,
+            Err(_) => match self.fs.metadata(path).await {
+                Ok(meta) => meta,
+                Err(_) => return,
+            },
+        };
+        self.validators(path, &*meta)
+            .await
+            .insert(res.headers_mut());
+        if self.oc_etag.unwrap_or(false) {
+            if let Some(etag) = res.headers().get(http::header::ETAG).cloned() {
+                res.headers_mut().insert("oc-etag", etag);
             }
-            if let Ok(modified) = m.modified() {
+        }
+    }
+
+    // RFC5995: POST to a collection adds a member, named by the server.
+    pub(crate) async fn handle_post<ReqBody, ReqData, ReqError>(
+        self,
+        req: &Request<()>,
+        body: ReqBody,
+    ) -> DavResult<Response<Body>>
+    where
+        ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+    {
+        let mut path = self.path(req);
+        let meta = self.fs.metadata(&path).await?;
+        if !meta.is_dir() {
+            return Err(DavError::StatusClose(SC::METHOD_NOT_ALLOWED));
+        }
+        path.add_slash();
+
+        // check the If and If-* headers on the collection.
+        let tokens = match self.if_match_get_tokens(req, Some(&*meta), &path).await {
+            Ok(t) => t,
+            Err(s) => {
+                let mut res = self.precondition_response(s, &path, Some(&*meta)).await;
                 res.headers_mut()
-                    .typed_insert(headers::LastModified::from(modified));
+                    .insert("connection", "close".parse().unwrap());
+                return Ok(res);
             }
+        };
+
+        // if the collection is locked check if we hold that lock.
+        if let Some(ref locksystem) = self.ls {
+            let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+            let principal = self.principal.as_deref();
+            if let Err(l) = locksystem.check(&path, principal, false, false, t) {
+                return Err(DavError::StatusClose(self.lock_status(&l, &tokens)));
+            }
+        }
+        if self.dry_run {
+            return Ok(Response::new(Body::empty()));
+        }
+
+        // pick a name that is not in use yet. create_new makes sure
+        // we never overwrite a member that was created concurrently.
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        oo.create_new = true;
+        let decoder = self.decoder(req);
+        let decompress = decoder.is_some();
+        if let Some(n) = req.headers().typed_get::<headers::ContentLength>() {
+            if !decompress {
+                oo.size = Some(n.0);
+            }
+        }
+        let mut tries = 0;
+        let (npath, mut file) = loop {
+            let mut npath = path.clone();
+            npath.push_segment(uuid::Uuid::new_v4().simple().to_string().as_bytes());
+            match self.fs.open(&npath, oo.clone()).await {
+                Ok(f) => break (npath, f),
+                Err(FsError::Exists) if tries < 3 => tries += 1,
+                Err(e) => return Err(DavError::FsError(e)),
+            }
+        };
+
+        let bufsize = self.write_buf_size();
+        let res = match copy_body(&mut file, body, None, bufsize, decoder).await {
+            Ok(_) => file.flush().await.map_err(DavError::FsError),
+            Err(e) => Err(e),
+        };
+        // the client never learns the name of a failed upload, so the
+        // partial file is always removed, whatever keep_partial_uploads says.
+        if let Err(e) = res {
+            drop(file);
+            let _ = self.fs.remove_file(&npath).await;
+            return upload_error(e);
         }
+        self.sync_file(&mut file).await?;
+
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = SC::CREATED;
+        res.headers_mut().typed_insert(headers::ContentLength(0));
+        res.headers_mut().insert(
+            "Location",
+            npath.with_prefix().as_url_string().parse().unwrap(),
+        );
+        self.put_validators(&mut res, &mut file, &npath).await;
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use bytes::Bytes;
+    use http::{Request, StatusCode};
+
+    use crate::async_stream::AsyncStream;
+    use crate::body::Body;
+    use crate::fs::*;
+    use crate::memfs::MemFs;
+    use crate::testfs::{MockFs, MockOp};
+    use crate::testing::{DavClient, Depth};
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn test_post_add_member() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+
+        let r = Request::post("/").body(Body::from("hello")).unwrap();
+        let res = client.request(r).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let location = res.headers().get("location").unwrap().to_str().unwrap();
+        assert!(location.len() > 1 && location.starts_with('/'));
+
+        let res = client.get(location).await;
+        assert_eq!(res.body().as_ref(), b"hello");
+
+        // POST to a non-collection is not allowed.
+        let r = Request::post(location).body(Body::from("x")).unwrap();
+        let res = client.request(r).await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn test_post_failed_upload() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .keep_partial_uploads(true)
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+
+        // the body breaks off after the first chunk.
+        let body = Body::from(AsyncStream::new(|mut tx| async move {
+            tx.send(Bytes::from_static(b"partial")).await;
+            Err::<(), _>(io::Error::new(io::ErrorKind::ConnectionReset, "gone"))
+        }));
+        let res = client
+            .request(Request::post("/dir/").body(body).unwrap())
+            .await;
+        assert!(res.headers().get("location").is_none());
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+
+        // and leaves no member behind.
+        let ms = client.propfind("/dir/", Depth::One, &[]).await.unwrap();
+        assert_eq!(ms.hrefs(), ["/dir/"]);
+    }
+
+    #[tokio::test]
+    async fn test_put_status() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+
+        // create, then overwrite. Both report an ETag.
+        for status in [StatusCode::CREATED, StatusCode::NO_CONTENT] {
+            let res = client
+                .request(Request::put("/file").body(Body::from("hello")).unwrap())
+                .await;
+            assert_eq!(res.status(), status);
+            assert!(res.headers().contains_key("etag"));
+        }
+
+        // partial PUT, on an existing and on a new file.
+        for (path, status) in [
+            ("/file", StatusCode::NO_CONTENT),
+            ("/new", StatusCode::CREATED),
+        ] {
+            let req = Request::put(path)
+                .header("Content-Range", "bytes 0-1/*")
+                .body(Body::from("HE"))
+                .unwrap();
+            let res = client.request(req).await;
+            assert_eq!(res.status(), status, "{}", path);
+            assert!(res.headers().contains_key("etag"));
+        }
+        assert_eq!(client.get("/file").await.body().as_ref(), b"HEllo");
+    }
+
+    #[tokio::test]
+    async fn test_put_validators() {
+        use crate::localfs::LocalFs;
+        use crate::testing::tempdir;
+        use std::time::{Duration, SystemTime};
+
+        // LocalFs is checked with its directory, to set the time of the file.
+        let dir = tempdir("put-etag");
+        let filesystems: Vec<(Box<dyn DavFileSystem>, Option<&std::path::Path>)> = vec![
+            (MemFs::new(), None),
+            (LocalFs::new(&dir, false, false, false), Some(&dir)),
+        ];
+
+        for (fs, dir) in filesystems {
+            let dav = DavHandler::builder()
+                .filesystem(fs)
+                .oc_etag(true)
+                .build_handler();
+            let client = DavClient::new(dav);
+            let header = |res: &http::Response<_>, name: &str| {
+                res.headers()
+                    .get(name)
+                    .map(|v| v.to_str().unwrap().to_string())
+            };
+
+            let res = client
+                .request(Request::put("/file").body(Body::from("hello")).unwrap())
+                .await;
+            assert_eq!(res.status(), StatusCode::CREATED);
+            let etag = header(&res, "etag").unwrap();
+            assert_eq!(header(&res, "oc-etag").as_ref(), Some(&etag));
+            assert!(res.headers().contains_key("last-modified"));
+            assert_eq!(
+                header(&client.get("/file").await, "etag"),
+                Some(etag.clone())
+            );
+
+            // a partial PUT that does not change the size changes the ETag.
+            match dir {
+                Some(dir) => {
+                    let old = SystemTime::now() - Duration::from_secs(60);
+                    let file = std::fs::File::open(dir.join("file")).unwrap();
+                    file.set_modified(old).unwrap();
+                }
+                None => std::thread::sleep(Duration::from_millis(2)),
+            }
+            let req = Request::put("/file")
+                .header("Content-Range", "bytes 0-1/*")
+                .body(Body::from("HE"))
+                .unwrap();
+            let res = client.request(req).await;
+            assert_eq!(res.status(), StatusCode::NO_CONTENT);
+            let etag2 = header(&res, "etag").unwrap();
+            assert_ne!(etag2, etag);
+            assert_eq!(header(&res, "oc-etag").as_ref(), Some(&etag2));
+            let res = client.get("/file").await;
+            assert_eq!(res.body().as_ref(), b"HEllo");
+            assert_eq!(header(&res, "etag"), Some(etag2));
+        }
+
+        // OC-ETag is off by default.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let res = DavClient::new(dav)
+            .request(Request::put("/file").body(Body::from("x")).unwrap())
+            .await;
+        assert!(res.headers().contains_key("etag"));
+        assert!(!res.headers().contains_key("oc-etag"));
+    }
+
+    #[tokio::test]
+    async fn test_put_range_total() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        let put_range = |range: &str, data: &'static str| {
+            Request::put("/file")
+                .header("Content-Range", range)
+                .body(Body::from(data))
+                .unwrap()
+        };
+
+        // an explicit total shrinks the file.
+        client.put_bytes("/file", "0123456789").await;
+        let res = client.request(put_range("bytes 2-3/6", "ab")).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(client.get("/file").await.body().as_ref(), b"01ab45");
+
+        // an unknown total does not.
+        let res = client.request(put_range("bytes 0-1/*", "AB")).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(client.get("/file").await.body().as_ref(), b"ABab45");
+
+        // a write past the end that fails does not grow the file.
+        let mut req = put_range("bytes 4-11/*", "cdef");
+        req.headers_mut()
+            .insert("content-length", "8".parse().unwrap());
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(client.get("/file").await.body().as_ref(), b"ABabcd");
+
+        // the last byte must be inside the total, and the range must
+        // not overflow.
+        for range in ["bytes 4-7/6", "bytes 0-18446744073709551615/*"] {
+            let res = client.request(put_range(range, "wxyz")).await;
+            assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE, "{}", range);
+        }
+        assert_eq!(client.get("/file").await.body().as_ref(), b"ABabcd");
+    }
+
+    #[tokio::test]
+    async fn test_patch_update_range() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        let patch = |range: &str, data: &'static str| {
+            Request::patch("/file")
+                .header("Content-Type", "application/x-sabredav-partialupdate")
+                .header("Content-Length", data.len())
+                .header("X-Update-Range", range)
+                .body(Body::from(data))
+                .unwrap()
+        };
+        client.put_bytes("/file", "0123456789").await;
+
+        let res = client.request(patch("bytes=8-", "ab")).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(client.get("/file").await.body().as_ref(), b"01234567ab");
+        let res = client.request(patch("bytes=10-", "cd")).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(client.get("/file").await.body().as_ref(), b"01234567abcd");
+
+        // starting beyond the end would leave a gap.
+        let res = client.request(patch("bytes=20-", "ef")).await;
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        // a range that does not fit in a u64.
+        let res = client
+            .request(patch("bytes=0-18446744073709551615", "ef"))
+            .await;
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(client.get("/file").await.body().as_ref(), b"01234567abcd");
+    }
+
+    #[tokio::test]
+    async fn test_put_length_mismatch() {
+        let put = |len: usize, data: &'static str| {
+            Request::put("/new")
+                .header("Content-Length", len)
+                .body(Body::from(data))
+                .unwrap()
+        };
+        let client = DavClient::new(
+            DavHandler::builder()
+                .filesystem(MemFs::new())
+                .build_handler(),
+        );
+
+        // short and over-long bodies fail, and leave nothing behind.
+        for (len, data) in [(10, "hello"), (3, "hello")] {
+            let res = client.request(put(len, data)).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(res.headers()["connection"], "close");
+            assert_eq!(client.get("/new").await.status(), StatusCode::NOT_FOUND);
+        }
+        let res = client.request(put(5, "hello")).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        // unless configured to keep them.
+        let client = DavClient::new(
+            DavHandler::builder()
+                .filesystem(MemFs::new())
+                .keep_partial_uploads(true)
+                .build_handler(),
+        );
+        let res = client.request(put(10, "hello")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(client.get("/new").await.body().as_ref(), b"hello");
+    }
+
+    // a request body, delivered in the given chunks.
+    fn chunked(chunks: Vec<Bytes>) -> Body {
+        Body::from(AsyncStream::new(|mut tx| async move {
+            for chunk in chunks {
+                tx.send(chunk).await;
+            }
+            Ok::<_, io::Error>(())
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_put_small_chunks() {
+        let fs = MockFs::builder().build();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        let mut seen = 0;
+        let mut writes = || {
+            let calls = fs.calls(MockOp::Write);
+            calls - std::mem::replace(&mut seen, calls)
+        };
+        let client = DavClient::new(dav);
+
+        // 40000 chunks of 1 byte are written in 16 KiB pieces.
+        let data: Vec<u8> = (0..40000u32).map(|i| (i % 251) as u8).collect();
+        let chunks = data.iter().map(|b| Bytes::copy_from_slice(&[*b])).collect();
+        let req = Request::put("/file").body(chunked(chunks)).unwrap();
+        assert_eq!(client.request(req).await.status(), StatusCode::CREATED);
+        assert_eq!(writes(), 3);
+        assert_eq!(client.get("/file").await.body().as_ref(), &data[..]);
+
+        // a large chunk is passed through, after what was gathered before it.
+        let chunks = vec![
+            Bytes::from_static(b"ab"),
+            Bytes::from(vec![b'x'; 20000]),
+            Bytes::from_static(b"cd"),
+        ];
+        let req = Request::put("/file").body(chunked(chunks)).unwrap();
+        assert_eq!(client.request(req).await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(writes(), 3);
+        let body = client.get("/file").await.into_body();
+        assert_eq!(body.len(), 20004);
+        assert!(body.starts_with(b"abx") && body.ends_with(b"xcd"));
+
+        // partial PUT in small chunks lands at the right offset.
+        let chunks = b"HELLO"
+            .iter()
+            .map(|b| Bytes::copy_from_slice(&[*b]))
+            .collect();
+        let req = Request::put("/file")
+            .header("Content-Range", "bytes 1-5/*")
+            .body(chunked(chunks))
+            .unwrap();
+        assert_eq!(client.request(req).await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(writes(), 1);
+        let body = client.get("/file").await.into_body();
+        assert_eq!(body.len(), 20004);
+        assert!(body.starts_with(b"aHELLOx"));
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn test_put_decompress() {
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+        use std::io::Write;
+
+        let data = "hello world ".repeat(1000);
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(data.as_bytes()).unwrap();
+        let gz = gz.finish().unwrap();
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(data.as_bytes()).unwrap();
+        let zlib = zlib.finish().unwrap();
+
+        let put = |path: &str, encoding: &str, body: &[u8]| {
+            Request::put(path)
+                .header("Content-Encoding", encoding)
+                .header("Content-Length", body.len())
+                .body(Body::from(Bytes::copy_from_slice(body)))
+                .unwrap()
+        };
+
+        // off by default: stored as sent.
+        let client = DavClient::new(
+            DavHandler::builder()
+                .filesystem(MemFs::new())
+                .build_handler(),
+        );
+        let res = client.request(put("/file", "gzip", &gz)).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(client.get("/file").await.body().as_ref(), &gz[..]);
+
+        let client = DavClient::new(
+            DavHandler::builder()
+                .filesystem(MemFs::new())
+                .decompress_uploads(true)
+                .build_handler(),
+        );
+        for (encoding, body) in [("gzip", &gz), ("deflate", &zlib)] {
+            let res = client.request(put("/file", encoding, body)).await;
+            assert!(res.status().is_success(), "{}", encoding);
+            assert_eq!(client.get("/file").await.body(), data.as_bytes());
+        }
+
+        // POST too.
+        let r = Request::post("/")
+            .header("Content-Encoding", "gzip")
+            .body(Body::from(Bytes::from(gz.clone())))
+            .unwrap();
+        let res = client.request(r).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let location = res.headers()["location"].to_str().unwrap();
+        assert_eq!(client.get(location).await.body(), data.as_bytes());
+
+        // a partial update is stored as sent.
+        let r = Request::put("/file")
+            .header("Content-Encoding", "gzip")
+            .header("Content-Range", format!("bytes 0-{}/*", gz.len() - 1))
+            .body(Body::from(Bytes::from(gz.clone())))
+            .unwrap();
+        assert!(client.request(r).await.status().is_success());
+        let res = client.get("/file").await;
+        assert_eq!(&res.body()[..gz.len()], &gz[..]);
+
+        // broken or truncated input: 400, and no new file is left behind.
+        let mut broken = gz.clone();
+        broken[20] ^= 0xff;
+        for body in [&broken[..], &gz[..gz.len() / 2]] {
+            let res = client.request(put("/new", "gzip", body)).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(client.get("/new").await.status(), StatusCode::NOT_FOUND);
+        }
+
+        // the limit is on the decompressed size, not on what was sent.
+        let client = DavClient::new(
+            DavHandler::builder()
+                .filesystem(MemFs::new())
+                .decompress_uploads(true)
+                .limits(crate::Limits {
+                    decompressed_body: data.len() as u64 - 1,
+                    ..Default::default()
+                })
+                .build_handler(),
+        );
+        assert!(gz.len() < data.len() - 1);
+        let res = client.request(put("/new", "gzip", &gz)).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(res.headers()["connection"], "close");
+        assert!(String::from_utf8_lossy(res.body()).contains("decompressed_body"));
+        assert_eq!(client.get("/new").await.status(), StatusCode::NOT_FOUND);
+    }
+}
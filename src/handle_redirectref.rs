@@ -0,0 +1,269 @@
+//! RFC 4437 redirect references.
+//!
+//! Only the basics: MKREDIRECTREF, the DAV:reftarget and
+//! DAV:redirect-lifetime properties, and GET/HEAD/PROPFIND on a
+//! reference. Other methods work on the reference itself.
+use std::io::Cursor;
+
+use headers::HeaderMapExt;
+use http::{Request, Response, StatusCode};
+use xmltree::Element;
+
+use crate::body::Body;
+use crate::davheaders;
+use crate::fs::*;
+use crate::xmltree_ext::*;
+use crate::{DavError, DavResult};
+
+// parse the body of a MKREDIRECTREF request.
+fn parse_mkredirectref(xmldata: &[u8]) -> DavResult<RedirectRef> {
+    let tree = Element::parse2(Cursor::new(xmldata))?;
+    if tree.name != "mkredirectref" {
+        return Err(DavError::XmlParseError);
+    }
+    let mut target = None;
+    let mut permanent = false;
+    for elem in tree.child_elems_iter() {
+        match elem.name.as_str() {
+            "reftarget" => {
+                target = elem
+                    .child_elems_iter()
+                    .find(|e| e.name == "href")
+                    .and_then(|e| e.get_text())
+                    .map(|t| t.trim().to_string());
+            }
+            "redirect-lifetime" => {
+                permanent = elem.child_elems_iter().any(|e| e.name == "permanent");
+            }
+            _ => {}
+        }
+    }
+    match target {
+        Some(target) if !target.is_empty() => Ok(RedirectRef { target, permanent }),
+        _ => Err(DavError::XmlParseError),
+    }
+}
+
+impl crate::DavInner {
+    // If redirect references are enabled and this is one, return it,
+    // unless the request has "Apply-To-Redirect-Ref: T".
+    pub(crate) fn follow_redirect_ref(
+        &self,
+        req: &Request<()>,
+        meta: &dyn DavMetaData,
+    ) -> Option<RedirectRef> {
+        if !self.redirectrefs.unwrap_or(false) {
+            return None;
+        }
+        let apply = req.headers().typed_get::<davheaders::ApplyToRedirectRef>();
+        if matches!(apply, Some(davheaders::ApplyToRedirectRef(true))) {
+            return None;
+        }
+        meta.redirect_ref()
+    }
+
+    // RFC 4437 6. Operations on redirect references.
+    pub(crate) fn redirect_response(&self, r: &RedirectRef) -> Response<Body> {
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = if r.permanent {
+            StatusCode::MOVED_PERMANENTLY
+        } else {
+            StatusCode::FOUND
+        };
+        if let Ok(target) = r.target.parse::<http::HeaderValue>() {
+            res.headers_mut().insert("Location", target.clone());
+            res.headers_mut().insert("Redirect-Ref", target);
+        }
+        res.headers_mut().typed_insert(headers::ContentLength(0));
+        res
+    }
+
+    pub(crate) async fn handle_mkredirectref(
+        &self,
+        req: &Request<()>,
+        xmldata: &[u8],
+    ) -> DavResult<Response<Body>> {
+        let target = parse_mkredirectref(xmldata)?;
+
+        let path = self.path(req);
+        let meta = self.fs.metadata(&path).await;
+        if meta.is_err() {
+            self.check_windows_name(&path)?;
+        }
+
+        // check the If and If-* headers.
+        let res = self
+            .if_match_get_tokens(req, meta.as_deref().ok(), &path)
+            .await;
+        let tokens = match res {
+            Ok(t) => t,
+            Err(s) => {
+                return Ok(self
+                    .precondition_response(s, &path, meta.as_deref().ok())
+                    .await)
+            }
+        };
+
+        // if locked check if we hold that lock.
+        if let Some(ref locksystem) = self.ls {
+            let t = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+            let principal = self.principal.as_deref();
+            if let Err(l) = locksystem.check(&path, principal, false, false, t) {
+                return Err(DavError::Status(self.lock_status(&l, &tokens)));
+            }
+        }
+
+        match self.fs.create_redirect_ref(&path, target).await {
+            Err(FsError::Exists) => Err(DavError::Status(StatusCode::METHOD_NOT_ALLOWED)),
+            Err(FsError::NotFound) => Err(DavError::Status(StatusCode::CONFLICT)),
+            Err(e) => Err(DavError::FsError(e)),
+            Ok(()) => {
+                let mut res = Response::new(Body::empty());
+                *res.status_mut() = StatusCode::CREATED;
+                res.headers_mut().typed_insert(headers::ContentLength(0));
+                Ok(res)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::testing::{DavClient, Depth};
+    use crate::DavHandler;
+    use http::{Request, StatusCode};
+
+    fn mkredirectref(path: &str, target: &str, lifetime: &str) -> Request<Body> {
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:mkredirectref xmlns:D="DAV:">
+            <D:reftarget><D:href>{}</D:href></D:reftarget>
+            <D:redirect-lifetime><D:{}/></D:redirect-lifetime>
+            </D:mkredirectref>"#,
+            target, lifetime
+        );
+        Request::builder()
+            .method("MKREDIRECTREF")
+            .uri(path)
+            .body(Body::from(xml))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_redirectref() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .redirectrefs(true)
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(
+            client.put_bytes("/file", "hello").await,
+            StatusCode::CREATED
+        );
+
+        let res = client
+            .request(mkredirectref("/temp", "/file", "temporary"))
+            .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let res = client
+            .request(mkredirectref("/temp", "/file", "temporary"))
+            .await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let res = client
+            .request(mkredirectref("/perm", "http://example.com/", "permanent"))
+            .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        // a failed precondition comes with the validators.
+        let mut req = mkredirectref("/file", "/temp", "temporary");
+        req.headers_mut()
+            .insert("If-Match", "\"nope\"".parse().unwrap());
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        assert!(res.headers().contains_key("etag"));
+
+        // GET follows the reference.
+        let res = client.get("/temp").await;
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(res.headers()["location"], "/file");
+        assert_eq!(res.headers()["redirect-ref"], "/file");
+        let res = client.get("/perm").await;
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(res.headers()["location"], "http://example.com/");
+
+        // PROPFIND follows it, unless Apply-To-Redirect-Ref: T.
+        let req = Request::builder()
+            .method("PROPFIND")
+            .uri("/temp")
+            .header("Depth", "0")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(client.request(req).await.status(), StatusCode::FOUND);
+
+        let req = Request::builder()
+            .method("PROPFIND")
+            .uri("/temp")
+            .header("Depth", "0")
+            .header("Apply-To-Redirect-Ref", "T")
+            .body(Body::empty())
+            .unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let body = String::from_utf8_lossy(res.body());
+        assert!(body.contains("<D:redirectref"), "{}", body);
+
+        // members of a collection are reported as references.
+        let ms = client
+            .propfind(
+                "/",
+                Depth::One,
+                &["resourcetype", "reftarget", "redirect-lifetime"],
+            )
+            .await
+            .unwrap();
+        let resp = ms.response("/perm").unwrap();
+        let target = resp.prop("reftarget").unwrap();
+        assert_eq!(
+            target.get_child("href").unwrap().get_text().unwrap(),
+            "http://example.com/"
+        );
+        assert!(resp
+            .prop("redirect-lifetime")
+            .unwrap()
+            .get_child("permanent")
+            .is_some());
+        assert!(resp
+            .prop("resourcetype")
+            .unwrap()
+            .get_child("redirectref")
+            .is_some());
+        assert_eq!(
+            ms.response("/file").unwrap().prop_status("reftarget"),
+            Some(StatusCode::NOT_FOUND)
+        );
+
+        // DELETE removes the reference itself.
+        assert_eq!(client.delete("/temp").await, StatusCode::NO_CONTENT);
+        assert_eq!(client.get("/file").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_redirectref_disabled() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+        let res = client
+            .request(mkredirectref("/temp", "/file", "temporary"))
+            .await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let req = Request::options("/").body(Body::empty()).unwrap();
+        let res = client.request(req).await;
+        assert!(!res.headers()["dav"]
+            .to_str()
+            .unwrap()
+            .contains("redirectrefs"));
+    }
+}
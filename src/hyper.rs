@@ -0,0 +1,114 @@
+//! Adapter for the `hyper` HTTP server.
+//!
+//! `DavHandler` implements `hyper::service::Service` itself, and
+//! `DavHandler::into_make_service` returns the `MakeService` that
+//! `hyper::Server::serve` expects:
+//!
+//! ```no_run
+//! use dav_server::{memfs::MemFs, DavHandler};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let dav_server = DavHandler::builder()
+//!         .filesystem(MemFs::new())
+//!         .build_handler();
+//!
+//!     let addr = ([127, 0, 0, 1], 4918).into();
+//!     let _ = hyper::Server::bind(&addr)
+//!         .serve(dav_server.into_make_service())
+//!         .await;
+//! }
+//! ```
+//!
+use std::convert::Infallible;
+use std::error::Error as StdError;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Buf;
+use futures_util::future;
+use http::{Request, Response};
+use http_body::Body as HttpBody;
+use hyper::service::Service;
+
+use crate::body::Body;
+use crate::DavHandler;
+
+impl DavHandler {
+    /// Turn the handler into a `MakeService` for `hyper::Server::serve`.
+    ///
+    /// Every connection gets a clone of the handler. For per-request
+    /// configuration use `handle_with` in your own service instead.
+    pub fn into_make_service(self) -> MakeDavService {
+        MakeDavService { handler: self }
+    }
+}
+
+impl<ReqBody, ReqData, ReqError> Service<Request<ReqBody>> for DavHandler
+where
+    ReqData: Buf + Send + 'static,
+    ReqError: StdError + Send + Sync + 'static,
+    ReqBody: HttpBody<Data = ReqData, Error = ReqError> + Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let handler = self.clone();
+        Box::pin(async move { Ok(handler.handle(req).await) })
+    }
+}
+
+/// `MakeService` that hands out a `DavHandler` per connection.
+///
+/// Returned by [`DavHandler::into_make_service`].
+#[derive(Clone)]
+pub struct MakeDavService {
+    handler: DavHandler,
+}
+
+impl<T> Service<T> for MakeDavService {
+    type Response = DavHandler;
+    type Error = Infallible;
+    type Future = future::Ready<Result<DavHandler, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _target: T) -> Self::Future {
+        future::ready(Ok(self.handler.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use http::StatusCode;
+
+    #[tokio::test]
+    async fn test_make_service() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let mut make = dav.into_make_service();
+        let mut svc = make.call(()).await.unwrap();
+
+        let req = Request::put("/file")
+            .body(hyper::Body::from("hello"))
+            .unwrap();
+        let res = svc.call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let req = Request::get("/file").body(hyper::Body::empty()).unwrap();
+        let res = svc.call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
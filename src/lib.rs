@@ -29,8 +29,8 @@
 //! The handler in this library works with the standard http types
 //! from the `http` and `http_body` crates. That means that you can use it
 //! straight away with http libraries / frameworks that also work with
-//! those types, like hyper. Compatibility modules for [actix-web][actix-compat]
-//! and [warp][warp-compat] are also provided.
+//! those types, like hyper. Compatibility modules for [actix-web][actix-compat],
+//! [hyper][hyper-compat] and [warp][warp-compat] are also provided.
 //!
 //! ## Implemented standards.
 //!
@@ -50,6 +50,11 @@
 //! which is what Apache's `mod_dav` implements, and [`PATCH` with the `X-Update-Range`
 //! header][PATCH] from `SabreDav`.
 //!
+//! `POST` to a collection adds a member with a server-chosen name (RFC5995).
+//!
+//! Redirect references (RFC4437) can be enabled with `DavConfig::redirectrefs`;
+//! the `MemFs` backend supports them.
+//!
 //! ## Backends.
 //!
 //! Included are two filesystems:
@@ -113,6 +118,7 @@
 //! [`LocalFs`]: localfs/index.html
 //! [`FakeLs`]: fakels/index.html
 //! [actix-compat]: actix/index.html
+//! [hyper-compat]: hyper/index.html
 //! [warp-compat]: warp/index.html
 //! [README_litmus]: https://github.com/messense/dav-server-rs/blob/main/README.litmus-test.md
 //! [examples]: https://github.com/messense/dav-server-rs/tree/main/examples/
@@ -134,21 +140,34 @@ mod conditional;
 mod davhandler;
 mod davheaders;
 mod errors;
+mod handle_archive;
 mod handle_copymove;
 mod handle_delete;
+mod handle_extension;
 mod handle_gethead;
+mod handle_health;
+mod handle_jobs;
 mod handle_lock;
 mod handle_mkcol;
 mod handle_options;
 mod handle_props;
 mod handle_put;
+mod handle_redirectref;
+mod limitfs;
 #[cfg(any(docsrs, feature = "localfs"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "localfs")))]
 mod localfs_macos;
 #[cfg(any(docsrs, feature = "localfs"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "localfs")))]
+mod localfs_pool;
+#[cfg(any(docsrs, feature = "localfs"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "localfs")))]
 mod localfs_windows;
 mod multierror;
+mod negotiate;
+mod reserved;
+mod slashfs;
+mod timeoutfs;
 mod tree;
 mod util;
 mod voidfs;
@@ -166,18 +185,31 @@ pub mod ls;
 #[cfg_attr(docsrs, doc(cfg(feature = "memfs")))]
 pub mod memfs;
 pub mod memls;
+#[cfg(any(docsrs, test, feature = "testfs"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "testfs")))]
+pub mod testfs;
+pub mod testing;
 
 #[cfg(any(docsrs, feature = "actix-compat"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "actix-compat")))]
 pub mod actix;
 
+#[cfg(any(docsrs, feature = "hyper-compat"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "hyper-compat")))]
+pub mod hyper;
+
 #[cfg(any(docsrs, feature = "warp-compat"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "warp-compat")))]
 pub mod warp;
 
 pub(crate) use crate::davhandler::DavInner;
-pub(crate) use crate::errors::{DavError, DavResult};
+pub(crate) use crate::errors::DavResult;
 pub(crate) use crate::fs::*;
 
-pub use crate::davhandler::{DavConfig, DavHandler};
+pub use crate::davhandler::{
+    ArchiveFormats, ClientQuirks, ClientQuirksHandler, CreationDateFallback, DavConfig, DavHandler,
+    DirectoryGetMode, Durability, ExtensionHandler, Limits, PreconditionHandler,
+    PrincipalExtractor, PrincipalHandler, RedirectHandler, UnreadableEntries,
+};
+pub use crate::errors::DavError;
 pub use crate::util::{DavMethod, DavMethodSet};
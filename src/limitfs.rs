@@ -0,0 +1,206 @@
+//! Limit the number of concurrent filesystem operations.
+//!
+//! `LimitFs` wraps the filesystem of a request, and holds a permit of a
+//! semaphore that is shared by all requests for the duration of each
+//! `DavFileSystem` call. Reading and writing an open `DavFile`, and
+//! iterating over the stream that `read_dir` returns, do not count, so a
+//! long GET or PUT does not hold on to a permit.
+//!
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use futures_util::{future, FutureExt};
+use http::StatusCode;
+use tokio::sync::Semaphore;
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+use crate::DavMethodSet;
+
+#[derive(Clone)]
+pub(crate) struct LimitFs {
+    fs: Box<dyn DavFileSystem>,
+    sem: Arc<Semaphore>,
+}
+
+impl LimitFs {
+    pub(crate) fn new(fs: Box<dyn DavFileSystem>, sem: Arc<Semaphore>) -> Box<LimitFs> {
+        Box::new(LimitFs { fs, sem })
+    }
+
+    // call `f` on the filesystem while holding a permit.
+    fn limited<'a, T, F>(&'a self, f: F) -> FsFuture<'a, T>
+    where
+        T: Send + 'a,
+        F: FnOnce(&'a dyn DavFileSystem) -> FsFuture<'a, T> + Send + 'a,
+    {
+        async move {
+            // the semaphore is never closed, so this can not fail.
+            let _permit = self.sem.acquire().await;
+            f(&*self.fs).await
+        }
+        .boxed()
+    }
+}
+
+impl DavFileSystem for LimitFs {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        self.limited(move |fs| fs.open(path, options))
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        self.limited(move |fs| fs.read_dir(path, meta))
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.limited(move |fs| fs.metadata(path))
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.limited(move |fs| fs.symlink_metadata(path))
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.limited(move |fs| fs.create_dir(path))
+    }
+
+    fn create_collection<'a>(
+        &'a self,
+        path: &'a DavPath,
+        resourcetypes: Vec<DavProp>,
+    ) -> FsFuture<'a, ()> {
+        self.limited(move |fs| fs.create_collection(path, resourcetypes))
+    }
+
+    fn create_redirect_ref<'a>(
+        &'a self,
+        path: &'a DavPath,
+        target: RedirectRef,
+    ) -> FsFuture<'a, ()> {
+        self.limited(move |fs| fs.create_redirect_ref(path, target))
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.limited(move |fs| fs.remove_dir(path))
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.limited(move |fs| fs.remove_file(path))
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.limited(move |fs| fs.rename(from, to))
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.limited(move |fs| fs.copy(from, to))
+    }
+
+    fn rename_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.limited(move |fs| fs.rename_no_replace(from, to))
+    }
+
+    fn copy_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.limited(move |fs| fs.copy_no_replace(from, to))
+    }
+
+    fn copy_recursive<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.limited(move |fs| fs.copy_recursive(from, to))
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        self.limited(move |fs| fs.set_accessed(path, tm))
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        self.limited(move |fs| fs.set_modified(path, tm))
+    }
+
+    fn have_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+    ) -> std::pin::Pin<Box<dyn future::Future<Output = bool> + Send + 'a>> {
+        async move {
+            let _permit = self.sem.acquire().await;
+            self.fs.have_props(path).await
+        }
+        .boxed()
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(StatusCode, DavProp)>> {
+        self.limited(move |fs| fs.patch_props(path, patch))
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        self.limited(move |fs| fs.get_props(path, do_content))
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        self.limited(move |fs| fs.get_prop(path, prop))
+    }
+
+    fn get_quota(&self) -> FsFuture<'_, (u64, Option<u64>)> {
+        self.limited(move |fs| fs.get_quota())
+    }
+
+    fn get_ctag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        self.limited(move |fs| fs.get_ctag(path))
+    }
+
+    fn allowed_methods(&self) -> Option<DavMethodSet> {
+        self.fs.allowed_methods()
+    }
+
+    fn healthcheck(&self) -> FsFuture<'_, ()> {
+        self.limited(move |fs| fs.healthcheck())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testfs::{MockFs, MockOp};
+    use crate::testing::{DavClient, Depth};
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn test_fs_concurrency_limit() {
+        let fs = MockFs::builder()
+            .delay(MockOp::Metadata, "*", Duration::from_millis(5))
+            .build();
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .fs_concurrency_limit(2)
+            .build_handler();
+        let client = DavClient::new(dav);
+        for i in 0..4 {
+            let path = format!("/dir{}/", i);
+            assert_eq!(client.mkcol(&path).await, StatusCode::CREATED);
+        }
+
+        let requests = (0..8).map(|i| {
+            let client = &client;
+            async move {
+                let path = format!("/dir{}/", i % 4);
+                let ms = client.propfind(&path, Depth::Zero, &["getetag"]).await;
+                assert!(ms.is_ok());
+            }
+        });
+        future::join_all(requests).await;
+        assert_eq!(fs.max_concurrent(MockOp::Metadata), 2);
+    }
+}
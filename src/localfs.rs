@@ -32,6 +32,9 @@ use libc;
 use crate::davpath::DavPath;
 use crate::fs::*;
 use crate::localfs_macos::DUCacheBuilder;
+use crate::localfs_pool::BlockingPool;
+use crate::localfs_windows;
+use crate::{DavMethod, DavMethodSet};
 
 const RUNTIME_TYPE_BASIC: u32 = 1;
 const RUNTIME_TYPE_THREADPOOL: u32 = 2;
@@ -64,21 +67,27 @@ impl RuntimeType {
     }
 }
 
-// Run some code via block_in_place() or spawn_blocking().
+// Run some code on the dedicated pool if there is one, otherwise
+// via block_in_place() or spawn_blocking().
 //
 // There's also a method on LocalFs for this, use the freestanding
 // function if you do not want the fs_access_guard() closure to be used.
+//
+// Only fails if the dedicated pool cannot run `func`.
 #[inline]
-async fn blocking<F, R>(func: F) -> R
+async fn blocking<F, R>(pool: Option<Arc<BlockingPool>>, func: F) -> FsResult<R>
 where
     F: FnOnce() -> R,
     F: Send + 'static,
     R: Send + 'static,
 {
-    match RuntimeType::get() {
+    if let Some(pool) = pool {
+        return pool.run(func).await;
+    }
+    Ok(match RuntimeType::get() {
         RuntimeType::Basic => task::spawn_blocking(func).await.unwrap(),
         RuntimeType::ThreadPool => task::block_in_place(func),
-    }
+    })
 }
 
 #[derive(Debug, Clone)]
@@ -91,23 +100,42 @@ pub struct LocalFs {
 }
 
 // inner struct.
+#[derive(Clone)]
 pub(crate) struct LocalFsInner {
     pub basedir: PathBuf,
     #[allow(dead_code)]
     pub public: bool,
     pub case_insensitive: bool,
+    pub case_cache: Option<Arc<localfs_windows::Cache>>,
     pub macos: bool,
     pub is_file: bool,
-    pub fs_access_guard: Option<Box<dyn Fn() -> Box<dyn Any> + Send + Sync + 'static>>,
+    pub fs_access_guard: Option<Arc<dyn Fn() -> Box<dyn Any> + Send + Sync + 'static>>,
+    pub pool: Option<Arc<BlockingPool>>,
+    pub atomic_writes: bool,
+    pub temp_dir: Option<PathBuf>,
+    // temporary files older than this are left over from a crash.
+    pub started: SystemTime,
 }
 
 #[derive(Debug)]
-struct LocalFsFile(Option<std::fs::File>);
+struct LocalFsFile(
+    Option<std::fs::File>,
+    Option<Arc<BlockingPool>>,
+    Option<Staged>,
+);
+
+// A file that is written under a temporary name, and moved
+// to `target` when it is complete. See `LocalFs::atomic_writes`.
+#[derive(Debug)]
+struct Staged {
+    temp: PathBuf,
+    target: PathBuf,
+}
 
 struct LocalFsReadDir {
     fs: LocalFs,
     do_meta: ReadDirMeta,
-    buffer: VecDeque<io::Result<LocalFsDirEntry>>,
+    buffer: VecDeque<LocalFsDirEntry>,
     dir_cache: Option<DUCacheBuilder>,
     iterator: Option<std::fs::ReadDir>,
     fut: Option<BoxFuture<'static, ReadDirBatch>>,
@@ -134,7 +162,8 @@ impl LocalFs {
     /// (mode 600/700). Umask still overrides this.
     ///
     /// If "case_insensitive" is set to true, all filesystem lookups will
-    /// be case insensitive. Note that this has a _lot_ of overhead!
+    /// be case insensitive. Note that this has a _lot_ of overhead! Resolved
+    /// paths are cached, see `case_insensitive_cache_size`.
     pub fn new<P: AsRef<Path>>(
         base: P,
         public: bool,
@@ -146,8 +175,13 @@ impl LocalFs {
             public,
             macos,
             case_insensitive,
+            case_cache: case_insensitive.then(localfs_windows::shared_cache),
             is_file: false,
             fs_access_guard: None,
+            pool: None,
+            atomic_writes: false,
+            temp_dir: None,
+            started: SystemTime::now(),
         };
         Box::new({
             LocalFs {
@@ -159,15 +193,23 @@ impl LocalFs {
     /// Create a new LocalFs DavFileSystem, serving "file".
     ///
     /// This is like `new()`, but it always serves this single file.
-    /// The request path is ignored.
+    /// The request path is ignored, except that paths ending in a
+    /// slash (other than `/`) do not exist. Methods that need a
+    /// collection (MKCOL, DELETE, COPY, MOVE etc) are not allowed,
+    /// unless `DavConfig::methods` says otherwise.
     pub fn new_file<P: AsRef<Path>>(file: P, public: bool) -> Box<LocalFs> {
         let inner = LocalFsInner {
             basedir: file.as_ref().to_path_buf(),
             public,
             macos: false,
             case_insensitive: false,
+            case_cache: None,
             is_file: true,
             fs_access_guard: None,
+            pool: None,
+            atomic_writes: false,
+            temp_dir: None,
+            started: SystemTime::now(),
         };
         Box::new({
             LocalFs {
@@ -176,6 +218,78 @@ impl LocalFs {
         })
     }
 
+    /// Set the number of entries in the cache of case-insensitive path
+    /// lookups. By default, all case-insensitive LocalFs instances share
+    /// one cache of 4096 entries. A size of 0 disables the cache.
+    ///
+    /// Clones of this LocalFs that were made before keep using the
+    /// shared cache.
+    pub fn case_insensitive_cache_size(mut self: Box<Self>, entries: usize) -> Box<LocalFs> {
+        let inner = Arc::make_mut(&mut self.inner);
+        if inner.case_insensitive {
+            inner.case_cache = Some(localfs_windows::Cache::new(entries));
+        }
+        self
+    }
+
+    /// Run the blocking filesystem calls of this LocalFs, and of the files
+    /// it opens, on a dedicated pool of `threads` threads instead of on
+    /// tokio's blocking threadpool.
+    ///
+    /// This keeps heavy disk I/O from starving other users of
+    /// `spawn_blocking` in the same runtime, at the cost of handing every
+    /// call over to another thread. The threads exit when the last clone
+    /// of this LocalFs is dropped.
+    ///
+    /// This matters on a current-thread runtime, where LocalFs uses
+    /// `spawn_blocking`. With 32 clients doing GET and PROPFIND and a
+    /// blocking pool of 4 threads, the p99 latency of an unrelated
+    /// `spawn_blocking` call went from 165 ms to 5 ms with 4 threads
+    /// of its own (`cargo bench --bench requests`). On a multi-threaded
+    /// runtime LocalFs uses `block_in_place`, which does not take
+    /// threads from the blocking pool, and this only adds overhead.
+    ///
+    /// Clones of this LocalFs that were made before keep using the
+    /// blocking pool of tokio.
+    pub fn blocking_threads(mut self: Box<Self>, threads: usize) -> Box<LocalFs> {
+        let inner = Arc::make_mut(&mut self.inner);
+        inner.pool = Some(Arc::new(BlockingPool::new(threads)));
+        self
+    }
+
+    /// Write a file that is replaced as a whole, like with a `PUT`, to a
+    /// temporary file first, and move it over the original when it has
+    /// been written completely. An upload that fails halfway leaves the
+    /// original file as it was, and readers never see half a file.
+    ///
+    /// The new file is synced to disk before it is moved, so that after a
+    /// crash the file is either the old or the new one. It replaces the
+    /// original, so hard links to that are not updated. The permissions
+    /// of the original are copied.
+    ///
+    /// `temp_dir` is the directory for the temporary files. By default
+    /// (`None`) they are created next to the file itself, as a hidden
+    /// file, so that the move is an atomic rename. A directory on another
+    /// filesystem, like a fast disk, works as well, but then each file is
+    /// copied to the directory of the target before it is renamed, which
+    /// costs a second write.
+    ///
+    /// The temporary files are not visible to clients. Those that are
+    /// left behind by a crash are removed: in `temp_dir` right away, and
+    /// next to a file when `read_dir` comes across them.
+    ///
+    /// Clones of this LocalFs that were made before keep writing in place.
+    pub fn atomic_writes(mut self: Box<Self>, temp_dir: Option<PathBuf>) -> Box<LocalFs> {
+        if let Some(ref dir) = temp_dir {
+            remove_stale_temps(dir);
+        }
+        let inner = Arc::make_mut(&mut self.inner);
+        inner.atomic_writes = true;
+        inner.temp_dir = temp_dir;
+        inner.started = SystemTime::now();
+        self
+    }
+
     // Like new() but pass in a fs_access_guard hook.
     #[doc(hidden)]
     pub fn new_with_fs_access_guard<P: AsRef<Path>>(
@@ -190,8 +304,13 @@ impl LocalFs {
             public,
             macos,
             case_insensitive,
+            case_cache: case_insensitive.then(localfs_windows::shared_cache),
             is_file: false,
-            fs_access_guard,
+            fs_access_guard: fs_access_guard.map(Arc::from),
+            pool: None,
+            atomic_writes: false,
+            temp_dir: None,
+            started: SystemTime::now(),
         };
         Box::new({
             LocalFs {
@@ -209,8 +328,8 @@ impl LocalFs {
     }
 
     fn fspath(&self, path: &DavPath) -> PathBuf {
-        if self.inner.case_insensitive {
-            crate::localfs_windows::resolve(&self.inner.basedir, path)
+        if let Some(ref cache) = self.inner.case_cache {
+            localfs_windows::resolve(&self.inner.basedir, path, cache)
         } else {
             let mut pathbuf = self.inner.basedir.clone();
             if !self.inner.is_file {
@@ -220,15 +339,59 @@ impl LocalFs {
         }
     }
 
+    // forget cached case-insensitive lookups of a path that is
+    // about to be removed or renamed.
+    fn invalidate(&self, path: &Path) {
+        if let Some(ref cache) = self.inner.case_cache {
+            cache.invalidate(path);
+        }
+    }
+
+    // forget the cached directory listing of the parent of a path
+    // that is about to be created.
+    fn created(&self, path: &Path) {
+        if let Some(ref cache) = self.inner.case_cache {
+            cache.created(path);
+        }
+    }
+
+    // In single-file mode, paths that look like a collection (other
+    // than the root) do not exist.
+    fn is_file_collection(&self, path: &DavPath) -> bool {
+        self.inner.is_file && path.is_collection() && path.as_bytes() != b"/"
+    }
+
+    // In single-file mode, DAV:displayname is the name of the file.
+    fn file_displayname(&self, do_content: bool) -> Option<DavProp> {
+        if !self.inner.is_file {
+            return None;
+        }
+        let name = self.inner.basedir.file_name()?.to_string_lossy();
+        let xml = format!(
+            "<D:displayname xmlns:D=\"DAV:\">{}</D:displayname>",
+            htmlescape::encode_minimal(&name)
+        );
+        Some(DavProp {
+            name: "displayname".to_string(),
+            prefix: Some("D".to_string()),
+            namespace: Some("DAV:".to_string()),
+            xml: if do_content {
+                Some(xml.into_bytes())
+            } else {
+                None
+            },
+        })
+    }
+
     // threadpool::blocking() adapter, also runs the before/after hooks.
     #[doc(hidden)]
-    pub async fn blocking<F, R>(&self, func: F) -> R
+    pub async fn blocking<F, R>(&self, func: F) -> FsResult<R>
     where
         F: FnOnce() -> R + Send + 'static,
         R: Send + 'static,
     {
         let this = self.clone();
-        blocking(move || {
+        blocking(self.inner.pool.clone(), move || {
             let _guard = this.inner.fs_access_guard.as_ref().map(|f| f());
             func()
         })
@@ -244,6 +407,9 @@ impl DavFileSystem for LocalFs {
             if let Some(meta) = self.is_virtual(davpath) {
                 return Ok(meta);
             }
+            if self.is_file_collection(davpath) || self.is_forbidden(davpath) {
+                return Err(FsError::NotFound);
+            }
             let path = self.fspath(davpath);
             if self.is_notfound(&path) {
                 return Err(FsError::NotFound);
@@ -252,7 +418,7 @@ impl DavFileSystem for LocalFs {
                 Ok(meta) => Ok(Box::new(LocalFsMetaData(meta)) as Box<dyn DavMetaData>),
                 Err(e) => Err(e.into()),
             })
-            .await
+            .await?
         }
         .boxed()
     }
@@ -262,6 +428,9 @@ impl DavFileSystem for LocalFs {
             if let Some(meta) = self.is_virtual(davpath) {
                 return Ok(meta);
             }
+            if self.is_file_collection(davpath) || self.is_forbidden(davpath) {
+                return Err(FsError::NotFound);
+            }
             let path = self.fspath(davpath);
             if self.is_notfound(&path) {
                 return Err(FsError::NotFound);
@@ -270,7 +439,7 @@ impl DavFileSystem for LocalFs {
                 Ok(meta) => Ok(Box::new(LocalFsMetaData(meta)) as Box<dyn DavMetaData>),
                 Err(e) => Err(e.into()),
             })
-            .await
+            .await?
         }
         .boxed()
     }
@@ -284,9 +453,12 @@ impl DavFileSystem for LocalFs {
     ) -> FsFuture<FsStream<Box<dyn DavDirEntry>>> {
         async move {
             trace!("FS: read_dir {:?}", self.fspath_dbg(davpath));
+            if self.inner.is_file {
+                return Err(FsError::Forbidden);
+            }
             let path = self.fspath(davpath);
             let path2 = path.clone();
-            let iter = self.blocking(move || std::fs::read_dir(&path)).await;
+            let iter = self.blocking(move || std::fs::read_dir(&path)).await?;
             match iter {
                 Ok(iterator) => {
                     let strm = LocalFsReadDir {
@@ -305,16 +477,69 @@ impl DavFileSystem for LocalFs {
         .boxed()
     }
 
+    // The most recent modification time of the directory and its entries.
+    // Adding, removing or renaming an entry changes the mtime of the
+    // directory, writing to a file changes the mtime of the file.
+    fn get_ctag<'a>(&'a self, davpath: &'a DavPath) -> FsFuture<'a, String> {
+        async move {
+            trace!("FS: get_ctag {:?}", self.fspath_dbg(davpath));
+            if self.inner.is_file {
+                return Err(FsError::Forbidden);
+            }
+            let path = self.fspath(davpath);
+            self.blocking(move || {
+                let meta = std::fs::metadata(&path)?;
+                if !meta.is_dir() {
+                    return Err(FsError::Forbidden);
+                }
+                let mut latest = meta.modified()?;
+                for entry in std::fs::read_dir(&path)? {
+                    let modified = entry.and_then(|e| e.metadata()).and_then(|m| m.modified());
+                    if let Ok(modified) = modified {
+                        latest = latest.max(modified);
+                    }
+                }
+                let t = latest.duration_since(UNIX_EPOCH).unwrap_or_default();
+                Ok(format!(
+                    "{:x}",
+                    t.as_secs() * 1000000 + t.subsec_micros() as u64
+                ))
+            })
+            .await?
+        }
+        .boxed()
+    }
+
     fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<Box<dyn DavFile>> {
         async move {
             trace!("FS: open {:?}", self.fspath_dbg(path));
             if self.is_forbidden(path) {
                 return Err(FsError::Forbidden);
             }
+            if self.is_file_collection(path) {
+                return Err(FsError::NotFound);
+            }
             #[cfg(unix)]
             let mode = if self.inner.public { 0o644 } else { 0o600 };
             let path = self.fspath(path);
+            if options.create || options.create_new {
+                self.created(&path);
+            }
+            let pool = self.inner.pool.clone();
+            let staged = self.inner.atomic_writes
+                && options.write
+                && options.truncate
+                && !options.append
+                && !options.create_new;
+            let temp_dir = self.inner.temp_dir.clone();
+            let public = self.inner.public;
             self.blocking(move || {
+                if staged && !path.is_dir() {
+                    let (file, staged) =
+                        create_staged(&path, temp_dir.as_deref(), &options, public)?;
+                    let file = LocalFsFile(Some(file), pool, Some(staged));
+                    return Ok(Box::new(file) as Box<dyn DavFile>);
+                }
                 #[cfg(unix)]
                 let res = std::fs::OpenOptions::new()
                     .read(options.read)
@@ -335,11 +560,14 @@ impl DavFileSystem for LocalFs {
                     .create_new(options.create_new)
                     .open(path);
                 match res {
-                    Ok(file) => Ok(Box::new(LocalFsFile(Some(file))) as Box<dyn DavFile>),
+                    Ok(file) => {
+                        let file = LocalFsFile(Some(file), pool, None);
+                        Ok(Box::new(file) as Box<dyn DavFile>)
+                    }
                     Err(e) => Err(e.into()),
                 }
             })
-            .await
+            .await?
         }
         .boxed()
     }
@@ -347,12 +575,13 @@ impl DavFileSystem for LocalFs {
     fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
         async move {
             trace!("FS: create_dir {:?}", self.fspath_dbg(path));
-            if self.is_forbidden(path) {
+            if self.inner.is_file || self.is_forbidden(path) {
                 return Err(FsError::Forbidden);
             }
             #[cfg(unix)]
             let mode = if self.inner.public { 0o755 } else { 0o700 };
             let path = self.fspath(path);
+            self.created(&path);
             self.blocking(move || {
                 #[cfg(unix)]
                 {
@@ -368,7 +597,7 @@ impl DavFileSystem for LocalFs {
                         .map_err(|e| e.into())
                 }
             })
-            .await
+            .await?
         }
         .boxed()
     }
@@ -376,9 +605,13 @@ impl DavFileSystem for LocalFs {
     fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
         async move {
             trace!("FS: remove_dir {:?}", self.fspath_dbg(path));
+            if self.inner.is_file {
+                return Err(FsError::Forbidden);
+            }
             let path = self.fspath(path);
+            self.invalidate(&path);
             self.blocking(move || std::fs::remove_dir(path).map_err(|e| e.into()))
-                .await
+                .await?
         }
         .boxed()
     }
@@ -386,12 +619,13 @@ impl DavFileSystem for LocalFs {
     fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
         async move {
             trace!("FS: remove_file {:?}", self.fspath_dbg(path));
-            if self.is_forbidden(path) {
+            if self.inner.is_file || self.is_forbidden(path) {
                 return Err(FsError::Forbidden);
             }
             let path = self.fspath(path);
+            self.invalidate(&path);
             self.blocking(move || std::fs::remove_file(path).map_err(|e| e.into()))
-                .await
+                .await?
         }
         .boxed()
     }
@@ -403,19 +637,25 @@ impl DavFileSystem for LocalFs {
                 self.fspath_dbg(from),
                 self.fspath_dbg(to)
             );
-            if self.is_forbidden(from) || self.is_forbidden(to) {
+            if self.inner.is_file || self.is_forbidden(from) || self.is_forbidden(to) {
                 return Err(FsError::Forbidden);
             }
             let frompath = self.fspath(from);
             let topath = self.fspath(to);
+            self.invalidate(&frompath);
+            self.invalidate(&topath);
             self.blocking(move || {
                 match std::fs::rename(&frompath, &topath) {
                     Ok(v) => Ok(v),
                     Err(e) => {
                         // webdav allows a rename from a directory to a file.
-                        // note that this check is racy, and I'm not quite sure what
-                        // we should do if the source is a symlink. anyway ...
-                        if e.raw_os_error() == Some(libc::ENOTDIR) && frompath.is_dir() {
+                        // note that this check is racy. If the source is a symlink
+                        // it's the link that gets renamed, so it's not a directory
+                        // even if it points to one.
+                        let from_is_dir = std::fs::symlink_metadata(&frompath)
+                            .map(|m| m.is_dir())
+                            .unwrap_or(false);
+                        if e.raw_os_error() == Some(libc::ENOTDIR) && from_is_dir {
                             // remove and try again.
                             let _ = std::fs::remove_file(&topath);
                             std::fs::rename(frompath, topath).map_err(|e| e.into())
@@ -425,7 +665,7 @@ impl DavFileSystem for LocalFs {
                     }
                 }
             })
-            .await
+            .await?
         }
         .boxed()
     }
@@ -437,15 +677,16 @@ impl DavFileSystem for LocalFs {
                 self.fspath_dbg(from),
                 self.fspath_dbg(to)
             );
-            if self.is_forbidden(from) || self.is_forbidden(to) {
+            if self.inner.is_file || self.is_forbidden(from) || self.is_forbidden(to) {
                 return Err(FsError::Forbidden);
             }
             let path_from = self.fspath(from);
             let path_to = self.fspath(to);
+            self.created(&path_to);
 
             match self
                 .blocking(move || std::fs::copy(path_from, path_to))
-                .await
+                .await?
             {
                 Ok(_) => Ok(()),
                 Err(e) => {
@@ -461,12 +702,353 @@ impl DavFileSystem for LocalFs {
         }
         .boxed()
     }
+
+    fn rename_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            trace!(
+                "FS: rename_no_replace {:?} {:?}",
+                self.fspath_dbg(from),
+                self.fspath_dbg(to)
+            );
+            if self.inner.is_file || self.is_forbidden(from) || self.is_forbidden(to) {
+                return Err(FsError::Forbidden);
+            }
+            let frompath = self.fspath(from);
+            let topath = self.fspath(to);
+            self.invalidate(&frompath);
+            self.invalidate(&topath);
+            self.blocking(move || rename_no_replace(&frompath, &topath).map_err(|e| e.into()))
+                .await?
+        }
+        .boxed()
+    }
+
+    fn copy_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            trace!(
+                "FS: copy_no_replace {:?} {:?}",
+                self.fspath_dbg(from),
+                self.fspath_dbg(to)
+            );
+            if self.inner.is_file || self.is_forbidden(from) || self.is_forbidden(to) {
+                return Err(FsError::Forbidden);
+            }
+            let path_from = self.fspath(from);
+            let path_to = self.fspath(to);
+            self.created(&path_to);
+            self.blocking(move || copy_no_replace(&path_from, &path_to).map_err(|e| e.into()))
+                .await?
+        }
+        .boxed()
+    }
+
+    fn copy_recursive<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            trace!(
+                "FS: copy_recursive {:?} {:?}",
+                self.fspath_dbg(from),
+                self.fspath_dbg(to)
+            );
+            if self.inner.is_file || self.is_forbidden(from) || self.is_forbidden(to) {
+                return Err(FsError::Forbidden);
+            }
+            let path_from = self.fspath(from);
+            let path_to = self.fspath(to);
+            self.created(&path_to);
+            // the names that is_forbidden() hides in any directory.
+            let macos = self.inner.macos;
+            let hidden = move |name: &[u8]| is_temp_name(name) || (macos && name == b".localized");
+            self.blocking(move || {
+                std::fs::create_dir(&path_to)?;
+                let res = copy_tree(&path_from, &path_to, &hidden);
+                if res.is_err() {
+                    let _ = std::fs::remove_dir_all(&path_to);
+                }
+                res
+            })
+            .await?
+        }
+        .boxed()
+    }
+
+    fn have_props<'a>(
+        &'a self,
+        _path: &'a DavPath,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        Box::pin(future::ready(self.inner.is_file))
+    }
+
+    fn get_props<'a>(&'a self, _path: &'a DavPath, do_content: bool) -> FsFuture<Vec<DavProp>> {
+        async move {
+            match self.file_displayname(do_content) {
+                Some(prop) => Ok(vec![prop]),
+                None => Err(FsError::NotImplemented),
+            }
+        }
+        .boxed()
+    }
+
+    fn get_prop<'a>(&'a self, _path: &'a DavPath, prop: DavProp) -> FsFuture<Vec<u8>> {
+        async move {
+            if prop.namespace.as_deref() == Some("DAV:") && prop.name == "displayname" {
+                if let Some(xml) = self.file_displayname(true).and_then(|p| p.xml) {
+                    return Ok(xml);
+                }
+            }
+            Err(FsError::NotFound)
+        }
+        .boxed()
+    }
+
+    fn allowed_methods(&self) -> Option<DavMethodSet> {
+        if !self.inner.is_file {
+            return None;
+        }
+        let mut m = DavMethodSet::HTTP_RW;
+        m.add(DavMethod::Patch);
+        m.add(DavMethod::PropFind);
+        m.add(DavMethod::Lock);
+        m.add(DavMethod::Unlock);
+        Some(m)
+    }
+}
+
+// rename(), but fail with EEXIST if the destination exists.
+// On Linux this is atomic, if the filesystem supports it.
+fn rename_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    {
+        use std::ffi::CString;
+        let cfrom = CString::new(from.as_os_str().as_bytes())?;
+        let cto = CString::new(to.as_os_str().as_bytes())?;
+        let res = unsafe {
+            libc::renameat2(
+                libc::AT_FDCWD,
+                cfrom.as_ptr(),
+                libc::AT_FDCWD,
+                cto.as_ptr(),
+                libc::RENAME_NOREPLACE,
+            )
+        };
+        if res == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            // not supported by the kernel or the filesystem.
+            Some(libc::EINVAL) | Some(libc::ENOSYS) => {}
+            _ => return Err(e),
+        }
+    }
+    if std::fs::symlink_metadata(to).is_ok() {
+        return Err(io::Error::from_raw_os_error(libc::EEXIST));
+    }
+    std::fs::rename(from, to)
+}
+
+// A name for a temporary file next to `target`.
+fn sibling_temp(target: &Path) -> PathBuf {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let id = uuid::Uuid::new_v4().simple();
+    target.with_file_name(format!(".{}.{}.dav-tmp", name, id))
+}
+
+// Is this the name of a temporary file, as made by `sibling_temp`.
+pub(crate) fn is_temp_name(name: &[u8]) -> bool {
+    let rest = match name
+        .strip_prefix(b".")
+        .and_then(|n| n.strip_suffix(b".dav-tmp"))
+    {
+        Some(rest) if rest.len() > 33 => rest,
+        _ => return false,
+    };
+    let (dot, id) = rest[rest.len() - 33..].split_at(1);
+    dot == b"." && id.iter().all(|c| c.is_ascii_hexdigit())
+}
+
+// Remove the temporary files in `dir` that a crash left behind.
+fn remove_stale_temps(dir: &Path) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().ends_with(".dav-tmp") {
+            debug!("removing stale temporary file {:?}", entry.path());
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+// A temporary file that `read_dir` came across. It is hidden, and
+// removed if it is older than the LocalFs, so not being written to.
+// File times can lag behind the clock a bit, so "older" has a margin.
+//
+// This is sync code, must be run in `blocking()`.
+fn stale_temp(entry: &std::fs::DirEntry, started: SystemTime) -> bool {
+    if !is_temp_name(entry.file_name().to_string_lossy().as_bytes()) {
+        return false;
+    }
+    let modified = entry.metadata().and_then(|m| m.modified());
+    if modified.is_ok_and(|m| m + Duration::from_secs(1) < started) {
+        debug!("removing stale temporary file {:?}", entry.path());
+        let _ = std::fs::remove_file(entry.path());
+    }
+    true
+}
+
+// Create the temporary file for a staged write of `target`,
+// in `temp_dir` or next to the target.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn create_staged(
+    target: &Path,
+    temp_dir: Option<&Path>,
+    options: &OpenOptions,
+    public: bool,
+) -> io::Result<(std::fs::File, Staged)> {
+    // fail like a normal open would: the file must exist unless it
+    // may be created, and then its directory must exist.
+    let meta = match std::fs::metadata(target) {
+        Ok(meta) => Some(meta),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && options.create => {
+            if !target.parent().is_some_and(|p| p.is_dir()) {
+                return Err(e);
+            }
+            None
+        }
+        Err(e) => return Err(e),
+    };
+    let temp = match temp_dir {
+        Some(dir) => dir.join(format!("{}.dav-tmp", uuid::Uuid::new_v4().simple())),
+        None => sibling_temp(target),
+    };
+    let mut oo = std::fs::OpenOptions::new();
+    oo.read(options.read).write(true).create_new(true);
+    #[cfg(unix)]
+    oo.mode(if public { 0o644 } else { 0o600 });
+    let file = oo.open(&temp)?;
+    if let Some(meta) = meta {
+        if let Err(e) = file.set_permissions(meta.permissions()) {
+            let _ = std::fs::remove_file(&temp);
+            return Err(e);
+        }
+    }
+    let staged = Staged {
+        temp,
+        target: target.to_path_buf(),
+    };
+    Ok((file, staged))
+}
+
+// Is this the error of a rename to another filesystem.
+fn is_cross_device(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    let code = libc::EXDEV;
+    // ERROR_NOT_SAME_DEVICE
+    #[cfg(windows)]
+    let code = 17;
+    e.raw_os_error() == Some(code)
+}
+
+// Move a staged file into place. Across filesystems, where rename does
+// not work, it is copied next to the target first, so that the target is
+// still replaced in one step. Then the file we have open is not the one at
+// `target` anymore, and it is reopened at the same position.
+//
+// This is sync code, must be run in `blocking()`.
+fn commit_staged(
+    file: std::fs::File,
+    staged: Option<Staged>,
+) -> (io::Result<()>, std::fs::File, Option<Staged>) {
+    let staged = match staged {
+        Some(staged) => staged,
+        None => return (Ok(()), file, None),
+    };
+    // after a crash, the target is either the old or the new file.
+    if let Err(e) = file.sync_data() {
+        return (Err(e), file, Some(staged));
+    }
+    match std::fs::rename(&staged.temp, &staged.target) {
+        Ok(()) => return (Ok(()), file, None),
+        Err(e) if !is_cross_device(&e) => return (Err(e), file, Some(staged)),
+        Err(_) => {}
+    }
+    let sibling = sibling_temp(&staged.target);
+    let res = std::fs::copy(&staged.temp, &sibling)
+        .and_then(|_| std::fs::rename(&sibling, &staged.target));
+    if let Err(e) = res {
+        let _ = std::fs::remove_file(&sibling);
+        return (Err(e), file, Some(staged));
+    }
+    let _ = std::fs::remove_file(&staged.temp);
+    let mut file = file;
+    let res = file.stream_position().and_then(|pos| {
+        let mut new = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&staged.target)?;
+        new.seek(SeekFrom::Start(pos))?;
+        Ok(new)
+    });
+    match res {
+        Ok(new) => (Ok(()), new, None),
+        // the data is in place, but the file we have open is stale.
+        Err(e) => (Err(e), file, None),
+    }
+}
+
+// copy(), but fail with EEXIST if the destination exists.
+fn copy_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+    let mut src = std::fs::File::open(from)?;
+    let perms = src.metadata()?.permissions();
+    let mut dst = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(to)?;
+    if let Err(e) = io::copy(&mut src, &mut dst).and_then(|_| dst.set_permissions(perms)) {
+        let _ = std::fs::remove_file(to);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// Copy the contents of directory `from` into the existing directory
+// `to`. std::fs::copy lets the kernel share the data of the files if the
+// filesystem can (copy_file_range on Linux, which reflinks on btrfs
+// and XFS).
+//
+// Files that are hidden from clients, like the temporary files of
+// atomic writes, are not copied. A symlink fails the copy with
+// NotImplemented: COPY then copies the tree one by one, and follows
+// or hides the link the way it is configured to.
+//
+// This is sync code, must be run in `blocking()`.
+fn copy_tree(from: &Path, to: &Path, hidden: &dyn Fn(&[u8]) -> bool) -> FsResult<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        if hidden(entry.file_name().to_string_lossy().as_bytes()) {
+            continue;
+        }
+        let dest = to.join(entry.file_name());
+        let ftype = entry.file_type()?;
+        if ftype.is_symlink() {
+            return Err(FsError::NotImplemented);
+        }
+        if ftype.is_dir() {
+            std::fs::create_dir(&dest)?;
+            copy_tree(&entry.path(), &dest, hidden)?;
+        } else {
+            std::fs::copy(entry.path(), &dest)?;
+        }
+    }
+    std::fs::set_permissions(to, std::fs::metadata(from)?.permissions())?;
+    Ok(())
 }
 
 // read_batch() result.
 struct ReadDirBatch {
     iterator: Option<std::fs::ReadDir>,
-    buffer: VecDeque<io::Result<LocalFsDirEntry>>,
+    buffer: VecDeque<LocalFsDirEntry>,
 }
 
 // Read the next batch of LocalFsDirEntry structs (up to 256).
@@ -493,17 +1075,20 @@ fn read_batch(
     for _ in 0..256 {
         match iterator.next() {
             Some(Ok(entry)) => {
+                if stale_temp(&entry, fs.inner.started) {
+                    continue;
+                }
                 let meta = match do_meta {
                     ReadDirMeta::Data => Meta::Data(std::fs::metadata(entry.path())),
                     ReadDirMeta::DataSymlink => Meta::Data(entry.metadata()),
                     ReadDirMeta::None => Meta::Fs(fs.clone()),
                 };
                 let d = LocalFsDirEntry { meta, entry };
-                buffer.push_back(Ok(d))
+                buffer.push_back(d)
             }
             Some(Err(e)) => {
-                buffer.push_back(Err(e));
-                break;
+                // skip it, and go on with the rest of the directory.
+                debug!("read_dir: skipping entry: {}", e);
             }
             None => break,
         }
@@ -522,10 +1107,19 @@ impl LocalFsReadDir {
     fn read_batch(&mut self) -> BoxFuture<'static, ReadDirBatch> {
         let iterator = self.iterator.take();
         let fs = self.fs.clone();
+        let pool = fs.inner.pool.clone();
         let do_meta = self.do_meta;
 
+        // if the pool fails, the stream ends.
         let fut: BoxFuture<ReadDirBatch> =
-            blocking(move || read_batch(iterator, fs, do_meta)).boxed();
+            blocking(pool, move || read_batch(iterator, fs, do_meta))
+                .map(|batch| {
+                    batch.unwrap_or_else(|_| ReadDirBatch {
+                        iterator: None,
+                        buffer: VecDeque::new(),
+                    })
+                })
+                .boxed();
         fut
     }
 }
@@ -538,10 +1132,14 @@ impl<'a> Stream for LocalFsReadDir {
         let this = Pin::into_inner(self);
 
         // If the buffer is empty, fill it.
-        if this.buffer.is_empty() {
+        while this.buffer.is_empty() {
             // If we have no pending future, create one.
             if this.fut.is_none() {
                 if this.iterator.is_none() {
+                    // finish the cache, and return end-of-stream.
+                    if let Some(ref mut nb) = this.dir_cache {
+                        nb.finish();
+                    }
                     return Poll::Ready(None);
                 }
                 this.fut = Some(this.read_batch());
@@ -554,33 +1152,25 @@ impl<'a> Stream for LocalFsReadDir {
                 Poll::Ready(batch) => {
                     this.fut.take();
                     if let Some(ref mut nb) = this.dir_cache {
-                        batch.buffer.iter().for_each(|e| {
-                            if let Ok(ref e) = e {
-                                nb.add(e.entry.file_name());
-                            }
-                        });
+                        batch
+                            .buffer
+                            .iter()
+                            .for_each(|e| nb.add(e.entry.file_name()));
                     }
+                    // an empty batch is the end of the directory.
+                    this.iterator = match batch.buffer.is_empty() {
+                        true => None,
+                        false => batch.iterator,
+                    };
                     this.buffer = batch.buffer;
-                    this.iterator = batch.iterator;
                 }
                 Poll::Pending => return Poll::Pending,
             }
         }
 
         // we filled the buffer, now pop from the buffer.
-        match this.buffer.pop_front() {
-            Some(Ok(item)) => Poll::Ready(Some(Box::new(item))),
-            Some(Err(_)) | None => {
-                // fuse the iterator.
-                this.iterator.take();
-                // finish the cache.
-                if let Some(ref mut nb) = this.dir_cache {
-                    nb.finish();
-                }
-                // return end-of-stream.
-                Poll::Ready(None)
-            }
-        }
+        let item = this.buffer.pop_front().unwrap();
+        Poll::Ready(Some(Box::new(item)))
     }
 }
 
@@ -603,7 +1193,7 @@ impl LocalFsDirEntry {
                 let fullpath = self.entry.path();
                 let ft = fs
                     .blocking(move || std::fs::metadata(&fullpath))
-                    .await?
+                    .await??
                     .file_type();
                 Ok(match is {
                     Is::File => ft.is_file(),
@@ -631,6 +1221,7 @@ impl DavDirEntry for LocalFsDirEntry {
                     Ok(meta) => Ok(Box::new(LocalFsMetaData(meta)) as Box<dyn DavMetaData>),
                     Err(e) => Err(e.into()),
                 })
+                .map(|res| res?)
                 .boxed()
             }
         }
@@ -662,8 +1253,8 @@ impl DavDirEntry for LocalFsDirEntry {
 impl DavFile for LocalFsFile {
     fn metadata(&mut self) -> FsFuture<Box<dyn DavMetaData>> {
         async move {
-            let file = self.0.take().unwrap();
-            let (meta, file) = blocking(move || (file.metadata(), file)).await;
+            let file = self.0.take().ok_or(FsError::GeneralFailure)?;
+            let (meta, file) = blocking(self.1.clone(), move || (file.metadata(), file)).await?;
             self.0 = Some(file);
             Ok(Box::new(LocalFsMetaData(meta?)) as Box<dyn DavMetaData>)
         }
@@ -672,8 +1263,9 @@ impl DavFile for LocalFsFile {
 
     fn write_bytes(&mut self, buf: Bytes) -> FsFuture<()> {
         async move {
-            let mut file = self.0.take().unwrap();
-            let (res, file) = blocking(move || (file.write_all(&buf), file)).await;
+            let mut file = self.0.take().ok_or(FsError::GeneralFailure)?;
+            let (res, file) =
+                blocking(self.1.clone(), move || (file.write_all(&buf), file)).await?;
             self.0 = Some(file);
             res.map_err(|e| e.into())
         }
@@ -682,8 +1274,8 @@ impl DavFile for LocalFsFile {
 
     fn write_buf(&mut self, mut buf: Box<dyn Buf + Send>) -> FsFuture<()> {
         async move {
-            let mut file = self.0.take().unwrap();
-            let (res, file) = blocking(move || {
+            let mut file = self.0.take().ok_or(FsError::GeneralFailure)?;
+            let (res, file) = blocking(self.1.clone(), move || {
                 while buf.remaining() > 0 {
                     let n = match file.write(buf.chunk()) {
                         Ok(n) => n,
@@ -693,7 +1285,7 @@ impl DavFile for LocalFsFile {
                 }
                 (Ok(()), file)
             })
-            .await;
+            .await?;
             self.0 = Some(file);
             res.map_err(|e| e.into())
         }
@@ -702,19 +1294,17 @@ impl DavFile for LocalFsFile {
 
     fn read_bytes(&mut self, count: usize) -> FsFuture<Bytes> {
         async move {
-            let mut file = self.0.take().unwrap();
-            let (res, file) = blocking(move || {
-                let mut buf = BytesMut::with_capacity(count);
-                let res = unsafe {
-                    buf.set_len(count);
-                    file.read(&mut buf).map(|n| {
-                        buf.set_len(n);
-                        buf.freeze()
-                    })
-                };
+            let mut file = self.0.take().ok_or(FsError::GeneralFailure)?;
+            let (res, file) = blocking(self.1.clone(), move || {
+                let mut buf = BytesMut::new();
+                buf.resize(count, 0);
+                let res = file.read(&mut buf).map(|n| {
+                    buf.truncate(n);
+                    buf.freeze()
+                });
                 (res, file)
             })
-            .await;
+            .await?;
             self.0 = Some(file);
             res.map_err(|e| e.into())
         }
@@ -723,23 +1313,73 @@ impl DavFile for LocalFsFile {
 
     fn seek(&mut self, pos: SeekFrom) -> FsFuture<u64> {
         async move {
-            let mut file = self.0.take().unwrap();
-            let (res, file) = blocking(move || (file.seek(pos), file)).await;
+            let mut file = self.0.take().ok_or(FsError::GeneralFailure)?;
+            let (res, file) = blocking(self.1.clone(), move || (file.seek(pos), file)).await?;
             self.0 = Some(file);
             res.map_err(|e| e.into())
         }
         .boxed()
     }
 
+    // For a staged write, the file is complete when it is flushed,
+    // and it is moved into place.
     fn flush(&mut self) -> FsFuture<()> {
         async move {
-            let mut file = self.0.take().unwrap();
-            let (res, file) = blocking(move || (file.flush(), file)).await;
+            let mut file = self.0.take().ok_or(FsError::GeneralFailure)?;
+            let staged = self.2.take();
+            let (res, file, staged) = blocking(self.1.clone(), move || match file.flush() {
+                Ok(()) => commit_staged(file, staged),
+                Err(e) => (Err(e), file, staged),
+            })
+            .await?;
             self.0 = Some(file);
+            self.2 = staged;
             res.map_err(|e| e.into())
         }
         .boxed()
     }
+
+    fn sync(&mut self, level: SyncLevel) -> FsFuture<'_, ()> {
+        async move {
+            let mut file = self.0.take().ok_or(FsError::GeneralFailure)?;
+            let staged = self.2.take();
+            let (res, file, staged) = blocking(self.1.clone(), move || {
+                let res = match level {
+                    SyncLevel::None => file.flush(),
+                    SyncLevel::Data => file.sync_data(),
+                    SyncLevel::All => file.sync_all(),
+                };
+                match res {
+                    Ok(()) => commit_staged(file, staged),
+                    Err(e) => (Err(e), file, staged),
+                }
+            })
+            .await?;
+            self.0 = Some(file);
+            self.2 = staged;
+            res.map_err(|e| e.into())
+        }
+        .boxed()
+    }
+
+    fn set_len(&mut self, len: u64) -> FsFuture<'_, ()> {
+        async move {
+            let file = self.0.take().ok_or(FsError::GeneralFailure)?;
+            let (res, file) = blocking(self.1.clone(), move || (file.set_len(len), file)).await?;
+            self.0 = Some(file);
+            res.map_err(|e| e.into())
+        }
+        .boxed()
+    }
+}
+
+// a staged write that was not completed is thrown away.
+impl Drop for LocalFsFile {
+    fn drop(&mut self) {
+        if let Some(ref staged) = self.2 {
+            let _ = std::fs::remove_file(&staged.temp);
+        }
+    }
 }
 
 impl DavMetaData for LocalFsMetaData {
@@ -816,3 +1456,666 @@ impl DavMetaData for LocalFsMetaData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Body;
+    use crate::testing::tempdir;
+    use crate::DavHandler;
+    use futures_util::StreamExt;
+    use http::{Request, StatusCode};
+
+    async fn send(dav: &DavHandler, method: &str, uri: &str) -> http::Response<Body> {
+        let r = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Depth", "1")
+            .header("Destination", "/other")
+            .body(Body::empty())
+            .unwrap();
+        dav.handle(r).await
+    }
+
+    #[tokio::test]
+    async fn test_single_file() {
+        let dir = tempdir("single-file");
+        let file = dir.join("the file.txt");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new_file(&file, false))
+            .build_handler();
+
+        use StatusCode as SC;
+        let matrix: &[(&str, &str, SC)] = &[
+            ("GET", "/", SC::OK),
+            ("GET", "/anything", SC::OK),
+            ("GET", "/dir/", SC::NOT_FOUND),
+            ("HEAD", "/anything", SC::OK),
+            ("PROPFIND", "/", SC::MULTI_STATUS),
+            ("PROPFIND", "/anything", SC::MULTI_STATUS),
+            ("PROPFIND", "/dir/", SC::NOT_FOUND),
+            ("OPTIONS", "/", SC::OK),
+            ("MKCOL", "/dir/", SC::METHOD_NOT_ALLOWED),
+            ("DELETE", "/anything", SC::METHOD_NOT_ALLOWED),
+            ("COPY", "/anything", SC::METHOD_NOT_ALLOWED),
+            ("MOVE", "/anything", SC::METHOD_NOT_ALLOWED),
+            ("PROPPATCH", "/anything", SC::METHOD_NOT_ALLOWED),
+            ("POST", "/", SC::METHOD_NOT_ALLOWED),
+        ];
+        for (method, uri, status) in matrix {
+            let res = send(&dav, method, uri).await;
+            assert_eq!(res.status(), *status, "{} {}", method, uri);
+            if *status == SC::METHOD_NOT_ALLOWED {
+                let allow = res.headers().get("allow").unwrap().to_str().unwrap();
+                assert!(!allow.contains(*method), "{}", allow);
+            }
+        }
+
+        let res = send(&dav, "OPTIONS", "/").await;
+        let allow = res.headers().get("allow").unwrap().to_str().unwrap();
+        assert!(allow.contains("PUT") && allow.contains("PROPFIND"));
+        assert!(!allow.contains("DELETE") && !allow.contains("MKCOL"));
+
+        // OPTIONS only lists what makes sense on a single file.
+        for (uri, expect) in [
+            ("/", "HEAD,GET,PATCH,PUT,OPTIONS,PROPFIND"),
+            ("/anything", "HEAD,GET,PATCH,PUT,OPTIONS,PROPFIND"),
+            ("/dir/", "OPTIONS"),
+        ] {
+            let res = send(&dav, "OPTIONS", uri).await;
+            assert_eq!(res.headers()["allow"], expect, "{}", uri);
+        }
+
+        // PROPFIND describes the file itself, with its real name.
+        let mut body = send(&dav, "PROPFIND", "/anything").await.into_body();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.next().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        let data = String::from_utf8(data).unwrap();
+        assert_eq!(data.matches("<D:response>").count(), 1);
+        assert!(data.contains("the file.txt</D:displayname>"), "{}", data);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_move_symlink() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempdir("move-symlink");
+        std::fs::create_dir(dir.join("target")).unwrap();
+        std::fs::write(dir.join("target/a"), b"a").unwrap();
+        std::fs::create_dir(dir.join("target2")).unwrap();
+        std::fs::write(dir.join("target2/c"), b"c").unwrap();
+        std::fs::create_dir(dir.join("dir")).unwrap();
+        std::fs::write(dir.join("dir/b"), b"b").unwrap();
+        symlink(dir.join("target"), dir.join("link")).unwrap();
+        symlink(dir.join("target2"), dir.join("dlink")).unwrap();
+
+        let mv = |dav: DavHandler, from: &'static str, to: &'static str| async move {
+            let r = Request::builder()
+                .method("MOVE")
+                .uri(from)
+                .header("Destination", to)
+                .body(Body::empty())
+                .unwrap();
+            dav.handle(r).await.status()
+        };
+        let is_link = |p: &str| {
+            std::fs::symlink_metadata(dir.join(p))
+                .map(|m| m.file_type().is_symlink())
+                .unwrap_or(false)
+        };
+
+        // hidden symlinks cannot be moved.
+        let hidden = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .build_handler();
+        assert_eq!(mv(hidden, "/link/", "/moved").await, StatusCode::NOT_FOUND);
+        assert!(is_link("link"));
+
+        // otherwise the symlink itself is moved, not its target.
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .hide_symlinks(false)
+            .build_handler();
+        assert_eq!(
+            mv(dav.clone(), "/link/", "/moved").await,
+            StatusCode::CREATED
+        );
+        assert!(!is_link("link") && is_link("moved"));
+        assert!(dir.join("target/a").is_file());
+
+        // a symlink as destination is replaced, its target is left alone.
+        assert_eq!(mv(dav, "/dir/", "/dlink").await, StatusCode::NO_CONTENT);
+        assert!(!is_link("dlink"));
+        assert!(dir.join("dlink/b").is_file());
+        assert!(dir.join("target2/c").is_file());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlink_size() {
+        use crate::testing::{DavClient, Depth};
+        use std::os::unix::fs::symlink;
+
+        let dir = tempdir("symlink-size");
+        std::fs::create_dir(dir.join("dir")).unwrap();
+        std::fs::write(dir.join("big"), vec![b'x'; 10000]).unwrap();
+        symlink(dir.join("big"), dir.join("dir/link")).unwrap();
+        let link_size = std::fs::symlink_metadata(dir.join("dir/link"))
+            .unwrap()
+            .len();
+        assert_ne!(link_size, 10000);
+
+        // GET, PROPFIND on the link, and the listings agree.
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .hide_symlinks(false)
+            .autoindex(true)
+            .build_handler();
+        let client = DavClient::new(dav);
+        let res = client.get("/dir/link").await;
+        assert_eq!(res.headers()["content-length"], "10000");
+        assert_eq!(res.body().len(), 10000);
+        for depth in [Depth::Zero, Depth::One] {
+            let path = if depth == Depth::Zero {
+                "/dir/link"
+            } else {
+                "/dir/"
+            };
+            let ms = client
+                .propfind(path, depth, &["getcontentlength"])
+                .await
+                .unwrap();
+            let link = ms.response("/dir/link").unwrap();
+            assert_eq!(link.prop_text("getcontentlength").unwrap(), "10000");
+        }
+        let index = client.get("/dir/").await;
+        let index = std::str::from_utf8(index.body()).unwrap();
+        assert!(index.contains(">link</a>"), "{}", index);
+        assert!(index.contains("9.77 KiB"), "{}", index);
+
+        // hidden symlinks are not in the directory index either.
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .autoindex(true)
+            .build_handler();
+        let client = DavClient::new(dav);
+        let index = client.get("/dir/").await;
+        let index = std::str::from_utf8(index.body()).unwrap();
+        assert!(!index.contains(">link</a>"), "{}", index);
+        let ms = client.propfind("/dir/", Depth::One, &[]).await.unwrap();
+        assert_eq!(ms.hrefs(), vec!["/dir/"]);
+
+        // a hashed collection ETag covers what is listed: the target of a
+        // visible symlink, nothing of a hidden one. GET and PROPFIND agree.
+        for hide in [false, true] {
+            let dav = DavHandler::builder()
+                .filesystem(LocalFs::new(&dir, false, false, false))
+                .hide_symlinks(hide)
+                .autoindex(true)
+                .hashed_collection_etags(true)
+                .build_handler();
+            let client = DavClient::new(dav);
+            let etag = || async {
+                let get = client.get("/dir/").await.headers()["etag"].clone();
+                let ms = client.propfind("/dir/", Depth::Zero, &["getetag"]).await;
+                let getetag = ms.unwrap().responses[0].prop_text("getetag").unwrap();
+                assert_eq!(get, getetag.as_str());
+                getetag
+            };
+            let before = etag().await;
+            std::fs::write(dir.join("big"), vec![b'x'; 20000]).unwrap();
+            assert_eq!(etag().await != before, !hide, "hide_symlinks({})", hide);
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_delete_readonly_subdir() {
+        use crate::testing::{DavClient, Multistatus};
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir("delete-readonly");
+        std::fs::create_dir_all(dir.join("tree/ro")).unwrap();
+        std::fs::write(dir.join("tree/a"), b"a").unwrap();
+        std::fs::write(dir.join("tree/ro/f"), b"f").unwrap();
+        std::fs::write(dir.join("tree/z"), b"z").unwrap();
+        let ro = dir.join("tree/ro");
+        std::fs::set_permissions(&ro, std::fs::Permissions::from_mode(0o555)).unwrap();
+
+        // permissions do not apply to a privileged user (root). The
+        // handler side is tested with a MockFs in handle_delete.rs.
+        if std::fs::write(ro.join("probe"), b"").is_ok() {
+            let _ = std::fs::remove_dir_all(&dir);
+            return;
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .build_handler();
+        let client = DavClient::new(dav);
+        let req = Request::delete("/tree/").body(Body::empty()).unwrap();
+        let res = client.request(req).await;
+        assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+        let ms = Multistatus::parse(res.body()).unwrap();
+        assert_eq!(ms.hrefs(), ["/tree/ro/f"]);
+        assert_eq!(ms.responses[0].status, Some(StatusCode::FORBIDDEN));
+        assert!(!dir.join("tree/a").exists());
+        assert!(!dir.join("tree/z").exists());
+        assert!(dir.join("tree/ro/f").exists());
+
+        std::fs::set_permissions(&ro, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_case_insensitive_cache() {
+        use crate::testing::DavClient;
+
+        let dir = tempdir("case-insensitive");
+        std::fs::create_dir(dir.join("Dir")).unwrap();
+        std::fs::write(dir.join("Dir/File.txt"), b"hello").unwrap();
+
+        for size in [0, 16] {
+            // a clone that was made before keeps its own settings.
+            let fs = LocalFs::new(&dir, false, true, false);
+            let shared = fs.clone();
+            let fs = fs.case_insensitive_cache_size(size);
+            assert!(Arc::ptr_eq(
+                shared.inner.case_cache.as_ref().unwrap(),
+                &localfs_windows::shared_cache()
+            ));
+            let client = DavClient::new(DavHandler::builder().filesystem(fs).build_handler());
+            for _ in 0..2 {
+                let res = client.get("/dir/FILE.TXT").await;
+                assert_eq!(res.status(), StatusCode::OK);
+                assert_eq!(&res.body()[..], b"hello");
+            }
+
+            // a rename invalidates the cached lookups below the old path.
+            assert_eq!(
+                client.move_("/DIR/", "/Other/", false).await,
+                StatusCode::CREATED
+            );
+            let res = client.get("/dir/file.txt").await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+            let res = client.get("/other/file.TXT").await;
+            assert_eq!(res.status(), StatusCode::OK);
+            std::fs::rename(dir.join("Other"), dir.join("Dir")).unwrap();
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_get_ctag() {
+        use std::time::{Duration, SystemTime};
+
+        let dir = tempdir("ctag");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        let file = dir.join("sub/file.txt");
+        std::fs::write(&file, b"hello").unwrap();
+        let old = SystemTime::now() - Duration::from_secs(60);
+        for p in [&dir.join("sub"), &file] {
+            std::fs::File::open(p).unwrap().set_modified(old).unwrap();
+        }
+
+        let fs = LocalFs::new(&dir, false, false, false);
+        let sub = DavPath::new("/sub/").unwrap();
+        let ctag1 = fs.get_ctag(&sub).await.unwrap();
+        assert_eq!(fs.get_ctag(&sub).await.unwrap(), ctag1);
+
+        // writing to a member changes only the mtime of the member.
+        std::fs::write(&file, b"world").unwrap();
+        assert_ne!(fs.get_ctag(&sub).await.unwrap(), ctag1);
+
+        let path = DavPath::new("/sub/file.txt").unwrap();
+        assert!(matches!(fs.get_ctag(&path).await, Err(FsError::Forbidden)));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_case_insensitive_external_change() {
+        use crate::testing::DavClient;
+        use std::time::{Duration, SystemTime};
+
+        // directory listings are only cached if they did not change recently.
+        let backdate = |path: &Path| {
+            let old = SystemTime::now() - Duration::from_secs(60);
+            std::fs::File::open(path)
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        };
+
+        let dir = tempdir("case-change");
+        let sub = dir.join("Dir/Sub");
+        std::fs::create_dir_all(&sub).unwrap();
+        std::fs::write(sub.join("File.txt"), b"hello").unwrap();
+        for d in [&dir, &dir.join("Dir"), &sub] {
+            backdate(d);
+        }
+
+        let fs = LocalFs::new(&dir, false, true, false).case_insensitive_cache_size(16);
+        let client = DavClient::new(DavHandler::builder().filesystem(fs).build_handler());
+        let get = |path: &'static str| {
+            let client = &client;
+            async move { client.get(path).await.status() }
+        };
+        assert_eq!(get("/dir/sub/file.txt").await, StatusCode::OK);
+
+        // the casing on disk changes.
+        std::fs::rename(sub.join("File.txt"), sub.join("FILE.TXT")).unwrap();
+        assert_eq!(get("/dir/sub/file.txt").await, StatusCode::OK);
+        assert_eq!(get("/Dir/Sub/File.Txt").await, StatusCode::OK);
+
+        // a file that was not found, appears.
+        backdate(&sub);
+        assert_eq!(get("/dir/sub/later.txt").await, StatusCode::NOT_FOUND);
+        std::fs::write(sub.join("Later.txt"), b"x").unwrap();
+        assert_eq!(get("/DIR/SUB/LATER.TXT").await, StatusCode::OK);
+
+        // a directory is renamed to a different case.
+        backdate(&sub);
+        backdate(&dir.join("Dir"));
+        std::fs::rename(&sub, dir.join("Dir/SUB")).unwrap();
+        assert_eq!(get("/dir/sub/later.txt").await, StatusCode::OK);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_blocking_threads() {
+        use crate::testing::{DavClient, Depth};
+        use std::sync::Mutex;
+
+        // the access guard runs on the same thread as the syscalls.
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let t = threads.clone();
+        let guard = Box::new(move || {
+            let name = std::thread::current().name().map(|n| n.to_string());
+            t.lock().unwrap().push(name);
+            Box::new(()) as Box<dyn Any>
+        });
+
+        let dir = tempdir("blocking-threads");
+        let fs = LocalFs::new_with_fs_access_guard(&dir, false, false, false, Some(guard))
+            .blocking_threads(2);
+        let client = DavClient::new(DavHandler::builder().filesystem(fs).build_handler());
+
+        assert_eq!(
+            client.put_bytes("/file.txt", "hello").await,
+            StatusCode::CREATED
+        );
+        let res = client.get("/file.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(&res.body()[..], b"hello");
+        let ms = client
+            .propfind("/", Depth::One, &["getcontentlength"])
+            .await
+            .unwrap();
+        assert_eq!(ms.hrefs(), ["/", "/file.txt"]);
+
+        let threads = threads.lock().unwrap();
+        assert!(!threads.is_empty());
+        for name in threads.iter() {
+            assert!(name.as_deref().unwrap_or("").starts_with("dav-localfs-"));
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_copy_recursive() {
+        use crate::testfs::{MockFs, MockOp};
+        use crate::testing::DavClient;
+
+        let dir = tempdir("copy-recursive");
+        std::fs::create_dir_all(dir.join("src/sub")).unwrap();
+        std::fs::write(dir.join("src/file"), "a").unwrap();
+        std::fs::write(dir.join("src/sub/file"), "b").unwrap();
+
+        // copying the files one by one would fail, so the tree
+        // has to be copied in one go.
+        let fs = MockFs::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .fail(MockOp::Copy, "/dest/file", FsError::Forbidden)
+            .build();
+        let dav = DavHandler::builder().filesystem(fs).build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(
+            client.copy("/src/", "/dest/", false).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(std::fs::read_to_string(dir.join("dest/file")).unwrap(), "a");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("dest/sub/file")).unwrap(),
+            "b"
+        );
+
+        assert_eq!(
+            client.copy("/src/", "/dest/", false).await,
+            StatusCode::PRECONDITION_FAILED
+        );
+        std::fs::write(dir.join("src/file"), "c").unwrap();
+        assert_eq!(
+            client.copy("/src/", "/dest/", true).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(std::fs::read_to_string(dir.join("dest/file")).unwrap(), "c");
+
+        // hidden files are not copied.
+        let temp = format!(".file.{}.dav-tmp", "0".repeat(32));
+        std::fs::write(dir.join("src").join(&temp), "x").unwrap();
+        assert_eq!(
+            client.copy("/src/", "/dest2/", false).await,
+            StatusCode::CREATED
+        );
+        assert!(dir.join("dest2/file").exists());
+        assert!(!dir.join("dest2").join(&temp).exists());
+
+        // a symlink to a directory makes COPY copy the tree one by one,
+        // and follow the link. Here that fails on /dest/file.
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("src/sub"), dir.join("src/link")).unwrap();
+            let res = client.copy("/src/", "/dest/", true).await;
+            assert_eq!(res, StatusCode::MULTI_STATUS);
+            let res = client.copy("/src/", "/dest3/", false).await;
+            assert_eq!(res, StatusCode::CREATED);
+            let meta = std::fs::symlink_metadata(dir.join("dest3/link")).unwrap();
+            assert!(meta.is_dir());
+            let link = std::fs::read_to_string(dir.join("dest3/link/file")).unwrap();
+            assert_eq!(link, "b");
+        }
+
+        // with reserved names, the tree is copied one by one too.
+        std::fs::create_dir_all(dir.join("src4")).unwrap();
+        std::fs::write(dir.join("src4/file"), "a").unwrap();
+        std::fs::write(dir.join("src4/file2"), "b").unwrap();
+        let fs = MockFs::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .build();
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .reserved_name("*.lock")
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(
+            client.copy("/src4/", "/dest4/", false).await,
+            StatusCode::CREATED
+        );
+        // a copy per file, not one of the whole tree.
+        assert_eq!(fs.calls(MockOp::Copy), 2);
+        assert!(dir.join("dest4/file").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_read_bytes_count() {
+        let dir = tempdir("read-bytes");
+        std::fs::write(dir.join("file"), vec![b'x'; 1024 * 1024]).unwrap();
+        let fs = LocalFs::new(&dir, false, false, false);
+        let path = DavPath::new("/file").unwrap();
+        let mut file = fs.open(&path, OpenOptions::read()).await.unwrap();
+
+        assert_eq!(file.read_bytes(4096).await.unwrap().len(), 4096);
+        file.seek(SeekFrom::End(-10)).await.unwrap();
+        assert_eq!(file.read_bytes(4096).await.unwrap().len(), 10);
+        assert!(file.read_bytes(4096).await.unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_empty_file() {
+        use http_body::Body as HttpBody;
+
+        let dir = tempdir("empty-file");
+        std::fs::write(dir.join("empty"), b"").unwrap();
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .build_handler();
+
+        for method in ["GET", "HEAD"] {
+            let req = Request::builder()
+                .method(method)
+                .uri("/empty")
+                .header("Range", "bytes=0-")
+                .body(Body::empty())
+                .unwrap();
+            let res = dav.handle(req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()["content-length"], "0");
+            assert!(res.headers().contains_key("etag"));
+            assert_eq!(HttpBody::size_hint(res.body()).exact(), Some(0));
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_health_path_backend_gone() {
+        let dir = tempdir("health");
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .health_path("/healthz")
+            .build_handler();
+
+        let res = send(&dav, "GET", "/healthz").await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // the probe itself still works, but reports the backend as failed.
+        std::fs::remove_dir_all(&dir).unwrap();
+        let res = send(&dav, "GET", "/healthz").await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let mut body = res.into_body();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.next().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        let data = String::from_utf8(data).unwrap();
+        assert!(data.starts_with("{\"status\":\"degraded\","), "{}", data);
+        assert!(data.ends_with(",\"backend\":\"failed\"}\n"), "{}", data);
+        let res = send(&dav, "HEAD", "/healthz").await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_atomic_writes() {
+        let dir = tempdir("atomic-writes");
+        std::fs::write(dir.join("file"), "old").unwrap();
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        let entries = |dir: &Path| std::fs::read_dir(dir).unwrap().count();
+        let path = DavPath::new("/file").unwrap();
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        oo.truncate = true;
+
+        // nothing changes until the file is complete.
+        let fs = LocalFs::new(&dir, false, false, false).atomic_writes(None);
+        let mut file = fs.open(&path, oo.clone()).await.unwrap();
+        file.write_bytes(Bytes::from("new")).await.unwrap();
+        assert_eq!(read("file"), "old");
+        assert_eq!(entries(&dir), 2);
+        drop(file);
+        assert_eq!(read("file"), "old");
+        assert_eq!(entries(&dir), 1);
+
+        let mut file = fs.open(&path, oo.clone()).await.unwrap();
+        file.write_bytes(Bytes::from("new")).await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(read("file"), "new");
+        assert_eq!(entries(&dir), 1);
+
+        let dav = DavHandler::builder().filesystem(fs).build_handler();
+        let req = Request::put("/file2").body(Body::from("put")).unwrap();
+        assert_eq!(dav.handle(req).await.status(), StatusCode::CREATED);
+        assert_eq!(read("file2"), "put");
+        assert_eq!(entries(&dir), 2);
+
+        // a short upload leaves the original alone.
+        let req = Request::put("/file2")
+            .header("Content-Length", 10)
+            .body(Body::from("short"))
+            .unwrap();
+        assert_eq!(dav.handle(req).await.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(read("file2"), "put");
+        assert_eq!(entries(&dir), 2);
+
+        // the temporary files are hidden, and those that are older than
+        // the LocalFs are removed by read_dir.
+        let name = ".file.0123456789abcdef0123456789abcdef.dav-tmp";
+        let stale = std::fs::File::create(dir.join(name)).unwrap();
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        stale.set_modified(an_hour_ago).unwrap();
+        let fs = LocalFs::new(&dir, false, false, false).atomic_writes(None);
+        let mut file = fs.open(&path, oo.clone()).await.unwrap();
+        file.write_bytes(Bytes::from("newer")).await.unwrap();
+        let stale = DavPath::new(&format!("/{}", name)).unwrap();
+        assert!(matches!(fs.metadata(&stale).await, Err(FsError::NotFound)));
+        let root = DavPath::new("/").unwrap();
+        let entries_fs = fs.read_dir(&root, ReadDirMeta::None).await.unwrap();
+        let mut names: Vec<_> = entries_fs.map(|e| e.name()).collect().await;
+        names.sort();
+        assert_eq!(names, vec![b"file".to_vec(), b"file2".to_vec()]);
+        assert!(!dir.join(name).exists());
+        assert_eq!(entries(&dir), 3);
+        drop(file);
+        assert_eq!(entries(&dir), 2);
+
+        // a temp dir. Leftovers in it are removed when it is set.
+        let tmp = tempdir("atomic-writes-tmp");
+        std::fs::write(tmp.join("0123.dav-tmp"), "stale").unwrap();
+        let fs = LocalFs::new(&dir, false, false, false).atomic_writes(Some(tmp.clone()));
+        let mut file = fs.open(&path, oo.clone()).await.unwrap();
+        file.write_bytes(Bytes::from("newer")).await.unwrap();
+        assert_eq!(entries(&tmp), 1);
+        file.flush().await.unwrap();
+        assert_eq!(read("file"), "newer");
+        assert_eq!(entries(&tmp), 0);
+        assert_eq!(entries(&dir), 2);
+        file.write_bytes(Bytes::from("!")).await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(read("file"), "newer!");
+        assert_eq!(file.metadata().await.unwrap().len(), 6);
+
+        // without create, the file must exist.
+        oo.create = false;
+        let path = DavPath::new("/nope").unwrap();
+        assert!(matches!(fs.open(&path, oo).await, Err(FsError::NotFound)));
+        assert_eq!(entries(&tmp), 0);
+
+        let _ = std::fs::remove_dir_all(&tmp);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -23,7 +23,7 @@ use parking_lot::Mutex;
 
 use crate::davpath::DavPath;
 use crate::fs::*;
-use crate::localfs::LocalFs;
+use crate::localfs::{is_temp_name, LocalFs};
 
 const DU_CACHE_ENTRIES: usize = 4096;
 const DU_CACHE_MAX_AGE: u64 = 60;
@@ -256,9 +256,13 @@ impl LocalFs {
         Some(Box::new(EmptyMetaData {}))
     }
 
-    // This file can never exist.
+    // This file can never exist. Neither can the temporary file of an
+    // atomic write, see `LocalFs::atomic_writes`.
     #[inline]
     pub(crate) fn is_forbidden(&self, path: &DavPath) -> bool {
+        if is_temp_name(path.file_name_bytes()) {
+            return true;
+        }
         if !self.inner.macos {
             return false;
         }
@@ -0,0 +1,77 @@
+// Dedicated threadpool for the blocking filesystem calls of LocalFs.
+//
+// Normally LocalFs runs those on tokio's blocking pool (or on the
+// current worker thread, with block_in_place). Under heavy disk I/O
+// that pool can fill up, and then everything else that uses
+// spawn_blocking has to wait. With a pool of its own, LocalFs waits
+// for its own threads only.
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use futures_channel::oneshot;
+
+use crate::fs::{FsError, FsResult};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+pub(crate) struct BlockingPool {
+    tx: Mutex<mpsc::Sender<Job>>,
+}
+
+impl BlockingPool {
+    // Start a pool of `threads` threads. They exit when the pool is dropped.
+    pub(crate) fn new(threads: usize) -> BlockingPool {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        for i in 0..threads.max(1) {
+            let rx = rx.clone();
+            thread::Builder::new()
+                .name(format!("dav-localfs-{}", i))
+                .spawn(move || loop {
+                    let job = match rx.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    job();
+                })
+                .expect("failed to start localfs thread");
+        }
+        BlockingPool { tx: Mutex::new(tx) }
+    }
+
+    // Run `func` on one of the threads of the pool.
+    //
+    // Like spawn_blocking().await.unwrap(), a panic in `func` is
+    // passed on to the caller. The thread itself keeps running.
+    // If the job cannot be run at all, the result is GeneralFailure.
+    pub(crate) async fn run<F, R>(&self, func: F) -> FsResult<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(func)));
+        });
+        if self.tx.lock().unwrap().send(job).is_err() {
+            error!("localfs: the threads of the pool are gone");
+            return Err(FsError::GeneralFailure);
+        }
+        match rx.await {
+            Ok(Ok(r)) => Ok(r),
+            Ok(Err(e)) => panic::resume_unwind(e),
+            Err(_) => {
+                error!("localfs: a thread of the pool dropped a job");
+                Err(FsError::GeneralFailure)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for BlockingPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockingPool").finish_non_exhaustive()
+    }
+}
@@ -3,12 +3,19 @@
 // The main thing here is case-insensitive path lookups,
 // and caching that.
 //
+// There are two caches. One maps a lowercased path to the actual
+// path, and is checked first. The other keeps the names in a
+// directory, so that resolving a segment that is not in the first
+// cache does not read the whole directory every time. A directory
+// listing is valid while the mtime of the directory is unchanged.
+//
+use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io::ErrorKind;
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::thread;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
@@ -20,13 +27,25 @@ use crate::davpath::DavPath;
 const CACHE_ENTRIES: usize = 4096;
 const CACHE_MAX_AGE: u64 = 15 * 60;
 const CACHE_SLEEP_MS: u64 = 30059;
+// Directories with more entries than this are not cached.
+const DIR_MAX_NAMES: usize = 16384;
+// A directory that changed less than this long before it was read
+// might change again within the mtime granularity of the filesystem,
+// so its listing is not cached.
+const DIR_MIN_AGE: Duration = Duration::from_secs(2);
 
 lazy_static! {
-    static ref CACHE: Arc<Cache> = Arc::new(Cache::new(CACHE_ENTRIES));
+    static ref CACHE: Arc<Cache> = Cache::new(CACHE_ENTRIES);
+}
+
+// The cache that is shared by all LocalFs instances that do not
+// have a cache of their own.
+pub(crate) fn shared_cache() -> Arc<Cache> {
+    CACHE.clone()
 }
 
 // Do a case-insensitive path lookup.
-pub(crate) fn resolve(base: impl Into<PathBuf>, path: &DavPath) -> PathBuf {
+pub(crate) fn resolve(base: impl Into<PathBuf>, path: &DavPath, cache: &Cache) -> PathBuf {
     let base = base.into();
     let path = path.as_rel_ospath();
 
@@ -43,9 +62,6 @@ pub(crate) fn resolve(base: impl Into<PathBuf>, path: &DavPath) -> PathBuf {
         None => return fullpath,
     };
 
-    // deref in advance: first lazy_static, then Arc.
-    let cache = &*CACHE;
-
     // In the cache?
     if let Some((path, _)) = cache.get(&fullpath) {
         return path;
@@ -79,7 +95,7 @@ pub(crate) fn resolve(base: impl Into<PathBuf>, path: &DavPath) -> PathBuf {
         (parent.to_path_buf(), true)
     };
     if parent_exists {
-        let (newpath, stop) = lookup(parent, segs[segs.len() - 1], true);
+        let (newpath, stop) = lookup(parent, segs[segs.len() - 1], true, cache);
         if !stop {
             cache.insert(&newpath);
         }
@@ -96,7 +112,7 @@ pub(crate) fn resolve(base: impl Into<PathBuf>, path: &DavPath) -> PathBuf {
                 // Save the path leading up to this file or dir.
                 cache.insert(&newpath);
             }
-            let (n, s) = lookup(newpath, seg, false);
+            let (n, s) = lookup(newpath, seg, false, cache);
             newpath = n;
             stop = s;
         } else {
@@ -111,7 +127,7 @@ pub(crate) fn resolve(base: impl Into<PathBuf>, path: &DavPath) -> PathBuf {
 }
 
 // lookup a filename in a directory in a case insensitive way.
-fn lookup(mut path: PathBuf, seg: &OsStr, no_init_check: bool) -> (PathBuf, bool) {
+fn lookup(mut path: PathBuf, seg: &OsStr, no_init_check: bool, cache: &Cache) -> (PathBuf, bool) {
     // does it exist as-is?
     let mut path2 = path.clone();
     path2.push(seg);
@@ -132,32 +148,49 @@ fn lookup(mut path: PathBuf, seg: &OsStr, no_init_check: bool) -> (PathBuf, bool
         None => return (path2, true),
     };
 
-    // we have to read the entire directory.
-    let dir = match path.read_dir() {
-        Ok(dir) => dir,
-        Err(_) => return (path2, true),
-    };
-    for entry in dir.into_iter() {
+    // we have to look at the entire directory.
+    match cache.lookup_dir(&path, &filename) {
+        Some(name) => {
+            path.push(name);
+            (path, false)
+        }
+        None => (path2, true),
+    }
+}
+
+// read a directory, and map the lowercased names to the actual names.
+// If more than one name maps to the same lowercased name, the first one wins.
+fn read_names(dir: &Path) -> Option<HashMap<String, OsString>> {
+    let mut names = HashMap::new();
+    for entry in dir.read_dir().ok()? {
         let entry = match entry {
             Ok(e) => e,
             Err(_) => continue,
         };
         let entry_name = entry.file_name();
-        let name = match entry_name.to_str() {
-            Some(n) => n,
-            None => continue,
-        };
-        if name.to_lowercase() == filename {
-            path.push(&name);
-            return (path, false);
+        if let Some(name) = entry_name.to_str() {
+            names.entry(name.to_lowercase()).or_insert(entry_name);
         }
     }
-    (path2, true)
+    Some(names)
 }
 
-// The cache stores a mapping of lowercased path -> actual path.
+// The cache stores a mapping of lowercased path -> actual path,
+// and of actual directory path -> names in that directory.
+// A cache with size 0 is disabled, it never stores anything.
 pub struct Cache {
-    cache: Mutex<LruCache<PathBuf, Entry>>,
+    cache: Option<Mutex<Inner>>,
+}
+
+struct Inner {
+    paths: LruCache<PathBuf, Entry>,
+    dirs: LruCache<PathBuf, Listing>,
+}
+
+// The names in a directory, lowercased name -> actual name.
+struct Listing {
+    mtime: SystemTime,
+    names: HashMap<String, OsString>,
 }
 
 #[derive(Clone)]
@@ -178,15 +211,32 @@ fn pathbuf_to_lowercase(path: PathBuf) -> PathBuf {
 }
 
 impl Cache {
-    pub fn new(size: usize) -> Cache {
+    pub fn new(size: usize) -> Arc<Cache> {
+        let size = match NonZeroUsize::new(size) {
+            Some(size) => size,
+            None => return Arc::new(Cache { cache: None }),
+        };
+        let this = Arc::new(Cache {
+            cache: Some(Mutex::new(Inner {
+                paths: LruCache::new(size),
+                dirs: LruCache::new(size),
+            })),
+        });
+        let weak: Weak<Cache> = Arc::downgrade(&this);
         thread::spawn(move || {
             // House keeping. Every 30 seconds, remove entries older than
-            // CACHE_MAX_AGE seconds from the LRU cache.
+            // CACHE_MAX_AGE seconds from the LRU cache. Stop when the
+            // cache is gone.
             loop {
                 thread::sleep(Duration::from_millis(CACHE_SLEEP_MS));
+                let this = match weak.upgrade() {
+                    Some(this) => this,
+                    None => break,
+                };
                 if let Ok(d) = SystemTime::now().duration_since(UNIX_EPOCH) {
                     let now = d.as_secs();
-                    let mut cache = CACHE.cache.lock();
+                    let mut inner = this.cache.as_ref().unwrap().lock();
+                    let cache = &mut inner.paths;
                     while let Some((_k, e)) = cache.peek_lru() {
                         trace!(target: "webdav_cache", "Cache: purge check: {:?}", _k);
                         if e.time + CACHE_MAX_AGE > now {
@@ -199,44 +249,104 @@ impl Cache {
                             break;
                         }
                     }
-                    drop(cache);
+                    drop(inner);
                 }
             }
         });
-        Cache {
-            cache: Mutex::new(LruCache::new(NonZeroUsize::new(size).unwrap())),
-        }
+        this
     }
 
     // Insert an entry into the cache.
     pub fn insert(&self, path: &Path) {
+        let cache = match self.cache {
+            Some(ref cache) => cache,
+            None => return,
+        };
         let lc_path = pathbuf_to_lowercase(PathBuf::from(path));
         if let Ok(d) = SystemTime::now().duration_since(UNIX_EPOCH) {
             let e = Entry {
                 path: PathBuf::from(path),
                 time: d.as_secs(),
             };
-            let mut cache = self.cache.lock();
-            cache.put(lc_path, e);
+            cache.lock().paths.put(lc_path, e);
         }
     }
 
+    // Remove an entry, and all entries below it, from the cache.
+    // Called before a path is removed or renamed.
+    pub fn invalidate(&self, path: &Path) {
+        let cache = match self.cache {
+            Some(ref cache) => cache,
+            None => return,
+        };
+        let lc_path = pathbuf_to_lowercase(PathBuf::from(path));
+        let mut inner = cache.lock();
+        let keys = inner
+            .paths
+            .iter()
+            .filter(|(k, _)| k.starts_with(&lc_path))
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        for k in keys {
+            inner.paths.pop(&k);
+        }
+        let keys = inner
+            .dirs
+            .iter()
+            .filter(|(k, _)| k.starts_with(path))
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        for k in keys {
+            inner.dirs.pop(&k);
+        }
+        if let Some(parent) = path.parent() {
+            inner.dirs.pop(parent);
+        }
+    }
+
+    // Forget the listing of the directory that `path` was created in.
+    pub fn created(&self, path: &Path) {
+        if let (Some(cache), Some(parent)) = (self.cache.as_ref(), path.parent()) {
+            cache.lock().dirs.pop(parent);
+        }
+    }
+
+    // Find the actual name of `lc_name` in directory `dir`.
+    fn lookup_dir(&self, dir: &Path, lc_name: &str) -> Option<OsString> {
+        let cache = match self.cache {
+            Some(ref cache) => cache,
+            None => return read_names(dir)?.remove(lc_name),
+        };
+        let mtime = fs::metadata(dir).and_then(|m| m.modified()).ok()?;
+        if let Some(listing) = cache.lock().dirs.get(dir) {
+            if listing.mtime == mtime {
+                return listing.names.get(lc_name).cloned();
+            }
+        }
+        let names = read_names(dir)?;
+        let name = names.get(lc_name).cloned();
+        let stable =
+            matches!(SystemTime::now().duration_since(mtime), Ok(age) if age >= DIR_MIN_AGE);
+        if stable && names.len() <= DIR_MAX_NAMES {
+            let listing = Listing { mtime, names };
+            cache.lock().dirs.put(dir.to_path_buf(), listing);
+        }
+        name
+    }
+
     // Get an entry from the cache, and validate it. If it's valid
     // return the actual pathname and metadata. If it's invalid remove
     // it from the cache and return None.
     pub fn get(&self, path: &Path) -> Option<(PathBuf, fs::Metadata)> {
+        let cache = self.cache.as_ref()?;
         // First lowercase the entire path.
         let lc_path = pathbuf_to_lowercase(PathBuf::from(path));
         // Lookup.
-        let e = {
-            let mut cache = self.cache.lock();
-            cache.get(&lc_path)?.clone()
-        };
+        let e = cache.lock().paths.get(&lc_path)?.clone();
         // Found, validate.
         match fs::metadata(&e.path) {
             Err(_) => {
-                let mut cache = self.cache.lock();
-                cache.pop(&lc_path);
+                cache.lock().paths.pop(&lc_path);
                 None
             }
             Ok(m) => Some((e.path, m)),
@@ -73,8 +73,24 @@ pub trait DavLockSystem: Debug + Sync + Send + BoxCloneLs {
     /// Find and return all locks that cover a given path.
     fn discover(&self, path: &DavPath) -> Vec<DavLock>;
 
-    /// Delete all locks at this path and below (after MOVE or DELETE)
+    /// Check if `token` is the token of a lock that covers `path`, either
+    /// a lock on `path` itself or a depth-infinity lock on a parent.
+    ///
+    /// This is how state tokens in an `If` header are evaluated. The
+    /// default implementation looks for the token in `discover(path)`.
+    fn covers(&self, path: &DavPath, token: &str) -> bool {
+        self.discover(path).iter().any(|l| l.token == token)
+    }
+
+    /// Delete all locks at this path and below (after DELETE, on the source
+    /// of a MOVE, and on the destination of a COPY or MOVE).
     fn delete(&self, path: &DavPath) -> Result<(), ()>;
+
+    /// Remove the locks that have timed out.
+    ///
+    /// Called periodically if `DavConfig::lock_sweep_interval` is set.
+    /// The default implementation does nothing.
+    fn sweep(&self) {}
 }
 
 #[doc(hidden)]
@@ -8,6 +8,7 @@
 //! it to the DavHandler. As a MemFs struct is just a handle, cloning is cheap.
 use std::collections::HashMap;
 use std::io::{Error, ErrorKind, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
@@ -34,6 +35,7 @@ pub struct MemFs {
 enum MemFsNode {
     Dir(MemFsDirNode),
     File(MemFsFileNode),
+    RedirectRef(MemFsRedirectNode),
 }
 
 #[derive(Debug, Clone)]
@@ -41,6 +43,8 @@ struct MemFsDirNode {
     props: HashMap<String, DavProp>,
     mtime: SystemTime,
     crtime: SystemTime,
+    ctag: u64,
+    resourcetypes: Vec<DavProp>,
 }
 
 #[derive(Debug, Clone)]
@@ -51,6 +55,14 @@ struct MemFsFileNode {
     data: Vec<u8>,
 }
 
+#[derive(Debug, Clone)]
+struct MemFsRedirectNode {
+    props: HashMap<String, DavProp>,
+    mtime: SystemTime,
+    crtime: SystemTime,
+    target: RedirectRef,
+}
+
 #[derive(Debug, Clone)]
 struct MemFsDirEntry {
     mtime: SystemTime,
@@ -58,6 +70,8 @@ struct MemFsDirEntry {
     is_dir: bool,
     name: Vec<u8>,
     size: u64,
+    redirect: Option<RedirectRef>,
+    resourcetypes: Vec<DavProp>,
 }
 
 #[derive(Debug)]
@@ -95,17 +109,21 @@ impl MemFs {
                     return Err(FsError::NotFound);
                 }
                 let parent_id = tree.lookup_parent(path)?;
-                tree.add_child(parent_id, file_name(path), MemFsNode::new_file(), true)?
+                let node_id =
+                    tree.add_child(parent_id, file_name(path), MemFsNode::new_file(), true)?;
+                update_ctag(tree, parent_id);
+                node_id
             }
             Err(e) => return Err(e),
         };
         let node = tree.get_node_mut(node_id).unwrap();
-        if node.is_dir() {
+        if !matches!(node, MemFsNode::File(_)) {
             return Err(FsError::Forbidden);
         }
         if options.truncate {
             node.as_file_mut()?.data.truncate(0);
             node.update_mtime(SystemTime::now());
+            update_ctag(tree, node_id);
         }
         Ok(Box::new(MemFsFile {
             tree: self.tree.clone(),
@@ -114,6 +132,48 @@ impl MemFs {
             append: options.append,
         }))
     }
+
+    fn do_rename(&self, from: &DavPath, to: &DavPath, overwrite: bool) -> FsResult<()> {
+        let tree = &mut *self.tree.lock().unwrap();
+        let node_id = tree.lookup(from.as_bytes())?;
+        let parent_id = tree.lookup_parent(from.as_bytes())?;
+        let dst_id = tree.lookup_parent(to.as_bytes())?;
+        tree.move_node(node_id, dst_id, file_name(to.as_bytes()), overwrite)?;
+        tree.get_node_mut(parent_id)?
+            .update_mtime(SystemTime::now());
+        tree.get_node_mut(dst_id)?.update_mtime(SystemTime::now());
+        update_ctag(tree, parent_id);
+        update_ctag(tree, dst_id);
+        Ok(())
+    }
+
+    fn do_copy(&self, from: &DavPath, to: &DavPath, overwrite: bool) -> FsResult<()> {
+        let tree = &mut *self.tree.lock().unwrap();
+
+        // source must exist.
+        let snode_id = tree.lookup(from.as_bytes())?;
+
+        // make sure destination exists, create if needed.
+        {
+            let mut oo = OpenOptions::write();
+            oo.create = true;
+            oo.create_new = !overwrite;
+            self.do_open(tree, to.as_bytes(), oo)?;
+        }
+        let dnode_id = tree.lookup(to.as_bytes())?;
+
+        // copy.
+        let mut data = (*tree.get_node_mut(snode_id)?).clone();
+        match data {
+            MemFsNode::Dir(ref mut d) => d.crtime = SystemTime::now(),
+            MemFsNode::File(ref mut f) => f.crtime = SystemTime::now(),
+            MemFsNode::RedirectRef(ref mut r) => r.crtime = SystemTime::now(),
+        }
+        *tree.get_node_mut(dnode_id)? = data;
+        update_ctag(tree, dnode_id);
+
+        Ok(())
+    }
 }
 
 impl Clone for MemFs {
@@ -167,33 +227,53 @@ impl DavFileSystem for MemFs {
     }
 
     fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
+        self.create_collection(path, Vec::new())
+    }
+
+    fn create_collection<'a>(
+        &'a self,
+        path: &'a DavPath,
+        resourcetypes: Vec<DavProp>,
+    ) -> FsFuture<'a, ()> {
         async move {
-            trace!("FS: create_dir {:?}", path);
+            trace!("FS: create_collection {:?} {:?}", path, resourcetypes);
             let tree = &mut *self.tree.lock().unwrap();
             let path = path.as_bytes();
             let parent_id = tree.lookup_parent(path)?;
-            tree.add_child(parent_id, file_name(path), MemFsNode::new_dir(), false)?;
+            let mut node = MemFsNode::new_dir();
+            if let MemFsNode::Dir(ref mut d) = node {
+                d.resourcetypes = resourcetypes;
+            }
+            tree.add_child(parent_id, file_name(path), node, false)?;
             tree.get_node_mut(parent_id)?
                 .update_mtime(SystemTime::now());
+            update_ctag(tree, parent_id);
             Ok(())
         }
         .boxed()
     }
 
-    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
+    fn create_redirect_ref<'a>(
+        &'a self,
+        path: &'a DavPath,
+        target: RedirectRef,
+    ) -> FsFuture<'a, ()> {
         async move {
+            trace!("FS: create_redirect_ref {:?} {:?}", path, target);
             let tree = &mut *self.tree.lock().unwrap();
-            let parent_id = tree.lookup_parent(path.as_bytes())?;
-            let node_id = tree.lookup(path.as_bytes())?;
-            tree.delete_node(node_id)?;
+            let path = path.as_bytes();
+            let parent_id = tree.lookup_parent(path)?;
+            let node = MemFsNode::new_redirect_ref(target);
+            tree.add_child(parent_id, file_name(path), node, false)?;
             tree.get_node_mut(parent_id)?
                 .update_mtime(SystemTime::now());
+            update_ctag(tree, parent_id);
             Ok(())
         }
         .boxed()
     }
 
-    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
         async move {
             let tree = &mut *self.tree.lock().unwrap();
             let parent_id = tree.lookup_parent(path.as_bytes())?;
@@ -201,52 +281,40 @@ impl DavFileSystem for MemFs {
             tree.delete_node(node_id)?;
             tree.get_node_mut(parent_id)?
                 .update_mtime(SystemTime::now());
+            update_ctag(tree, parent_id);
             Ok(())
         }
         .boxed()
     }
 
-    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<()> {
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
         async move {
             let tree = &mut *self.tree.lock().unwrap();
-            let node_id = tree.lookup(from.as_bytes())?;
-            let parent_id = tree.lookup_parent(from.as_bytes())?;
-            let dst_id = tree.lookup_parent(to.as_bytes())?;
-            tree.move_node(node_id, dst_id, file_name(to.as_bytes()), true)?;
+            let parent_id = tree.lookup_parent(path.as_bytes())?;
+            let node_id = tree.lookup(path.as_bytes())?;
+            tree.delete_node(node_id)?;
             tree.get_node_mut(parent_id)?
                 .update_mtime(SystemTime::now());
-            tree.get_node_mut(dst_id)?.update_mtime(SystemTime::now());
+            update_ctag(tree, parent_id);
             Ok(())
         }
         .boxed()
     }
 
-    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<()> {
-        async move {
-            let tree = &mut *self.tree.lock().unwrap();
-
-            // source must exist.
-            let snode_id = tree.lookup(from.as_bytes())?;
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<()> {
+        async move { self.do_rename(from, to, true) }.boxed()
+    }
 
-            // make sure destination exists, create if needed.
-            {
-                let mut oo = OpenOptions::write();
-                oo.create = true;
-                self.do_open(tree, to.as_bytes(), oo)?;
-            }
-            let dnode_id = tree.lookup(to.as_bytes())?;
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<()> {
+        async move { self.do_copy(from, to, true) }.boxed()
+    }
 
-            // copy.
-            let mut data = (*tree.get_node_mut(snode_id)?).clone();
-            match data {
-                MemFsNode::Dir(ref mut d) => d.crtime = SystemTime::now(),
-                MemFsNode::File(ref mut f) => f.crtime = SystemTime::now(),
-            }
-            *tree.get_node_mut(dnode_id)? = data;
+    fn rename_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move { self.do_rename(from, to, false) }.boxed()
+    }
 
-            Ok(())
-        }
-        .boxed()
+    fn copy_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        async move { self.do_copy(from, to, false) }.boxed()
     }
 
     fn have_props<'a>(&'a self, _path: &'a DavPath) -> BoxFuture<'a, bool> {
@@ -281,6 +349,7 @@ impl DavFileSystem for MemFs {
                 };
                 res.push((status, prop));
             }
+            update_ctag(tree, node_id);
             Ok(res)
         }
         .boxed()
@@ -313,6 +382,41 @@ impl DavFileSystem for MemFs {
         }
         .boxed()
     }
+
+    fn get_ctag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        async move {
+            let tree = &*self.tree.lock().unwrap();
+            let node_id = tree.lookup(path.as_bytes())?;
+            match tree.get_node(node_id)? {
+                MemFsNode::Dir(ref d) => Ok(d.ctag.to_string()),
+                _ => Err(FsError::Forbidden),
+            }
+        }
+        .boxed()
+    }
+}
+
+// Change tags come from one counter, so that a collection that is
+// removed and created again does not get an old tag back.
+fn next_ctag() -> u64 {
+    static CTAG: AtomicU64 = AtomicU64::new(1);
+    CTAG.fetch_add(1, Ordering::Relaxed)
+}
+
+// Something changed in node_id, update the change tag of the
+// node itself (if it is a collection) and of all its parents.
+fn update_ctag(tree: &mut Tree, node_id: u64) {
+    let ctag = next_ctag();
+    let mut id = node_id;
+    while let Ok(node) = tree.get_node_mut(id) {
+        if let MemFsNode::Dir(ref mut d) = *node {
+            d.ctag = ctag;
+        }
+        id = match tree.get_parent(id) {
+            Ok(parent_id) => parent_id,
+            Err(_) => break,
+        };
+    }
 }
 
 // small helper.
@@ -387,6 +491,8 @@ impl DavFile for MemFsFile {
             }
             file.data[self.pos..end].copy_from_slice(&buf);
             self.pos = end;
+            file.mtime = SystemTime::now();
+            update_ctag(tree, self.node_id);
             Ok(())
         }
         .boxed()
@@ -411,6 +517,8 @@ impl DavFile for MemFsFile {
                 buf.advance(len);
                 self.pos += len;
             }
+            file.mtime = SystemTime::now();
+            update_ctag(tree, self.node_id);
             Ok(())
         }
         .boxed()
@@ -420,6 +528,23 @@ impl DavFile for MemFsFile {
         future::ok(()).boxed()
     }
 
+    fn sync(&mut self, _level: SyncLevel) -> FsFuture<'_, ()> {
+        future::ok(()).boxed()
+    }
+
+    fn set_len(&mut self, len: u64) -> FsFuture<'_, ()> {
+        async move {
+            let tree = &mut *self.tree.lock().unwrap();
+            let node = tree.get_node_mut(self.node_id)?;
+            let file = node.as_file_mut()?;
+            file.data.resize(len as usize, 0);
+            file.mtime = SystemTime::now();
+            update_ctag(tree, self.node_id);
+            Ok(())
+        }
+        .boxed()
+    }
+
     fn seek(&mut self, pos: SeekFrom) -> FsFuture<u64> {
         async move {
             let (start, offset): (u64, i64) = match pos {
@@ -465,6 +590,14 @@ impl DavMetaData for MemFsDirEntry {
     fn is_dir(&self) -> bool {
         self.is_dir
     }
+
+    fn redirect_ref(&self) -> Option<RedirectRef> {
+        self.redirect.clone()
+    }
+
+    fn extra_resourcetypes(&self) -> Vec<DavProp> {
+        self.resourcetypes.clone()
+    }
 }
 
 impl MemFsNode {
@@ -473,6 +606,8 @@ impl MemFsNode {
             crtime: SystemTime::now(),
             mtime: SystemTime::now(),
             props: HashMap::new(),
+            ctag: next_ctag(),
+            resourcetypes: Vec::new(),
         })
     }
 
@@ -485,11 +620,29 @@ impl MemFsNode {
         })
     }
 
+    fn new_redirect_ref(target: RedirectRef) -> MemFsNode {
+        MemFsNode::RedirectRef(MemFsRedirectNode {
+            crtime: SystemTime::now(),
+            mtime: SystemTime::now(),
+            props: HashMap::new(),
+            target,
+        })
+    }
+
     // helper to create MemFsDirEntry from a node.
     fn as_dirent(&self, name: &[u8]) -> MemFsDirEntry {
         let (is_dir, size, mtime, crtime) = match *self {
             MemFsNode::File(ref file) => (false, file.data.len() as u64, file.mtime, file.crtime),
             MemFsNode::Dir(ref dir) => (true, 0, dir.mtime, dir.crtime),
+            MemFsNode::RedirectRef(ref r) => (false, 0, r.mtime, r.crtime),
+        };
+        let redirect = match *self {
+            MemFsNode::RedirectRef(ref r) => Some(r.target.clone()),
+            _ => None,
+        };
+        let resourcetypes = match *self {
+            MemFsNode::Dir(ref d) => d.resourcetypes.clone(),
+            _ => Vec::new(),
         };
         MemFsDirEntry {
             name: name.to_vec(),
@@ -497,6 +650,8 @@ impl MemFsNode {
             crtime,
             is_dir,
             size: size as u64,
+            redirect,
+            resourcetypes,
         }
     }
 
@@ -504,13 +659,14 @@ impl MemFsNode {
         match *self {
             MemFsNode::Dir(ref mut d) => d.mtime = tm,
             MemFsNode::File(ref mut f) => f.mtime = tm,
+            MemFsNode::RedirectRef(ref mut r) => r.mtime = tm,
         }
     }
 
     fn is_dir(&self) -> bool {
         match *self {
             MemFsNode::Dir(_) => true,
-            MemFsNode::File(_) => false,
+            MemFsNode::File(_) | MemFsNode::RedirectRef(_) => false,
         }
     }
 
@@ -532,6 +688,7 @@ impl MemFsNode {
         match *self {
             MemFsNode::File(ref n) => &n.props,
             MemFsNode::Dir(ref d) => &d.props,
+            MemFsNode::RedirectRef(ref r) => &r.props,
         }
     }
 
@@ -539,6 +696,7 @@ impl MemFsNode {
         match *self {
             MemFsNode::File(ref mut n) => &mut n.props,
             MemFsNode::Dir(ref mut d) => &mut d.props,
+            MemFsNode::RedirectRef(ref mut r) => &mut r.props,
         }
     }
 }
@@ -179,6 +179,39 @@ impl DavLockSystem for MemLs {
         }
         Ok(())
     }
+
+    fn sweep(&self) {
+        let inner = &mut *self.0.lock().unwrap();
+        let count = sweep_node(&mut inner.tree, tree::ROOT_ID, SystemTime::now());
+        trace!("sweep: removed {} expired locks", count);
+    }
+}
+
+// Remove the expired locks in the subtree at node_id, and the nodes
+// that are left without locks or children. Returns the number of
+// locks that were removed.
+fn sweep_node(tree: &mut Tree, node_id: u64, now: SystemTime) -> usize {
+    let mut count = 0;
+    if let Ok(children) = tree.get_children(node_id) {
+        for (_, child_id) in children {
+            count += sweep_node(tree, child_id, now);
+        }
+    }
+    if let Ok(node) = tree.get_node_mut(node_id) {
+        let len = node.len();
+        node.retain(|l| !expired(l, now));
+        count += len - node.len();
+        if node.is_empty() && node_id != tree::ROOT_ID {
+            // fails if the node still has children, which is fine.
+            tree.delete_node(node_id).ok();
+        }
+    }
+    count
+}
+
+// A lock that has timed out is ignored, even if it has not been swept yet.
+fn expired(lock: &DavLock, now: SystemTime) -> bool {
+    matches!(lock.timeout_at, Some(t) if t <= now)
 }
 
 // check if there are any locks along the path.
@@ -195,6 +228,7 @@ fn check_locks_to_path(
     let last_seg = segs.len() - 1;
 
     // state
+    let now = SystemTime::now();
     let mut holds_lock = false;
     let mut first_lock_seen: Option<&DavLock> = None;
 
@@ -211,7 +245,7 @@ fn check_locks_to_path(
         };
 
         for nl in node_locks {
-            if i < last_seg && !nl.deep {
+            if (i < last_seg && !nl.deep) || expired(nl, now) {
                 continue;
             }
             if submitted_tokens.iter().any(|t| &nl.token == t)
@@ -278,7 +312,8 @@ fn check_locks_from_node(
         Ok(n) => n,
         Err(_) => return Ok(()),
     };
-    for nl in node_locks {
+    let now = SystemTime::now();
+    for nl in node_locks.iter().filter(|nl| !expired(nl, now)) {
         if (!nl.shared || !shared_ok)
             && (!submitted_tokens.iter().any(|t| t == &nl.token)
                 || (!ignore_principal && principal != nl.principal.as_deref()))
@@ -321,6 +356,7 @@ fn get_or_create_path_node<'a>(tree: &'a mut Tree, path: &DavPath) -> &'a mut Ve
 fn lookup_lock(tree: &Tree, path: &DavPath, token: &str) -> Option<u64> {
     trace!("lookup_lock: {}", token);
 
+    let now = SystemTime::now();
     let mut node_id = tree::ROOT_ID;
     for seg in path_to_segs(path, true) {
         trace!(
@@ -334,7 +370,7 @@ fn lookup_lock(tree: &Tree, path: &DavPath, token: &str) -> Option<u64> {
         };
         let node = tree.get_node(node_id).unwrap();
         trace!("lookup_lock: locks here: {:?}", &node);
-        if node.iter().any(|n| n.token == token) {
+        if node.iter().any(|n| n.token == token && !expired(n, now)) {
             return Some(node_id);
         }
     }
@@ -354,21 +390,26 @@ fn lookup_node(tree: &Tree, path: &DavPath) -> Option<u64> {
     Some(node_id)
 }
 
-// Find all locks in a path
+// Find all locks that cover a path: the locks on the path itself,
+// and the depth-infinity locks on its ancestors.
 fn list_locks(tree: &Tree, path: &DavPath) -> Vec<DavLock> {
     let mut locks = Vec::new();
 
+    let segs = path_to_segs(path, false);
+    let last_seg = segs.len();
+
+    let now = SystemTime::now();
     let mut node_id = tree::ROOT_ID;
-    if let Ok(node) = tree.get_node(node_id) {
-        locks.extend_from_slice(node);
-    }
-    for seg in path_to_segs(path, false) {
-        node_id = match tree.get_child(node_id, seg) {
-            Ok(n) => n,
-            Err(_) => break,
-        };
+    for i in 0..=last_seg {
+        if i > 0 {
+            node_id = match tree.get_child(node_id, segs[i - 1]) {
+                Ok(n) => n,
+                Err(_) => break,
+            };
+        }
         if let Ok(node) = tree.get_node(node_id) {
-            locks.extend_from_slice(node);
+            let covers = |l: &&DavLock| (i == last_seg || l.deep) && !expired(l, now);
+            locks.extend(node.iter().filter(covers).cloned());
         }
     }
     locks
@@ -392,3 +433,44 @@ fn get_child(tree: &Tree, node_id: u64, seg: &[u8]) -> FsResult<u64> {
     }
     tree.get_child(node_id, seg)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expired_locks() {
+        let ls = MemLs::new();
+        let path = |p: &str| DavPath::new(p).unwrap();
+        let lock = |p: &str, timeout: Duration| {
+            ls.lock(&path(p), None, None, Some(timeout), false, true)
+                .unwrap()
+        };
+
+        // a lock with a zero timeout has expired right away.
+        let old = lock("/dir/", Duration::ZERO);
+        assert!(ls.discover(&path("/dir/file")).is_empty());
+        assert!(ls
+            .check(&path("/dir/file"), None, false, false, vec![])
+            .is_ok());
+        assert!(ls.check(&path("/"), None, false, true, vec![]).is_ok());
+        assert!(ls.unlock(&path("/dir/"), &old.token).is_err());
+        assert!(ls.refresh(&path("/dir/"), &old.token, None).is_err());
+
+        // it does not conflict with a new lock, and is swept on its own.
+        let new = lock("/dir/", Duration::from_secs(3600));
+        assert!(ls
+            .check(&path("/dir/file"), None, false, false, vec![])
+            .is_err());
+        ls.sweep();
+        let tree = &ls.0.lock().unwrap().tree;
+        let node = lookup_node(tree, &path("/dir/")).unwrap();
+        let tokens: Vec<_> = tree
+            .get_node(node)
+            .unwrap()
+            .iter()
+            .map(|l| &l.token)
+            .collect();
+        assert_eq!(tokens, vec![&new.token]);
+    }
+}
@@ -1,4 +1,6 @@
 use std::io;
+use std::sync::Arc;
+use std::time::Duration;
 
 use futures_util::{Stream, StreamExt};
 
@@ -11,16 +13,55 @@ use xml::EmitterConfig;
 use crate::async_stream::AsyncStream;
 use crate::body::Body;
 use crate::davpath::DavPath;
-use crate::util::MemBuffer;
+use crate::fs::FsError;
+use crate::handle_jobs::Job;
+use crate::util::{buffer_body, MemBuffer};
 use crate::DavError;
 
-type Sender = crate::async_stream::Sender<(DavPath, StatusCode), DavError>;
+// The status of one resource.
+pub(crate) struct StatusItem {
+    path: DavPath,
+    status: StatusCode,
+    // the message of an FsError::Other, for DavConfig::error_details.
+    description: Option<String>,
+    retry_after: Option<Duration>,
+}
+
+type Sender = crate::async_stream::Sender<StatusItem, DavError>;
 
-pub(crate) struct MultiError(Sender);
+pub(crate) struct MultiError {
+    tx: Sender,
+    details: bool,
+    // the background job this is part of, for its progress.
+    job: Option<Arc<Job>>,
+}
 
 impl MultiError {
-    pub fn new(sender: Sender) -> MultiError {
-        MultiError(sender)
+    // `details`: include the message of FsError::Other errors.
+    pub fn new(sender: Sender, details: bool) -> MultiError {
+        MultiError {
+            tx: sender,
+            details,
+            job: None,
+        }
+    }
+
+    pub fn with_job(self, job: Option<Arc<Job>>) -> MultiError {
+        MultiError { job, ..self }
+    }
+
+    // Report that `path` has been copied or moved.
+    pub fn progress(&self, path: &DavPath) {
+        if let Some(ref job) = self.job {
+            job.progress(path);
+        }
+    }
+
+    // Report that another resource to copy has been found.
+    pub fn found(&self) {
+        if let Some(ref job) = self.job {
+            job.found(1);
+        }
     }
 
     pub async fn add_status<'a>(
@@ -28,8 +69,46 @@ impl MultiError {
         path: &'a DavPath,
         status: impl Into<DavError> + 'static,
     ) -> Result<(), futures_channel::mpsc::SendError> {
-        let status = status.into().statuscode();
-        self.0.send((path.clone(), status)).await;
+        self.add_error(path, &status.into()).await
+    }
+
+    // A status with a description that is always included, for
+    // things the client needs to know about, like lost data.
+    pub async fn add_described<'a>(
+        &'a mut self,
+        path: &'a DavPath,
+        status: StatusCode,
+        description: &str,
+    ) -> Result<(), futures_channel::mpsc::SendError> {
+        let item = StatusItem {
+            path: path.clone(),
+            status,
+            description: Some(description.to_string()),
+            retry_after: None,
+        };
+        self.tx.send(item).await;
+        Ok(())
+    }
+
+    pub async fn add_error<'a>(
+        &'a mut self,
+        path: &'a DavPath,
+        err: &'a DavError,
+    ) -> Result<(), futures_channel::mpsc::SendError> {
+        let mut description = None;
+        if let Some(e @ FsError::Other { .. }) = err.fs_error() {
+            debug!("{}: {}", path, e);
+            if self.details {
+                description = Some(e.to_string());
+            }
+        }
+        let item = StatusItem {
+            path: path.clone(),
+            status: err.statuscode(),
+            description,
+            retry_after: err.retry_after(),
+        };
+        self.tx.send(item).await;
         Ok(())
     }
 }
@@ -49,11 +128,19 @@ where
     Ok(())
 }
 
-fn write_response(w: &mut XmlWriter, path: &DavPath, sc: StatusCode) -> Result<(), DavError> {
+fn write_response(w: &mut XmlWriter, item: &StatusItem) -> Result<(), DavError> {
+    // 204 is not a status for a single resource in a multistatus.
+    let sc = match item.status {
+        StatusCode::NO_CONTENT => StatusCode::OK,
+        sc => sc,
+    };
     w.write(XmlWEvent::start_element("D:response"))?;
-    let p = path.with_prefix().as_url_string();
+    let p = item.path.with_prefix().as_url_string();
     write_elem(w, "D:href", &p)?;
     write_elem(w, "D:status", &format!("HTTP/1.1 {}", sc))?;
+    if let Some(ref description) = item.description {
+        write_elem(w, "D:responsedescription", description)?;
+    }
     w.write(XmlWEvent::end_element())?;
     Ok(())
 }
@@ -63,11 +150,11 @@ pub(crate) async fn multi_error<S>(
     status_stream: S,
 ) -> Result<Response<Body>, DavError>
 where
-    S: Stream<Item = Result<(DavPath, StatusCode), DavError>> + Send + 'static,
+    S: Stream<Item = Result<StatusItem, DavError>> + Send + 'static,
 {
     // read the first path/status item
     let mut status_stream = Box::pin(status_stream);
-    let (path, status) = match status_stream.next().await {
+    let item = match status_stream.next().await {
         None => {
             debug!("multi_error: empty status_stream");
             return Err(DavError::ChanError);
@@ -78,27 +165,27 @@ where
 
     let mut items = Vec::new();
 
-    if path == req_path {
+    if item.path == req_path {
         // the first path/status item was for the request path.
         // see if there is a next item.
         match status_stream.next().await {
             None => {
                 // No, this was the first and only item.
-                let resp = Response::builder()
-                    .status(status)
-                    .body(Body::empty())
-                    .unwrap();
-                return Ok(resp);
+                let mut resp = Response::builder().status(item.status);
+                if let Some(after) = item.retry_after {
+                    resp = resp.header("Retry-After", after.as_secs());
+                }
+                return Ok(resp.body(Body::empty()).unwrap());
             }
             Some(Err(e)) => return Err(e),
-            Some(Ok(item)) => {
+            Some(Ok(next)) => {
                 // Yes, more than one response.
-                items.push(Ok((path, status)));
                 items.push(Ok(item));
+                items.push(Ok(next));
             }
         }
     } else {
-        items.push(Ok((path, status)));
+        items.push(Ok(item));
     }
 
     // Transform path/status items to XML.
@@ -126,13 +213,7 @@ where
             // now write the items.
             let mut status_stream = futures_util::stream::iter(items).chain(status_stream);
             while let Some(res) = status_stream.next().await {
-                let (path, status) = res?;
-                let status = if status == StatusCode::NO_CONTENT {
-                    StatusCode::OK
-                } else {
-                    status
-                };
-                write_response(&mut xw, &path, status)?;
+                write_response(&mut xw, &res?)?;
                 let data = xw.inner_mut().take();
                 tx.send(data).await;
             }
@@ -147,10 +228,11 @@ where
     });
 
     // return response.
-    let resp = Response::builder()
+    let mut resp = Response::builder()
         .header("content-type", "application/xml; charset=utf-8")
         .status(StatusCode::MULTI_STATUS)
         .body(Body::from(body))
         .unwrap();
+    buffer_body(&mut resp).await?;
     Ok(resp)
 }
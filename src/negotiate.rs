@@ -0,0 +1,151 @@
+// Content negotiation on the Accept header (RFC 7231 5.3.2).
+//
+// Used for generated content that can be sent in more than one form,
+// like the directory index. Media type parameters other than "q" are
+// ignored, so "text/html;level=1" is the same as "text/html".
+//
+
+// One media range from an Accept header.
+struct MediaRange<'a> {
+    mtype: &'a str,
+    subtype: &'a str,
+    // quality, in thousandths.
+    q: u16,
+}
+
+impl<'a> MediaRange<'a> {
+    fn parse(s: &'a str) -> Option<MediaRange<'a>> {
+        let mut parts = s.split(';');
+        let (mtype, subtype) = parts.next()?.trim().split_once('/')?;
+        if mtype.is_empty() || subtype.is_empty() || (mtype == "*" && subtype != "*") {
+            return None;
+        }
+        let mut q = 1000;
+        for param in parts {
+            if let Some((name, value)) = param.split_once('=') {
+                if name.trim().eq_ignore_ascii_case("q") {
+                    q = parse_qvalue(value.trim())?;
+                    // anything after q are accept-ext parameters.
+                    break;
+                }
+            }
+        }
+        Some(MediaRange { mtype, subtype, q })
+    }
+
+    // How specific the match with `mtype/subtype` is, if it matches.
+    fn specificity(&self, mtype: &str, subtype: &str) -> Option<u8> {
+        if self.mtype == "*" {
+            Some(0)
+        } else if !self.mtype.eq_ignore_ascii_case(mtype) {
+            None
+        } else if self.subtype == "*" {
+            Some(1)
+        } else if self.subtype.eq_ignore_ascii_case(subtype) {
+            Some(2)
+        } else {
+            None
+        }
+    }
+}
+
+// qvalue = ( "0" [ "." 0*3DIGIT ] ) / ( "1" [ "." 0*3("0") ] )
+fn parse_qvalue(s: &str) -> Option<u16> {
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    if frac.len() > 3 || !frac.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let frac = format!("{:0<3}", frac).parse::<u16>().ok()?;
+    match int {
+        "0" => Some(frac),
+        "1" if frac == 0 => Some(1000),
+        _ => None,
+    }
+}
+
+// Pick one of `offers` (media types like "text/html", in order of
+// preference) for a request with this Accept header.
+//
+// Each offer gets the quality of the most specific range that matches
+// it. The offer with the highest quality wins; on a tie, the one that
+// comes first in `offers`. Returns `None` if the client accepts none of
+// them. No Accept header, or one without a single valid range, accepts
+// anything.
+pub(crate) fn negotiate<'a>(accept: Option<&str>, offers: &[&'a str]) -> Option<&'a str> {
+    let ranges = accept
+        .into_iter()
+        .flat_map(|a| a.split(','))
+        .filter_map(MediaRange::parse)
+        .collect::<Vec<_>>();
+    if ranges.is_empty() {
+        return offers.first().copied();
+    }
+
+    let mut best: Option<(&'a str, u16)> = None;
+    for offer in offers {
+        let (mtype, subtype) = offer.split_once('/').unwrap_or((offer, ""));
+        let q = ranges
+            .iter()
+            .filter_map(|r| r.specificity(mtype, subtype).map(|s| (s, r.q)))
+            .max_by_key(|&(s, _)| s)
+            .map_or(0, |(_, q)| q);
+        if q > 0 && !matches!(best, Some((_, bq)) if bq >= q) {
+            best = Some((offer, q));
+        }
+    }
+    best.map(|(offer, _)| offer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFERS: &[&str] = &["text/html", "application/json"];
+
+    #[test]
+    fn test_negotiate() {
+        let n = |accept| negotiate(accept, OFFERS);
+        assert_eq!(n(None), Some("text/html"));
+        assert_eq!(n(Some("*/*")), Some("text/html"));
+        assert_eq!(n(Some("application/*;q=0.5, text/html")), Some("text/html"));
+        assert_eq!(
+            n(Some("application/*;q=0.5, text/html;q=0.4")),
+            Some("application/json")
+        );
+        assert_eq!(
+            n(Some("application/json, */*;q=0.1")),
+            Some("application/json")
+        );
+        assert_eq!(
+            n(Some("text/*;q=0.3, TEXT/HTML;level=1;q=0.7")),
+            Some("text/html")
+        );
+
+        // the most specific range decides, also if it excludes.
+        assert_eq!(n(Some("*/*, text/html;q=0")), Some("application/json"));
+
+        // nothing acceptable.
+        assert_eq!(n(Some("image/png")), None);
+        assert_eq!(n(Some("text/plain, application/xml;q=0.9")), None);
+        assert_eq!(n(Some("*/*;q=0")), None);
+
+        // invalid ranges are skipped.
+        assert_eq!(n(Some("garbage")), Some("text/html"));
+        assert_eq!(
+            n(Some("*/html, application/json;q=2, application/json")),
+            Some("application/json")
+        );
+    }
+
+    #[test]
+    fn test_qvalue() {
+        assert_eq!(parse_qvalue("1"), Some(1000));
+        assert_eq!(parse_qvalue("1.000"), Some(1000));
+        assert_eq!(parse_qvalue("0.5"), Some(500));
+        assert_eq!(parse_qvalue("0.05"), Some(50));
+        assert_eq!(parse_qvalue("0"), Some(0));
+        assert_eq!(parse_qvalue("1.5"), None);
+        assert_eq!(parse_qvalue("0.1234"), None);
+        assert_eq!(parse_qvalue(".5"), None);
+    }
+}
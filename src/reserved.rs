@@ -0,0 +1,284 @@
+// URL paths that are served by the handler, but that are not part of
+// the webdav namespace.
+//
+// Features register them here: the health endpoint and the job status
+// resources. Users can reserve paths too, with `DavConfig::reserved_prefix`,
+// `DavConfig::reserved_handler` and `DavConfig::reserved_name`. A reserved
+// resource is left out of PROPFIND listings, the autoindex and archives,
+// and can not be created with PUT, PATCH, MKCOL, MKREDIRECTREF, COPY or
+// MOVE. A COPY of a collection leaves reserved members out. Requests for
+// the path of a feature or of a handler are routed to it by the
+// dispatcher.
+//
+// The namespace is built once, in `DavConfig::build_handler`.
+//
+use std::sync::Arc;
+
+use crate::davhandler::ExtensionHandler;
+use crate::davpath::DavPath;
+use crate::util::in_url_path;
+
+// What handles the requests for a reserved prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Route {
+    Health,
+    Jobs,
+    // a handler of the user, an index in `handlers`.
+    Handler(usize),
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct ReservedNamespace {
+    // URL paths, matched before the prefix is stripped.
+    prefixes: Vec<(String, Option<Route>)>,
+    // patterns for a path segment, a `*` matches anything.
+    names: Vec<String>,
+    handlers: Vec<Arc<dyn ExtensionHandler>>,
+}
+
+impl ReservedNamespace {
+    pub(crate) fn add_prefix(&mut self, prefix: impl Into<String>, route: Option<Route>) {
+        self.prefixes.push((prefix.into(), route));
+    }
+
+    pub(crate) fn add_handler(&mut self, prefix: impl Into<String>, h: Arc<dyn ExtensionHandler>) {
+        self.handlers.push(h);
+        let route = Route::Handler(self.handlers.len() - 1);
+        self.add_prefix(prefix, Some(route));
+    }
+
+    pub(crate) fn handler(&self, n: usize) -> Arc<dyn ExtensionHandler> {
+        self.handlers[n].clone()
+    }
+
+    pub(crate) fn add_name(&mut self, pattern: impl Into<String>) {
+        self.names.push(pattern.into());
+    }
+
+    // The feature that handles the request path `path`.
+    pub(crate) fn route(&self, path: &str) -> Option<Route> {
+        self.prefixes
+            .iter()
+            .find(|(prefix, route)| route.is_some() && in_url_path(prefix, path))
+            .and_then(|(_, route)| *route)
+    }
+
+    // Can `path`, or anything below it, be a reserved resource.
+    pub(crate) fn overlaps(&self, path: &DavPath) -> bool {
+        !self.names.is_empty() || self.prefix_below(path) || self.contains(path)
+    }
+
+    // Is there a reserved prefix at or below `path`. Moving `path` would
+    // move it out of the reserved namespace.
+    pub(crate) fn prefix_below(&self, path: &DavPath) -> bool {
+        let url = path.with_prefix().as_url_string();
+        self.prefixes.iter().any(|(p, _)| in_url_path(&url, p))
+    }
+
+    // Is `path` a reserved resource, or below one.
+    pub(crate) fn contains(&self, path: &DavPath) -> bool {
+        if !self.prefixes.is_empty() {
+            let url = path.with_prefix().as_url_string();
+            if self.prefixes.iter().any(|(p, _)| in_url_path(p, &url)) {
+                return true;
+            }
+        }
+        if self.names.is_empty() {
+            return false;
+        }
+        path.as_bytes()
+            .split(|&c| c == b'/')
+            .filter(|s| !s.is_empty())
+            .any(|s| self.names.iter().any(|n| glob_match(n.as_bytes(), s)))
+    }
+}
+
+// Does `name` match `pattern`, in which a `*` matches any sequence.
+pub(crate) fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, n));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == name[n] {
+            p += 1;
+            n += 1;
+        } else if let Some((sp, sn)) = star {
+            // let the last star match one more byte.
+            p = sp + 1;
+            n = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Body;
+    use crate::fs::{DavFileSystem, OpenOptions};
+    use crate::memfs::MemFs;
+    use crate::testing::{DavClient, Depth};
+    use crate::DavHandler;
+    use http::{Request, StatusCode};
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b".props", b".props"));
+        assert!(glob_match(b".props*", b".props-file"));
+        assert!(glob_match(b"*.tmp", b"a.b.tmp"));
+        assert!(glob_match(b"a*b*c", b"axxbyyc"));
+        assert!(glob_match(b"*", b""));
+        assert!(!glob_match(b".props", b".props2"));
+        assert!(!glob_match(b"*.tmp", b"a.tmpx"));
+        assert!(!glob_match(b"a*b", b"ac"));
+    }
+
+    #[tokio::test]
+    async fn test_reserved_namespace() {
+        let fs = MemFs::new();
+        let path = |p: &str| DavPath::new(p).unwrap();
+        fs.create_dir(&path("/private/")).await.unwrap();
+        fs.create_dir(&path("/dir/")).await.unwrap();
+        for p in ["/private/file", "/dir/_props-file", "/dir/file"] {
+            let mut oo = OpenOptions::write();
+            oo.create = true;
+            fs.open(&path(p), oo).await.unwrap();
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .reserved_prefix("/private")
+            .reserved_name("_props*")
+            .autoindex(true)
+            .build_handler();
+        let client = DavClient::new(dav);
+
+        // not listed.
+        let ms = client.propfind("/", Depth::One, &[]).await.unwrap();
+        assert_eq!(ms.hrefs(), ["/", "/dir/"]);
+        let ms = client.propfind("/dir/", Depth::One, &[]).await.unwrap();
+        assert_eq!(ms.hrefs(), ["/dir/", "/dir/file"]);
+        let req = Request::get("/dir/")
+            .header("Accept", "text/html")
+            .body(Body::empty())
+            .unwrap();
+        let index = client.request(req).await;
+        let index = String::from_utf8_lossy(index.body());
+        assert!(index.contains("file") && !index.contains("_props"));
+
+        // and cannot be created.
+        assert_eq!(
+            client.put_bytes("/private/new", "x").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(client.mkcol("/dir/_props2/").await, StatusCode::FORBIDDEN);
+        assert_eq!(
+            client.copy("/dir/file", "/dir/_props-copy", false).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            client.move_("/dir/file", "/private/file2", false).await,
+            StatusCode::FORBIDDEN
+        );
+
+        // a sibling is not reserved.
+        assert_eq!(client.mkcol("/privatedir/").await, StatusCode::CREATED);
+        assert_eq!(
+            client.put_bytes("/dir/props", "x").await,
+            StatusCode::CREATED
+        );
+        let ms = client.propfind("/", Depth::One, &[]).await.unwrap();
+        let mut hrefs = ms.hrefs();
+        hrefs.sort();
+        assert_eq!(hrefs, ["/", "/dir/", "/privatedir/"]);
+
+        // a COPY of the parent leaves reserved members out.
+        assert_eq!(
+            client.copy("/dir/", "/dir2/", false).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(client.get("/dir2/file").await.status(), StatusCode::OK);
+        assert!(fs.metadata(&path("/dir2/_props-file")).await.is_err());
+        assert!(fs.metadata(&path("/dir/_props-file")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_reserved_prefix_below() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .reserved_prefix("/area/inner")
+            .build_handler();
+        let client = DavClient::new(dav);
+        assert_eq!(client.mkcol("/area/").await, StatusCode::CREATED);
+        assert_eq!(client.mkcol("/other/").await, StatusCode::CREATED);
+
+        // moving or replacing /area/ would take /area/inner along.
+        let status = client.move_("/area/", "/area2/", false).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = client.copy("/other/", "/area/", true).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = client.copy("/area/", "/area2/", false).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_reserved_handler() {
+        use bytes::Bytes;
+        use futures_util::future::{BoxFuture, FutureExt};
+        use std::sync::Arc;
+
+        struct Hook;
+
+        impl ExtensionHandler for Hook {
+            fn handle(
+                &self,
+                path: DavPath,
+                req: Request<Body>,
+            ) -> BoxFuture<'_, http::Response<Body>> {
+                async move {
+                    let text = format!("{} {}", req.method(), path.as_url_string());
+                    http::Response::new(Body::from(Bytes::from(text)))
+                }
+                .boxed()
+            }
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .strip_prefix("/dav")
+            .reserved_handler("/dav/hook", Arc::new(Hook))
+            .reserved_handler("/status", Arc::new(Hook))
+            .build_handler();
+        let client = DavClient::new(dav);
+
+        // any method goes to the handler, inside the prefix or not.
+        for (method, uri, body) in [
+            ("GET", "/dav/hook/x", "GET /hook/x"),
+            ("PUT", "/dav/hook", "PUT /hook"),
+            ("PROPFIND", "/status", "PROPFIND /status"),
+        ] {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            let res = client.request(req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.body().as_ref(), body.as_bytes());
+        }
+
+        // a sibling is not routed, and the route cannot be created.
+        assert_eq!(
+            client.get("/dav/hooks").await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(client.mkcol("/dav/dir/").await, StatusCode::CREATED);
+        let status = client.copy("/dav/dir/", "/dav/hook/", false).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+}
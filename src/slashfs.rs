@@ -0,0 +1,194 @@
+//! A path that ends in a slash is a collection.
+//!
+//! `SlashFs` wraps the filesystem of each request, and makes all
+//! backends agree on this: a file at a path with a trailing slash,
+//! like `/file/`, does not exist, so every method answers it with
+//! `404 Not Found`.
+//!
+use std::time::SystemTime;
+
+use futures_util::{future, FutureExt};
+use http::StatusCode;
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+use crate::DavMethodSet;
+
+#[derive(Clone)]
+pub(crate) struct SlashFs {
+    fs: Box<dyn DavFileSystem>,
+}
+
+impl SlashFs {
+    pub(crate) fn new(fs: Box<dyn DavFileSystem>) -> Box<SlashFs> {
+        Box::new(SlashFs { fs })
+    }
+
+    fn check<'a>(
+        &'a self,
+        path: &'a DavPath,
+        lookup: FsFuture<'a, Box<dyn DavMetaData>>,
+    ) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        async move {
+            // "/file/" is not "/file". A symlink is looked at as it is, and
+            // the root can be a single file (see LocalFs::new_file).
+            let slash = path.is_collection() && path.as_bytes() != b"/";
+            match lookup.await {
+                Ok(meta) if slash && !meta.is_dir() && !meta.is_symlink() => Err(FsError::NotFound),
+                res => res,
+            }
+        }
+        .boxed()
+    }
+}
+
+impl DavFileSystem for SlashFs {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        self.fs.open(path, options)
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        self.fs.read_dir(path, meta)
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.check(path, self.fs.metadata(path))
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.check(path, self.fs.symlink_metadata(path))
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.create_dir(path)
+    }
+
+    fn create_collection<'a>(
+        &'a self,
+        path: &'a DavPath,
+        resourcetypes: Vec<DavProp>,
+    ) -> FsFuture<'a, ()> {
+        self.fs.create_collection(path, resourcetypes)
+    }
+
+    fn create_redirect_ref<'a>(
+        &'a self,
+        path: &'a DavPath,
+        target: RedirectRef,
+    ) -> FsFuture<'a, ()> {
+        self.fs.create_redirect_ref(path, target)
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.remove_dir(path)
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.remove_file(path)
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.rename(from, to)
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.copy(from, to)
+    }
+
+    fn rename_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.rename_no_replace(from, to)
+    }
+
+    fn copy_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.copy_no_replace(from, to)
+    }
+
+    fn copy_recursive<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.copy_recursive(from, to)
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        self.fs.set_accessed(path, tm)
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        self.fs.set_modified(path, tm)
+    }
+
+    fn have_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+    ) -> std::pin::Pin<Box<dyn future::Future<Output = bool> + Send + 'a>> {
+        self.fs.have_props(path)
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(StatusCode, DavProp)>> {
+        self.fs.patch_props(path, patch)
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        self.fs.get_props(path, do_content)
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        self.fs.get_prop(path, prop)
+    }
+
+    fn get_quota(&self) -> FsFuture<'_, (u64, Option<u64>)> {
+        self.fs.get_quota()
+    }
+
+    fn get_ctag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        self.fs.get_ctag(path)
+    }
+
+    fn allowed_methods(&self) -> Option<DavMethodSet> {
+        self.fs.allowed_methods()
+    }
+
+    fn healthcheck(&self) -> FsFuture<'_, ()> {
+        self.fs.healthcheck()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+
+    #[tokio::test]
+    async fn test_slashfs() {
+        let fs = SlashFs::new(MemFs::new());
+        let file = DavPath::new("/file").unwrap();
+        let dir = DavPath::new("/dir").unwrap();
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        fs.open(&file, oo).await.unwrap();
+        fs.create_dir(&DavPath::new("/dir/").unwrap())
+            .await
+            .unwrap();
+
+        assert!(fs.metadata(&file).await.unwrap().is_file());
+        assert!(fs.metadata(&dir).await.unwrap().is_dir());
+        let file = DavPath::new("/file/").unwrap();
+        assert!(matches!(fs.metadata(&file).await, Err(FsError::NotFound)));
+        assert!(matches!(
+            fs.symlink_metadata(&file).await,
+            Err(FsError::NotFound)
+        ));
+        let dir = DavPath::new("/dir/").unwrap();
+        assert!(fs.metadata(&dir).await.unwrap().is_dir());
+    }
+}
@@ -0,0 +1,835 @@
+//! Filesystem with scripted failures, for tests.
+//!
+//! `MockFs` wraps another filesystem (a [`MemFs`] by default) and passes
+//! every call on to it, unless a rule says otherwise. A rule matches an
+//! operation on a path, and makes it fail with an [`FsError`], delays
+//! it, or changes what it returns. That makes it easy to test how a
+//! handler or a client reacts to errors that are hard to produce with
+//! real IO: a full disk, a file that cannot be read, a backend that is
+//! slow to answer. It also counts the calls, so that a test can check
+//! how often the handler went to the filesystem.
+//!
+//! ```
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! use dav_server::testfs::{MockFs, MockOp};
+//! use dav_server::{fs::FsError, testing::DavClient, DavHandler};
+//! use http::StatusCode;
+//!
+//! let fs = MockFs::builder()
+//!     .fail(MockOp::Write, "/full", FsError::InsufficientStorage)
+//!     .build();
+//! let dav = DavHandler::builder().filesystem(fs).build_handler();
+//! let client = DavClient::new(dav);
+//!
+//! assert_eq!(client.put_bytes("/full", "hello").await, StatusCode::INSUFFICIENT_STORAGE);
+//! assert_eq!(client.put_bytes("/file", "hello").await, StatusCode::CREATED);
+//! # }
+//! ```
+//!
+//! [`MemFs`]: ../memfs/struct.MemFs.html
+//! [`FsError`]: ../fs/enum.FsError.html
+//!
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use futures_util::{future, FutureExt, StreamExt};
+use http::StatusCode;
+use tokio::sync::Semaphore;
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+use crate::memfs::MemFs;
+use crate::reserved::glob_match;
+use crate::DavMethodSet;
+
+/// The operations that a rule can match.
+///
+/// Most of them are a `DavFileSystem` method, or a group of methods
+/// that do the same thing. `Read`, `Write` and `Flush` are methods
+/// of a `DavFile`, and match the path that the file was opened with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MockOp {
+    /// `open`.
+    Open,
+    /// `read_dir`.
+    ReadDir,
+    /// `metadata` and `symlink_metadata`, of the filesystem, of an
+    /// entry in a directory listing, and of an open file.
+    Metadata,
+    /// `create_dir`, `create_collection` and `create_redirect_ref`.
+    Create,
+    /// `remove_dir`.
+    RemoveDir,
+    /// `remove_file`.
+    RemoveFile,
+    /// `rename` and `rename_no_replace`. Matches the source and the destination.
+    Rename,
+    /// `copy`, `copy_no_replace` and `copy_recursive`. Matches the source and the destination.
+    Copy,
+    /// `set_accessed` and `set_modified`.
+    SetTimes,
+    /// `have_props`.
+    HaveProps,
+    /// `get_props`, `get_prop` and `get_ctag`.
+    GetProps,
+    /// `patch_props`.
+    PatchProps,
+    /// `get_quota`. Matches the path `/`.
+    Quota,
+    /// `healthcheck`. Matches the path `/`.
+    Healthcheck,
+    /// `read_bytes` and `seek`.
+    Read,
+    /// `write_buf`, `write_bytes` and `set_len`.
+    Write,
+    /// `flush` and `sync`.
+    Flush,
+}
+
+/// What a rule does with an operation that it matches.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum MockAction {
+    /// Fail with this error.
+    Fail(FsError),
+    /// Wait this long, then go on.
+    Delay(Duration),
+    /// Take a permit of the semaphore, then go on. The permit is used
+    /// up, so a test can let operations through one by one.
+    Wait(Arc<Semaphore>),
+    /// Go on, but let other tasks run before returning the result.
+    Yield,
+    /// `Read` only: return at most this many bytes per read.
+    ShortRead(usize),
+    /// `Metadata` only: report this ETag, and whether it is weak.
+    ETag(String, bool),
+    /// `Metadata` only: report no creation time, like a filesystem
+    /// that does not record it.
+    NoCreated,
+}
+
+#[derive(Debug)]
+struct Rule {
+    op: MockOp,
+    path: String,
+    action: MockAction,
+    // how many more times it applies, `None` is forever.
+    times: Option<usize>,
+}
+
+impl Rule {
+    // A `*` in the pattern matches any sequence, also of several path
+    // segments. "/dir" and "/dir/" are the same.
+    fn matches(&self, path: &[u8]) -> bool {
+        glob_match(trim_slash(self.path.as_bytes()), trim_slash(path))
+    }
+}
+
+fn trim_slash(p: &[u8]) -> &[u8] {
+    match p {
+        [rest @ .., b'/'] if !rest.is_empty() => rest,
+        _ => p,
+    }
+}
+
+// What the rules said about an operation that may go on.
+#[derive(Default)]
+struct Outcome {
+    yield_after: bool,
+    short_read: Option<usize>,
+    etag: Option<(String, bool)>,
+    no_created: bool,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    calls: HashMap<MockOp, usize>,
+    active: HashMap<MockOp, usize>,
+    max_active: HashMap<MockOp, usize>,
+    reads: Vec<(usize, usize)>,
+    open_files: usize,
+}
+
+#[derive(Debug, Default)]
+struct Rules {
+    rules: Mutex<Vec<Rule>>,
+    stats: Mutex<Stats>,
+}
+
+impl Rules {
+    // Count the call, and apply the rules for `op` on `paths`: all
+    // delays that match, up to the first failure.
+    async fn check(&self, op: MockOp, paths: &[&[u8]]) -> FsResult<Outcome> {
+        *self.stats.lock().unwrap().calls.entry(op).or_default() += 1;
+        let mut outcome = Outcome::default();
+        let (delay, wait, err) = {
+            let mut rules = self.rules.lock().unwrap();
+            let mut delay = Duration::ZERO;
+            let mut wait = Vec::new();
+            let mut err = None;
+            for rule in rules.iter_mut() {
+                if rule.op != op || rule.times == Some(0) {
+                    continue;
+                }
+                if !paths.iter().any(|p| rule.matches(p)) {
+                    continue;
+                }
+                if let Some(ref mut times) = rule.times {
+                    *times -= 1;
+                }
+                match rule.action {
+                    MockAction::Delay(d) => delay += d,
+                    MockAction::Wait(ref sem) => wait.push(sem.clone()),
+                    MockAction::Yield => outcome.yield_after = true,
+                    MockAction::ShortRead(n) => outcome.short_read = Some(n),
+                    MockAction::ETag(ref tag, weak) => outcome.etag = Some((tag.clone(), weak)),
+                    MockAction::NoCreated => outcome.no_created = true,
+                    MockAction::Fail(ref e) => {
+                        err = Some(e.clone());
+                        break;
+                    }
+                }
+            }
+            (delay, wait, err)
+        };
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        for sem in wait {
+            if let Ok(permit) = sem.acquire().await {
+                permit.forget();
+            }
+        }
+        match err {
+            Some(e) => Err(e),
+            None => Ok(outcome),
+        }
+    }
+
+    // Mark `op` as running until the guard is dropped.
+    fn enter(self: &Arc<Self>, op: MockOp) -> ActiveGuard {
+        let mut stats = self.stats.lock().unwrap();
+        let active = stats.active.entry(op).or_default();
+        *active += 1;
+        let active = *active;
+        let max = stats.max_active.entry(op).or_default();
+        *max = (*max).max(active);
+        ActiveGuard {
+            rules: self.clone(),
+            op,
+        }
+    }
+}
+
+struct ActiveGuard {
+    rules: Arc<Rules>,
+    op: MockOp,
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        let mut stats = self.rules.stats.lock().unwrap();
+        if let Some(active) = stats.active.get_mut(&self.op) {
+            *active -= 1;
+        }
+    }
+}
+
+// Run `f` for `op` on `paths`, after the rules had their say.
+async fn run<'a, T, F>(rules: &'a Arc<Rules>, op: MockOp, paths: [&'a [u8]; 2], f: F) -> FsResult<T>
+where
+    F: FnOnce(Outcome) -> FsFuture<'a, T>,
+{
+    let _active = rules.enter(op);
+    let outcome = rules.check(op, &paths).await?;
+    let yield_after = outcome.yield_after;
+    let res = f(outcome).await;
+    if yield_after {
+        tokio::task::yield_now().await;
+    }
+    res
+}
+
+/// Builder for a [`MockFs`].
+///
+/// Rules are tried in the order they were added.
+pub struct MockFsBuilder {
+    fs: Option<Box<dyn DavFileSystem>>,
+    rules: Vec<Rule>,
+}
+
+impl MockFsBuilder {
+    /// The filesystem to pass the calls on to (default an empty `MemFs`).
+    pub fn filesystem(self, fs: Box<dyn DavFileSystem>) -> Self {
+        let mut this = self;
+        this.fs = Some(fs);
+        this
+    }
+
+    /// Add a rule for `op` on `path`.
+    ///
+    /// `path` is the path without the prefix of the handler. If it ends
+    /// in `*`, it matches every path that starts with the rest, so `*`
+    /// alone matches everything. `times` limits how often the rule
+    /// applies, after that it is ignored.
+    pub fn rule(self, op: MockOp, path: &str, action: MockAction, times: Option<usize>) -> Self {
+        let mut this = self;
+        this.rules.push(Rule {
+            op,
+            path: path.to_string(),
+            action,
+            times,
+        });
+        this
+    }
+
+    /// Make `op` on `path` always fail with `err`.
+    pub fn fail(self, op: MockOp, path: &str, err: FsError) -> Self {
+        self.rule(op, path, MockAction::Fail(err), None)
+    }
+
+    /// Make `op` on `path` fail with `err` the first `times` times.
+    pub fn fail_times(self, op: MockOp, path: &str, err: FsError, times: usize) -> Self {
+        self.rule(op, path, MockAction::Fail(err), Some(times))
+    }
+
+    /// Delay `op` on `path` by `delay`.
+    pub fn delay(self, op: MockOp, path: &str, delay: Duration) -> Self {
+        self.rule(op, path, MockAction::Delay(delay), None)
+    }
+
+    /// Let `op` on `path` wait for a permit of `gate`, that it uses up.
+    pub fn wait(self, op: MockOp, path: &str, gate: Arc<Semaphore>) -> Self {
+        self.rule(op, path, MockAction::Wait(gate), None)
+    }
+
+    /// Report `tag` as the ETag of `path`.
+    pub fn etag(self, path: &str, tag: &str, weak: bool) -> Self {
+        self.rule(
+            MockOp::Metadata,
+            path,
+            MockAction::ETag(tag.to_string(), weak),
+            None,
+        )
+    }
+
+    /// Report no creation time for `path`.
+    pub fn no_created(self, path: &str) -> Self {
+        self.rule(MockOp::Metadata, path, MockAction::NoCreated, None)
+    }
+
+    /// Build the filesystem.
+    pub fn build(self) -> Box<MockFs> {
+        Box::new(MockFs {
+            fs: self.fs.unwrap_or_else(|| MemFs::new()),
+            rules: Arc::new(Rules {
+                rules: Mutex::new(self.rules),
+                stats: Mutex::default(),
+            }),
+        })
+    }
+}
+
+/// Filesystem with scripted failures and delays.
+///
+/// Clones share the rules, and the underlying filesystem if that is
+/// shared by its clones too, like `MemFs`.
+#[derive(Clone)]
+pub struct MockFs {
+    fs: Box<dyn DavFileSystem>,
+    rules: Arc<Rules>,
+}
+
+impl MockFs {
+    /// Start building a new `MockFs`.
+    pub fn builder() -> MockFsBuilder {
+        MockFsBuilder {
+            fs: None,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Add a rule while the filesystem is in use. Clones see it too.
+    pub fn add_rule(&self, op: MockOp, path: &str, action: MockAction, times: Option<usize>) {
+        self.rules.rules.lock().unwrap().push(Rule {
+            op,
+            path: path.to_string(),
+            action,
+            times,
+        });
+    }
+
+    /// How often `op` was called, on any path.
+    pub fn calls(&self, op: MockOp) -> usize {
+        let stats = self.rules.stats.lock().unwrap();
+        stats.calls.get(&op).copied().unwrap_or(0)
+    }
+
+    /// The most calls of `op` that ever ran at the same time.
+    pub fn max_concurrent(&self, op: MockOp) -> usize {
+        let stats = self.rules.stats.lock().unwrap();
+        stats.max_active.get(&op).copied().unwrap_or(0)
+    }
+
+    /// How many files opened through this filesystem are still open.
+    pub fn open_files(&self) -> usize {
+        self.rules.stats.lock().unwrap().open_files
+    }
+
+    /// The size asked for and the size returned of every `read_bytes`
+    /// since the last call of this method.
+    pub fn take_reads(&self) -> Vec<(usize, usize)> {
+        std::mem::take(&mut self.rules.stats.lock().unwrap().reads)
+    }
+
+    // check the rules, then call `f` on the filesystem.
+    fn mocked<'a, T, F>(&'a self, op: MockOp, paths: [&'a [u8]; 2], f: F) -> FsFuture<'a, T>
+    where
+        T: Send + 'a,
+        F: FnOnce(&'a dyn DavFileSystem) -> FsFuture<'a, T> + Send + 'a,
+    {
+        run(&self.rules, op, paths, move |_| f(&*self.fs)).boxed()
+    }
+}
+
+impl DavFileSystem for MockFs {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        let p = path.as_bytes();
+        self.mocked(MockOp::Open, [p, p], move |fs| {
+            async move {
+                let file = fs.open(path, options).await?;
+                self.rules.stats.lock().unwrap().open_files += 1;
+                Ok(Box::new(MockFile {
+                    file,
+                    path: path.as_bytes().to_vec(),
+                    rules: self.rules.clone(),
+                }) as Box<dyn DavFile>)
+            }
+            .boxed()
+        })
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        let p = path.as_bytes();
+        self.mocked(MockOp::ReadDir, [p, p], move |fs| {
+            async move {
+                let entries = fs.read_dir(path, meta).await?;
+                let dir = path.clone();
+                let rules = self.rules.clone();
+                let entries = entries.map(move |entry| {
+                    let mut path = dir.clone();
+                    path.push_segment(&entry.name());
+                    Box::new(MockDirEntry {
+                        entry,
+                        path: path.as_bytes().to_vec(),
+                        rules: rules.clone(),
+                    }) as Box<dyn DavDirEntry>
+                });
+                Ok(Box::pin(entries) as FsStream<Box<dyn DavDirEntry>>)
+            }
+            .boxed()
+        })
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        let p = path.as_bytes();
+        let fut = run(&self.rules, MockOp::Metadata, [p, p], move |outcome| {
+            MockMeta::wrap(self.fs.metadata(path), outcome)
+        });
+        fut.boxed()
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        let p = path.as_bytes();
+        let fut = run(&self.rules, MockOp::Metadata, [p, p], move |outcome| {
+            MockMeta::wrap(self.fs.symlink_metadata(path), outcome)
+        });
+        fut.boxed()
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        let p = path.as_bytes();
+        self.mocked(MockOp::Create, [p, p], move |fs| fs.create_dir(path))
+    }
+
+    fn create_collection<'a>(
+        &'a self,
+        path: &'a DavPath,
+        resourcetypes: Vec<DavProp>,
+    ) -> FsFuture<'a, ()> {
+        let p = path.as_bytes();
+        self.mocked(MockOp::Create, [p, p], move |fs| {
+            fs.create_collection(path, resourcetypes)
+        })
+    }
+
+    fn create_redirect_ref<'a>(
+        &'a self,
+        path: &'a DavPath,
+        target: RedirectRef,
+    ) -> FsFuture<'a, ()> {
+        let p = path.as_bytes();
+        self.mocked(MockOp::Create, [p, p], move |fs| {
+            fs.create_redirect_ref(path, target)
+        })
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        let p = path.as_bytes();
+        self.mocked(MockOp::RemoveDir, [p, p], move |fs| fs.remove_dir(path))
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        let p = path.as_bytes();
+        self.mocked(MockOp::RemoveFile, [p, p], move |fs| fs.remove_file(path))
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        let paths = [from.as_bytes(), to.as_bytes()];
+        self.mocked(MockOp::Rename, paths, move |fs| fs.rename(from, to))
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        let paths = [from.as_bytes(), to.as_bytes()];
+        self.mocked(MockOp::Copy, paths, move |fs| fs.copy(from, to))
+    }
+
+    fn rename_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        let paths = [from.as_bytes(), to.as_bytes()];
+        self.mocked(MockOp::Rename, paths, move |fs| {
+            fs.rename_no_replace(from, to)
+        })
+    }
+
+    fn copy_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        let paths = [from.as_bytes(), to.as_bytes()];
+        self.mocked(MockOp::Copy, paths, move |fs| fs.copy_no_replace(from, to))
+    }
+
+    fn copy_recursive<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        let paths = [from.as_bytes(), to.as_bytes()];
+        self.mocked(MockOp::Copy, paths, move |fs| fs.copy_recursive(from, to))
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        let p = path.as_bytes();
+        self.mocked(MockOp::SetTimes, [p, p], move |fs| {
+            fs.set_accessed(path, tm)
+        })
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        let p = path.as_bytes();
+        self.mocked(MockOp::SetTimes, [p, p], move |fs| {
+            fs.set_modified(path, tm)
+        })
+    }
+
+    fn have_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+    ) -> std::pin::Pin<Box<dyn future::Future<Output = bool> + Send + 'a>> {
+        // no way to report an error, so a failure means "no".
+        let p = path.as_bytes();
+        let fut = run(&self.rules, MockOp::HaveProps, [p, p], move |_| {
+            self.fs.have_props(path).map(Ok).boxed()
+        });
+        fut.map(|res| res.unwrap_or(false)).boxed()
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(StatusCode, DavProp)>> {
+        let p = path.as_bytes();
+        self.mocked(MockOp::PatchProps, [p, p], move |fs| {
+            fs.patch_props(path, patch)
+        })
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        let p = path.as_bytes();
+        self.mocked(MockOp::GetProps, [p, p], move |fs| {
+            fs.get_props(path, do_content)
+        })
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        let p = path.as_bytes();
+        self.mocked(MockOp::GetProps, [p, p], move |fs| fs.get_prop(path, prop))
+    }
+
+    fn get_quota(&self) -> FsFuture<'_, (u64, Option<u64>)> {
+        self.mocked(MockOp::Quota, [b"/", b"/"], |fs| fs.get_quota())
+    }
+
+    fn get_ctag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        let p = path.as_bytes();
+        self.mocked(MockOp::GetProps, [p, p], move |fs| fs.get_ctag(path))
+    }
+
+    fn allowed_methods(&self) -> Option<DavMethodSet> {
+        self.fs.allowed_methods()
+    }
+
+    fn healthcheck(&self) -> FsFuture<'_, ()> {
+        self.mocked(MockOp::Healthcheck, [b"/", b"/"], |fs| fs.healthcheck())
+    }
+}
+
+struct MockDirEntry {
+    entry: Box<dyn DavDirEntry>,
+    path: Vec<u8>,
+    rules: Arc<Rules>,
+}
+
+impl DavDirEntry for MockDirEntry {
+    fn name(&self) -> Vec<u8> {
+        self.entry.name()
+    }
+
+    fn metadata(&self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        let p = &self.path[..];
+        let fut = run(&self.rules, MockOp::Metadata, [p, p], move |outcome| {
+            MockMeta::wrap(self.entry.metadata(), outcome)
+        });
+        fut.boxed()
+    }
+}
+
+// Metadata with the ETag, or without the creation time, that a rule asked for.
+#[derive(Debug, Clone)]
+struct MockMeta {
+    meta: Box<dyn DavMetaData>,
+    etag: Option<(String, bool)>,
+    no_created: bool,
+}
+
+impl MockMeta {
+    fn wrap(
+        fut: FsFuture<'_, Box<dyn DavMetaData>>,
+        outcome: Outcome,
+    ) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        async move {
+            let meta = fut.await?;
+            if outcome.etag.is_none() && !outcome.no_created {
+                return Ok(meta);
+            }
+            Ok(Box::new(MockMeta {
+                meta,
+                etag: outcome.etag,
+                no_created: outcome.no_created,
+            }) as Box<dyn DavMetaData>)
+        }
+        .boxed()
+    }
+}
+
+impl DavMetaData for MockMeta {
+    fn len(&self) -> u64 {
+        self.meta.len()
+    }
+    fn modified(&self) -> FsResult<SystemTime> {
+        self.meta.modified()
+    }
+    fn is_dir(&self) -> bool {
+        self.meta.is_dir()
+    }
+    fn is_file(&self) -> bool {
+        self.meta.is_file()
+    }
+    fn is_symlink(&self) -> bool {
+        self.meta.is_symlink()
+    }
+    fn etag(&self) -> Option<String> {
+        match self.etag {
+            Some((ref etag, _)) => Some(etag.clone()),
+            None => self.meta.etag(),
+        }
+    }
+    fn etag_is_weak(&self) -> bool {
+        match self.etag {
+            Some((_, weak)) => weak,
+            None => self.meta.etag_is_weak(),
+        }
+    }
+    fn accessed(&self) -> FsResult<SystemTime> {
+        self.meta.accessed()
+    }
+    fn created(&self) -> FsResult<SystemTime> {
+        if self.no_created {
+            return Err(FsError::NotImplemented);
+        }
+        self.meta.created()
+    }
+    fn status_changed(&self) -> FsResult<SystemTime> {
+        self.meta.status_changed()
+    }
+    fn executable(&self) -> FsResult<bool> {
+        self.meta.executable()
+    }
+    fn redirect_ref(&self) -> Option<RedirectRef> {
+        self.meta.redirect_ref()
+    }
+}
+
+#[derive(Debug)]
+struct MockFile {
+    file: Box<dyn DavFile>,
+    path: Vec<u8>,
+    rules: Arc<Rules>,
+}
+
+impl Drop for MockFile {
+    fn drop(&mut self) {
+        self.rules.stats.lock().unwrap().open_files -= 1;
+    }
+}
+
+impl MockFile {
+    // check the rules, then call `f` on the file.
+    fn mocked<'a, T, F>(&'a mut self, op: MockOp, f: F) -> FsFuture<'a, T>
+    where
+        T: Send + 'a,
+        F: FnOnce(&'a mut dyn DavFile, Outcome) -> FsFuture<'a, T> + Send + 'a,
+    {
+        let file = &mut *self.file;
+        let p = &self.path[..];
+        run(&self.rules, op, [p, p], move |outcome| f(file, outcome)).boxed()
+    }
+}
+
+impl DavFile for MockFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        self.mocked(MockOp::Metadata, |f, outcome| {
+            MockMeta::wrap(f.metadata(), outcome)
+        })
+    }
+
+    fn write_buf(&mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<'_, ()> {
+        self.mocked(MockOp::Write, move |f, _| f.write_buf(buf))
+    }
+
+    fn write_bytes(&mut self, buf: bytes::Bytes) -> FsFuture<'_, ()> {
+        self.mocked(MockOp::Write, move |f, _| f.write_bytes(buf))
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, bytes::Bytes> {
+        let rules = self.rules.clone();
+        self.mocked(MockOp::Read, move |f, outcome| {
+            let count = outcome.short_read.map_or(count, |n| n.min(count));
+            async move {
+                let buf = f.read_bytes(count).await?;
+                rules.stats.lock().unwrap().reads.push((count, buf.len()));
+                Ok(buf)
+            }
+            .boxed()
+        })
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        self.mocked(MockOp::Read, move |f, _| f.seek(pos))
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        self.mocked(MockOp::Flush, |f, _| f.flush())
+    }
+
+    fn set_len(&mut self, len: u64) -> FsFuture<'_, ()> {
+        self.mocked(MockOp::Write, move |f, _| f.set_len(len))
+    }
+
+    fn sync(&mut self, level: SyncLevel) -> FsFuture<'_, ()> {
+        self.mocked(MockOp::Flush, move |f, _| f.sync(level))
+    }
+
+    fn redirect_url(&mut self) -> FsFuture<'_, Option<String>> {
+        self.file.redirect_url()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{DavClient, Depth};
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn test_mockfs() {
+        let memfs = MemFs::new();
+        memfs
+            .create_dir(&DavPath::new("/readonly/").unwrap())
+            .await
+            .unwrap();
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        memfs
+            .open(&DavPath::new("/busy").unwrap(), oo)
+            .await
+            .unwrap();
+
+        let fs = MockFs::builder()
+            .filesystem(memfs)
+            .fail(MockOp::Create, "/readonly/*", FsError::Forbidden)
+            .fail_times(MockOp::Open, "/flaky", FsError::GeneralFailure, 1)
+            .fail(
+                MockOp::Open,
+                "/busy",
+                FsError::other("busy").with_status_hint(StatusCode::SERVICE_UNAVAILABLE),
+            )
+            .delay(MockOp::ReadDir, "/", Duration::from_millis(20))
+            .build();
+        let dav = DavHandler::builder().filesystem(fs).build_handler();
+        let client = DavClient::new(dav);
+
+        assert_eq!(client.mkcol("/readonly/dir/").await, StatusCode::FORBIDDEN);
+        assert_eq!(
+            client.get("/busy").await.status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        // the rule applies once.
+        assert_eq!(
+            client.put_bytes("/flaky", "x").await,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(client.put_bytes("/flaky", "x").await, StatusCode::CREATED);
+
+        let start = std::time::Instant::now();
+        let ms = client.propfind("/", Depth::One, &[]).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let mut hrefs = ms.hrefs();
+        hrefs.sort();
+        assert_eq!(hrefs, ["/", "/busy", "/flaky", "/readonly/"]);
+    }
+
+    #[tokio::test]
+    async fn test_mockfs_stats() {
+        let path = DavPath::new("/").unwrap();
+        let fs = MockFs::builder()
+            .fail_times(MockOp::GetProps, "/", FsError::Forbidden, 1)
+            .build();
+
+        // have_props is an operation of its own, and does not use up
+        // the rule for get_props.
+        assert!(fs.have_props(&path).await);
+        assert!(fs.have_props(&path).await);
+        assert_eq!(fs.calls(MockOp::HaveProps), 2);
+        assert_eq!(fs.calls(MockOp::GetProps), 0);
+        assert!(fs.get_props(&path, false).await.is_err());
+        assert!(fs.get_props(&path, false).await.is_ok());
+        assert_eq!(fs.calls(MockOp::GetProps), 2);
+    }
+}
@@ -0,0 +1,440 @@
+//! In-process WebDAV client, for tests.
+//!
+//! `DavClient` wraps a [`DavHandler`] and sends requests to it directly,
+//! without a network or an HTTP server. It has typed helpers for the
+//! common WebDAV methods, and parses `207 Multi-Status` responses into
+//! a [`Multistatus`].
+//!
+//! This is useful for writing regression tests for a custom
+//! [`DavFileSystem`] or [`DavLockSystem`]:
+//!
+//! ```
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! use dav_server::{memfs::MemFs, testing::{DavClient, Depth}, DavHandler};
+//! use http::StatusCode;
+//!
+//! let dav = DavHandler::builder().filesystem(MemFs::new()).build_handler();
+//! let client = DavClient::new(dav);
+//!
+//! assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+//! assert_eq!(client.put_bytes("/dir/file", "hello").await, StatusCode::CREATED);
+//!
+//! let ms = client.propfind("/dir/", Depth::One, &["getcontentlength"]).await.unwrap();
+//! let resp = ms.response("/dir/file").unwrap();
+//! assert_eq!(resp.prop_text("getcontentlength").as_deref(), Some("5"));
+//! # }
+//! ```
+//!
+//! [`DavFileSystem`]: ../fs/trait.DavFileSystem.html
+//! [`DavLockSystem`]: ../ls/trait.DavLockSystem.html
+//! [`DavHandler`]: ../struct.DavHandler.html
+//!
+use std::io::Cursor;
+
+use bytes::{Bytes, BytesMut};
+use futures_util::StreamExt;
+use http::{Method, Request, Response, StatusCode};
+use xmltree::{Element, XMLNode};
+
+use crate::body::Body;
+use crate::DavHandler;
+
+/// Value of the `Depth:` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Depth {
+    /// `Depth: 0`
+    Zero,
+    /// `Depth: 1`
+    One,
+    /// `Depth: infinity`
+    Infinity,
+}
+
+impl Depth {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Depth::Zero => "0",
+            Depth::One => "1",
+            Depth::Infinity => "infinity",
+        }
+    }
+}
+
+/// In-process WebDAV client.
+#[derive(Clone)]
+pub struct DavClient {
+    handler: DavHandler,
+}
+
+impl DavClient {
+    /// Create a client that sends its requests to `handler`.
+    pub fn new(handler: DavHandler) -> DavClient {
+        DavClient { handler }
+    }
+
+    /// Send a request and read the entire response body.
+    pub async fn request(&self, req: Request<Body>) -> Response<Bytes> {
+        let (parts, mut body) = self.handler.handle(req).await.into_parts();
+        let mut data = BytesMut::new();
+        while let Some(chunk) = body.next().await {
+            // the handler only fails the body stream on a filesystem
+            // error halfway, and then the response is truncated.
+            match chunk {
+                Ok(chunk) => data.extend_from_slice(&chunk),
+                Err(_) => break,
+            }
+        }
+        Response::from_parts(parts, data.freeze())
+    }
+
+    // Build and send a request.
+    async fn send(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: Body,
+    ) -> Response<Bytes> {
+        let mut req = Request::builder()
+            .method(Method::from_bytes(method.as_bytes()).unwrap())
+            .uri(path);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        self.request(req.body(body).unwrap()).await
+    }
+
+    /// GET a resource.
+    pub async fn get(&self, path: &str) -> Response<Bytes> {
+        self.send("GET", path, &[], Body::empty()).await
+    }
+
+    /// GET the bytes `start` up to and including `end` of a resource.
+    pub async fn get_range(&self, path: &str, start: u64, end: u64) -> Response<Bytes> {
+        let range = format!("bytes={}-{}", start, end);
+        self.send("GET", path, &[("Range", &range)], Body::empty())
+            .await
+    }
+
+    /// PUT `data` at `path`.
+    pub async fn put_bytes(&self, path: &str, data: impl Into<Bytes>) -> StatusCode {
+        let body = Body::from(data.into());
+        self.send("PUT", path, &[], body).await.status()
+    }
+
+    /// DELETE a resource.
+    pub async fn delete(&self, path: &str) -> StatusCode {
+        self.send("DELETE", path, &[], Body::empty()).await.status()
+    }
+
+    /// Create a collection.
+    pub async fn mkcol(&self, path: &str) -> StatusCode {
+        self.send("MKCOL", path, &[], Body::empty()).await.status()
+    }
+
+    /// COPY `from` to `to`.
+    pub async fn copy(&self, from: &str, to: &str, overwrite: bool) -> StatusCode {
+        self.copymove("COPY", from, to, overwrite).await
+    }
+
+    /// MOVE `from` to `to`.
+    pub async fn move_(&self, from: &str, to: &str, overwrite: bool) -> StatusCode {
+        self.copymove("MOVE", from, to, overwrite).await
+    }
+
+    async fn copymove(&self, method: &str, from: &str, to: &str, overwrite: bool) -> StatusCode {
+        let overwrite = if overwrite { "T" } else { "F" };
+        let headers = [("Destination", to), ("Overwrite", overwrite)];
+        self.send(method, from, &headers, Body::empty())
+            .await
+            .status()
+    }
+
+    /// PROPFIND `props` on `path`.
+    ///
+    /// Property names are in the `DAV:` namespace, unless written
+    /// in Clark notation (`{namespace}name`). An empty list of
+    /// properties does an `allprop` request.
+    ///
+    /// If the response is not a `207 Multi-Status`, the status is returned as error.
+    pub async fn propfind(
+        &self,
+        path: &str,
+        depth: Depth,
+        props: &[&str],
+    ) -> Result<Multistatus, StatusCode> {
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8" ?>"#);
+        xml.push_str(r#"<D:propfind xmlns:D="DAV:">"#);
+        if props.is_empty() {
+            xml.push_str("<D:allprop/>");
+        } else {
+            xml.push_str("<D:prop>");
+            for prop in props {
+                match split_name(prop) {
+                    ("DAV:", name) => xml.push_str(&format!("<D:{}/>", name)),
+                    (ns, name) => {
+                        let ns = htmlescape::encode_attribute(ns);
+                        xml.push_str(&format!(r#"<{} xmlns="{}"/>"#, name, ns));
+                    }
+                }
+            }
+            xml.push_str("</D:prop>");
+        }
+        xml.push_str("</D:propfind>");
+
+        let headers = [("Depth", depth.as_str())];
+        let res = self.send("PROPFIND", path, &headers, Body::from(xml)).await;
+        if res.status() != StatusCode::MULTI_STATUS {
+            return Err(res.status());
+        }
+        Multistatus::parse(res.body()).ok_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    /// Take an exclusive write lock on `path`, and return the lock token.
+    pub async fn lock(&self, path: &str, depth: Depth) -> Result<String, StatusCode> {
+        let xml = concat!(
+            r#"<?xml version="1.0" encoding="utf-8" ?>"#,
+            r#"<D:lockinfo xmlns:D="DAV:">"#,
+            r#"<D:lockscope><D:exclusive/></D:lockscope>"#,
+            r#"<D:locktype><D:write/></D:locktype>"#,
+            r#"</D:lockinfo>"#,
+        );
+        let headers = [("Depth", depth.as_str())];
+        let res = self.send("LOCK", path, &headers, Body::from(xml)).await;
+        if !res.status().is_success() {
+            return Err(res.status());
+        }
+        res.headers()
+            .get("lock-token")
+            .and_then(|t| t.to_str().ok())
+            .map(|t| t.trim_start_matches('<').trim_end_matches('>').to_string())
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    /// Remove the lock with `token` from `path`.
+    pub async fn unlock(&self, path: &str, token: &str) -> StatusCode {
+        let token = format!("<{}>", token);
+        let headers = [("Lock-Token", token.as_str())];
+        self.send("UNLOCK", path, &headers, Body::empty())
+            .await
+            .status()
+    }
+}
+
+/// A parsed `207 Multi-Status` response body.
+#[derive(Debug, Clone)]
+pub struct Multistatus {
+    /// The `response` elements, in document order.
+    pub responses: Vec<MultistatusResponse>,
+}
+
+/// One `response` element of a `Multistatus`.
+#[derive(Debug, Clone)]
+pub struct MultistatusResponse {
+    /// The href, as sent by the server (so percent-encoded).
+    pub href: String,
+    /// The status of the response, if it has one instead of `propstat`s.
+    pub status: Option<StatusCode>,
+    /// The `propstat` elements.
+    pub propstat: Vec<Propstat>,
+}
+
+/// One `propstat` element of a `MultistatusResponse`.
+#[derive(Debug, Clone)]
+pub struct Propstat {
+    /// The status of the properties.
+    pub status: StatusCode,
+    /// The properties.
+    pub props: Vec<Element>,
+}
+
+impl Multistatus {
+    /// Parse a multistatus XML body.
+    pub fn parse(xml: &[u8]) -> Option<Multistatus> {
+        let root = Element::parse(Cursor::new(xml)).ok()?;
+        if !is_dav(&root, "multistatus") {
+            return None;
+        }
+        let mut responses = Vec::new();
+        for resp in child_elems(&root).filter(|e| is_dav(e, "response")) {
+            let href = child_elems(resp)
+                .find(|e| is_dav(e, "href"))
+                .and_then(|e| e.get_text())?
+                .trim()
+                .to_string();
+            let status = child_elems(resp)
+                .find(|e| is_dav(e, "status"))
+                .and_then(parse_status);
+            let mut propstat = Vec::new();
+            for ps in child_elems(resp).filter(|e| is_dav(e, "propstat")) {
+                let status = child_elems(ps)
+                    .find(|e| is_dav(e, "status"))
+                    .and_then(parse_status)?;
+                let props = child_elems(ps)
+                    .find(|e| is_dav(e, "prop"))
+                    .map(|p| child_elems(p).cloned().collect())
+                    .unwrap_or_default();
+                propstat.push(Propstat { status, props });
+            }
+            responses.push(MultistatusResponse {
+                href,
+                status,
+                propstat,
+            });
+        }
+        Some(Multistatus { responses })
+    }
+
+    /// Find the response for `href`.
+    ///
+    /// A trailing slash is ignored, so `/dir` finds `/dir/`.
+    pub fn response(&self, href: &str) -> Option<&MultistatusResponse> {
+        let href = href.trim_end_matches('/');
+        self.responses
+            .iter()
+            .find(|r| r.href.trim_end_matches('/') == href)
+    }
+
+    /// All hrefs, in document order.
+    pub fn hrefs(&self) -> Vec<&str> {
+        self.responses.iter().map(|r| r.href.as_str()).collect()
+    }
+}
+
+impl MultistatusResponse {
+    /// Find a property that was returned with status `200 OK`.
+    ///
+    /// The name is in the `DAV:` namespace unless written in
+    /// Clark notation (`{namespace}name`).
+    pub fn prop(&self, name: &str) -> Option<&Element> {
+        self.find_prop(name)
+            .filter(|(status, _)| *status == StatusCode::OK)
+            .map(|(_, e)| e)
+    }
+
+    /// The text content of a property that was returned with status `200 OK`.
+    pub fn prop_text(&self, name: &str) -> Option<String> {
+        self.prop(name)
+            .map(|e| e.get_text().map(|t| t.into_owned()).unwrap_or_default())
+    }
+
+    /// The status that a property was returned with.
+    pub fn prop_status(&self, name: &str) -> Option<StatusCode> {
+        self.find_prop(name).map(|(status, _)| status)
+    }
+
+    fn find_prop(&self, name: &str) -> Option<(StatusCode, &Element)> {
+        let (ns, name) = split_name(name);
+        self.propstat.iter().find_map(|ps| {
+            ps.props
+                .iter()
+                .find(|e| e.name == name && e.namespace.as_deref() == Some(ns))
+                .map(|e| (ps.status, e))
+        })
+    }
+}
+
+// Split "{namespace}name" into namespace and name.
+fn split_name(name: &str) -> (&str, &str) {
+    if let Some(rest) = name.strip_prefix('{') {
+        if let Some((ns, name)) = rest.split_once('}') {
+            return (ns, name);
+        }
+    }
+    ("DAV:", name)
+}
+
+fn is_dav(e: &Element, name: &str) -> bool {
+    e.name == name && e.namespace.as_deref() == Some("DAV:")
+}
+
+fn child_elems(e: &Element) -> impl Iterator<Item = &Element> {
+    e.children.iter().filter_map(|n| match n {
+        XMLNode::Element(e) => Some(e),
+        _ => None,
+    })
+}
+
+// "HTTP/1.1 200 OK" -> 200.
+fn parse_status(e: &Element) -> Option<StatusCode> {
+    let text = e.get_text()?;
+    let code = text.split_whitespace().nth(1)?;
+    StatusCode::from_bytes(code.as_bytes()).ok()
+}
+
+// An empty directory for a test, named after the test and the process.
+#[cfg(test)]
+pub(crate) fn tempdir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("dav-server-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::memls::MemLs;
+
+    #[tokio::test]
+    async fn test_client() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        let client = DavClient::new(dav);
+
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        assert_eq!(
+            client.put_bytes("/dir/file", "hello world").await,
+            StatusCode::CREATED
+        );
+        let res = client.get_range("/dir/file", 6, 10).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.body().as_ref(), b"world");
+
+        assert_eq!(
+            client.copy("/dir/file", "/dir/copy", false).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            client.copy("/dir/file", "/dir/copy", false).await,
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(
+            client.move_("/dir/copy", "/moved", true).await,
+            StatusCode::CREATED
+        );
+
+        let props = ["getcontentlength", "resourcetype", "{urn:x}nope"];
+        let ms = client.propfind("/dir/", Depth::One, &props).await.unwrap();
+        assert_eq!(ms.hrefs(), vec!["/dir/", "/dir/file"]);
+        let dir = ms.response("/dir").unwrap();
+        assert!(dir
+            .prop("resourcetype")
+            .unwrap()
+            .get_child("collection")
+            .is_some());
+        let file = ms.response("/dir/file").unwrap();
+        assert_eq!(file.prop_text("getcontentlength").as_deref(), Some("11"));
+        assert_eq!(file.prop_status("{urn:x}nope"), Some(StatusCode::NOT_FOUND));
+        assert!(file.prop("{urn:x}nope").is_none());
+        assert_eq!(
+            client
+                .propfind("/nope", Depth::Zero, &[])
+                .await
+                .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+
+        let token = client.lock("/dir/file", Depth::Zero).await.unwrap();
+        assert_eq!(client.delete("/dir/file").await, StatusCode::LOCKED);
+        assert_eq!(
+            client.unlock("/dir/file", &token).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(client.delete("/dir/file").await, StatusCode::NO_CONTENT);
+    }
+}
@@ -0,0 +1,310 @@
+//! Time out filesystem operations.
+//!
+//! `TimeoutFs` wraps the filesystem of a request, and fails the
+//! `DavFileSystem` calls that take longer than the timeout with a
+//! `504 Gateway Timeout`. Files that it opens get the same treatment
+//! for each read and write, so a long GET or PUT is only cut off if
+//! it makes no progress for that long. The stream that `read_dir`
+//! returns can not fail, so it is not covered.
+//!
+//! A call that times out is dropped, but a backend that runs it on
+//! another thread (like `LocalFs`) can not stop it, and it may still
+//! complete later. That is why the calls that change the namespace or
+//! the properties, like `create_dir`, `rename` or `patch_props`, are
+//! passed on without a timeout: a `504` would not tell the client
+//! whether the change happened. Writes to an open file do time out;
+//! they leave a partial file, like any upload that fails.
+//!
+//! Without a tokio runtime there is no timer, and nothing times out.
+//!
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::time::{Duration, SystemTime};
+
+use futures_util::{future, FutureExt};
+use http::StatusCode;
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+use crate::DavMethodSet;
+
+/// The error of an operation that timed out.
+pub(crate) fn timed_out() -> FsError {
+    let err = io::Error::new(io::ErrorKind::TimedOut, "operation timed out");
+    FsError::other(err).with_status_hint(StatusCode::GATEWAY_TIMEOUT)
+}
+
+// run `fut` for at most `timeout`, `None` if it took longer. Outside
+// of a tokio runtime `fut` runs to completion.
+pub(crate) async fn within<F: Future>(timeout: Duration, fut: F) -> Option<F::Output> {
+    if tokio::runtime::Handle::try_current().is_err() {
+        return Some(fut.await);
+    }
+    tokio::time::timeout(timeout, fut).await.ok()
+}
+
+// run `fut` for at most `timeout`.
+fn timed<'a, T: Send + 'a>(timeout: Duration, fut: FsFuture<'a, T>) -> FsFuture<'a, T> {
+    async move {
+        within(timeout, fut)
+            .await
+            .unwrap_or_else(|| Err(timed_out()))
+    }
+    .boxed()
+}
+
+#[derive(Clone)]
+pub(crate) struct TimeoutFs {
+    fs: Box<dyn DavFileSystem>,
+    timeout: Duration,
+}
+
+impl TimeoutFs {
+    pub(crate) fn new(fs: Box<dyn DavFileSystem>, timeout: Duration) -> Box<TimeoutFs> {
+        Box::new(TimeoutFs { fs, timeout })
+    }
+}
+
+impl DavFileSystem for TimeoutFs {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        let timeout = self.timeout;
+        async move {
+            let file = timed(timeout, self.fs.open(path, options)).await?;
+            Ok(Box::new(TimeoutFile { file, timeout }) as Box<dyn DavFile>)
+        }
+        .boxed()
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        timed(self.timeout, self.fs.read_dir(path, meta))
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        timed(self.timeout, self.fs.metadata(path))
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        timed(self.timeout, self.fs.symlink_metadata(path))
+    }
+
+    // the calls that change the filesystem have no timeout, see above.
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.create_dir(path)
+    }
+
+    fn create_collection<'a>(
+        &'a self,
+        path: &'a DavPath,
+        resourcetypes: Vec<DavProp>,
+    ) -> FsFuture<'a, ()> {
+        self.fs.create_collection(path, resourcetypes)
+    }
+
+    fn create_redirect_ref<'a>(
+        &'a self,
+        path: &'a DavPath,
+        target: RedirectRef,
+    ) -> FsFuture<'a, ()> {
+        self.fs.create_redirect_ref(path, target)
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.remove_dir(path)
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.remove_file(path)
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.rename(from, to)
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.copy(from, to)
+    }
+
+    fn rename_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.rename_no_replace(from, to)
+    }
+
+    fn copy_no_replace<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.copy_no_replace(from, to)
+    }
+
+    fn copy_recursive<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        self.fs.copy_recursive(from, to)
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        self.fs.set_accessed(path, tm)
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        self.fs.set_modified(path, tm)
+    }
+
+    fn have_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+    ) -> std::pin::Pin<Box<dyn future::Future<Output = bool> + Send + 'a>> {
+        // no way to report an error, so a timeout means "no".
+        async move {
+            within(self.timeout, self.fs.have_props(path))
+                .await
+                .unwrap_or(false)
+        }
+        .boxed()
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(StatusCode, DavProp)>> {
+        // changes the properties, so no timeout.
+        self.fs.patch_props(path, patch)
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        timed(self.timeout, self.fs.get_props(path, do_content))
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        timed(self.timeout, self.fs.get_prop(path, prop))
+    }
+
+    fn get_quota(&self) -> FsFuture<'_, (u64, Option<u64>)> {
+        timed(self.timeout, self.fs.get_quota())
+    }
+
+    fn get_ctag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, String> {
+        timed(self.timeout, self.fs.get_ctag(path))
+    }
+
+    fn allowed_methods(&self) -> Option<DavMethodSet> {
+        self.fs.allowed_methods()
+    }
+
+    fn healthcheck(&self) -> FsFuture<'_, ()> {
+        timed(self.timeout, self.fs.healthcheck())
+    }
+}
+
+#[derive(Debug)]
+struct TimeoutFile {
+    file: Box<dyn DavFile>,
+    timeout: Duration,
+}
+
+impl DavFile for TimeoutFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        timed(self.timeout, self.file.metadata())
+    }
+
+    fn write_buf(&mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<'_, ()> {
+        timed(self.timeout, self.file.write_buf(buf))
+    }
+
+    fn write_bytes(&mut self, buf: bytes::Bytes) -> FsFuture<'_, ()> {
+        timed(self.timeout, self.file.write_bytes(buf))
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, bytes::Bytes> {
+        timed(self.timeout, self.file.read_bytes(count))
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        timed(self.timeout, self.file.seek(pos))
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        timed(self.timeout, self.file.flush())
+    }
+
+    fn set_len(&mut self, len: u64) -> FsFuture<'_, ()> {
+        timed(self.timeout, self.file.set_len(len))
+    }
+
+    fn sync(&mut self, level: SyncLevel) -> FsFuture<'_, ()> {
+        timed(self.timeout, self.file.sync(level))
+    }
+
+    fn redirect_url(&mut self) -> FsFuture<'_, Option<String>> {
+        timed(self.timeout, self.file.redirect_url())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testfs::{MockAction, MockFs, MockOp};
+    use crate::testing::{DavClient, Depth};
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn test_operation_timeout() {
+        // metadata hangs under "/slow", and reading "/stall" is slow
+        // and returns a byte at a time.
+        let fs = MockFs::builder()
+            .delay(MockOp::Metadata, "/slow*", Duration::from_secs(3600))
+            .delay(MockOp::Read, "/stall", Duration::from_millis(30))
+            .rule(MockOp::Read, "/stall", MockAction::ShortRead(1), None)
+            .build();
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .operation_timeout(Duration::from_millis(100))
+            .build_handler();
+        let client = DavClient::new(dav);
+
+        assert_eq!(
+            client.get("/slow/file").await.status(),
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        let res = client.propfind("/slow/", Depth::Zero, &[]).await;
+        assert_eq!(res.err(), Some(StatusCode::GATEWAY_TIMEOUT));
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+
+        // each read makes progress in time, so a download that takes
+        // longer than the timeout in total is not cut off.
+        let data = "0123456789";
+        assert_eq!(client.put_bytes("/stall", data).await, StatusCode::CREATED);
+        let res = client.get("/stall").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body().as_ref(), data.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_no_timeout_for_changes() {
+        // a slow change is not reported as a 504 while it completes.
+        let fs = MockFs::builder()
+            .delay(MockOp::Create, "/dir/", Duration::from_millis(300))
+            .delay(MockOp::Rename, "/moved", Duration::from_millis(300))
+            .build();
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .operation_timeout(Duration::from_millis(100))
+            .build_handler();
+        let client = DavClient::new(dav);
+
+        assert_eq!(client.mkcol("/dir/").await, StatusCode::CREATED);
+        assert_eq!(client.put_bytes("/file", "x").await, StatusCode::CREATED);
+        let res = client.move_("/file", "/moved", false).await;
+        assert_eq!(res, StatusCode::CREATED);
+    }
+
+    #[test]
+    fn test_no_runtime() {
+        // without a runtime there is no timer, and nothing times out.
+        let res = within(Duration::from_millis(1), async { 1 }).now_or_never();
+        assert_eq!(res, Some(Some(1)));
+    }
+}
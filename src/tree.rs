@@ -122,6 +122,12 @@ impl<K: Eq + Hash + Debug + Clone, D: Debug> Tree<K, D> {
         Ok(&mut n.data)
     }
 
+    /// Get the id of the parent of a node.
+    pub fn get_parent(&self, id: u64) -> FsResult<u64> {
+        let n = self.nodes.get(&id).ok_or(FsError::NotFound)?;
+        Ok(n.parent_id)
+    }
+
     fn delete_node_from_parent(&mut self, id: u64) -> FsResult<()> {
         let parent_id = self.nodes.get(&id).ok_or(FsError::NotFound)?.parent_id;
         let key = {
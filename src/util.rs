@@ -1,9 +1,12 @@
 use std::io::{Cursor, Write};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use bytes::Bytes;
-use headers::Header;
+use bytes::{Bytes, BytesMut};
+use futures_util::StreamExt;
+use headers::{Header, HeaderMapExt};
 use http::method::InvalidMethod;
+use http::{Response, StatusCode};
+use percent_encoding as pct;
 use time::format_description::well_known::Rfc3339;
 use time::macros::offset;
 
@@ -28,6 +31,8 @@ pub enum DavMethod {
     Delete = 0x0400,
     Lock = 0x0800,
     Unlock = 0x1000,
+    Post = 0x2000,
+    MkRedirectRef = 0x4000,
 }
 
 // translate method into our own enum that has webdav methods as well.
@@ -39,6 +44,7 @@ pub(crate) fn dav_method(m: &http::Method) -> DavResult<DavMethod> {
         http::Method::PATCH => DavMethod::Patch,
         http::Method::DELETE => DavMethod::Delete,
         http::Method::OPTIONS => DavMethod::Options,
+        http::Method::POST => DavMethod::Post,
         _ => match m.as_str() {
             "PROPFIND" => DavMethod::PropFind,
             "PROPPATCH" => DavMethod::PropPatch,
@@ -47,6 +53,7 @@ pub(crate) fn dav_method(m: &http::Method) -> DavResult<DavMethod> {
             "MOVE" => DavMethod::Move,
             "LOCK" => DavMethod::Lock,
             "UNLOCK" => DavMethod::Unlock,
+            "MKREDIRECTREF" => DavMethod::MkRedirectRef,
             _ => {
                 return Err(DavError::UnknownDavMethod);
             }
@@ -125,6 +132,8 @@ impl DavMethodSet {
                 "move" => DavMethod::Move as u32,
                 "lock" => DavMethod::Lock as u32,
                 "unlock" => DavMethod::Unlock as u32,
+                "post" => DavMethod::Post as u32,
+                "mkredirectref" => DavMethod::MkRedirectRef as u32,
                 "http-ro" => Self::HTTP_RO.0,
                 "http-rw" => Self::HTTP_RW.0,
                 "webdav-ro" => Self::WEBDAV_RO.0,
@@ -151,6 +160,85 @@ pub(crate) fn dav_xml_error(body: &str) -> Body {
     Body::from(xml)
 }
 
+// Namespace of the DAV:error elements that are specific to this crate.
+// Fixed, so that clients can match on it.
+pub(crate) const NS_DAV_SERVER_URI: &str = "urn:dav-server:";
+
+// Response for a request that exceeds one of the `Limits`. The DAV:error
+// body names the limit; there is no standard element for these.
+pub(crate) fn limit_error(status: StatusCode, limit: &str) -> Response<Body> {
+    let xml = format!(
+        r#"<S:limit-exceeded xmlns:S="{}">{}</S:limit-exceeded>"#,
+        NS_DAV_SERVER_URI, limit
+    );
+    let body = dav_xml_error(&xml);
+    let mut res = Response::new(body);
+    *res.status_mut() = status;
+    res.headers_mut().insert(
+        "content-type",
+        "application/xml; charset=utf-8".parse().unwrap(),
+    );
+    res
+}
+
+// A small HTML page for an error response. `path` is the request path.
+pub(crate) fn html_error_page(status: StatusCode, path: &str) -> String {
+    let display = pct::percent_decode_str(path).decode_utf8_lossy();
+    let trimmed = path.trim_end_matches('/');
+    let parent = match trimmed.rfind('/') {
+        Some(idx) => &trimmed[..idx + 1],
+        None => "/",
+    };
+    let title = format!(
+        "{} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("")
+    );
+    format!(
+        "<!DOCTYPE html>\n\
+        <html><head>\n\
+        <meta name=\"referrer\" content=\"no-referrer\" />\n\
+        <title>{title}</title>\n\
+        <style>\n\
+        body {{ font-family: sans-serif; padding: 0.3em; }}\n\
+        .mono {{ font-family: monospace; }}\n\
+        </style>\n\
+        </head>\n\
+        <body>\n\
+        <h1>{title}</h1>\n\
+        <p class=\"mono\">{path}</p>\n\
+        <p><a href=\"{parent}\">Parent Directory</a></p>\n\
+        </body></html>\n",
+        title = htmlescape::encode_minimal(title.trim_end()),
+        path = htmlescape::encode_minimal(&display),
+        parent = htmlescape::encode_minimal(parent),
+    )
+}
+
+// Is the URL path `path` the path `base`, or below it.
+pub(crate) fn in_url_path(base: &str, path: &str) -> bool {
+    let base = base.trim_end_matches('/');
+    let path = path.trim_end_matches('/');
+    match path.strip_prefix(base) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+// Read the body of a response into memory and set Content-Length.
+// Some HTTP/1.0 clients and proxies cannot handle a chunked multistatus.
+pub(crate) async fn buffer_body(res: &mut Response<Body>) -> DavResult<()> {
+    let mut body = std::mem::replace(res.body_mut(), Body::empty());
+    let mut data = BytesMut::new();
+    while let Some(chunk) = body.next().await {
+        data.extend_from_slice(&chunk?);
+    }
+    res.headers_mut()
+        .typed_insert(headers::ContentLength(data.len() as u64));
+    *res.body_mut() = Body::from(data.freeze());
+    Ok(())
+}
+
 pub(crate) fn systemtime_to_offsetdatetime(t: SystemTime) -> time::OffsetDateTime {
     match t.duration_since(UNIX_EPOCH) {
         Ok(t) => {
@@ -173,6 +261,34 @@ pub(crate) fn systemtime_to_rfc3339(t: SystemTime) -> String {
     systemtime_to_offsetdatetime(t).format(&Rfc3339).unwrap()
 }
 
+// Why `name` cannot be used as a file name on Windows, if it cannot.
+//
+// Device names like CON are reserved, also with an extension ("nul.txt").
+// Trailing dots and spaces are silently stripped by the Win32 API, so a
+// file called "foo." would be opened as "foo".
+pub(crate) fn windows_name_problem(name: &[u8]) -> Option<&'static str> {
+    const RESERVED: &[&str] = &["CON", "PRN", "AUX", "NUL"];
+    if name
+        .iter()
+        .any(|&c| c < 0x20 || b"<>:\"/\\|?*".contains(&c))
+    {
+        return Some("contains a character that is not allowed");
+    }
+    if name.ends_with(b".") || name.ends_with(b" ") {
+        return Some("ends with a dot or a space");
+    }
+    let stem = name.split(|&c| c == b'.').next().unwrap_or(name);
+    let stem = stem.to_ascii_uppercase();
+    let device = match stem.as_slice() {
+        [b'C', b'O', b'M', b'1'..=b'9'] | [b'L', b'P', b'T', b'1'..=b'9'] => true,
+        s => RESERVED.iter().any(|r| r.as_bytes() == s),
+    };
+    if device {
+        return Some("is a reserved device name");
+    }
+    None
+}
+
 // A buffer that implements "Write".
 #[derive(Clone)]
 pub(crate) struct MemBuffer(Cursor<Vec<u8>>);
@@ -208,4 +324,33 @@ mod tests {
     fn test_rfc3339() {
         assert!(systemtime_to_rfc3339(UNIX_EPOCH) == "1970-01-01T00:00:00Z");
     }
+
+    #[test]
+    fn test_in_url_path() {
+        assert!(in_url_path("/healthz", "/healthz"));
+        assert!(in_url_path("/healthz", "/healthz/"));
+        assert!(in_url_path("/healthz/", "/healthz/x"));
+        assert!(!in_url_path("/healthz", "/healthzz"));
+        assert!(!in_url_path("/healthz", "/"));
+    }
+
+    #[test]
+    fn test_windows_name_problem() {
+        for name in [
+            "CON",
+            "con",
+            "Nul.txt",
+            "com1",
+            "LPT9.tar.gz",
+            "foo.",
+            "foo ",
+            "a:b",
+            "a*",
+        ] {
+            assert!(windows_name_problem(name.as_bytes()).is_some(), "{}", name);
+        }
+        for name in ["file.txt", "CONFIG", "com10", "lpt", ".hidden", "foo.bar"] {
+            assert!(windows_name_problem(name.as_bytes()).is_none(), "{}", name);
+        }
+    }
 }
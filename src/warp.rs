@@ -8,37 +8,58 @@
 use std::convert::Infallible;
 use std::path::Path;
 
-use crate::{fakels::FakeLs, localfs::LocalFs, DavHandler};
+use crate::body::{Body, BodyType};
+use crate::{fakels::FakeLs, localfs::LocalFs, DavConfig, DavHandler};
 use warp::{filters::BoxedFilter, Filter, Reply};
 
 /// Reply-filter that runs a DavHandler.
 ///
 /// Just pass in a pre-configured DavHandler. If a prefix was not
 /// configured, it will be the request path up to this point.
+///
+/// Warp filters cannot take the headers out of the request, so they are
+/// copied once per request. The values themselves are shared, not copied.
+/// To hand the request to the handler as it is, serve the handler
+/// with hyper directly, see the `hyper` module (`hyper-compat` feature).
 pub fn dav_handler(handler: DavHandler) -> BoxedFilter<(impl Reply,)> {
     use http::header::HeaderMap;
     use http::uri::Uri;
     use http::Response;
     use warp::path::{FullPath, Tail};
 
+    // the query string is optional.
+    let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+
     warp::method()
         .and(warp::path::full())
         .and(warp::path::tail())
+        .and(query)
+        // warp has no filter that hands out the HeaderMap by value.
         .and(warp::header::headers_cloned())
         .and(warp::body::stream())
         .and_then(
-            move |method, path_full: FullPath, path_tail: Tail, headers: HeaderMap, body| {
+            move |method,
+                  path_full: FullPath,
+                  path_tail: Tail,
+                  query: String,
+                  headers: HeaderMap,
+                  body| {
                 let handler = handler.clone();
 
                 async move {
                     // rebuild an http::Request struct.
                     let path_str = path_full.as_str();
-                    let uri = path_str.parse::<Uri>().unwrap();
-                    let mut builder = http::Request::builder().method(method).uri(uri);
-                    for (k, v) in headers.iter() {
-                        builder = builder.header(k, v);
-                    }
-                    let request = builder.body(body).unwrap();
+                    let uri = if query.is_empty() {
+                        path_str.parse::<Uri>().unwrap()
+                    } else {
+                        format!("{}?{}", path_str, query).parse::<Uri>().unwrap()
+                    };
+                    let mut request = http::Request::builder()
+                        .method(method)
+                        .uri(uri)
+                        .body(body)
+                        .unwrap();
+                    *request.headers_mut() = headers;
 
                     let response = if handler.config.prefix.is_some() {
                         // Run a handler with the configured path prefix.
@@ -48,13 +69,13 @@ pub fn dav_handler(handler: DavHandler) -> BoxedFilter<(impl Reply,)> {
                         let path_len = path_str.len();
                         let tail_len = path_tail.as_str().len();
                         let prefix = path_str[..path_len - tail_len].to_string();
-                        let config = DavHandler::builder().strip_prefix(prefix);
+                        let config = DavConfig::new().strip_prefix(prefix);
                         handler.handle_stream_with(config, request).await
                     };
 
                     // Need to remap the http_body::Body to a hyper::Body.
                     let (parts, body) = response.into_parts();
-                    let response = Response::from_parts(parts, hyper::Body::wrap_stream(body));
+                    let response = Response::from_parts(parts, to_hyper_body(body));
                     Ok::<_, Infallible>(response)
                 }
             },
@@ -62,6 +83,17 @@ pub fn dav_handler(handler: DavHandler) -> BoxedFilter<(impl Reply,)> {
         .boxed()
 }
 
+// Bodies that are already in memory are passed as-is, so that hyper
+// knows their length. Only real streams are wrapped, and those
+// usually come with a Content-Length header.
+fn to_hyper_body(body: Body) -> hyper::Body {
+    match body.inner {
+        BodyType::Bytes(Some(b)) => hyper::Body::from(b),
+        BodyType::Bytes(None) | BodyType::Empty => hyper::Body::empty(),
+        BodyType::AsyncStream(_) => hyper::Body::wrap_stream(body),
+    }
+}
+
 /// Creates a Filter that serves files and directories at the
 /// base path joined with the remainder of the request path,
 /// like `warp::filters::fs::dir`.
@@ -112,3 +144,48 @@ pub fn dav_file(file: impl AsRef<Path>) -> BoxedFilter<(impl Reply,)> {
         .build_handler();
     dav_handler(handler)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::memls::MemLs;
+
+    #[tokio::test]
+    async fn test_range_and_propfind() {
+        let handler = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        let filter = dav_handler(handler);
+
+        let resp = warp::test::request()
+            .method("PUT")
+            .path("/file.txt")
+            .body("hello world")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), 201);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/file.txt?x=1")
+            .header("Range", "bytes=0-4")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), 206);
+        assert_eq!(resp.headers()["content-length"], "5");
+        assert!(resp.headers().get("transfer-encoding").is_none());
+        assert_eq!(resp.body().as_ref(), b"hello");
+
+        let resp = warp::test::request()
+            .method("PROPFIND")
+            .path("/")
+            .header("Depth", "1")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), 207);
+        let body = String::from_utf8_lossy(resp.body());
+        assert!(body.contains("/file.txt"));
+    }
+}
@@ -2,6 +2,7 @@ use std::borrow::Cow;
 use std::io::{Read, Write};
 
 use xml::common::XmlVersion;
+use xml::reader::{EventReader, XmlEvent as XmlREvent};
 use xml::writer::EventWriter;
 use xml::writer::XmlEvent as XmlWEvent;
 use xml::EmitterConfig;
@@ -95,12 +96,7 @@ impl ElementExt for Element {
     }
 
     fn parse2<R: Read>(r: R) -> Result<Element, DavError> {
-        let res = Element::parse(r);
-        match res {
-            Ok(elems) => Ok(elems),
-            Err(xmltree::ParseError::MalformedXml(_)) => Err(DavError::XmlParseError),
-            Err(_) => Err(DavError::XmlReadError),
-        }
+        Element::parse(r).map_err(|_| DavError::XmlReadError)
     }
 
     fn write_ev<W: Write>(&self, emitter: &mut EventWriter<W>) -> xml::writer::Result<()> {
@@ -159,6 +155,33 @@ impl ElementExt for Element {
     }
 }
 
+// Are there more than `max` elements at `depth` in the XML document
+// `data`, where the root element is at depth 1. This stops reading as
+// soon as there are, so that a request that is too large is refused
+// before it is parsed into a tree. A document that is not well-formed
+// is left to the real parse to report.
+pub(crate) fn too_many_elements(data: &[u8], depth: usize, max: usize) -> bool {
+    let mut level = 0;
+    let mut count = 0;
+    for event in EventReader::new(data) {
+        match event {
+            Ok(XmlREvent::StartElement { .. }) => {
+                level += 1;
+                if level == depth {
+                    count += 1;
+                    if count > max {
+                        return true;
+                    }
+                }
+            }
+            Ok(XmlREvent::EndElement { .. }) => level -= 1,
+            Ok(_) => {}
+            Err(_) => return false,
+        }
+    }
+    false
+}
+
 pub(crate) fn emitter<W: Write>(w: W) -> DavResult<EventWriter<W>> {
     let mut emitter = EventWriter::new_with_config(
         w,